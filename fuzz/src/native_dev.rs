@@ -0,0 +1,57 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uxn::{test_utils::diff, Backend, EmptyDevice, Uxn, UxnRam};
+
+/// Maximum number of instructions to run with the interpreter
+const FUEL: usize = 65536;
+
+fuzz_target!(|data: &[u8]| {
+    // The first 256 bytes are initial device memory, and the rest is the ROM
+    let Some((dev, rom)) = data.split_first_chunk::<256>() else {
+        return;
+    };
+
+    let mut ram_v = UxnRam::new();
+    let mut vm_v = Uxn::new(&mut ram_v, Backend::Interpreter);
+    let mut ram_n = UxnRam::new();
+    let mut vm_n = Uxn::new(&mut ram_n, Backend::Native);
+    for vm in [&mut vm_v, &mut vm_n] {
+        // Don't load any programs that require auxiliary memory
+        if !vm.reset(rom).is_empty() {
+            return;
+        }
+        for (i, &d) in dev.iter().enumerate() {
+            vm.write_dev_mem(i as u8, d);
+        }
+        // Fill the rest of RAM with a pattern, so that loads see real data
+        for i in 0x100 + rom.len()..0x10000 {
+            vm.ram_write_byte(i as u16, (i as u8) ^ (i >> 8) as u8);
+        }
+    }
+
+    let Some(pc_v) =
+        vm_v.run_until(&mut EmptyDevice, 0x100, |_, _, i| i + 1 >= FUEL)
+    else {
+        return;
+    };
+    let pc_n = vm_n.run(&mut EmptyDevice, 0x100);
+
+    let diffs = diff(&vm_v, &vm_n);
+    if pc_v != pc_n || !diffs.is_empty() {
+        println!("PC: {pc_v:#06x} vs {pc_n:#06x}");
+        for d in &diffs {
+            println!("{d}");
+        }
+        print!("Instructions:\n  ");
+        for (i, d) in rom.iter().enumerate() {
+            print!(
+                "{}{}",
+                if i == 0 { "" } else { " " },
+                uxn::op::NAMES[usize::from(*d)]
+            );
+        }
+        println!();
+        panic!("mismatch found");
+    }
+});
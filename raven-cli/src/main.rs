@@ -1,12 +1,21 @@
-use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use uxn::{Backend, Uxn, UxnRam};
-use varvara::Varvara;
+use varvara::{
+    rom::{RomFile, RomInfo, Symbols},
+    theme::Theme,
+    Bundle, OffsetClock, Output, Varvara,
+};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use log::info;
+use log::{info, warn};
+
+mod redirect;
+
+#[cfg(all(feature = "raw", unix))]
+mod raw;
 
 /// Uxn runner
 #[derive(Parser)]
@@ -19,40 +28,186 @@ struct Args {
     #[clap(long)]
     native: bool,
 
+    /// Print the ROM's metadata and exit
+    #[clap(long)]
+    describe: bool,
+
+    /// Check the ROM for likely bugs, print any findings, and exit
+    ///
+    /// Exits with an error if anything was found; see `raven_uxn::analyze`
+    /// for details on the analysis
+    #[clap(long)]
+    analyze: bool,
+
+    /// Translate the ROM into Rust source, write it to a file, and exit
+    ///
+    /// See `raven_uxn::aot` for details on using the generated code
+    #[clap(long, value_name = "OUT")]
+    transpile: Option<PathBuf>,
+
+    /// Load a `.theme` file as the default palette
+    #[clap(long)]
+    theme: Option<PathBuf>,
+
+    /// Load labels from a symbol file, to annotate `System/debug` output
+    ///
+    /// By default, `<ROM>.sym` is loaded if it exists
+    #[clap(long, value_name = "PATH")]
+    symbols: Option<PathBuf>,
+
+    /// Save a PNG screenshot when the ROM exits or input ends
+    #[clap(long, value_name = "PATH")]
+    screenshot: Option<PathBuf>,
+
+    /// Directory in which the ROM can read and write files
+    ///
+    /// Paths from the ROM are resolved relative to this directory, and can't
+    /// escape it; by default, the current directory is used
+    #[clap(long, value_name = "DIR")]
+    file_root: Option<PathBuf>,
+
+    /// Create missing directories when the ROM writes a file
+    #[clap(long)]
+    create_dirs: bool,
+
+    /// Report UTC (instead of local time) through the datetime device
+    #[clap(long)]
+    utc: bool,
+
+    /// Write console output to a file instead of stdout
+    #[clap(long)]
+    stdout_file: Option<PathBuf>,
+
+    /// Write console errors to a file instead of stderr
+    #[clap(long)]
+    stderr_file: Option<PathBuf>,
+
+    /// Rotate output files once they reach this many bytes
+    #[clap(long, value_name = "BYTES")]
+    rotate_size: Option<u64>,
+
+    /// Number of rotated output files to keep
+    #[clap(long, default_value_t = 5, requires = "rotate_size")]
+    rotate_keep: usize,
+
+    /// Disable logging, so that only the ROM's console output is printed
+    #[clap(short, long, conflicts_with = "log_file")]
+    quiet: bool,
+
+    /// Write log messages to a file instead of stderr
+    #[clap(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Put the terminal into raw mode, sending each keypress to the ROM
+    ///
+    /// Keys are passed through without line editing or echo (so escape
+    /// sequences reach the ROM as-is, and Ctrl-C is sent as byte 0x03); the
+    /// terminal is restored when the CLI exits
+    #[cfg(all(feature = "raw", unix))]
+    #[clap(long)]
+    raw: bool,
+
     /// Arguments to pass into the VM
     #[arg(last = true)]
     args: Vec<String>,
 }
 
-fn main() -> Result<()> {
-    let env = env_logger::Env::default()
-        .filter_or("UXN_LOG", "info")
-        .write_style_or("UXN_LOG", "always");
-    env_logger::init_from_env(env);
+/// Opens a ROM, unpacking it if it's a bundle with data files
+///
+/// Files ending in `.zip` must be valid bundles; other files are only treated
+/// as bundles if they parse as one (e.g. a ROM with a zip archive appended).
+fn open_rom(path: &Path) -> Result<(RomFile, Option<Bundle>)> {
+    // SAFETY: ROMs aren't expected to change while they're running; if one
+    // is truncated underneath us, we'll crash (as documented for `open`)
+    let rom = unsafe { RomFile::open(path) }
+        .with_context(|| format!("failed to open {path:?}"))?;
+    let is_zip = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+    match Bundle::parse(&rom) {
+        Ok(b) => {
+            info!("loaded bundle from {path:?}");
+            Ok((b.rom().to_vec().into(), Some(b)))
+        }
+        Err(e) if is_zip => {
+            Err(e).with_context(|| format!("failed to load bundle {path:?}"))
+        }
+        Err(_) => Ok((rom, None)),
+    }
+}
 
+fn main() -> Result<()> {
     let args = Args::parse();
-    let mut f = std::fs::File::open(&args.rom)
-        .with_context(|| format!("failed to open {:?}", args.rom))?;
+    init_logger(&args)?;
+
+    let (rom, bundle) = open_rom(&args.rom)?;
+    let rom = Arc::new(rom);
 
-    let mut rom = vec![];
-    f.read_to_end(&mut rom).context("failed to read file")?;
+    if args.describe {
+        let Some(info) = RomInfo::parse(&rom) else {
+            anyhow::bail!("no metadata in {:?}", args.rom);
+        };
+        println!("{}", info.text);
+        return Ok(());
+    }
+
+    if args.analyze {
+        let findings = uxn::analyze(&rom);
+        for f in &findings {
+            println!("{f}");
+        }
+        if !findings.is_empty() {
+            anyhow::bail!("found {} potential problem(s)", findings.len());
+        }
+        return Ok(());
+    }
+
+    if let Some(out) = &args.transpile {
+        std::fs::write(out, uxn::aot::transpile(&rom))
+            .with_context(|| format!("failed to write {out:?}"))?;
+        return Ok(());
+    }
+
+    let mut console = redirect::Console::new(
+        args.stdout_file.as_deref(),
+        args.stderr_file.as_deref(),
+        args.rotate_size,
+        args.rotate_keep,
+    )?;
 
     let mut ram = UxnRam::new();
     let mut vm = Uxn::new(
         &mut ram,
         if args.native {
-            #[cfg(not(target_arch = "aarch64"))]
-            anyhow::bail!("no native implementation for this arch");
-
-            #[cfg(target_arch = "aarch64")]
+            if !Backend::Native.is_available() {
+                anyhow::bail!("no native implementation for this arch");
+            }
             Backend::Native
         } else {
             Backend::Interpreter
         },
     );
-    let mut dev = Varvara::new();
-    let data = vm.reset(&rom);
-    dev.reset(data);
+    let mut builder = Varvara::builder();
+    if args.utc {
+        builder = builder.clock(OffsetClock::utc());
+    }
+    if let Some(b) = &bundle {
+        if args.file_root.is_some() {
+            warn!("ignoring --file-root, because files come from the bundle");
+        }
+        builder = builder.file_backend(b.files());
+    } else if let Some(root) = &args.file_root {
+        builder = builder.file_root(root);
+    }
+    let mut dev = builder.build();
+    dev.set_create_file_dirs(args.create_dirs);
+    if let Some(path) = &args.theme {
+        dev.set_theme(load_theme(path)?);
+    }
+    dev.reset_from(&mut vm, rom);
+    let symbols = Symbols::load(args.symbols.as_deref(), &args.rom)
+        .context("failed to load symbols")?;
+    dev.set_symbols(symbols);
     dev.init_args(&mut vm, &args.args);
 
     // Run the reset vector
@@ -60,16 +215,78 @@ fn main() -> Result<()> {
     vm.run(&mut dev, 0x100);
     info!("startup complete in {:?}", start.elapsed());
 
-    dev.output(&vm).check()?;
-    dev.send_args(&mut vm, &args.args).check()?;
+    check(&mut console, &args, dev.output(&vm), false)?;
+    check(
+        &mut console,
+        &args,
+        dev.send_args(&mut vm, &args.args),
+        false,
+    )?;
+
+    #[cfg(all(feature = "raw", unix))]
+    if args.raw && !raw::enable()? {
+        log::warn!("stdin is not a terminal; ignoring --raw");
+    }
 
     // Blocking loop, listening to the stdin reader thread
     let (tx, rx) = std::sync::mpsc::channel();
-    varvara::spawn_console_worker(move |e| tx.send(e));
-    while let Ok(c) = rx.recv() {
+    varvara::spawn_console_worker_with_eof(move |e| tx.send(e));
+    while let Ok(Some(c)) = rx.recv() {
         dev.console(&mut vm, c);
-        dev.output(&vm).check()?;
+        check(&mut console, &args, dev.output(&vm), false)?;
+    }
+
+    // At end-of-file, give the ROM a chance to finish up
+    dev.console_end(&mut vm);
+    check(&mut console, &args, dev.output(&vm), true)?;
+
+    Ok(())
+}
+
+/// Handles output from the VM
+///
+/// If `--screenshot` was given, the screen is saved before exiting (either
+/// because the ROM requested it, or because `last` is set).
+fn check(
+    console: &mut redirect::Console,
+    args: &Args,
+    out: Output,
+    last: bool,
+) -> Result<()> {
+    if let (Some(path), true) = (&args.screenshot, last || out.exit.is_some()) {
+        std::fs::write(path, out.screenshot())
+            .with_context(|| format!("failed to write screenshot {path:?}"))?;
     }
+    console.check(out)
+}
 
+/// Sets up logging, which never goes to stdout
+///
+/// Logs go to stderr by default, or to a file with `--log-file`; `--quiet`
+/// disables them entirely, so the CLI can be used in a pipeline.
+fn init_logger(args: &Args) -> Result<()> {
+    if args.quiet {
+        return Ok(());
+    }
+    let env = env_logger::Env::default()
+        .filter_or("UXN_LOG", "info")
+        .write_style_or("UXN_LOG", "auto");
+    let mut builder = env_logger::Builder::from_env(env);
+    if let Some(path) = &args.log_file {
+        let f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open {path:?}"))?;
+        builder.target(env_logger::Target::Pipe(Box::new(f)));
+    }
+    builder.init();
     Ok(())
 }
+
+fn load_theme(path: &std::path::Path) -> Result<Theme> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("failed to read theme {path:?}"))?;
+    Theme::parse(&data)
+        .with_context(|| format!("invalid theme {path:?} (expected 6 bytes)"))
+}
@@ -0,0 +1,56 @@
+//! Raw terminal mode, for interactive ROMs
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+
+/// Terminal settings from before raw mode was enabled
+static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+
+/// Puts the terminal attached to `stdin` into raw mode
+///
+/// Input is no longer line-buffered or echoed, and control characters (e.g.
+/// Ctrl-C) are delivered as bytes instead of raising signals.  Output
+/// processing is left alone, so `\n` still starts a new line.
+///
+/// The original settings are restored when the process exits, including
+/// through [`std::process::exit`].  Returns `false` (doing nothing) if `stdin`
+/// is not a terminal.
+pub fn enable() -> Result<bool> {
+    let fd = libc::STDIN_FILENO;
+    // SAFETY: `isatty` has no preconditions
+    if unsafe { libc::isatty(fd) } == 0 {
+        return Ok(false);
+    }
+
+    // SAFETY: `termios` is plain data, and is filled in by `tcgetattr`
+    let mut t: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut t) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("failed to read terminal settings");
+    }
+    if ORIGINAL.set(t).is_ok() {
+        // SAFETY: `restore` is an `extern "C"` function which never unwinds
+        if unsafe { libc::atexit(restore) } != 0 {
+            anyhow::bail!("failed to register terminal restore handler");
+        }
+    }
+
+    t.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+    t.c_iflag &= !(libc::IXON | libc::BRKINT | libc::ISTRIP);
+    t.c_cc[libc::VMIN] = 1;
+    t.c_cc[libc::VTIME] = 0;
+    // SAFETY: `t` is a valid `termios` from `tcgetattr`
+    if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &t) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("failed to enable raw mode");
+    }
+    Ok(true)
+}
+
+/// Restores the original terminal settings
+extern "C" fn restore() {
+    if let Some(t) = ORIGINAL.get() {
+        // SAFETY: `t` is a valid `termios` from `tcgetattr`
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, t) };
+    }
+}
@@ -0,0 +1,164 @@
+//! Redirection of console output to files
+use std::{
+    fs::{File, OpenOptions},
+    io::{LineWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use varvara::Output;
+
+/// Log file which is rotated once it grows past a size limit
+struct LogFile {
+    path: PathBuf,
+    file: LineWriter<File>,
+
+    /// Bytes written to the current file
+    size: u64,
+
+    /// Size limit, after which the file is rotated
+    max_size: Option<u64>,
+
+    /// Number of rotated files to keep (`path.1`, `path.2`, etc)
+    keep: usize,
+}
+
+impl LogFile {
+    fn open(path: &Path, max_size: Option<u64>, keep: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open {path:?}"))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            file: LineWriter::new(file),
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn rotated(&self, i: usize) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(format!(".{i}"));
+        p.into()
+    }
+
+    /// Moves `path` to `path.1` (shifting older files up) and starts afresh
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.keep).rev() {
+                let src = self.rotated(i);
+                if src.exists() {
+                    std::fs::rename(src, self.rotated(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        *self = Self::open(&self.path, self.max_size, self.keep)?;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self
+            .max_size
+            .is_some_and(|m| self.size > 0 && self.size >= m)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Destination for one of the console's output streams
+enum Sink {
+    Stdout,
+    Stderr,
+    File(LogFile),
+}
+
+impl Sink {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        match self {
+            Sink::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(data)?;
+                stdout.flush()?;
+            }
+            Sink::Stderr => {
+                let mut stderr = std::io::stderr().lock();
+                stderr.write_all(data)?;
+                stderr.flush()?;
+            }
+            Sink::File(f) => f.write(data)?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Sink::File(f) = self {
+            f.file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Console output handler, which writes to either the terminal or files
+///
+/// Files are line-buffered and opened in append mode.
+pub struct Console {
+    stdout: Sink,
+    stderr: Sink,
+}
+
+impl Console {
+    /// Builds a new console handler
+    ///
+    /// If `max_size` is provided, each file is rotated once it reaches that
+    /// many bytes, keeping up to `keep` older files.
+    pub fn new(
+        stdout: Option<&Path>,
+        stderr: Option<&Path>,
+        max_size: Option<u64>,
+        keep: usize,
+    ) -> Result<Self> {
+        let open = |p: Option<&Path>, default| -> Result<Sink> {
+            Ok(match p {
+                Some(p) => Sink::File(LogFile::open(p, max_size, keep)?),
+                None => default,
+            })
+        };
+        Ok(Self {
+            stdout: open(stdout, Sink::Stdout)?,
+            stderr: open(stderr, Sink::Stderr)?,
+        })
+    }
+
+    /// Writes console output, then exits if requested by the VM
+    ///
+    /// This is equivalent to [`Output::check`], but respects redirection.
+    pub fn check(&mut self, out: Output) -> Result<()> {
+        self.stdout.write(&out.stdout)?;
+        self.stderr.write(&out.stderr)?;
+        if let Some(e) = out.exit {
+            log::info!("requested exit ({e})");
+            if let (true, Some(v)) = (e != 0, out.last_vector) {
+                log::info!("last vector: {v}");
+            }
+            self.stdout.flush()?;
+            self.stderr.flush()?;
+            std::process::exit(e);
+        }
+        Ok(())
+    }
+}
@@ -1,21 +1,33 @@
-use uxn::Uxn;
-use varvara::{Key, MouseState, Varvara, AUDIO_CHANNELS, AUDIO_SAMPLE_RATE};
+use uxn::{Device, Uxn};
+use varvara::{
+    theme::Theme, AudioOutput, Bundle, FrameTimer, GamepadState, Key,
+    KeyRepeat, MouseState, PixelFormat, Region, RepeatTiming, Resampler, Touch,
+    TouchPhase, Varvara, AUDIO_CHANNELS, AUDIO_SAMPLE_RATE,
+    SCROLL_PIXELS_PER_LINE,
+};
 
-use std::sync::{mpsc, Arc, Mutex};
+use std::{collections::VecDeque, sync::mpsc};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use cpal::traits::StreamTrait;
 use eframe::egui;
-use log::{error, info};
+use log::{error, info, warn};
 
 /// Injected events from the [`Stage::rx`] queue
 #[derive(Debug)]
 pub enum Event {
     LoadRom(Vec<u8>),
     SetMuted(bool),
+    SetAlwaysOnTop(bool),
+    SetBorderless(bool),
     Console(u8),
+    ConsoleEnd,
+    Gamepad(GamepadState),
 }
 
+/// Callback run before exiting, with the exit code (if requested by the ROM)
+pub type ExitCallback = Box<dyn FnOnce(Option<i32>, &Uxn, &mut Varvara)>;
+
 pub struct Stage<'a> {
     vm: Uxn<'a>,
     dev: Varvara,
@@ -29,25 +41,81 @@ pub struct Stage<'a> {
     /// resized and this value is updated accordingly.
     size: (u16, u16),
 
-    /// Time (in seconds) at which we should draw the next frame
-    next_frame: f64,
+    /// Pacing for the screen vector
+    timer: FrameTimer,
 
+    /// Scroll amount (in lines) since the last frame
     scroll: (f32, f32),
+
+    /// Number of points of smooth scrolling which are treated as one line
+    ///
+    /// Mouse wheels typically report scrolling in lines, while trackpads (and
+    /// some browsers) report it in points; this is used to normalize the
+    /// latter, so that ROMs see similar scroll magnitudes on every platform.
+    scroll_divisor: f32,
     cursor_pos: Option<(f32, f32)>,
 
+    /// Raw mouse motion (in screen pixels) since the last frame, used while
+    /// the pointer is captured
+    motion: (f32, f32),
+
+    /// The pointer is currently grabbed (toggled with F7)
+    captured: bool,
+
+    /// Files are being served from a bundle, rather than the default storage
+    bundled: bool,
+
     texture: egui::TextureHandle,
 
     /// Event injector
     event_rx: mpsc::Receiver<Event>,
 
+    /// Events received but not yet handled, in order of arrival
+    ///
+    /// At most [`MAX_EVENTS_PER_FRAME`] events are handled per frame, so that
+    /// a flood of events (e.g. a large paste into the console) can't stall
+    /// the GUI; the rest wait here for subsequent frames.
+    pending: VecDeque<Event>,
+
+    /// Largest size of `pending` since it was last empty
+    peak_backlog: usize,
+
     /// Callback when the size is changed by the ROM
     resized: Option<Box<dyn FnMut(u16, u16)>>,
+
+    /// Callback run once, right before the application exits
+    exiting: Option<ExitCallback>,
+
+    /// The window is floating above other windows (toggled with F9)
+    always_on_top: bool,
+
+    /// The window has no title bar or border (toggled with F10)
+    borderless: bool,
+
+    /// The audio mixer panel is visible (toggled with F8)
+    show_mixer: bool,
+
+    /// The about panel is visible (toggled with F1)
+    show_about: bool,
+
+    /// ROM name shown in the window title, from its metadata
+    title: Option<String>,
+
+    /// Background color, which matches the ROM's color 0
+    background: egui::Color32,
+
+    /// Region of the screen which has changed since the texture was last
+    /// uploaded
+    damage: Option<Region>,
+
+    /// Time (in seconds) of the most recent input or screen change
+    last_active: f64,
 }
 
 impl<'a> Stage<'a> {
     pub fn new(
         vm: Uxn<'a>,
-        dev: Varvara,
+        mut dev: Varvara,
         size: (u16, u16),
         scale: f32,
         event_rx: mpsc::Receiver<Event>,
@@ -61,19 +129,44 @@ impl<'a> Stage<'a> {
         let texture =
             ctx.load_texture("frame", image, egui::TextureOptions::NEAREST);
 
+        // Render frames in the texture's byte order
+        dev.set_pixel_format(PixelFormat::Rgba);
+
+        // Generate key repeats in the controller, since their timing varies
+        // between platforms (and browsers)
+        dev.set_key_repeat(KeyRepeat {
+            timing: Some(RepeatTiming::default()),
+            ..KeyRepeat::default()
+        });
+
         Stage {
             vm,
             dev,
 
             scale,
             size,
-            next_frame: 0.0,
+            timer: FrameTimer::new(),
 
             event_rx,
+            pending: VecDeque::new(),
+            peak_backlog: 0,
             resized: None,
+            exiting: None,
+            always_on_top: false,
+            borderless: false,
+            show_mixer: false,
+            show_about: false,
+            title: None,
+            background: egui::Color32::BLACK,
+            damage: None,
+            last_active: 0.0,
 
             scroll: (0.0, 0.0),
+            scroll_divisor: SCROLL_PIXELS_PER_LINE,
             cursor_pos: None,
+            motion: (0.0, 0.0),
+            captured: false,
+            bundled: false,
 
             texture,
         }
@@ -84,19 +177,275 @@ impl<'a> Stage<'a> {
         self.resized = Some(f);
     }
 
+    /// Sets the number of points of smooth scrolling per line
+    pub fn set_scroll_divisor(&mut self, d: f32) {
+        self.scroll_divisor = d;
+    }
+
+    /// Sets a callback that is triggered right before the application exits
+    ///
+    /// This is called when the ROM requests an exit (with its exit code) and
+    /// when the window is closed (with `None`), giving the host a chance to
+    /// save its state.  It is called at most once.
+    pub fn set_exit_callback(&mut self, f: ExitCallback) {
+        self.exiting = Some(f);
+    }
+
+    /// Runs the exit callback, if it's present and hasn't yet been called
+    ///
+    /// Any recording in progress is saved first.
+    fn run_exit_callback(&mut self, code: Option<i32>) {
+        if self.dev.is_recording() {
+            self.toggle_recording();
+        }
+        if self.dev.is_capturing_audio() {
+            self.toggle_audio_capture();
+        }
+        if let Some(f) = self.exiting.take() {
+            f(code, &self.vm, &mut self.dev);
+        }
+    }
+
+    fn set_always_on_top(&mut self, ctx: &egui::Context, b: bool) {
+        self.always_on_top = b;
+        ctx.send_viewport_cmd(egui::ViewportCommand::WindowLevel(if b {
+            egui::WindowLevel::AlwaysOnTop
+        } else {
+            egui::WindowLevel::Normal
+        }));
+    }
+
+    fn set_borderless(&mut self, ctx: &egui::Context, b: bool) {
+        self.borderless = b;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Decorations(!b));
+    }
+
+    /// Saves a screenshot to `raven-<timestamp>.png` (bound to F12)
+    fn save_screenshot(&self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = format!("raven-{}.png", timestamp());
+            match std::fs::write(&path, self.dev.screenshot(&self.vm)) {
+                Ok(()) => info!("saved screenshot to {path}"),
+                Err(e) => warn!("could not save screenshot to {path}: {e}"),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        warn!("screenshots are not supported on the web");
+    }
+
+    /// Starts or stops recording an animated PNG (bound to F11)
+    ///
+    /// Recordings are saved to `raven-<timestamp>.apng.png`.
+    fn toggle_recording(&mut self) {
+        if let Some(rec) = self.dev.stop_recording() {
+            let n = rec.frames();
+            match rec.finish() {
+                Ok(()) => info!("saved recording ({n} frames)"),
+                Err(e) => warn!("could not save recording: {e}"),
+            }
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = format!("raven-{}.apng.png", timestamp());
+            match std::fs::File::create(&path) {
+                Ok(f) => {
+                    info!("recording to {path}");
+                    let f = std::io::BufWriter::new(f);
+                    self.dev.start_recording(varvara::Recorder::apng(f));
+                }
+                Err(e) => warn!("could not create {path}: {e}"),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        warn!("recording is not supported on the web");
+    }
+
+    /// Starts or stops capturing audio to a WAV file (bound to F6)
+    ///
+    /// Captures are saved to `raven-<timestamp>.wav`.
+    fn toggle_audio_capture(&mut self) {
+        if let Some(sink) = self.dev.stop_audio_capture() {
+            match sink.finish() {
+                Ok(()) => info!("saved audio capture"),
+                Err(e) => warn!("could not save audio capture: {e}"),
+            }
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = format!("raven-{}.wav", timestamp());
+            let w = std::fs::File::create(&path).and_then(|f| {
+                varvara::WavWriter::new(std::io::BufWriter::new(f))
+            });
+            match w {
+                Ok(w) => {
+                    info!("capturing audio to {path}");
+                    self.dev.start_audio_capture(w);
+                }
+                Err(e) => warn!("could not create {path}: {e}"),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        warn!("audio capture is not supported on the web");
+    }
+
+    /// Draws the audio mixer panel, if it's visible
+    ///
+    /// Each channel can be muted or soloed; while any channel is soloed, only
+    /// soloed channels are audible.
+    fn mixer_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_mixer;
+        egui::Window::new("Mixer")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("mixer").show(ui, |ui| {
+                    for i in 0..4 {
+                        let label = egui::RichText::new(format!("Audio{i}"));
+                        ui.label(if self.dev.audio_channel_audible(i) {
+                            label.strong()
+                        } else {
+                            label.weak()
+                        });
+                        let mut m = self.dev.audio_channel_muted(i);
+                        if ui.checkbox(&mut m, "mute").changed() {
+                            self.dev.audio_set_channel_muted(i, m);
+                        }
+                        let mut s = self.dev.audio_channel_solo(i);
+                        if ui.checkbox(&mut s, "solo").changed() {
+                            self.dev.audio_set_channel_solo(i, s);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        self.show_mixer = open;
+    }
+
+    /// Draws the about panel, if it's visible
+    ///
+    /// This shows the metadata published by the ROM.
+    fn about_panel(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_about;
+        egui::Window::new("About")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| match self.dev.devices().system.metadata() {
+                Some(m) => {
+                    ui.label(egui::RichText::new(&m.name).strong());
+                    let lines = [&m.details, &m.author, &m.date];
+                    for s in lines.into_iter().flatten() {
+                        ui.label(s);
+                    }
+                }
+                None => {
+                    ui.label(egui::RichText::new("No metadata").weak());
+                }
+            });
+        self.show_about = open;
+    }
+
+    /// Updates the window title if the ROM's metadata has changed
+    fn update_title(&mut self, ctx: &egui::Context) {
+        let name = self.dev.devices().system.metadata().map(|m| &m.name);
+        if name != self.title.as_ref() {
+            self.title = name.cloned();
+            let title = self.title.as_deref().unwrap_or("Varvara");
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.into()));
+        }
+    }
+
+    fn load_theme(&mut self, data: &[u8]) -> Result<()> {
+        let theme = Theme::parse(data)
+            .ok_or_else(|| anyhow!("invalid theme (expected 6 bytes)"))?;
+        info!("applying theme {theme:?}");
+        self.dev.set_theme(theme);
+        Ok(())
+    }
+
     fn load_rom(&mut self, data: &[u8]) -> Result<()> {
-        let data = self.vm.reset(data);
-        self.dev.reset(data);
+        // Bundles bring their own files; a plain ROM goes back to the default
+        // storage (although a file root, if there was one, isn't restored)
+        let bundle = Bundle::parse(data).ok();
+        let data = match &bundle {
+            Some(b) => {
+                info!("serving files from bundle");
+                self.dev.set_file_root(None::<std::path::PathBuf>);
+                self.dev.set_file_backend(b.files());
+                self.bundled = true;
+                b.rom()
+            }
+            None => {
+                if std::mem::take(&mut self.bundled) {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    self.dev.set_file_backend(varvara::StdFs);
+                    #[cfg(target_arch = "wasm32")]
+                    self.dev.set_file_backend(varvara::MemoryFs::new());
+                }
+                data
+            }
+        };
+        self.dev.load_rom(&mut self.vm, data);
         self.vm.run(&mut self.dev, 0x100);
+        if let Some(code) = self.dev.exit_code() {
+            self.run_exit_callback(Some(code));
+        }
         let out = self.dev.output(&self.vm);
         out.check()?;
+        self.damage = union(self.damage, out.dirty);
         Ok(())
     }
 }
 
+/// Returns the current time in seconds since the Unix epoch, for file names
+#[cfg(not(target_arch = "wasm32"))]
+fn timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Combines two (optional) changed regions of the screen
+/// Grabs (and hides) or releases the pointer
+fn grab_pointer(ctx: &egui::Context, b: bool) {
+    ctx.send_viewport_cmd(egui::ViewportCommand::CursorGrab(if b {
+        egui::CursorGrab::Locked
+    } else {
+        egui::CursorGrab::None
+    }));
+    ctx.send_viewport_cmd(egui::ViewportCommand::CursorVisible(!b));
+}
+
+fn union(a: Option<Region>, b: Option<Region>) -> Option<Region> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Maximum number of injected events to handle in a single frame
+const MAX_EVENTS_PER_FRAME: usize = 256;
+
+/// Time (in seconds) without activity before we lower the repaint rate
+const IDLE_DELAY: f64 = 1.0;
+
+/// Repaint interval when idle
+const IDLE_REPAINT: std::time::Duration = std::time::Duration::from_millis(100);
+
 impl eframe::App for Stage<'_> {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        while let Ok(e) = self.event_rx.try_recv() {
+        self.pending.extend(self.event_rx.try_iter());
+        let n = self.pending.len().min(MAX_EVENTS_PER_FRAME);
+        let mut active = n > 0;
+        for _ in 0..n {
+            let Some(e) = self.pending.pop_front() else {
+                break;
+            };
             match e {
                 Event::LoadRom(data) => {
                     if let Err(e) = self.load_rom(&data) {
@@ -106,25 +455,72 @@ impl eframe::App for Stage<'_> {
                 Event::SetMuted(m) => {
                     self.dev.audio_set_muted(m);
                 }
+                Event::SetAlwaysOnTop(b) => self.set_always_on_top(ctx, b),
+                Event::SetBorderless(b) => self.set_borderless(ctx, b),
                 Event::Console(b) => {
                     self.dev.console(&mut self.vm, b);
                 }
+                Event::ConsoleEnd => {
+                    self.dev.console_end(&mut self.vm);
+                }
+                Event::Gamepad(g) => {
+                    self.dev.gamepad(&mut self.vm, g);
+                }
             }
         }
+        if !self.pending.is_empty() {
+            if self.peak_backlog == 0 {
+                warn!(
+                    "event backlog: {} events deferred to later frames",
+                    self.pending.len()
+                );
+            }
+            self.peak_backlog = self.peak_backlog.max(self.pending.len());
+            // Make sure that we come back soon to handle the rest
+            active = true;
+        } else if self.peak_backlog > 0 {
+            info!("event backlog cleared (peak: {} events)", self.peak_backlog);
+            self.peak_backlog = 0;
+        }
 
-        // Repaint at vsync rate (60 FPS)
-        ctx.request_repaint();
-        ctx.input(|i| {
-            while i.time >= self.next_frame {
-                // Screen callback (limited to 60 FPS).  We want to err on the
-                // side of redrawing early, rather than missing frames.
-                self.next_frame += 0.0166667;
-                self.dev.redraw(&mut self.vm);
+        let mut toggle_on_top = false;
+        let mut toggle_borderless = false;
+        let mut toggle_mixer = false;
+        let mut toggle_about = false;
+        let mut toggle_capture = false;
+        let mut toggle_audio_capture = false;
+        let mut touched = false;
+        let mut take_screenshot = false;
+        let mut toggle_recording = false;
+        let time = ctx.input(|i| {
+            // Screen callback (limited to 60 FPS)
+            let now = std::time::Duration::from_secs_f64(i.time);
+            if self.dev.tick(&mut self.vm, &mut self.timer, now) {
+                active = true;
             }
+            active |= self.dev.console_backlog() > 0
+                || !i.events.is_empty()
+                || !i.raw.dropped_files.is_empty()
+                || i.pointer.is_moving();
 
             if i.raw.dropped_files.len() == 1 {
                 let target = &i.raw.dropped_files[0];
-                let r = if let Some(path) = &target.path {
+                let is_theme = match &target.path {
+                    Some(path) => {
+                        path.extension().is_some_and(|e| e == "theme")
+                    }
+                    None => target.name.ends_with(".theme"),
+                };
+                let r = if is_theme {
+                    let data = match (&target.path, &target.bytes) {
+                        (Some(path), _) => {
+                            std::fs::read(path).expect("failed to read file")
+                        }
+                        (None, Some(data)) => data.to_vec(),
+                        (None, None) => vec![],
+                    };
+                    self.load_theme(&data)
+                } else if let Some(path) = &target.path {
                     let data =
                         std::fs::read(path).expect("failed to read file");
                     info!("loading {} bytes from {path:?}", data.len());
@@ -161,6 +557,54 @@ impl eframe::App for Stage<'_> {
                             }
                         }
                     }
+                    egui::Event::Key {
+                        key: egui::Key::F1,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_about = true,
+                    egui::Event::Key {
+                        key: egui::Key::F6,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_audio_capture = true,
+                    egui::Event::Key {
+                        key: egui::Key::F7,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_capture = true,
+                    egui::Event::Key {
+                        key: egui::Key::F8,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_mixer = true,
+                    egui::Event::Key {
+                        key: egui::Key::F9,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_on_top = true,
+                    egui::Event::Key {
+                        key: egui::Key::F10,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_borderless = true,
+                    egui::Event::Key {
+                        key: egui::Key::F12,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => take_screenshot = true,
+                    egui::Event::Key {
+                        key: egui::Key::F11,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } => toggle_recording = true,
                     egui::Event::Key {
                         key,
                         pressed,
@@ -175,9 +619,48 @@ impl eframe::App for Stage<'_> {
                             }
                         }
                     }
-                    egui::Event::Scroll(s) => {
-                        self.scroll.0 += s.x;
-                        self.scroll.1 -= s.y;
+                    egui::Event::Touch { id, phase, pos, .. } => {
+                        let phase = match phase {
+                            egui::TouchPhase::Start => TouchPhase::Start,
+                            egui::TouchPhase::Move => TouchPhase::Move,
+                            egui::TouchPhase::End => TouchPhase::End,
+                            egui::TouchPhase::Cancel => TouchPhase::Cancel,
+                        };
+                        let t = Touch {
+                            id: id.0,
+                            phase,
+                            pos: (pos.x / self.scale, pos.y / self.scale),
+                        };
+                        self.dev.touch(&mut self.vm, t);
+                        touched = true;
+                    }
+                    egui::Event::MouseMoved(d) => {
+                        self.motion.0 += d.x / self.scale;
+                        self.motion.1 += d.y / self.scale;
+                    }
+                    egui::Event::MouseWheel {
+                        unit,
+                        delta,
+                        modifiers,
+                    } => {
+                        let lines = match unit {
+                            egui::MouseWheelUnit::Point => {
+                                *delta / self.scroll_divisor
+                            }
+                            egui::MouseWheelUnit::Line => *delta,
+                            egui::MouseWheelUnit::Page => {
+                                *delta * f32::from(self.size.1 / 8)
+                            }
+                        };
+                        // Shift turns a vertical wheel into a horizontal one
+                        // (some platforms already do this for us)
+                        let lines = if modifiers.shift && lines.x == 0.0 {
+                            egui::vec2(lines.y, 0.0)
+                        } else {
+                            lines
+                        };
+                        self.scroll.0 += lines.x;
+                        self.scroll.1 -= lines.y;
                     }
                     _ => (),
                 }
@@ -203,29 +686,84 @@ impl eframe::App for Stage<'_> {
                 egui::PointerButton::Primary,
                 egui::PointerButton::Middle,
                 egui::PointerButton::Secondary,
+                egui::PointerButton::Extra1,
+                egui::PointerButton::Extra2,
             ]
             .into_iter()
             .enumerate()
             .map(|(i, b)| (ptr.button_down(b) as u8) << i)
             .fold(0, |a, b| a | b);
-            let m = MouseState {
-                pos: self.cursor_pos.unwrap_or((0.0, 0.0)),
-                scroll: std::mem::take(&mut self.scroll),
-                buttons,
-            };
-            self.dev.mouse(&mut self.vm, m);
+            let m = MouseState::new(self.cursor_pos.unwrap_or((0.0, 0.0)))
+                .with_delta(std::mem::take(&mut self.motion))
+                .with_scroll_lines(std::mem::take(&mut self.scroll))
+                .with_buttons(buttons);
+            // egui also emulates a pointer from touches, which would fight
+            // with the touch events sent above
+            if !touched && self.dev.devices().mouse.touches() == 0 {
+                self.dev.mouse(&mut self.vm, m);
+            }
             i.time
         });
 
+        if toggle_on_top {
+            self.set_always_on_top(ctx, !self.always_on_top);
+        }
+        if toggle_borderless {
+            self.set_borderless(ctx, !self.borderless);
+        }
+        if toggle_mixer {
+            self.show_mixer = !self.show_mixer;
+        }
+        if toggle_capture {
+            let captured = self.dev.devices().mouse.captured();
+            self.dev.set_mouse_capture(!captured);
+        }
+        if toggle_about {
+            self.show_about = !self.show_about;
+        }
+        if take_screenshot {
+            self.save_screenshot();
+        }
+        if toggle_recording {
+            self.toggle_recording();
+        }
+        if toggle_audio_capture {
+            self.toggle_audio_capture();
+        }
+        self.update_title(ctx);
+
         // Handle audio callback
-        self.dev.audio(&mut self.vm);
+        active |= self.dev.audio(&mut self.vm);
+
+        // Finish background file operations
+        active |= self.dev.file(&mut self.vm);
+
+        // Repaint at vsync rate (60 FPS) while the ROM is drawing or receiving
+        // input, dropping to a lower rate when idle to save power.  Input
+        // events wake egui immediately, so this doesn't add input latency.
+        if active {
+            self.last_active = time;
+        }
+        if time - self.last_active < IDLE_DELAY {
+            ctx.request_repaint();
+        } else {
+            ctx.request_repaint_after(IDLE_REPAINT);
+        }
 
+        // Give the host a chance to clean up before a ROM-requested exit
+        if let Some(code) = self.dev.exit_code() {
+            self.run_exit_callback(Some(code));
+        }
         let out = self.dev.output(&self.vm);
 
         // Update our GUI based on current state
         if out.hide_mouse {
             ctx.set_cursor_icon(egui::CursorIcon::None);
         }
+        if out.capture_mouse != self.captured {
+            self.captured = out.capture_mouse;
+            grab_pointer(ctx, out.capture_mouse);
+        }
         if self.size != out.size {
             info!("resizing window to {:?}", out.size);
             self.size = out.size;
@@ -237,17 +775,52 @@ impl eframe::App for Stage<'_> {
             }
         }
 
-        // TODO reduce allocation here?
-        let mut image = egui::ColorImage::new(
-            [out.size.0 as usize, out.size.1 as usize],
-            egui::Color32::BLACK,
-        );
-        for (i, o) in out.frame.chunks(4).zip(image.pixels.iter_mut()) {
-            *o = egui::Color32::from_rgba_unmultiplied(i[2], i[1], i[0], i[3]);
+        if let Some(p) = out.palette {
+            let [_a, r, g, b] = p[0].to_be_bytes();
+            self.background = egui::Color32::from_rgb(r, g, b);
+        }
+
+        // Only upload the parts of the texture which have changed
+        let size = [usize::from(out.size.0), usize::from(out.size.1)];
+        self.damage = union(self.damage, out.dirty);
+        if let Some(r) = self.damage.take() {
+            // If the screen has been resized, then upload the whole texture
+            let r = if self.texture.size() == size {
+                r
+            } else {
+                Region {
+                    x: 0,
+                    y: 0,
+                    w: out.size.0,
+                    h: out.size.1,
+                }
+            };
+            let (x, w) = (usize::from(r.x), usize::from(r.w));
+            let (y, h) = (usize::from(r.y), usize::from(r.h));
+            // The frame is RGBA and always opaque, so its bytes are already
+            // valid (premultiplied) colors, and rows can be copied as-is
+            let src: &[egui::Color32] = bytemuck::cast_slice(out.frame);
+            let mut pixels = Vec::with_capacity(w * h);
+            for y in y..y + h {
+                pixels.extend_from_slice(&src[y * size[0] + x..][..w]);
+            }
+            let image = egui::ColorImage {
+                size: [w, h],
+                pixels,
+            };
+            if [x, y] == [0, 0] && image.size == size {
+                self.texture.set(image, egui::TextureOptions::NEAREST);
+            } else {
+                self.texture.set_partial(
+                    [x, y],
+                    image,
+                    egui::TextureOptions::NEAREST,
+                );
+            }
         }
-        self.texture.set(image, egui::TextureOptions::NEAREST);
 
-        egui::CentralPanel::default().show(ctx, |ui| {
+        let frame = egui::Frame::none().fill(self.background);
+        egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
             let mut mesh = egui::Mesh::with_texture(self.texture.id());
             mesh.add_rect_with_uv(
                 egui::Rect {
@@ -268,34 +841,66 @@ impl eframe::App for Stage<'_> {
 
         // Update stdout / stderr / exiting
         out.check().expect("failed to print output?");
+
+        self.mixer_panel(ctx);
+        self.about_panel(ctx);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.run_exit_callback(None);
     }
 }
 
+/// Plays the mixed audio output on the default output device
+///
+/// The device is opened at 44.1 kHz with our channel count if possible;
+/// otherwise, any `f32` configuration is used and the output is resampled
+/// to match it.
 pub fn audio_setup(
-    data: [Arc<Mutex<varvara::StreamData>>; 4],
-) -> Option<(cpal::Device, [cpal::Stream; 4])> {
+    output: AudioOutput,
+) -> Option<(cpal::Device, cpal::Stream)> {
     use cpal::traits::{DeviceTrait, HostTrait};
     let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("no output device available");
-    let supported_configs_range = device
-        .supported_output_configs()
-        .expect("error while querying configs");
-
-    let Some(supported_config) = supported_configs_range
+    let Some(device) = host.default_output_device() else {
+        error!("no audio output device available");
+        return None;
+    };
+    let configs = match device.supported_output_configs() {
+        Ok(c) => c.collect::<Vec<_>>(),
+        Err(e) => {
+            error!("could not query audio configs: {e}");
+            return None;
+        }
+    };
+    let f32_configs = configs
+        .iter()
+        .filter(|c| c.sample_format() == cpal::SampleFormat::F32);
+
+    // Prefer our own sample rate (with our channel count, if possible), then
+    // the device's default, then anything at all
+    let rate = cpal::SampleRate(AUDIO_SAMPLE_RATE);
+    let supported_config = f32_configs
+        .clone()
         .filter(|c| usize::from(c.channels()) == AUDIO_CHANNELS)
-        .filter(|c| c.sample_format() == cpal::SampleFormat::F32)
-        .find_map(|c| {
-            c.try_with_sample_rate(cpal::SampleRate(AUDIO_SAMPLE_RATE))
+        .find_map(|c| c.try_with_sample_rate(rate))
+        .or_else(|| {
+            f32_configs
+                .clone()
+                .find_map(|c| c.try_with_sample_rate(rate))
         })
-    else {
-        error!(
-            "could not find supported audio config ({} channels, {} Hz, f32)",
-            AUDIO_CHANNELS, AUDIO_SAMPLE_RATE
-        );
+        .or_else(|| {
+            device
+                .default_output_config()
+                .ok()
+                .filter(|c| c.sample_format() == cpal::SampleFormat::F32)
+        })
+        .or_else(|| {
+            f32_configs.clone().next().map(|c| c.with_max_sample_rate())
+        });
+    let Some(supported_config) = supported_config else {
+        error!("could not find an f32 audio config");
         error!("available configs:");
-        for c in device.supported_output_configs().unwrap() {
+        for c in &configs {
             if c.min_sample_rate() == c.max_sample_rate() {
                 error!(
                     "  channels: {}, sample_rate: {} Hz, {}",
@@ -316,24 +921,33 @@ pub fn audio_setup(
         return None;
     };
     let config = supported_config.config();
+    info!(
+        "playing audio with {} channels at {} Hz",
+        config.channels, config.sample_rate.0
+    );
 
-    let streams = data.map(|d| {
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data: &mut [f32], _opt: &cpal::OutputCallbackInfo| {
-                    d.lock().unwrap().next(data);
-                },
-                move |err| {
-                    panic!("{err}");
-                },
-                None,
-            )
-            .expect("could not build stream");
-        stream.play().unwrap();
-        stream
-    });
-    Some((device, streams))
+    let mut r =
+        Resampler::new(output, config.sample_rate.0, config.channels.into());
+    let stream = match device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _opt: &cpal::OutputCallbackInfo| {
+            r.next(data);
+        },
+        move |err| {
+            error!("audio stream error: {err}");
+        },
+        None,
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("could not build audio stream: {e}");
+            return None;
+        }
+    };
+    if let Err(e) = stream.play() {
+        error!("could not play audio stream: {e}");
+    }
+    Some((device, stream))
 }
 
 fn decode_key(k: egui::Key, shift: bool) -> Option<Key> {
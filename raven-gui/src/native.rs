@@ -1,12 +1,20 @@
 use anyhow::{anyhow, Context};
-use std::{io::Read, sync::mpsc};
+use std::{
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+};
 
 use uxn::{Backend, Uxn, UxnRam};
-use varvara::Varvara;
+use varvara::{
+    keymap::KeyMap,
+    rom::{RomFile, RomInfo, Symbols},
+    theme::Theme,
+    Bundle, ConsoleWriter, GamepadState, Varvara, CONTROLLER_PLAYERS,
+};
 
 use anyhow::Result;
 use eframe::egui;
-use log::info;
+use log::{info, warn};
 
 use clap::Parser;
 
@@ -17,7 +25,7 @@ use crate::{audio_setup, Stage};
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// ROM to load and execute
-    rom: std::path::PathBuf,
+    rom: PathBuf,
 
     /// Scale factor for the window
     #[clap(long)]
@@ -27,11 +35,86 @@ struct Args {
     #[clap(long)]
     native: bool,
 
+    /// Keep the window above other windows (toggle with F9)
+    #[clap(long)]
+    always_on_top: bool,
+
+    /// Hide the window's title bar and border (toggle with F10)
+    #[clap(long)]
+    borderless: bool,
+
+    /// Load a `.theme` file as the default palette
+    ///
+    /// Themes can also be changed by dropping a `.theme` file onto the window
+    #[clap(long)]
+    theme: Option<PathBuf>,
+
+    /// Load a key map file, assigning keys to controller buttons
+    ///
+    /// Each line is a button name, `=`, and a comma-separated list of keys
+    /// (e.g. `up = w, up`).  By default, buttons use Ctrl, Alt, Shift, Home,
+    /// and the arrow keys.
+    #[clap(long, value_name = "PATH")]
+    keymap: Option<PathBuf>,
+
+    /// Load labels from a symbol file, to annotate `System/debug` output
+    ///
+    /// By default, `<ROM>.sym` is loaded if it exists
+    #[clap(long, value_name = "PATH")]
+    symbols: Option<PathBuf>,
+
+    /// Deliver at most this many console bytes per frame
+    ///
+    /// This is useful for ROMs which mishandle bursts of console input (e.g.
+    /// long arguments or pasted text).
+    #[clap(long)]
+    console_pacing: Option<std::num::NonZeroUsize>,
+
+    /// Number of points of trackpad scrolling which count as one line
+    ///
+    /// Mouse wheels scroll by whole lines; increase this value if trackpad
+    /// scrolling is too fast.
+    #[clap(long, default_value_t = varvara::SCROLL_PIXELS_PER_LINE)]
+    scroll_divisor: f32,
+
+    /// Directory in which the ROM can read and write files
+    ///
+    /// Paths from the ROM are resolved relative to this directory, and can't
+    /// escape it; by default, the current directory is used
+    #[clap(long, value_name = "DIR")]
+    file_root: Option<PathBuf>,
+
+    /// Create missing directories when the ROM writes a file
+    #[clap(long)]
+    create_dirs: bool,
+
+    /// Read and write files on a background thread
+    ///
+    /// This keeps the window responsive when files are on slow storage, but
+    /// only works with ROMs which wait for the file device's vector.
+    #[clap(long)]
+    async_file_io: bool,
+
+    /// Interpolation used when playing audio samples
+    ///
+    /// `cubic` and `sinc` reduce aliasing in low-pitched samples, at some
+    /// cost in CPU time.
+    #[clap(long, value_enum, default_value_t = Interpolation::Linear)]
+    interpolation: Interpolation,
+
     /// Arguments to pass into the VM
     #[arg(trailing_var_arg = true)]
     args: Vec<String>,
 }
 
+/// Audio interpolation methods, as command-line values
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+enum Interpolation {
+    Linear,
+    Cubic,
+    Sinc,
+}
+
 pub fn run() -> Result<()> {
     let env = env_logger::Env::default()
         .filter_or("UXN_LOG", "info")
@@ -39,31 +122,63 @@ pub fn run() -> Result<()> {
     env_logger::init_from_env(env);
 
     let args = Args::parse();
-    let mut f = std::fs::File::open(&args.rom)
-        .with_context(|| format!("failed to open {:?}", args.rom))?;
-
-    let mut rom = vec![];
-    f.read_to_end(&mut rom).context("failed to read file")?;
+    let (rom, bundle) = open_rom(&args.rom)?;
+    let rom = Arc::new(rom);
 
-    let ram = UxnRam::new();
-    let mut vm = Uxn::new(
-        ram.leak(),
+    let mut vm = Uxn::new_owned(
+        UxnRam::new(),
         if args.native {
-            #[cfg(not(target_arch = "aarch64"))]
-            anyhow::bail!("no native implementation for this arch");
-
-            #[cfg(target_arch = "aarch64")]
+            if !Backend::Native.is_available() {
+                anyhow::bail!("no native implementation for this arch");
+            }
             Backend::Native
         } else {
             Backend::Interpreter
         },
     );
     let mut dev = Varvara::new();
-    let extra = vm.reset(&rom);
-    dev.reset(extra);
+    if let Some(path) = &args.theme {
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read theme {path:?}"))?;
+        let theme = Theme::parse(&data).with_context(|| {
+            format!("invalid theme {path:?} (expected 6 bytes)")
+        })?;
+        dev.set_theme(theme);
+    }
+    dev.set_create_file_dirs(args.create_dirs);
+    dev.set_async_file_io(args.async_file_io);
+    if let Some(b) = &bundle {
+        if args.file_root.is_some() {
+            warn!("ignoring --file-root, because files come from the bundle");
+        }
+        dev.set_file_backend(b.files());
+    } else {
+        dev.set_file_root(args.file_root.as_ref());
+    }
+    if let Some(path) = &args.keymap {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read key map {path:?}"))?;
+        let map = KeyMap::parse(&text)
+            .with_context(|| format!("invalid key map {path:?}"))?;
+        dev.set_key_map(&mut vm, map);
+    }
+    dev.audio_set_interpolation(match args.interpolation {
+        Interpolation::Linear => varvara::Interpolation::Linear,
+        Interpolation::Cubic => varvara::Interpolation::Cubic,
+        Interpolation::Sinc => varvara::Interpolation::Sinc,
+    });
+    dev.set_console_pacing(args.console_pacing);
+    dev.set_console_backend(Some(Box::new(ConsoleWriter::stdio())));
+    let title = RomInfo::parse(&rom)
+        .map(|info| info.name.to_owned())
+        .unwrap_or_else(|| "Varvara".to_owned());
+    dev.reset_from(&mut vm, rom);
+    let symbols = Symbols::load(args.symbols.as_deref(), &args.rom)
+        .context("failed to load symbols")?;
+    dev.set_symbols(symbols);
     dev.init_args(&mut vm, &args.args);
 
-    let _audio = audio_setup(dev.audio_streams());
+    let _audio = audio_setup(dev.audio_output());
 
     // Run the reset vector
     let start = std::time::Instant::now();
@@ -76,24 +191,123 @@ pub fn run() -> Result<()> {
     let size @ (width, height) = dev.output(&vm).size;
     let scale = args.scale.unwrap_or(if width < 320 { 2.0 } else { 1.0 });
     info!("creating window with size ({width}, {height}) and scale {scale}");
+    let (always_on_top, borderless) = (args.always_on_top, args.borderless);
+    let scroll_divisor = args.scroll_divisor;
+    if !scroll_divisor.is_finite() || scroll_divisor <= 0.0 {
+        anyhow::bail!("scroll divisor must be positive");
+    }
     let options = eframe::NativeOptions {
         window_builder: Some(Box::new(move |v| {
             v.with_inner_size(
                 egui::Vec2::new(width as f32, height as f32) * scale,
             )
             .with_resizable(false)
+            .with_title(title)
+            .with_window_level(if always_on_top {
+                egui::WindowLevel::AlwaysOnTop
+            } else {
+                egui::WindowLevel::Normal
+            })
+            .with_decorations(!borderless)
         })),
         ..Default::default()
     };
 
     let (tx, rx) = mpsc::channel();
-    varvara::spawn_console_worker(move |c| tx.send(crate::Event::Console(c)));
+
+    // Record the initial window mode, so that the hotkeys toggle correctly
+    tx.send(crate::Event::SetAlwaysOnTop(always_on_top))?;
+    tx.send(crate::Event::SetBorderless(borderless))?;
+    spawn_gamepad_worker(tx.clone());
+    varvara::spawn_console_worker_with_eof(move |c| {
+        tx.send(match c {
+            Some(c) => crate::Event::Console(c),
+            None => crate::Event::ConsoleEnd,
+        })
+    });
     eframe::run_native(
         "Varvara",
         options,
         Box::new(move |cc| {
-            Box::new(Stage::new(vm, dev, size, scale, rx, &cc.egui_ctx))
+            let mut s =
+                Box::new(Stage::new(vm, dev, size, scale, rx, &cc.egui_ctx));
+            s.set_scroll_divisor(scroll_divisor);
+            s.bundled = bundle.is_some();
+            s
         }),
     )
     .map_err(|e| anyhow!("got egui error: {e:?}"))
 }
+
+/// Opens a ROM, unpacking it if it's a bundle with data files
+///
+/// Files ending in `.zip` must be valid bundles; other files are only treated
+/// as bundles if they parse as one (e.g. a ROM with a zip archive appended).
+fn open_rom(path: &Path) -> Result<(RomFile, Option<Bundle>)> {
+    // SAFETY: ROMs aren't expected to change while they're running; if one
+    // is truncated underneath us, we'll crash (as documented for `open`)
+    let rom = unsafe { RomFile::open(path) }
+        .with_context(|| format!("failed to open {path:?}"))?;
+    let is_zip = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+    match Bundle::parse(&rom) {
+        Ok(b) => {
+            info!("loaded bundle from {path:?}");
+            Ok((b.rom().to_vec().into(), Some(b)))
+        }
+        Err(e) if is_zip => {
+            Err(e).with_context(|| format!("failed to load bundle {path:?}"))
+        }
+        Err(_) => Ok((rom, None)),
+    }
+}
+
+/// Spawns a thread which sends gamepad state to the GUI
+///
+/// Each gamepad is assigned to a controller player in order of connection;
+/// gamepads beyond [`CONTROLLER_PLAYERS`] are ignored.
+fn spawn_gamepad_worker(tx: mpsc::Sender<crate::Event>) {
+    std::thread::spawn(move || {
+        let mut gilrs = match gilrs::Gilrs::new() {
+            Ok(g) => g,
+            Err(e) => {
+                log::warn!("gamepads are unavailable: {e}");
+                return;
+            }
+        };
+        while let Some(ev) = gilrs.next_event_blocking(None) {
+            let player = usize::from(ev.id);
+            if player >= CONTROLLER_PLAYERS {
+                continue;
+            }
+            let state = if ev.event == gilrs::EventType::Disconnected {
+                GamepadState {
+                    player,
+                    ..GamepadState::default()
+                }
+            } else {
+                use gilrs::{Axis, Button};
+                let g = gilrs.gamepad(ev.id);
+                GamepadState {
+                    player,
+                    south: g.is_pressed(Button::South),
+                    east: g.is_pressed(Button::East),
+                    select: g.is_pressed(Button::Select),
+                    start: g.is_pressed(Button::Start),
+                    up: g.is_pressed(Button::DPadUp),
+                    down: g.is_pressed(Button::DPadDown),
+                    left: g.is_pressed(Button::DPadLeft),
+                    right: g.is_pressed(Button::DPadRight),
+                    stick: (
+                        g.value(Axis::LeftStickX),
+                        g.value(Axis::LeftStickY),
+                    ),
+                }
+            };
+            if tx.send(crate::Event::Gamepad(state)).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -40,11 +40,9 @@ pub fn run() -> Result<()> {
         .map(|(_name, data)| *data)
         .unwrap_or(include_bytes!("../../roms/controller.rom"));
 
-    let ram = UxnRam::new();
-    let mut vm = Uxn::new(ram.leak(), Backend::Interpreter);
+    let mut vm = Uxn::new_owned(UxnRam::new(), Backend::auto());
     let mut dev = Varvara::new();
-    let extra = vm.reset(rom);
-    dev.reset(extra);
+    dev.load_rom(&mut vm, rom);
 
     // Run the reset vector
     vm.run(&mut dev, 0x100);
@@ -175,7 +173,7 @@ pub fn run() -> Result<()> {
     std::mem::forget(a);
 
     let mut _audio = None;
-    let mut audio_data = Some(dev.audio_streams());
+    let mut audio_data = Some(dev.audio_output());
     let audio_check = document
         .get_element_by_id("audio-check")
         .ok_or_else(|| anyhow!("could not find audio-check"))?
@@ -0,0 +1,102 @@
+//! Compares the interpreter's `match`-based dispatch against a table of
+//! function pointers, on a synthetic loop and a real ROM
+//!
+//! [`Uxn::run`] still dispatches through the `match`.  Replacing it with a
+//! table (or a tail-call loop) is left open; this benchmark is the yardstick
+//! for that change, and on x86-64 the table is currently ahead on both
+//! workloads.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use raven_uxn::{op, Backend, Device, Uxn, UxnRam};
+
+/// Device which ignores all I/O
+struct NullDevice;
+impl Device for NullDevice {
+    fn dei(&mut self, _vm: &mut Uxn, _target: u8) {}
+    fn deo(&mut self, _vm: &mut Uxn, _target: u8) -> bool {
+        true
+    }
+}
+
+/// Counts down from `#ffff`, 16 times over
+#[rustfmt::skip]
+const LOOP: &[u8] = &[
+    // #10 @outer #ffff @inner #0001 SUB2 DUP2 ORA ?inner POP2
+    op::LIT, 0x10, op::LIT2, 0xff, 0xff,
+    op::LIT2, 0x00, 0x01, op::SUB2, op::DUP2, op::ORA, op::JCI, 0xff, 0xf7,
+    op::POP2,
+    // #01 SUB DUP ?outer POP BRK
+    op::LIT, 0x01, op::SUB, op::DUP, op::JCI, 0xff, 0xec, op::POP, op::BRK,
+];
+
+type OpFn = fn(&mut Uxn, &mut NullDevice, u16) -> Option<u16>;
+
+/// Executes a single opcode, which is known at compile time
+fn op_fn<const OP: u8>(
+    vm: &mut Uxn,
+    dev: &mut NullDevice,
+    pc: u16,
+) -> Option<u16> {
+    vm.dispatch(OP, dev, pc)
+}
+
+macro_rules! op_table {
+    ($($hi:literal)*) => {
+        [$(
+            op_fn::<{ $hi * 16 + 0x0 }>, op_fn::<{ $hi * 16 + 0x1 }>,
+            op_fn::<{ $hi * 16 + 0x2 }>, op_fn::<{ $hi * 16 + 0x3 }>,
+            op_fn::<{ $hi * 16 + 0x4 }>, op_fn::<{ $hi * 16 + 0x5 }>,
+            op_fn::<{ $hi * 16 + 0x6 }>, op_fn::<{ $hi * 16 + 0x7 }>,
+            op_fn::<{ $hi * 16 + 0x8 }>, op_fn::<{ $hi * 16 + 0x9 }>,
+            op_fn::<{ $hi * 16 + 0xa }>, op_fn::<{ $hi * 16 + 0xb }>,
+            op_fn::<{ $hi * 16 + 0xc }>, op_fn::<{ $hi * 16 + 0xd }>,
+            op_fn::<{ $hi * 16 + 0xe }>, op_fn::<{ $hi * 16 + 0xf }>,
+        )*]
+    };
+}
+
+/// Dispatch table, with one function pointer per opcode
+#[rustfmt::skip]
+const OPS: [OpFn; 256] = op_table!(
+    0x0 0x1 0x2 0x3 0x4 0x5 0x6 0x7 0x8 0x9 0xa 0xb 0xc 0xd 0xe 0xf
+);
+
+/// Runs the reset vector, dispatching through [`OPS`]
+fn run_table(vm: &mut Uxn, dev: &mut NullDevice) -> u16 {
+    let mut pc = 0x100;
+    loop {
+        let op = vm.ram_read_byte(pc);
+        pc = pc.wrapping_add(1);
+        match OPS[usize::from(op)](vm, dev, pc) {
+            Some(next) => pc = next,
+            None => break pc,
+        }
+    }
+}
+
+fn bench_rom(c: &mut Criterion, name: &str, rom: &[u8]) {
+    let mut g = c.benchmark_group(name);
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = NullDevice;
+    g.bench_function("match", |b| {
+        b.iter(|| {
+            let _ = vm.reset(black_box(rom));
+            vm.run(&mut dev, 0x100)
+        })
+    });
+    g.bench_function("table", |b| {
+        b.iter(|| {
+            let _ = vm.reset(black_box(rom));
+            run_table(&mut vm, &mut dev)
+        })
+    });
+    g.finish();
+}
+
+fn dispatch(c: &mut Criterion) {
+    bench_rom(c, "loop", LOOP);
+    bench_rom(c, "mandelbrot", include_bytes!("../../roms/mandelbrot.rom"));
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
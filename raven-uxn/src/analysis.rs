@@ -0,0 +1,503 @@
+//! Static analysis of ROMs
+//!
+//! [`analyze`] walks a ROM's code from its vectors and reports likely bugs.
+//! The analysis is heuristic: it only follows jumps whose targets are known
+//! statically (immediate jumps, and stack jumps directly after a literal), and
+//! only tracks the depth of the working stack, giving up on any path where
+//! that depth can't be determined.  It aims to report problems which are
+//! obvious, rather than to prove that a ROM is correct.
+extern crate alloc;
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+use crate::aot::{rom_short, Instruction, ROM_START};
+use crate::op;
+
+/// A potential problem found by [`analyze`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Finding {
+    /// A jump lands in the operand bytes of another instruction
+    JumpIntoOperand {
+        /// Address of the jump instruction
+        from: u16,
+        /// Address of the jump target
+        target: u16,
+    },
+    /// A range of ROM is never reached, and doesn't look like data
+    ///
+    /// Ranges which are entirely zero, or which contain an address pushed by a
+    /// literal in reachable code, are assumed to be data.
+    Unreachable {
+        /// First address in the range
+        start: u16,
+        /// Last address in the range (inclusive)
+        end: u16,
+    },
+    /// An instruction in a vector reads more bytes than the working stack
+    /// holds, assuming that the stack was empty when the vector began
+    StackUnderflow {
+        /// Address of the instruction
+        addr: u16,
+    },
+    /// A vector ends (with `BRK`) with bytes left on the working stack
+    StackLeak {
+        /// Address of the `BRK` instruction
+        addr: u16,
+        /// Number of bytes left on the stack (negative if the vector popped
+        /// more bytes than it pushed, e.g. through a subroutine)
+        depth: i32,
+    },
+    /// An instruction is reached with different working stack depths
+    ///
+    /// This usually indicates a loop which grows or shrinks the stack on every
+    /// iteration.
+    StackMismatch {
+        /// Address of the instruction
+        addr: u16,
+    },
+    /// A zero-page address is written but never read
+    ///
+    /// This is only reported if every zero-page read in the ROM uses an
+    /// address known statically.
+    UnreadZeroPage {
+        /// Zero-page address
+        addr: u8,
+    },
+}
+
+impl core::fmt::Display for Finding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Finding::JumpIntoOperand { from, target } => write!(
+                f,
+                "{from:#06x}: jump into operand bytes at {target:#06x}"
+            ),
+            Finding::Unreachable { start, end } => {
+                write!(f, "{start:#06x}-{end:#06x}: unreachable code")
+            }
+            Finding::StackUnderflow { addr } => {
+                write!(f, "{addr:#06x}: working stack underflow")
+            }
+            Finding::StackLeak { addr, depth } => write!(
+                f,
+                "{addr:#06x}: vector ends with {depth} byte(s) on the \
+                 working stack"
+            ),
+            Finding::StackMismatch { addr } => write!(
+                f,
+                "{addr:#06x}: reached with different working stack depths"
+            ),
+            Finding::UnreadZeroPage { addr } => {
+                write!(f, "{addr:#04x}: zero-page write is never read")
+            }
+        }
+    }
+}
+
+/// Value pushed onto the working stack by the previous instruction
+#[derive(Copy, Clone, PartialEq)]
+enum Lit {
+    /// Byte pushed by `LIT`
+    Byte(u8),
+    /// Short pushed by `LIT2`
+    Short(u16),
+    /// Result of a comparison, which is either 0 or 1
+    Flag,
+}
+
+/// Control flow after an instruction
+enum Flow {
+    /// Continue to the following instruction
+    Next,
+    /// Stop evaluation (`BRK`)
+    Halt,
+    /// Return from a subroutine (`JMP2r`)
+    Return,
+    /// Jump to a known address
+    Jump(u16),
+    /// Either jump to a known address or continue
+    Branch(u16),
+    /// Call a subroutine at a known address, then continue
+    Call(u16),
+    /// Jump to an unknown address
+    Unknown,
+    /// Either jump to an unknown address or continue
+    UnknownBranch,
+    /// Call a subroutine at an unknown address, then continue
+    UnknownCall,
+}
+
+/// Decodes an instruction, returning `None` if it's not entirely in the ROM
+fn decode(rom: &[u8], addr: u16) -> Option<Instruction> {
+    let i = usize::from(addr.checked_sub(ROM_START)?);
+    let op = *rom.get(i)?;
+    let size = 1 + usize::from(op::INFO[usize::from(op)].operand);
+    (i + size <= rom.len() && addr.checked_add(size as u16).is_some())
+        .then_some(Instruction { addr, op })
+}
+
+/// Returns the value pushed onto the working stack by an instruction, if it's
+/// known well enough to find jump targets
+fn literal(rom: &[u8], i: &Instruction) -> Option<Lit> {
+    match i.op {
+        op::LIT => {
+            let v = rom[usize::from(i.pc() - ROM_START)];
+            Some(Lit::Byte(v))
+        }
+        op::LIT2 => rom_short(rom, i.pc()).map(Lit::Short),
+        o if o & 0x40 == 0
+            && matches!(o & 0x1f, op::EQU | op::NEQ | op::GTH | op::LTH) =>
+        {
+            Some(Lit::Flag)
+        }
+        _ => None,
+    }
+}
+
+/// Checks whether an opcode's source stack is the working stack
+fn uses_working_stack(op: u8) -> bool {
+    // BRK, JCI, JMI, and JSI reuse the flag bits, but only touch the working
+    // stack (or push onto the return stack, which is the "other" stack)
+    op & 0x40 == 0 || (op & 0x1f == 0 && !matches!(op, op::LITr | op::LIT2r))
+}
+
+/// Returns the control flow after an instruction
+///
+/// `lit` is the value pushed by the previous instruction, if it was a literal
+/// which falls through to this one.
+fn flow(rom: &[u8], i: &Instruction, lit: Option<Lit>) -> Flow {
+    let next = i.next();
+    let immediate = || {
+        let offset = rom_short(rom, i.pc()).unwrap();
+        next.wrapping_add(offset)
+    };
+    match i.op {
+        op::BRK => return Flow::Halt,
+        op::JCI => return Flow::Branch(immediate()),
+        op::JMI => return Flow::Jump(immediate()),
+        op::JSI => return Flow::Call(immediate()),
+        op::JMP2r => return Flow::Return,
+        _ => (),
+    }
+    if !op::INFO[usize::from(i.op)].jump {
+        return Flow::Next;
+    }
+    let target = match (lit, i.op & 0x40 == 0) {
+        (Some(Lit::Short(a)), true) if i.op & 0x20 != 0 => Some(a),
+        (Some(Lit::Byte(o)), true) if i.op & 0x20 == 0 => {
+            Some(next.wrapping_add(o as i8 as u16))
+        }
+        // Jumping by the result of a comparison skips a single byte, e.g.
+        // `GTHk JMP SWP` (which is either a jump or a fallthrough)
+        (Some(Lit::Flag), true) if i.op & 0x3f == op::JMP => {
+            return Flow::Branch(next.wrapping_add(1));
+        }
+        _ => None,
+    };
+    match (i.op & 0x1f, target) {
+        (op::JMP, Some(t)) => Flow::Jump(t),
+        (op::JCN, Some(t)) => Flow::Branch(t),
+        (op::JSR, Some(t)) => Flow::Call(t),
+        (op::JMP, None) => Flow::Unknown,
+        (op::JCN, None) => Flow::UnknownBranch,
+        (op::JSR, None) => Flow::UnknownCall,
+        _ => unreachable!(),
+    }
+}
+
+/// Checks whether an instruction installs a vector, i.e. it's a `DEO2` to
+/// the first port of a device directly after a `LIT2 addr LIT port`
+fn installed_vector(rom: &[u8], i: &Instruction) -> Option<u16> {
+    let addr = i.addr.checked_sub(ROM_START + 5)?;
+    let bytes = rom.get(usize::from(addr)..usize::from(addr) + 6)?;
+    match *bytes {
+        [op::LIT2, hi, lo, op::LIT, port, op::DEO2] if port & 0xf == 0 => {
+            Some(u16::from_be_bytes([hi, lo]))
+        }
+        _ => None,
+    }
+}
+
+/// State accumulated while walking a ROM's reachable code
+#[derive(Default)]
+struct Walk {
+    /// Reachable instructions, keyed by address
+    code: BTreeMap<u16, u8>,
+    /// Whether each instruction has been visited after a literal
+    lit_seen: BTreeMap<u16, bool>,
+    /// Vector entry points (including the reset vector)
+    vectors: BTreeSet<u16>,
+    /// Statically known jump edges, as `(from, target)` pairs
+    jumps: BTreeSet<(u16, u16)>,
+    /// Addresses pushed by `LIT2` and `LIT2r` in reachable code
+    pointers: BTreeSet<u16>,
+    /// Zero-page addresses written with a known address
+    zp_writes: BTreeSet<u8>,
+    /// Zero-page addresses read with a known address
+    zp_reads: BTreeSet<u8>,
+    /// Some zero-page read uses an address which isn't known statically
+    zp_dynamic: bool,
+}
+
+impl Walk {
+    fn run(&mut self, rom: &[u8]) {
+        let mut todo = alloc::vec![(ROM_START, None)];
+        self.vectors.insert(ROM_START);
+        while let Some((addr, lit)) = todo.pop() {
+            let has_lit = lit.is_some();
+            match self.lit_seen.get(&addr) {
+                Some(&seen) if seen || !has_lit => continue,
+                _ => (),
+            }
+            let Some(i) = decode(rom, addr) else {
+                continue;
+            };
+            self.lit_seen.insert(addr, has_lit);
+            self.code.insert(addr, i.op);
+            self.record_memory(&i, lit);
+            if let Some(v) = installed_vector(rom, &i) {
+                if self.vectors.insert(v) {
+                    todo.push((v, None));
+                }
+            }
+            if matches!(i.op, op::LIT2 | op::LIT2r) {
+                if let Some(v) = rom_short(rom, i.pc()) {
+                    self.pointers.insert(v);
+                }
+            }
+
+            let next = i.next();
+            let fallthrough = literal(rom, &i);
+            match flow(rom, &i, lit) {
+                Flow::Next | Flow::UnknownBranch | Flow::UnknownCall => {
+                    todo.push((next, fallthrough))
+                }
+                Flow::Halt | Flow::Return | Flow::Unknown => (),
+                Flow::Jump(t) => {
+                    self.jumps.insert((addr, t));
+                    todo.push((t, None));
+                }
+                Flow::Branch(t) | Flow::Call(t) => {
+                    self.jumps.insert((addr, t));
+                    todo.push((t, None));
+                    todo.push((next, None));
+                }
+            }
+        }
+    }
+
+    /// Records zero-page accesses by a single instruction
+    fn record_memory(&mut self, i: &Instruction, lit: Option<Lit>) {
+        let short = i.op & 0x20 != 0;
+        let working = i.op & 0x40 == 0;
+        let addr = match (i.op & 0x1f, lit) {
+            (op::LDZ | op::STZ, Some(Lit::Byte(a))) if working => Some(a),
+            (op::LDA | op::STA, Some(Lit::Short(a))) if working => {
+                u8::try_from(a).ok()
+            }
+            (op::LDZ, _) => {
+                self.zp_dynamic = true;
+                return;
+            }
+            _ => return,
+        };
+        let Some(a) = addr else {
+            return;
+        };
+        let set = if matches!(i.op & 0x1f, op::LDZ | op::LDA) {
+            &mut self.zp_reads
+        } else {
+            &mut self.zp_writes
+        };
+        set.insert(a);
+        if short {
+            set.insert(a.wrapping_add(1));
+        }
+    }
+}
+
+/// Whether a stack walk starts at a vector or a subroutine
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    /// The working stack is empty at entry, and must be empty at `BRK`
+    Vector,
+    /// The working stack depth is relative to the caller's
+    Subroutine,
+}
+
+/// Working stack analysis
+struct Stacks<'a> {
+    rom: &'a [u8],
+    /// Net effect of each subroutine on the working stack, if known
+    effects: BTreeMap<u16, Option<i32>>,
+    findings: BTreeSet<Finding>,
+}
+
+impl Stacks<'_> {
+    /// Returns the net effect of a subroutine on the working stack
+    fn effect(&mut self, addr: u16) -> Option<i32> {
+        if let Some(e) = self.effects.get(&addr) {
+            return *e;
+        }
+        // Recursive calls are treated as unknown
+        self.effects.insert(addr, None);
+        let e = self.walk(addr, Mode::Subroutine);
+        self.effects.insert(addr, e);
+        e
+    }
+
+    /// Walks from an entry point, tracking the working stack depth
+    ///
+    /// In [`Mode::Subroutine`], returns the net stack effect if every return
+    /// is reached with the same depth (and no path was abandoned).
+    fn walk(&mut self, entry: u16, mode: Mode) -> Option<i32> {
+        let mut seen: BTreeMap<u16, (i32, bool)> = BTreeMap::new();
+        let mut todo = alloc::vec![(entry, 0i32, None)];
+        let mut ret = None;
+        let mut complete = true;
+        while let Some((addr, depth, lit)) = todo.pop() {
+            let has_lit = lit.is_some();
+            if let Some(&(d, seen_lit)) = seen.get(&addr) {
+                if d != depth {
+                    self.findings.insert(Finding::StackMismatch { addr });
+                    complete = false;
+                    continue;
+                } else if seen_lit || !has_lit {
+                    continue;
+                }
+            }
+            let Some(i) = decode(self.rom, addr) else {
+                complete = false;
+                continue;
+            };
+            seen.insert(addr, (depth, has_lit));
+
+            let info = op::INFO[usize::from(i.op)];
+            let depth = if uses_working_stack(i.op) {
+                if mode == Mode::Vector && depth < i32::from(info.reads) {
+                    self.findings.insert(Finding::StackUnderflow { addr });
+                    complete = false;
+                    continue;
+                }
+                depth - i32::from(info.pops) + i32::from(info.pushes)
+            } else {
+                depth + i32::from(info.other_pushes)
+            };
+
+            let next = i.next();
+            let fallthrough = literal(self.rom, &i);
+            match flow(self.rom, &i, lit) {
+                Flow::Next | Flow::UnknownBranch => {
+                    todo.push((next, depth, fallthrough))
+                }
+                Flow::Halt => {
+                    if mode == Mode::Vector && depth != 0 {
+                        self.findings
+                            .insert(Finding::StackLeak { addr, depth });
+                    }
+                }
+                Flow::Return => match (mode, ret) {
+                    (Mode::Subroutine, None) => ret = Some(depth),
+                    (Mode::Subroutine, Some(d)) if d == depth => (),
+                    _ => complete = false,
+                },
+                Flow::Jump(t) => todo.push((t, depth, None)),
+                Flow::Branch(t) => {
+                    todo.push((t, depth, None));
+                    todo.push((next, depth, None));
+                }
+                Flow::Call(t) => match self.effect(t) {
+                    Some(e) => todo.push((next, depth + e, None)),
+                    None => complete = false,
+                },
+                Flow::Unknown | Flow::UnknownCall => complete = false,
+            }
+        }
+        ret.filter(|_| complete)
+    }
+}
+
+/// Finds ranges of the ROM which aren't reachable and don't look like data
+fn unreachable(rom: &[u8], walk: &Walk) -> Vec<Finding> {
+    let mut covered = alloc::vec![false; rom.len()];
+    for (&addr, &op) in &walk.code {
+        let start = usize::from(addr - ROM_START);
+        let size = 1 + usize::from(op::INFO[usize::from(op)].operand);
+        covered[start..start + size].fill(true);
+    }
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < rom.len() {
+        if covered[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < rom.len() && !covered[i] {
+            i += 1;
+        }
+        let range = (ROM_START + start as u16)..=(ROM_START + (i - 1) as u16);
+        let is_zero = rom[start..i].iter().all(|&b| b == 0);
+        let is_data = walk.pointers.range(range.clone()).next().is_some();
+        if !is_zero && !is_data {
+            out.push(Finding::Unreachable {
+                start: *range.start(),
+                end: *range.end(),
+            });
+        }
+    }
+    out
+}
+
+/// Analyzes a ROM, returning potential problems sorted by kind and address
+///
+/// The ROM is expected to be loaded at `0x100`.  Analysis begins at the reset
+/// vector, along with any vectors which are installed by reachable code (with
+/// the usual `LIT2 addr LIT port DEO2` sequence).  Vectors are expected to
+/// begin and end with an empty working stack.
+///
+/// This is only available if the `"alloc"` feature is enabled.
+pub fn analyze(rom: &[u8]) -> Vec<Finding> {
+    let rom = &rom[..rom.len().min(usize::from(u16::MAX - ROM_START) + 1)];
+    let mut walk = Walk::default();
+    walk.run(rom);
+
+    let mut findings = BTreeSet::new();
+    for &(from, target) in &walk.jumps {
+        // Operands are at most two bytes long
+        let inside = [1, 2].into_iter().any(|n| {
+            let Some(addr) = target.checked_sub(n) else {
+                return false;
+            };
+            walk.code
+                .get(&addr)
+                .is_some_and(|&op| Instruction { addr, op }.next() > target)
+        });
+        if inside {
+            findings.insert(Finding::JumpIntoOperand { from, target });
+        }
+    }
+
+    findings.extend(unreachable(rom, &walk));
+
+    let mut stacks = Stacks {
+        rom,
+        effects: BTreeMap::new(),
+        findings: BTreeSet::new(),
+    };
+    for &v in &walk.vectors {
+        stacks.walk(v, Mode::Vector);
+    }
+    findings.extend(stacks.findings);
+
+    if !walk.zp_dynamic {
+        findings.extend(
+            walk.zp_writes
+                .difference(&walk.zp_reads)
+                .map(|&addr| Finding::UnreadZeroPage { addr }),
+        );
+    }
+    findings.into_iter().collect()
+}
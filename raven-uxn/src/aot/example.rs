@@ -0,0 +1,68 @@
+// Generated by raven_uxn::aot::transpile
+pub fn run<D: Device>(vm: &mut Uxn, dev: &mut D, mut pc: u16) -> u16 {
+    loop {
+        pc = match pc {
+            0x0100 => {
+                const CODE: [u8; 5] = [0x80, 0x05, 0x60, 0x00, 0x09];
+                if vm.ram()[0x0100..][..5] != CODE {
+                    return vm.run(dev, pc);
+                }
+                vm.lit::<0b100>(0x0101);
+                match vm.jsi(0x0103) {
+                    Some(next) => next,
+                    None => return 0x0103,
+                }
+            }
+            0x0102 => {
+                const CODE: [u8; 3] = [0x60, 0x00, 0x09];
+                if vm.ram()[0x0102..][..3] != CODE {
+                    return vm.run(dev, pc);
+                }
+                match vm.jsi(0x0103) {
+                    Some(next) => next,
+                    None => return 0x0103,
+                }
+            }
+            0x0105 => {
+                const CODE: [u8; 7] = [0x80, 0x01, 0x19, 0x06, 0x20, 0xff, 0xf6];
+                if vm.ram()[0x0105..][..7] != CODE {
+                    return vm.run(dev, pc);
+                }
+                vm.lit::<0b100>(0x0106);
+                vm.sub::<0b000>(0x0108);
+                vm.dup::<0b000>(0x0109);
+                match vm.jci(0x010a) {
+                    Some(next) => next,
+                    None => return 0x010a,
+                }
+            }
+            0x010c => {
+                const CODE: [u8; 2] = [0x02, 0x00];
+                if vm.ram()[0x010c..][..2] != CODE {
+                    return vm.run(dev, pc);
+                }
+                vm.pop::<0b000>(0x010d);
+                match vm.brk(0x010e) {
+                    Some(next) => next,
+                    None => return 0x010e,
+                }
+            }
+            0x010e => {
+                const CODE: [u8; 8] = [0x80, 0x02, 0x10, 0x01, 0x80, 0x02, 0x11, 0x6c];
+                if vm.ram()[0x010e..][..8] != CODE {
+                    return vm.run(dev, pc);
+                }
+                vm.lit::<0b100>(0x010f);
+                vm.ldz::<0b000>(0x0111);
+                vm.inc::<0b000>(0x0112);
+                vm.lit::<0b100>(0x0113);
+                vm.stz::<0b000>(0x0115);
+                match vm.jmp::<0b011>(0x0116) {
+                    Some(next) => next,
+                    None => return 0x0116,
+                }
+            }
+            _ => return vm.run(dev, pc),
+        };
+    }
+}
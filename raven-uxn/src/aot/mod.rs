@@ -0,0 +1,228 @@
+//! Ahead-of-time translation of ROMs into Rust source
+//!
+//! [`transpile`] statically walks a ROM's reachable code, splitting it into
+//! basic blocks, and emits a Rust function which evaluates each block as a
+//! straight-line series of calls to the per-opcode functions on [`Uxn`] (see
+//! [`Uxn::dispatch`] for their stability guarantees).  This avoids decoding
+//! each instruction at runtime, and works on any architecture.
+//!
+//! The generated function has the same signature and behavior as
+//! [`Uxn::run`], and falls back to the interpreter whenever it reaches code
+//! which was not found by the static analysis.  Each block also checks that
+//! RAM still contains the bytes from which it was compiled before running, so
+//! ROMs which modify (or load) their own code remain correct.
+//!
+//! [`Uxn`]: crate::Uxn
+//! [`Uxn::run`]: crate::Uxn::run
+//! [`Uxn::dispatch`]: crate::Uxn::dispatch
+extern crate alloc;
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use crate::op;
+
+/// Address at which ROMs are loaded
+pub(crate) const ROM_START: u16 = 0x100;
+
+/// A single decoded instruction
+pub(crate) struct Instruction {
+    /// Address of the opcode
+    pub addr: u16,
+    /// Opcode
+    pub op: u8,
+}
+
+impl Instruction {
+    /// Address immediately after the opcode (passed to opcode functions)
+    pub fn pc(&self) -> u16 {
+        self.addr.wrapping_add(1)
+    }
+
+    /// Address of the following instruction
+    pub fn next(&self) -> u16 {
+        self.pc() + u16::from(op::INFO[usize::from(self.op)].operand)
+    }
+
+    /// Checks whether this instruction ends a basic block
+    ///
+    /// In addition to jumps, blocks end after any opcode which may halt
+    /// evaluation (`BRK`, `DEI`, `DEO`) or write to RAM outside of the zero
+    /// page (`STR`, `STA`), since such writes could modify code.
+    fn is_terminator(&self) -> bool {
+        op::INFO[usize::from(self.op)].jump
+            || self.op == op::BRK
+            || matches!(self.op & 0x1f, op::DEI | op::DEO | op::STR | op::STA)
+    }
+
+    /// Returns a Rust expression which executes this instruction
+    fn call(&self) -> String {
+        let pc = self.pc();
+        let name = match self.op {
+            op::BRK => return alloc::format!("vm.brk({pc:#06x})"),
+            op::JCI => return alloc::format!("vm.jci({pc:#06x})"),
+            op::JMI => return alloc::format!("vm.jmi({pc:#06x})"),
+            op::JSI => return alloc::format!("vm.jsi({pc:#06x})"),
+            op::LIT | op::LIT2 | op::LITr | op::LIT2r => "lit",
+            _ => op::NAMES[usize::from(self.op & 0x1f)],
+        };
+        let name = name.to_ascii_lowercase();
+        let flags = self.op >> 5;
+        if matches!(self.op & 0x1f, op::DEI | op::DEO) {
+            alloc::format!("vm.{name}::<{flags:#05b}>(dev, {pc:#06x})")
+        } else {
+            alloc::format!("vm.{name}::<{flags:#05b}>({pc:#06x})")
+        }
+    }
+}
+
+/// Reads a big-endian short from the ROM, returning `None` if out of bounds
+pub(crate) fn rom_short(rom: &[u8], addr: u16) -> Option<u16> {
+    let i = usize::from(addr.checked_sub(ROM_START)?);
+    let hi = *rom.get(i)?;
+    let lo = *rom.get(i + 1)?;
+    Some(u16::from_be_bytes([hi, lo]))
+}
+
+/// Decodes a basic block starting at the given address
+///
+/// Returns `None` if the block runs off the end of the ROM.
+fn decode_block(rom: &[u8], start: u16) -> Option<Vec<Instruction>> {
+    let end = ROM_START.checked_add(u16::try_from(rom.len()).ok()?)?;
+    let mut out = Vec::new();
+    let mut addr = start;
+    loop {
+        if addr < ROM_START || addr >= end {
+            return None;
+        }
+        let op = rom[usize::from(addr - ROM_START)];
+        let i = Instruction { addr, op };
+        let size = 1 + u16::from(op::INFO[usize::from(op)].operand);
+        let next = addr.checked_add(size)?;
+        if next > end {
+            return None;
+        }
+        let done = i.is_terminator();
+        out.push(i);
+        if done {
+            return Some(out);
+        }
+        addr = next;
+    }
+}
+
+/// Finds basic blocks in a ROM, returning a map from start address to block
+///
+/// Analysis begins at the reset vector (`0x100`), along with every address in
+/// the ROM which is pushed as a `LIT2` immediate (since vectors are usually
+/// installed this way).
+pub(crate) fn find_blocks(rom: &[u8]) -> BTreeMap<u16, Vec<Instruction>> {
+    let end = u16::try_from(rom.len())
+        .ok()
+        .and_then(|n| ROM_START.checked_add(n))
+        .unwrap_or(u16::MAX);
+
+    // Seed the worklist with the reset vector and every `LIT2` address
+    let mut todo = alloc::vec![ROM_START];
+    for (i, &b) in rom.iter().enumerate() {
+        if b == op::LIT2 {
+            let addr = ROM_START.wrapping_add(i as u16).wrapping_add(1);
+            if let Some(v) = rom_short(rom, addr) {
+                if (ROM_START..end).contains(&v) {
+                    todo.push(v);
+                }
+            }
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    while let Some(start) = todo.pop() {
+        if !seen.insert(start) {
+            continue;
+        }
+        let Some(block) = decode_block(rom, start) else {
+            continue;
+        };
+        let last = block.last().unwrap();
+        let next = last.next();
+        match last.op {
+            op::BRK => (),
+            op::JCI | op::JMI | op::JSI => {
+                let offset = rom_short(rom, last.pc()).unwrap();
+                todo.push(next.wrapping_add(offset));
+                if last.op != op::JMI {
+                    todo.push(next);
+                }
+            }
+            // Unconditional jumps don't fall through (but `JSR` returns)
+            o if o & 0x1f == op::JMP => (),
+            _ => todo.push(next),
+        }
+        blocks.insert(start, block);
+    }
+    blocks
+}
+
+/// Translates a ROM into Rust source code
+///
+/// The ROM is expected to be loaded at `0x100`.  Analysis begins at the reset
+/// vector (`0x100`), along with every address in the ROM which is pushed as a
+/// `LIT2` immediate (since vectors are usually installed this way).
+///
+/// The resulting code defines a function
+///
+/// ```text
+/// pub fn run<D: Device>(vm: &mut Uxn, dev: &mut D, pc: u16) -> u16
+/// ```
+///
+/// which is equivalent to `vm.run(dev, pc)`, and expects `Device` and `Uxn`
+/// to be in scope (e.g. by wrapping it in a module and using `include!`).  It
+/// always uses the interpreter for fallback, and does not check the VM's
+/// interrupt flag or record coverage.
+pub fn transpile(rom: &[u8]) -> String {
+    let blocks = find_blocks(rom);
+
+    // Writing to a `String` is infallible, so we unwrap in this macro
+    let mut out = String::new();
+    macro_rules! emit {
+        ($($t:tt)*) => { writeln!(out, $($t)*).unwrap() };
+    }
+    emit!("// Generated by raven_uxn::aot::transpile");
+    emit!("pub fn run<D: Device>(vm: &mut Uxn, dev: &mut D, mut pc: u16) -> u16 {{");
+    emit!("    loop {{");
+    emit!("        pc = match pc {{");
+    for (start, block) in &blocks {
+        let last = block.last().unwrap();
+        let len = last.next() - start;
+        let bytes = &rom[usize::from(start - ROM_START)..][..usize::from(len)];
+        let code = bytes
+            .iter()
+            .map(|b| alloc::format!("{b:#04x}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        emit!("            {start:#06x} => {{");
+        emit!("                const CODE: [u8; {len}] = [{code}];");
+        emit!(
+            "                if vm.ram()[{start:#06x}..][..{len}] != CODE {{"
+        );
+        emit!("                    return vm.run(dev, pc);");
+        emit!("                }}");
+        for i in &block[..block.len() - 1] {
+            emit!("                {};", i.call());
+        }
+        emit!("                match {} {{", last.call());
+        emit!("                    Some(next) => next,");
+        emit!("                    None => return {:#06x},", last.pc());
+        emit!("                }}");
+        emit!("            }}");
+    }
+    emit!("            _ => return vm.run(dev, pc),");
+    emit!("        }};");
+    emit!("    }}");
+    emit!("}}");
+    out
+}
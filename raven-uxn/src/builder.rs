@@ -0,0 +1,106 @@
+//! Builder for configuring a [`Uxn`]
+use core::sync::atomic::AtomicBool;
+
+use crate::{Backend, Coverage, CycleCosts, Uxn, UNIT_CYCLE_COSTS};
+
+/// Builder for a [`Uxn`], returned by [`Uxn::builder`]
+///
+/// Options which are not specified use the same defaults as [`Uxn::new`]:
+///
+/// - The backend is [`Backend::default`] (the interpreter)
+/// - There is no interrupt flag
+/// - Coverage is not recorded
+/// - Cycles are not counted, and there is no cycle limit
+/// - Stack high-water marks are not tracked
+#[must_use]
+pub struct UxnBuilder<'a> {
+    ram: &'a mut [u8; 65536],
+    backend: Backend,
+    interrupt: Option<&'a AtomicBool>,
+    coverage: Option<&'a mut Coverage>,
+    cycle_costs: Option<&'a CycleCosts>,
+    cycle_limit: Option<u64>,
+    stack_tracking: bool,
+}
+
+impl<'a> UxnBuilder<'a> {
+    /// Sets the preferred evaluation backend
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets a flag used to interrupt evaluation
+    ///
+    /// See [`Uxn::set_interrupt`] for details
+    pub fn interrupt(mut self, flag: &'a AtomicBool) -> Self {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    /// Records executed addresses into the given coverage set
+    ///
+    /// See [`Uxn::set_coverage`] for details
+    pub fn coverage(mut self, coverage: &'a mut Coverage) -> Self {
+        self.coverage = Some(coverage);
+        self
+    }
+
+    /// Counts cycles using the given per-opcode costs
+    ///
+    /// See [`Uxn::set_cycle_costs`] for details
+    pub fn cycle_costs(mut self, costs: &'a CycleCosts) -> Self {
+        self.cycle_costs = Some(costs);
+        self
+    }
+
+    /// Stops evaluation once the cycle counter reaches the given limit
+    ///
+    /// See [`Uxn::set_cycle_limit`] for details
+    pub fn cycle_limit(mut self, limit: u64) -> Self {
+        self.cycle_limit = Some(limit);
+        self
+    }
+
+    /// Stops evaluation after the given number of instructions
+    ///
+    /// This is shorthand for counting cycles with [`UNIT_CYCLE_COSTS`] and
+    /// setting a [`cycle_limit`](Self::cycle_limit) of `n`.
+    pub fn fuel(self, n: u64) -> Self {
+        self.cycle_costs(&UNIT_CYCLE_COSTS).cycle_limit(n)
+    }
+
+    /// Enables tracking of the stacks' high-water marks
+    ///
+    /// See [`Uxn::set_stack_tracking`] for details
+    pub fn stack_tracking(mut self, enabled: bool) -> Self {
+        self.stack_tracking = enabled;
+        self
+    }
+
+    /// Builds the VM
+    pub fn build(self) -> Uxn<'a> {
+        let mut vm = Uxn::new(self.ram, self.backend);
+        vm.set_interrupt(self.interrupt);
+        vm.set_coverage(self.coverage);
+        vm.set_cycle_costs(self.cycle_costs);
+        vm.set_cycle_limit(self.cycle_limit);
+        vm.set_stack_tracking(self.stack_tracking);
+        vm
+    }
+}
+
+impl<'a> Uxn<'a> {
+    /// Returns a builder for a VM using the given RAM
+    pub fn builder(ram: &'a mut [u8; 65536]) -> UxnBuilder<'a> {
+        UxnBuilder {
+            ram,
+            backend: Backend::default(),
+            interrupt: None,
+            coverage: None,
+            cycle_costs: None,
+            cycle_limit: None,
+            stack_tracking: false,
+        }
+    }
+}
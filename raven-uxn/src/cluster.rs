@@ -0,0 +1,101 @@
+//! Hosting several VMs on a shared device bus
+extern crate alloc;
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{Device, Uxn};
+
+/// A single VM within a [`Cluster`], along with its pending vectors
+struct Machine<'a> {
+    vm: Uxn<'a>,
+    queue: VecDeque<u16>,
+}
+
+/// A group of [`Uxn`] instances which share a single [`Device`]
+///
+/// Work is scheduled by queueing vectors on individual machines; the cluster
+/// then runs one vector at a time, moving round-robin between machines which
+/// have pending work.  Each vector runs to completion before the next machine
+/// gets a turn, so the shared device sees a single VM at a time.
+///
+/// This is only available if the `"alloc"` feature is enabled
+#[derive(Default)]
+pub struct Cluster<'a> {
+    machines: Vec<Machine<'a>>,
+
+    /// Index of the next machine to consider when scheduling
+    next: usize,
+}
+
+impl<'a> Cluster<'a> {
+    /// Builds a new, empty cluster
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a VM to the cluster, returning its index
+    pub fn push(&mut self, vm: Uxn<'a>) -> usize {
+        self.machines.push(Machine {
+            vm,
+            queue: VecDeque::new(),
+        });
+        self.machines.len() - 1
+    }
+
+    /// Returns the number of VMs in the cluster
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    /// Checks whether the cluster contains any VMs
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// Borrows the VM at the given index
+    pub fn get(&self, i: usize) -> Option<&Uxn<'a>> {
+        self.machines.get(i).map(|m| &m.vm)
+    }
+
+    /// Mutably borrows the VM at the given index
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut Uxn<'a>> {
+        self.machines.get_mut(i).map(|m| &mut m.vm)
+    }
+
+    /// Queues a vector to be run on the VM at the given index
+    ///
+    /// # Panics
+    /// If the index is out of range
+    pub fn queue(&mut self, i: usize, vector: u16) {
+        self.machines[i].queue.push_back(vector);
+    }
+
+    /// Checks whether any VM has pending vectors
+    pub fn is_idle(&self) -> bool {
+        self.machines.iter().all(|m| m.queue.is_empty())
+    }
+
+    /// Runs a single pending vector, choosing VMs in round-robin order
+    ///
+    /// Returns a tuple of `(index, pc)`, where `pc` is the program counter at
+    /// which the vector terminated, or `None` if no work was pending.
+    pub fn step<D: Device>(&mut self, dev: &mut D) -> Option<(usize, u16)> {
+        let n = self.machines.len();
+        for j in 0..n {
+            let i = (self.next + j) % n;
+            let m = &mut self.machines[i];
+            if let Some(vector) = m.queue.pop_front() {
+                self.next = (i + 1) % n;
+                let pc = m.vm.run(dev, vector);
+                return Some((i, pc));
+            }
+        }
+        None
+    }
+
+    /// Runs pending vectors until every queue is empty
+    pub fn run<D: Device>(&mut self, dev: &mut D) {
+        while self.step(dev).is_some() {
+            // keep going
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Instruction coverage tracking
+use crate::Uxn;
+
+/// Number of 64-bit words in the bitset
+const WORDS: usize = 65536 / 64;
+
+/// Set of RAM addresses at which instructions were executed
+///
+/// Only the address of each opcode is recorded, not the addresses of its
+/// immediate operands (e.g. the value following a `LIT`).
+///
+/// Coverage is attached to a VM with [`Uxn::set_coverage`].  It's recorded by
+/// the interpreter, so any backend falls back to the interpreter while
+/// coverage is attached.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Coverage {
+    bits: [u64; WORDS],
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    /// Builds an empty coverage set
+    pub const fn new() -> Self {
+        Self { bits: [0; WORDS] }
+    }
+
+    /// Marks the given address as executed
+    #[inline]
+    pub fn insert(&mut self, addr: u16) {
+        let addr = usize::from(addr);
+        self.bits[addr / 64] |= 1 << (addr % 64);
+    }
+
+    /// Checks whether the given address was executed
+    #[inline]
+    pub fn contains(&self, addr: u16) -> bool {
+        let addr = usize::from(addr);
+        self.bits[addr / 64] & (1 << (addr % 64)) != 0
+    }
+
+    /// Returns the number of executed addresses
+    pub fn len(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Checks whether no addresses have been executed
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|b| *b == 0)
+    }
+
+    /// Clears all recorded addresses
+    pub fn clear(&mut self) {
+        self.bits = [0; WORDS];
+    }
+
+    /// Adds every address from another coverage set to this one
+    pub fn merge(&mut self, other: &Coverage) {
+        for (a, b) in self.bits.iter_mut().zip(&other.bits) {
+            *a |= b;
+        }
+    }
+
+    /// Returns the raw bitset
+    ///
+    /// Address `n` is stored in bit `n % 64` of word `n / 64`.
+    pub fn as_bits(&self) -> &[u64; WORDS] {
+        &self.bits
+    }
+
+    /// Iterates over executed addresses, in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX).filter(|a| self.contains(*a))
+    }
+}
+
+impl core::fmt::Debug for Coverage {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("Coverage")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<'a> Uxn<'a> {
+    /// Attaches (or detaches) a coverage set
+    ///
+    /// While attached, [`run`](Self::run) records the address of every
+    /// instruction executed.  Coverage is only tracked by the interpreter, so
+    /// every backend falls back to the interpreter while a set is attached.
+    pub fn set_coverage(&mut self, coverage: Option<&'a mut Coverage>) {
+        self.coverage = coverage;
+    }
+
+    /// Returns the attached coverage set, if present
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_deref()
+    }
+
+    /// Returns a mutable reference to the attached coverage set, if present
+    pub fn coverage_mut(&mut self) -> Option<&mut Coverage> {
+        self.coverage.as_deref_mut()
+    }
+}
@@ -0,0 +1,50 @@
+//! Cycle accounting, for emulating a machine with a fixed speed
+use crate::Uxn;
+
+/// Table of per-opcode cycle costs, indexed by opcode
+pub type CycleCosts = [u16; 256];
+
+/// Cost table which charges one cycle for every opcode
+pub const UNIT_CYCLE_COSTS: CycleCosts = [1; 256];
+
+impl<'a> Uxn<'a> {
+    /// Sets (or clears) the table of per-opcode cycle costs
+    ///
+    /// While a table is set, [`run`](Self::run) adds the cost of each executed
+    /// opcode to the [`cycles`](Self::cycles) counter.  Like coverage, this is
+    /// only tracked by the interpreter, so every backend falls back to the
+    /// interpreter while a table is set.
+    pub fn set_cycle_costs(&mut self, costs: Option<&'a CycleCosts>) {
+        self.cycle_costs = costs;
+    }
+
+    /// Sets (or clears) a limit on the cycle counter
+    ///
+    /// When the counter reaches the limit, [`run`](Self::run) returns early
+    /// with the address of the next instruction to execute, in the same way
+    /// as an [interrupt](Self::set_interrupt).  The host can resume by raising
+    /// the limit (or resetting the counter) and calling `run` again with that
+    /// address.  The limit has no effect unless cycle costs are set.
+    pub fn set_cycle_limit(&mut self, limit: Option<u64>) {
+        self.cycle_limit = limit;
+    }
+
+    /// Returns the number of cycles executed since the counter (or VM) was
+    /// reset
+    #[inline]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Resets the cycle counter to zero, returning its previous value
+    pub fn reset_cycles(&mut self) -> u64 {
+        core::mem::take(&mut self.cycles)
+    }
+
+    /// Checks whether the cycle counter has reached its limit
+    #[inline]
+    pub fn is_out_of_cycles(&self) -> bool {
+        self.cycle_costs.is_some()
+            && self.cycle_limit.is_some_and(|n| self.cycles >= n)
+    }
+}
@@ -0,0 +1,67 @@
+//! Reasons for execution to stop
+
+/// Reason why the VM stopped running, returned by [`Uxn::run_halt`]
+///
+/// Every variant includes the final program counter (i.e. the address after
+/// the last instruction executed).  Every backend reports the same variants
+/// for the same program, although the native backend only notices an
+/// interrupt after a `DEI` or `DEO` (see [`Uxn::set_interrupt`]).
+///
+/// More reasons may be added in future releases.
+///
+/// [`Uxn::run_halt`]: crate::Uxn::run_halt
+/// [`Uxn::set_interrupt`]: crate::Uxn::set_interrupt
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Halt {
+    /// The program executed a `BRK` instruction
+    Break {
+        /// Program counter after the `BRK`
+        pc: u16,
+    },
+
+    /// A device requested that execution stop (by returning `false` from
+    /// [`Device::deo`](crate::Device::deo))
+    Exit {
+        /// Program counter after the `DEO` instruction
+        pc: u16,
+        /// Exit code reported by [`Device::exit_code`](crate::Device::exit_code)
+        code: Option<i32>,
+    },
+
+    /// The interrupt flag was set (see [`Uxn::set_interrupt`])
+    ///
+    /// [`Uxn::set_interrupt`]: crate::Uxn::set_interrupt
+    Interrupted {
+        /// Program counter of the next instruction to execute
+        pc: u16,
+    },
+
+    /// The cycle limit was reached (see [`Uxn::set_cycle_limit`])
+    ///
+    /// [`Uxn::set_cycle_limit`]: crate::Uxn::set_cycle_limit
+    OutOfCycles {
+        /// Program counter of the next instruction to execute
+        pc: u16,
+    },
+}
+
+impl Halt {
+    /// Returns the final program counter
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Halt::Break { pc }
+            | Halt::Exit { pc, .. }
+            | Halt::Interrupted { pc }
+            | Halt::OutOfCycles { pc } => pc,
+        }
+    }
+
+    /// Checks whether execution can be resumed from [`Halt::pc`]
+    ///
+    /// This is `true` for [`Halt::Interrupted`] and [`Halt::OutOfCycles`],
+    /// which stop between instructions.
+    pub fn is_resumable(&self) -> bool {
+        matches!(self, Halt::Interrupted { .. } | Halt::OutOfCycles { .. })
+    }
+}
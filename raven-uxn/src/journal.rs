@@ -0,0 +1,208 @@
+//! Reversible execution, for stepping backwards in a debugger
+extern crate alloc;
+use alloc::{boxed::Box, collections::VecDeque};
+
+use crate::{op, Device, Stack, Uxn};
+
+/// Number of stack bytes saved around the stack index
+///
+/// A single instruction touches at most 6 bytes below and 6 bytes above the
+/// index (e.g. `ROT2` and `ROT2k`), so this is comfortably large enough.
+const WINDOW: usize = 16;
+
+/// Stack bytes which may be modified by a single instruction
+struct StackDelta {
+    index: u8,
+    high_water: u8,
+    data: [u8; WINDOW],
+}
+
+impl StackDelta {
+    /// First saved slot, relative to the index
+    const OFFSET: u8 = (WINDOW / 2 - 1) as u8;
+
+    fn save(s: &Stack) -> Self {
+        let base = s.index.wrapping_sub(Self::OFFSET);
+        let mut data = [0u8; WINDOW];
+        for (i, d) in data.iter_mut().enumerate() {
+            *d = s.data[usize::from(base.wrapping_add(i as u8))];
+        }
+        Self {
+            index: s.index,
+            high_water: s.high_water,
+            data,
+        }
+    }
+
+    fn restore(&self, s: &mut Stack) {
+        let base = self.index.wrapping_sub(Self::OFFSET);
+        for (i, &d) in self.data.iter().enumerate() {
+            s.data[usize::from(base.wrapping_add(i as u8))] = d;
+        }
+        s.index = self.index;
+        s.high_water = self.high_water;
+    }
+}
+
+/// Undo record for a single instruction
+struct Step {
+    /// Address of the instruction
+    pc: u16,
+    stack: StackDelta,
+    ret: StackDelta,
+    /// Address and previous value of RAM written by a store
+    ram: Option<(u16, [u8; 2])>,
+    /// Previous device memory, for `DEI` and `DEO`
+    dev: Option<Box<[u8; 256]>>,
+}
+
+/// Bounded history of executed instructions, which can be undone
+///
+/// Instructions are executed with [`Journal::step`] (or
+/// [`Journal::run_until`]), which records the RAM, device memory, and stack
+/// bytes that each instruction may modify before running it.
+/// [`Journal::step_back`] then restores the most recent record, so a debugger
+/// can walk backwards from a breakpoint, e.g. to find the instruction which
+/// corrupted a stack.
+///
+/// Only the most recent [`capacity`](Journal::capacity) instructions are kept;
+/// older records are discarded as new ones are added.  Each record takes
+/// about 60 bytes, plus 256 bytes for instructions which access a device.
+///
+/// Only the VM's state is journaled.  Side effects inside the [`Device`] (for
+/// example, characters written to a console, or RAM written by the device in
+/// response to a `DEO`) are not undone.
+///
+/// Like [`Uxn::trace_iter`], this always uses the interpreter, and ignores
+/// the VM's interrupt flag, coverage, and cycle limit.
+pub struct Journal {
+    steps: VecDeque<Step>,
+    capacity: usize,
+}
+
+impl Journal {
+    /// Builds an empty journal which keeps up to `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            steps: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Returns the maximum number of instructions recorded
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of instructions which can currently be undone
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Checks whether there are no instructions to undo
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Discards all records
+    ///
+    /// This should be called if the VM is modified outside of the journal
+    /// (e.g. by a reset or by running a vector normally), since older records
+    /// would no longer apply.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// Returns the addresses of recorded instructions, oldest first
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = u16> + '_ {
+        self.steps.iter().map(|s| s.pc)
+    }
+
+    /// Executes and records a single instruction at `pc`
+    ///
+    /// Returns the address of the next instruction, or `None` if this
+    /// instruction ended the vector (in which case it can still be undone).
+    pub fn step<D: Device>(
+        &mut self,
+        vm: &mut Uxn,
+        dev: &mut D,
+        pc: u16,
+    ) -> Option<u16> {
+        if self.capacity > 0 {
+            if self.steps.len() == self.capacity {
+                self.steps.pop_front();
+            }
+            self.steps.push_back(Self::record(vm, pc));
+        }
+        let mut next = pc;
+        let op = vm.next(&mut next);
+        vm.dispatch(op, dev, next)
+    }
+
+    /// Executes instructions until reaching a breakpoint
+    ///
+    /// The instruction at `pc` is always executed, so this can be used to
+    /// resume from a breakpoint.  After that, execution stops before any
+    /// instruction for which `breakpoint(vm, addr)` returns `true`.
+    ///
+    /// Returns the address of the breakpoint, or `None` if the vector ended.
+    pub fn run_until<D: Device, F: Fn(&Uxn, u16) -> bool>(
+        &mut self,
+        vm: &mut Uxn,
+        dev: &mut D,
+        mut pc: u16,
+        breakpoint: F,
+    ) -> Option<u16> {
+        loop {
+            pc = self.step(vm, dev, pc)?;
+            if breakpoint(vm, pc) {
+                return Some(pc);
+            }
+        }
+    }
+
+    /// Undoes the most recent instruction
+    ///
+    /// Returns its address (i.e. the program counter from which execution
+    /// should resume), or `None` if the journal is empty.
+    pub fn step_back(&mut self, vm: &mut Uxn) -> Option<u16> {
+        let s = self.steps.pop_back()?;
+        s.stack.restore(&mut vm.stack);
+        s.ret.restore(&mut vm.ret);
+        if let Some((addr, [hi, lo])) = s.ram {
+            vm.ram[usize::from(addr)] = hi;
+            vm.ram[usize::from(addr.wrapping_add(1))] = lo;
+        }
+        if let Some(dev) = s.dev {
+            vm.dev = *dev;
+        }
+        Some(s.pc)
+    }
+
+    /// Saves the state which may be modified by the instruction at `pc`
+    fn record(vm: &Uxn, pc: u16) -> Step {
+        let op = vm.ram[usize::from(pc)];
+        let mut dev = None;
+        let addr = match op & 0x1f {
+            op::STZ | op::STR | op::STA => Some(vm.store_target(op, pc)),
+            op::DEI | op::DEO => {
+                dev = Some(Box::new(vm.dev));
+                None
+            }
+            _ => None,
+        };
+        let ram = addr.map(|a| {
+            let hi = vm.ram[usize::from(a)];
+            let lo = vm.ram[usize::from(a.wrapping_add(1))];
+            (a, [hi, lo])
+        });
+
+        Step {
+            pc,
+            stack: StackDelta::save(&vm.stack),
+            ret: StackDelta::save(&vm.ret),
+            ram,
+            dev,
+        }
+    }
+}
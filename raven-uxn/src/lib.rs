@@ -3,9 +3,17 @@
 #![warn(missing_docs)]
 #![cfg_attr(not(any(test, feature = "native")), forbid(unsafe_code))]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[cfg(feature = "native")]
 mod native;
 
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 const fn keep(flags: u8) -> bool {
     (flags & (1 << 2)) != 0
 }
@@ -20,7 +28,25 @@ const fn ret(flags: u8) -> bool {
 pub const DEV_SIZE: usize = 16;
 
 /// Simple circular stack, with room for 256 items
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+///
+/// The stack never overflows or underflows; instead, it wraps around.  In
+/// detail, the following semantics are guaranteed (and relied upon by devices
+/// and the native backend):
+///
+/// - The stack is a 256-byte ring buffer with a `u8` length; every operation
+///   changes the length with wrapping arithmetic.
+/// - Pushing a byte writes it into slot `len` and increments the length;
+///   pushing 256 bytes onto an empty stack leaves it empty (length 0), with
+///   every slot overwritten.
+/// - Popping a byte decrements the length and returns the byte at the new
+///   length; popping from an empty stack returns the byte in slot 255 and
+///   leaves the stack with length 255.
+/// - [`peek_byte_at(i)`](Stack::peek_byte_at) returns the byte that would be
+///   returned by the `i + 1`'th pop, without modifying the stack.
+/// - Shorts are pushed high byte first, so the low byte is on top.
+/// - [`set_len`](Stack::set_len) changes the length without touching data, so
+///   growing the stack exposes whatever bytes were previously in those slots.
+#[derive(Copy, Clone)]
 pub struct Stack {
     data: [u8; 256],
 
@@ -28,17 +54,61 @@ pub struct Stack {
     ///
     /// If the buffer is empty or full, it points to `u8::MAX`.
     index: u8,
+
+    /// Maximum length reached, returned by [`Stack::high_water`]
+    high_water: u8,
 }
 
 /// Uxn evaluation backend
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum Backend {
     /// Use a bytecode interpreter
     Interpreter,
 
-    #[cfg(feature = "native")]
     /// Use hand-written threaded assembly
+    ///
+    /// This is only available on `aarch64` with the `"native"` feature
+    /// enabled; otherwise, the interpreter is used instead.
     Native,
+
+    /// Compile the ROM into WebAssembly, run by the host's engine
+    ///
+    /// This is only available on `wasm32` with the `"wasm"` feature enabled;
+    /// otherwise, the interpreter is used instead.  See the [`wasm`] module
+    /// for details.
+    Wasm,
+}
+
+impl Default for Backend {
+    /// Returns [`Backend::Interpreter`], which is available in every build
+    fn default() -> Self {
+        Self::Interpreter
+    }
+}
+
+impl Backend {
+    /// Picks the fastest backend which is available in this build
+    pub const fn auto() -> Self {
+        if Self::Native.is_available() {
+            Self::Native
+        } else if Self::Wasm.is_available() {
+            Self::Wasm
+        } else {
+            Self::Interpreter
+        }
+    }
+
+    /// Checks whether this backend is available in this build
+    pub const fn is_available(&self) -> bool {
+        match self {
+            Self::Interpreter => true,
+            Self::Native => cfg!(feature = "native"),
+            Self::Wasm => {
+                cfg!(all(feature = "wasm", target_arch = "wasm32"))
+            }
+        }
+    }
 }
 
 /// Virtual stack, which is aware of `keep` and `short` modes
@@ -123,11 +193,22 @@ impl<'a, const FLAGS: u8> StackView<'a, FLAGS> {
     }
 }
 
+/// Stacks are equal if their contents and lengths are equal; the high-water
+/// mark is not compared.
+impl PartialEq for Stack {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data && self.index == other.index
+    }
+}
+
+impl Eq for Stack {}
+
 impl Default for Stack {
     fn default() -> Self {
         Self {
             data: [0u8; 256],
             index: u8::MAX,
+            high_water: 0,
         }
     }
 }
@@ -254,14 +335,117 @@ impl Stack {
     pub fn set_len(&mut self, n: u8) {
         self.index = n.wrapping_sub(1);
     }
+
+    /// Returns the maximum length that the stack has reached
+    ///
+    /// This shows how close a program came to filling the stack.  It's only
+    /// updated while stack tracking is enabled (see
+    /// [`Uxn::set_stack_tracking`]), so that other programs don't pay for it.
+    /// A stack which wrapped around after 256 bytes reports 255.
+    #[inline]
+    pub fn high_water(&self) -> u8 {
+        self.high_water
+    }
+
+    /// Raises the high-water mark to the current length, if needed
+    #[inline]
+    fn update_high_water(&mut self) {
+        self.high_water = self.high_water.max(self.len());
+    }
+
+    /// Resets the high-water mark to the current length
+    #[inline]
+    pub fn reset_high_water(&mut self) {
+        self.high_water = self.len();
+    }
+
+    /// Returns the contents of the stack, from bottom to top
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..usize::from(self.len())]
+    }
+
+    /// Iterates over the contents of the stack, from bottom to top
+    ///
+    /// Use `.rev()` to iterate from the top of the stack instead.
+    #[inline]
+    pub fn iter(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = u8> + ExactSizeIterator + '_ {
+        self.as_slice().iter().copied()
+    }
+}
+
+/// Prints the stack in the style of the reference emulator, e.g. `12 34 56|`
+impl core::fmt::Display for Stack {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, b) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{b:02x}")?;
+        }
+        write!(f, "|")
+    }
+}
+
+impl core::fmt::Debug for Stack {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Stack({self})")
+    }
+}
+
+/// VM memory, which is either borrowed or owned by the [`Uxn`]
+///
+/// Both variants are a single pointer to the array, so the `match` in the
+/// accessors below compiles to one load in the interpreter's hot loop.
+enum Ram<'a> {
+    Borrowed(&'a mut [u8; 65536]),
+    #[cfg(feature = "alloc")]
+    Owned(UxnRam),
+}
+
+impl core::ops::Deref for Ram<'_> {
+    type Target = [u8; 65536];
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Ram::Borrowed(r) => r,
+            #[cfg(feature = "alloc")]
+            Ram::Owned(r) => r,
+        }
+    }
+}
+
+impl core::ops::DerefMut for Ram<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Ram::Borrowed(r) => r,
+            #[cfg(feature = "alloc")]
+            Ram::Owned(r) => r,
+        }
+    }
 }
 
 /// The virtual machine itself
+///
+/// # Thread safety
+/// `Uxn` is both [`Send`] and [`Sync`]: it only holds plain data and borrows
+/// (RAM, coverage, cycle costs, and an [`AtomicBool`] interrupt flag), so it
+/// may be moved to a worker thread along with its RAM.  With borrowed RAM,
+/// this means running it in a scoped thread ([`std::thread::scope`]); a VM
+/// built with [`Uxn::new_owned`] can be moved anywhere.  (The exception is
+/// a `wasm32` build with the `"wasm"` feature, where the VM holds a
+/// JavaScript handle to its compiled module and is neither `Send` nor
+/// `Sync`.)
+///
+/// [`std::thread::scope`]: https://doc.rust-lang.org/std/thread/fn.scope.html
 pub struct Uxn<'a> {
     /// Device memory
     dev: [u8; 256],
     /// 64 KiB of VM memory
-    ram: &'a mut [u8; 65536],
+    ram: Ram<'a>,
     /// 256-byte data stack
     stack: Stack,
     /// 256-byte return stack
@@ -269,6 +453,31 @@ pub struct Uxn<'a> {
 
     /// Preferred evaluation backend
     backend: Backend,
+
+    /// Flag which, when set, causes evaluation to stop early
+    interrupt: Option<&'a AtomicBool>,
+
+    /// Set of executed addresses, recorded by the interpreter
+    coverage: Option<&'a mut Coverage>,
+
+    /// Per-opcode costs, used by the interpreter to update `cycles`
+    cycle_costs: Option<&'a CycleCosts>,
+
+    /// Whether the interpreter updates the stacks' high-water marks
+    track_stacks: bool,
+
+    /// Limit on `cycles`, after which evaluation stops early
+    cycle_limit: Option<u64>,
+
+    /// Number of cycles executed
+    cycles: u64,
+
+    /// Bitfield of device pages which are mapped, one bit per page
+    mapped_pages: u16,
+
+    /// Compiled module for the current ROM, used by [`Backend::Wasm`]
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    wasm: Option<wasm::Runtime>,
 }
 
 macro_rules! op_cmp {
@@ -308,16 +517,70 @@ macro_rules! op_bin {
 
 impl<'a> Uxn<'a> {
     /// Build a new `Uxn` with zeroed memory
+    ///
+    /// Every other option starts at its default, so this is equivalent to
+    /// `Uxn::builder(ram).backend(backend).build()` (see [`Uxn::builder`]).
+    /// See [`Uxn::new_owned`] to build a VM which owns its RAM.
     pub fn new(ram: &'a mut [u8; 65536], backend: Backend) -> Self {
+        Self::with_ram(Ram::Borrowed(ram), backend)
+    }
+
+    fn with_ram(ram: Ram<'a>, backend: Backend) -> Self {
         Self {
             dev: [0u8; 256],
             ram,
             stack: Stack::default(),
             ret: Stack::default(),
             backend,
+            interrupt: None,
+            coverage: None,
+            cycle_costs: None,
+            track_stacks: false,
+            cycle_limit: None,
+            cycles: 0,
+            mapped_pages: 0,
+            #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+            wasm: None,
         }
     }
 
+    /// Returns the preferred evaluation backend
+    #[inline]
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Sets (or clears) a flag used to interrupt evaluation
+    ///
+    /// When the flag is set (e.g. from another thread), [`run`](Self::run)
+    /// returns early with the address of the next instruction to execute; the
+    /// host can resume by calling `run` again with that address, after
+    /// clearing the flag.
+    ///
+    /// The interpreter checks the flag between every instruction.  The native
+    /// backend only checks it after `DEI` and `DEO` operations (stopping after
+    /// the device call), so a tight loop which never touches a device cannot
+    /// be interrupted.
+    pub fn set_interrupt(&mut self, flag: Option<&'a AtomicBool>) {
+        self.interrupt = flag;
+    }
+
+    /// Enables or disables tracking of the stacks' high-water marks
+    ///
+    /// While enabled, the interpreter updates [`Stack::high_water`] after
+    /// every instruction.  This is off by default, since it slows down
+    /// evaluation; like other instrumentation, it makes every backend fall
+    /// back to the interpreter.
+    pub fn set_stack_tracking(&mut self, enabled: bool) {
+        self.track_stacks = enabled;
+    }
+
+    /// Checks whether the interrupt flag is set
+    #[inline]
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupt.is_some_and(|f| f.load(Ordering::Relaxed))
+    }
+
     /// Reads a byte from RAM at the program counter
     #[inline]
     fn next(&mut self, pc: &mut u16) -> u8 {
@@ -334,6 +597,29 @@ impl<'a> Uxn<'a> {
         u16::from_le_bytes([lo, hi])
     }
 
+    /// Returns the first address written by a store instruction at `pc`
+    ///
+    /// `op` must be a `STZ`, `STR`, or `STA` opcode (in any mode); the address
+    /// is read from the stack without modifying it.  Short stores also write
+    /// the following address.
+    #[cfg(feature = "alloc")]
+    fn store_target(&self, op: u8, pc: u16) -> u16 {
+        let stack = if op & 0x40 != 0 {
+            &self.ret
+        } else {
+            &self.stack
+        };
+        match op & 0x1f {
+            op::STZ => u16::from(stack.peek_byte_at(0)),
+            op::STR => {
+                let offset = stack.peek_byte_at(0) as i8;
+                pc.wrapping_add(1).wrapping_add_signed(offset.into())
+            }
+            op::STA => stack.peek_short_at(0),
+            _ => unreachable!("not a store opcode: {op:#04x}"),
+        }
+    }
+
     #[inline]
     fn ram_write(&mut self, addr: u16, v: Value) {
         match v {
@@ -361,7 +647,7 @@ impl<'a> Uxn<'a> {
     }
 
     #[inline]
-    fn stack_view<const FLAGS: u8>(&mut self) -> StackView<FLAGS> {
+    fn stack_view<const FLAGS: u8>(&mut self) -> StackView<'_, FLAGS> {
         let stack = if ret(FLAGS) {
             &mut self.ret
         } else {
@@ -371,7 +657,7 @@ impl<'a> Uxn<'a> {
     }
 
     #[inline]
-    fn ret_stack_view<const FLAGS: u8>(&mut self) -> StackView<FLAGS> {
+    fn ret_stack_view<const FLAGS: u8>(&mut self) -> StackView<'_, FLAGS> {
         let stack = if ret(FLAGS) {
             &mut self.stack
         } else {
@@ -397,21 +683,150 @@ impl<'a> Uxn<'a> {
     }
 
     /// Runs the VM starting at the given address until it terminates
+    ///
+    /// Returns the final program counter; use [`run_halt`](Self::run_halt) to
+    /// also find out why execution stopped.
     #[inline]
-    pub fn run<D: Device>(&mut self, dev: &mut D, mut pc: u16) -> u16 {
-        match self.backend {
-            Backend::Interpreter => loop {
+    pub fn run<D: Device>(&mut self, dev: &mut D, pc: u16) -> u16 {
+        self.run_halt(dev, pc).pc()
+    }
+
+    /// Runs the VM starting at the given address, returning why it stopped
+    #[inline]
+    pub fn run_halt<D: Device>(&mut self, dev: &mut D, mut pc: u16) -> Halt {
+        // Instrumentation is only implemented by the interpreter, so every
+        // backend falls back to it (and reports the same `Halt`) when needed
+        #[cfg(feature = "native")]
+        if self.backend == Backend::Native && !self.is_instrumented() {
+            return native::entry(self, dev, pc);
+        }
+        #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+        if self.interrupt.is_none() && !self.is_instrumented() {
+            if let Some(w) = self.wasm.take() {
+                let out = self.run_wasm(&w, dev, pc);
+                self.wasm = Some(w);
+                return out;
+            }
+        }
+        if self.interrupt.is_some() || self.is_instrumented() {
+            loop {
+                if self.is_interrupted() {
+                    break Halt::Interrupted { pc };
+                } else if self.is_out_of_cycles() {
+                    break Halt::OutOfCycles { pc };
+                }
+                if let Some(c) = self.coverage.as_deref_mut() {
+                    c.insert(pc);
+                }
                 let op = self.next(&mut pc);
-                let Some(next) = self.op(op, dev, pc) else {
-                    break pc;
+                if let Some(c) = self.cycle_costs {
+                    self.cycles += u64::from(c[usize::from(op)]);
+                }
+                let next = self.dispatch(op, dev, pc);
+                // Each instruction pops before it pushes, so the stacks are
+                // longest at the end of an instruction
+                if self.track_stacks {
+                    self.stack.update_high_water();
+                    self.ret.update_high_water();
+                }
+                let Some(next) = next else {
+                    break self.halt_after(op, dev, pc);
+                };
+                pc = next;
+            }
+        } else {
+            loop {
+                let op = self.next(&mut pc);
+                let Some(next) = self.dispatch(op, dev, pc) else {
+                    break self.halt_after(op, dev, pc);
                 };
                 pc = next;
-            },
-            #[cfg(feature = "native")]
-            Backend::Native => native::entry(self, dev, pc),
+            }
+        }
+    }
+
+    /// Checks whether coverage, cycle counting, or stack tracking is enabled
+    #[inline]
+    fn is_instrumented(&self) -> bool {
+        self.coverage.is_some()
+            || self.cycle_costs.is_some()
+            || self.track_stacks
+    }
+
+    /// Runs compiled WebAssembly, falling back to the interpreter as needed
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    fn run_wasm<D: Device>(
+        &mut self,
+        w: &wasm::Runtime,
+        dev: &mut D,
+        mut pc: u16,
+    ) -> Halt {
+        loop {
+            let status = w.call(
+                self.ram.as_mut_ptr() as usize,
+                (
+                    self.stack.data.as_mut_ptr() as usize,
+                    &mut self.stack.index as *mut u8 as usize,
+                ),
+                (
+                    self.ret.data.as_mut_ptr() as usize,
+                    &mut self.ret.index as *mut u8 as usize,
+                ),
+                pc,
+            );
+            pc = status as u16;
+            if status & wasm::FALLBACK == 0 {
+                break Halt::Break { pc };
+            }
+            let op = self.next(&mut pc);
+            let Some(next) = self.dispatch(op, dev, pc) else {
+                break self.halt_after(op, dev, pc);
+            };
+            pc = next;
+        }
+    }
+
+    /// Builds a [`Halt`] for an instruction which stopped execution
+    ///
+    /// The only instructions which stop execution are `BRK` and a `DEO` for
+    /// which the device returned `false`.
+    #[cold]
+    fn halt_after<D: Device>(&self, op: u8, dev: &D, pc: u16) -> Halt {
+        if op == op::BRK {
+            Halt::Break { pc }
+        } else {
+            Halt::Exit {
+                pc,
+                code: dev.exit_code(),
+            }
         }
     }
 
+    /// Runs a vector to completion from within a device handler
+    ///
+    /// This may be called from [`Device::dei`] or [`Device::deo`] (passing
+    /// the device itself as `dev`), with either backend, to run a vector
+    /// synchronously; the instruction which triggered the device handler then
+    /// continues once the vector has finished.
+    ///
+    /// Both stacks are saved before running the vector and restored afterwards,
+    /// so the interrupted code sees no change to its stacks; the vector should
+    /// communicate its results through RAM or device memory.  Calls may be
+    /// nested, limited only by the host's own call stack.
+    ///
+    /// Returns the final program counter, as with [`run`](Self::run).
+    pub fn call_vector<D: Device>(&mut self, dev: &mut D, vector: u16) -> u16 {
+        let stack = self.stack;
+        let ret = self.ret;
+        let pc = self.run(dev, vector);
+        // Keep the high-water marks reached while running the vector
+        let hw = (self.stack.high_water, self.ret.high_water);
+        self.stack = stack;
+        self.ret = ret;
+        (self.stack.high_water, self.ret.high_water) = hw;
+        pc
+    }
+
     /// Runs until the program terminates or we hit a stop condition
     ///
     /// Returns the new program counter if the program terminated, or `None` if
@@ -428,7 +843,7 @@ impl<'a> Uxn<'a> {
     ) -> Option<u16> {
         for i in 0.. {
             let op = self.next(&mut pc);
-            let Some(next) = self.op(op, dev, pc) else {
+            let Some(next) = self.dispatch(op, dev, pc) else {
                 return Some(pc);
             };
             pc = next;
@@ -439,6 +854,28 @@ impl<'a> Uxn<'a> {
         unreachable!()
     }
 
+    /// Returns an iterator which executes one instruction per step
+    ///
+    /// Each step yields a tuple of `(pc, op)`, where `pc` is the address of the
+    /// instruction and `op` is the opcode that was just executed.  Iteration
+    /// ends after the program terminates; [`Trace::pc`] then returns the final
+    /// program counter (matching the value returned by [`run`](Self::run)).
+    ///
+    /// This function always uses the interpreter, ignoring
+    /// [`self.backend`](Self::backend).
+    pub fn trace_iter<'t, D: Device>(
+        &'t mut self,
+        dev: &'t mut D,
+        pc: u16,
+    ) -> Trace<'t, 'a, D> {
+        Trace {
+            vm: self,
+            dev,
+            pc,
+            done: false,
+        }
+    }
+
     /// Converts raw ports memory into a [`Ports`] object
     #[inline]
     pub fn dev<D: Ports>(&self) -> &D {
@@ -477,6 +914,55 @@ impl<'a> Uxn<'a> {
         self.ram[usize::from(addr)] = v;
     }
 
+    /// Shared borrow of the entire RAM
+    #[inline]
+    pub fn ram(&self) -> &[u8; 65536] {
+        &self.ram
+    }
+
+    /// Mutable borrow of the entire RAM
+    #[inline]
+    pub fn ram_mut(&mut self) -> &mut [u8; 65536] {
+        &mut self.ram
+    }
+
+    /// Borrows `len` bytes of RAM starting at `addr`, wrapping at the top
+    ///
+    /// Returns a `(head, tail)` tuple, where `tail` is the (possibly empty)
+    /// region starting at address 0; or `None` if `len` is larger than RAM.
+    #[inline]
+    pub fn ram_slice(&self, addr: u16, len: usize) -> Option<(&[u8], &[u8])> {
+        let (head, tail) = Self::split_range(addr, len)?;
+        let (lo, hi) = self.ram.split_at(head.start);
+        Some((&hi[..head.len()], &lo[..tail]))
+    }
+
+    /// Mutably borrows `len` bytes of RAM starting at `addr`
+    ///
+    /// See [`ram_slice`](Self::ram_slice) for details on wrapping.
+    #[inline]
+    pub fn ram_slice_mut(
+        &mut self,
+        addr: u16,
+        len: usize,
+    ) -> Option<(&mut [u8], &mut [u8])> {
+        let (head, tail) = Self::split_range(addr, len)?;
+        let (lo, hi) = self.ram.split_at_mut(head.start);
+        Some((&mut hi[..head.len()], &mut lo[..tail]))
+    }
+
+    /// Splits a wrapping range into a head range and tail length
+    #[inline]
+    fn split_range(addr: u16, len: usize) -> Option<(Range<usize>, usize)> {
+        const SIZE: usize = 65536;
+        if len > SIZE {
+            return None;
+        }
+        let start = usize::from(addr);
+        let end = (start + len).min(SIZE);
+        Some((start..end, len - (end - start)))
+    }
+
     /// Shared borrow of the working stack
     #[inline]
     pub fn stack(&self) -> &Stack {
@@ -511,8 +997,16 @@ impl<'a> Uxn<'a> {
         self.ram.fill(0);
         self.stack = Stack::default();
         self.ret = Stack::default();
+        self.cycles = 0;
         let n = (self.ram.len() - 0x100).min(rom.len());
         self.ram[0x100..][..n].copy_from_slice(&rom[..n]);
+        #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+        {
+            self.wasm = (self.backend == Backend::Wasm
+                && self.backend.is_available())
+            .then(|| wasm::Runtime::new(&wasm::compile(&rom[..n])))
+            .flatten();
+        }
         &rom[n..]
     }
 
@@ -529,8 +1023,37 @@ impl<'a> Uxn<'a> {
     }
 
     /// Executes a single operation
+    ///
+    /// `pc` is the address immediately after the opcode byte; the return value
+    /// is the address of the next instruction, or `None` if the opcode ended
+    /// evaluation of the current vector.
+    ///
+    /// This is the decoder used by the interpreter, and is exposed (along with
+    /// the per-opcode functions, e.g. [`inc`](Self::inc)) so that other crates
+    /// can build their own evaluation loops, e.g. to fuse common instruction
+    /// sequences, without duplicating the opcode table.
+    ///
+    /// # Stability
+    /// The per-opcode functions follow a fixed calling convention, which is
+    /// considered part of the crate's public API and will only change in a
+    /// semver-breaking release:
+    ///
+    /// - Each function is named after its opcode's mnemonic, in lowercase
+    /// - Opcodes with modes take a `const FLAGS: u8` parameter, which is the
+    ///   opcode's mode bits (i.e. `op >> 5`)
+    /// - Each function takes the program counter (pointing after the opcode
+    ///   byte) and returns the next program counter, or `None` to halt
+    /// - [`dei`](Self::dei) and [`deo`](Self::deo) also take the [`Device`]
+    ///
+    /// Calling the per-opcode function for a given opcode is guaranteed to be
+    /// equivalent to calling `dispatch` with that opcode.
     #[inline]
-    fn op<D: Device>(&mut self, op: u8, dev: &mut D, pc: u16) -> Option<u16> {
+    pub fn dispatch<D: Device>(
+        &mut self,
+        op: u8,
+        dev: &mut D,
+        pc: u16,
+    ) -> Option<u16> {
         match op {
             op::BRK => self.brk(pc),
             op::INC => self.inc::<0b000>(pc),
@@ -1225,6 +1748,11 @@ impl<'a> Uxn<'a> {
     /// ```text
     /// |00 @cell $2 |0100 .cell LDZ ( 00 )
     /// ```
+    ///
+    /// In short mode, the second byte is read from the address after `addr8`
+    /// _without_ wrapping within the zero page, so `#ff LDZ2` reads from `0xff`
+    /// and `0x100`.  This matches the reference implementation, which reads
+    /// both bytes relative to a pointer into RAM.
     #[inline]
     pub fn ldz<const FLAGS: u8>(&mut self, pc: u16) -> Option<u16> {
         let addr = self.stack_view::<FLAGS>().pop_byte();
@@ -1243,6 +1771,9 @@ impl<'a> Uxn<'a> {
     /// ```text
     /// |00 @cell $2 |0100 #abcd .cell STZ2  { ab cd }
     /// ```
+    ///
+    /// As with [`LDZ`](Self::ldz), a short written to `0xff` continues into
+    /// `0x100`, rather than wrapping within the zero page.
     #[inline]
     pub fn stz<const FLAGS: u8>(&mut self, pc: u16) -> Option<u16> {
         let mut s = self.stack_view::<FLAGS>();
@@ -1306,6 +1837,9 @@ impl<'a> Uxn<'a> {
     /// ```text
     /// ;cell LDA BRK @cell abcd ( ab )
     /// ```
+    ///
+    /// In short mode, a read from `0xffff` wraps around to `0x0000` for the
+    /// second byte.
     #[inline]
     pub fn lda<const FLAGS: u8>(&mut self, pc: u16) -> Option<u16> {
         let addr = self.stack_view::<FLAGS>().pop_short();
@@ -1325,6 +1859,9 @@ impl<'a> Uxn<'a> {
     /// ```text
     /// #abcd ;cell STA BRK @cell $1 ( ab )
     /// ```
+    ///
+    /// In short mode, a write to `0xffff` wraps around to `0x0000` for the
+    /// second byte.
     #[inline]
     pub fn sta<const FLAGS: u8>(&mut self, pc: u16) -> Option<u16> {
         let mut s = self.stack_view::<FLAGS>();
@@ -1358,11 +1895,9 @@ impl<'a> Uxn<'a> {
         // which affects the behavior of `System.rst/wst`
         let v = if short(FLAGS) {
             s.reserve(2);
-            dev.dei(self, i);
+            dev.dei2(self, i);
             let hi = self.dev[usize::from(i)];
-            let j = i.wrapping_add(1);
-            dev.dei(self, j);
-            let lo = self.dev[usize::from(j)];
+            let lo = self.dev[usize::from(i.wrapping_add(1))];
             Value::Short(u16::from_le_bytes([lo, hi]))
         } else {
             s.reserve(1);
@@ -1547,6 +2082,56 @@ impl<'a> Uxn<'a> {
     }
 }
 
+/// Iterator over executed instructions, returned by [`Uxn::trace_iter`]
+pub struct Trace<'t, 'a, D> {
+    vm: &'t mut Uxn<'a>,
+    dev: &'t mut D,
+    pc: u16,
+    done: bool,
+}
+
+impl<'a, D> Trace<'_, 'a, D> {
+    /// Returns the address of the next instruction to execute
+    ///
+    /// If the program has terminated, this is the final program counter.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Checks whether the program has terminated
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Borrows the VM being traced
+    pub fn vm(&self) -> &Uxn<'a> {
+        self.vm
+    }
+
+    /// Borrows the device attached to the VM
+    pub fn dev(&self) -> &D {
+        self.dev
+    }
+}
+
+impl<D: Device> Iterator for Trace<'_, '_, D> {
+    type Item = (u16, u8);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let addr = self.pc;
+        let op = self.vm.next(&mut self.pc);
+        match self.vm.dispatch(op, self.dev, self.pc) {
+            Some(next) => self.pc = next,
+            None => self.done = true,
+        }
+        Some((addr, op))
+    }
+}
+
 /// Trait for a Uxn-compatible device
 pub trait Device {
     /// Performs the `DEI` operation for the given target
@@ -1555,6 +2140,20 @@ pub trait Device {
     /// evaluation loop will then copy this value to the stack.
     fn dei(&mut self, vm: &mut Uxn, target: u8);
 
+    /// Performs the `DEI2` operation for the given target
+    ///
+    /// This function must write its output to `vm.dev[target]` (high byte)
+    /// and `vm.dev[target + 1]` (low byte, wrapping within device memory).
+    ///
+    /// The default implementation calls [`dei`](Self::dei) for each byte,
+    /// high byte first.  Devices with 16-bit (or otherwise related) values
+    /// which may change between the two calls, e.g. a clock, should override
+    /// this to update both bytes at once.
+    fn dei2(&mut self, vm: &mut Uxn, target: u8) {
+        self.dei(vm, target);
+        self.dei(vm, target.wrapping_add(1));
+    }
+
     /// Performs the `DEO` operation on the given target
     ///
     /// The input byte will be written to `vm.dev[target]` before this function
@@ -1562,8 +2161,19 @@ pub trait Device {
     ///
     /// Returns `true` if the CPU should keep running, `false` if it should
     /// exit.
+    ///
+    /// To run a vector synchronously from within this function, use
+    /// [`Uxn::call_vector`].
     #[must_use]
     fn deo(&mut self, vm: &mut Uxn, target: u8) -> bool;
+
+    /// Returns the exit code requested by the program, if any
+    ///
+    /// This is checked when [`deo`](Self::deo) stops execution, and reported
+    /// in [`Halt::Exit`]; the default implementation returns `None`.
+    fn exit_code(&self) -> Option<i32> {
+        None
+    }
 }
 
 /// Trait for a type which can be cast to a device ports `struct`
@@ -1608,6 +2218,29 @@ mod ram {
         }
     }
 
+    impl crate::Uxn<'static> {
+        /// Build a new `Uxn` which owns its RAM
+        ///
+        /// Unlike [`Uxn::new`](crate::Uxn::new), this doesn't borrow the RAM,
+        /// so the VM can be stored or moved freely without resorting to
+        /// [`UxnRam::leak`].
+        ///
+        /// This is only available if the `"alloc"` feature is enabled
+        pub fn new_owned(ram: UxnRam, backend: crate::Backend) -> Self {
+            Self::with_ram(crate::Ram::Owned(ram), backend)
+        }
+
+        /// Consumes the VM, returning its RAM if it was owned
+        ///
+        /// This is only available if the `"alloc"` feature is enabled
+        pub fn into_ram(self) -> Option<UxnRam> {
+            match self.ram {
+                crate::Ram::Owned(r) => Some(r),
+                crate::Ram::Borrowed(..) => None,
+            }
+        }
+    }
+
     impl Default for UxnRam {
         fn default() -> Self {
             Self::new()
@@ -1630,6 +2263,57 @@ mod ram {
 #[cfg(feature = "alloc")]
 pub use ram::UxnRam;
 
+#[cfg(feature = "alloc")]
+mod cluster;
+
+#[cfg(feature = "alloc")]
+pub use cluster::Cluster;
+
+#[cfg(feature = "alloc")]
+pub mod aot;
+
+#[cfg(feature = "alloc")]
+pub mod wasm;
+
+#[cfg(feature = "alloc")]
+mod analysis;
+
+#[cfg(feature = "alloc")]
+pub use analysis::{analyze, Finding};
+
+#[cfg(feature = "alloc")]
+mod journal;
+
+#[cfg(feature = "alloc")]
+pub use journal::Journal;
+
+#[cfg(feature = "alloc")]
+pub mod test_utils;
+
+mod builder;
+pub use builder::UxnBuilder;
+
+/// Compile-time check that the VM can cross threads
+#[allow(dead_code)]
+const fn assert_send_sync<T: Send + Sync>() {}
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+const _: () = assert_send_sync::<Uxn<'static>>();
+const _: () = assert_send_sync::<Stack>();
+
+mod coverage;
+pub use coverage::Coverage;
+
+mod cycles;
+pub use cycles::{CycleCosts, UNIT_CYCLE_COSTS};
+
+mod pages;
+pub use pages::DevicePage;
+
+mod halt;
+pub use halt::Halt;
+
+pub mod prelude;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Opcode names and constants
@@ -1925,17 +2609,97 @@ pub mod op {
         "STA2kr", "DEI2kr", "DEO2kr", "ADD2kr", "SUB2kr", "MUL2kr", "DIV2kr",
         "AND2kr", "ORA2kr", "EOR2kr", "SFT2kr",
     ];
-}
 
-#[cfg(all(feature = "alloc", test))]
-mod test {
-    use super::*;
+    /// Static information about an opcode, including its stack effects
+    ///
+    /// Stack effects are given in bytes.  The "source" stack is the working
+    /// stack, or the return stack if the `r` flag is set; the "other" stack is
+    /// the opposite one.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct OpInfo {
+        /// Bytes read from the top of the source stack
+        pub reads: u8,
+        /// Bytes removed from the source stack (0 in `keep` mode)
+        pub pops: u8,
+        /// Bytes pushed onto the source stack
+        pub pushes: u8,
+        /// Bytes pushed onto the other stack (by `STH`, `JSR`, and `JSI`)
+        pub other_pushes: u8,
+        /// Immediate bytes following the opcode in memory
+        pub operand: u8,
+        /// The opcode may move the program counter non-sequentially
+        pub jump: bool,
+    }
 
-    /// Simple parser for textual opcodes
-    fn decode_op(s: &str) -> Result<u8, &str> {
-        let (s, ret) =
-            s.strip_suffix('r').map(|s| (s, true)).unwrap_or((s, false));
-        let (s, keep) =
+    impl OpInfo {
+        const fn new(op: u8) -> Self {
+            let short = op & 0x20 != 0;
+            let keep = op & 0x80 != 0;
+            let w = if short { 2 } else { 1 };
+
+            // (reads, pushes, other_pushes, operand, jump)
+            let (reads, pushes, other_pushes, operand, jump) = match op {
+                BRK => (0, 0, 0, 0, false),
+                JCI => (1, 0, 0, 2, true),
+                JMI => (0, 0, 0, 2, true),
+                JSI => (0, 0, 2, 2, true),
+                LIT | LIT2 | LITr | LIT2r => (0, w, 0, w, false),
+                _ => match op & 0x1f {
+                    INC => (w, w, 0, 0, false),
+                    POP => (w, 0, 0, 0, false),
+                    NIP => (2 * w, w, 0, 0, false),
+                    SWP => (2 * w, 2 * w, 0, 0, false),
+                    ROT => (3 * w, 3 * w, 0, 0, false),
+                    DUP => (w, 2 * w, 0, 0, false),
+                    OVR => (2 * w, 3 * w, 0, 0, false),
+                    EQU | NEQ | GTH | LTH => (2 * w, 1, 0, 0, false),
+                    JMP => (w, 0, 0, 0, true),
+                    JCN => (w + 1, 0, 0, 0, true),
+                    JSR => (w, 0, 2, 0, true),
+                    STH => (w, 0, w, 0, false),
+                    LDZ | LDR | DEI => (1, w, 0, 0, false),
+                    STZ | STR | DEO => (w + 1, 0, 0, 0, false),
+                    LDA => (2, w, 0, 0, false),
+                    STA => (w + 2, 0, 0, 0, false),
+                    SFT => (w + 1, w, 0, 0, false),
+                    // ADD, SUB, MUL, DIV, AND, ORA, EOR
+                    _ => (2 * w, w, 0, 0, false),
+                },
+            };
+            let is_lit = matches!(op, LIT | LIT2 | LITr | LIT2r);
+            let pops = if keep && !is_lit { 0 } else { reads };
+            Self {
+                reads,
+                pops,
+                pushes,
+                other_pushes,
+                operand,
+                jump,
+            }
+        }
+    }
+
+    /// Information about every opcode, indexed by opcode
+    pub const INFO: [OpInfo; 256] = {
+        let mut out = [OpInfo::new(0); 256];
+        let mut i = 0;
+        while i < 256 {
+            out[i] = OpInfo::new(i as u8);
+            i += 1;
+        }
+        out
+    };
+}
+
+#[cfg(all(feature = "alloc", test))]
+mod test {
+    use super::*;
+
+    /// Simple parser for textual opcodes
+    fn decode_op(s: &str) -> Result<u8, &str> {
+        let (s, ret) =
+            s.strip_suffix('r').map(|s| (s, true)).unwrap_or((s, false));
+        let (s, keep) =
             s.strip_suffix('k').map(|s| (s, true)).unwrap_or((s, false));
         let (s, short) =
             s.strip_suffix('2').map(|s| (s, true)).unwrap_or((s, false));
@@ -2117,6 +2881,1218 @@ mod test {
         ";
     }
 
+    #[test]
+    fn ram_slice() {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        for (i, b) in vm.ram_mut().iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(
+            vm.ram_slice(0x10, 3),
+            Some((&[0x10, 0x11, 0x12][..], &[][..]))
+        );
+        assert_eq!(
+            vm.ram_slice(0xfffe, 4),
+            Some((&[0xfe, 0xff][..], &[0x00, 0x01][..]))
+        );
+        assert_eq!(vm.ram_slice(0xffff, 0), Some((&[][..], &[][..])));
+        let (head, tail) = vm.ram_slice(0x1234, 65536).unwrap();
+        assert_eq!((head.len(), tail.len()), (65536 - 0x1234, 0x1234));
+        assert_eq!(vm.ram_slice(0, 65537), None);
+
+        let (head, tail) = vm.ram_slice_mut(0xffff, 2).unwrap();
+        head[0] = 0xab;
+        tail[0] = 0xcd;
+        assert_eq!(vm.ram_read_byte(0xffff), 0xab);
+        assert_eq!(vm.ram()[0], 0xcd);
+    }
+
+    #[test]
+    fn interrupt() {
+        /// Device which sets the interrupt flag on `DEO`
+        struct Interrupter<'a>(&'a AtomicBool);
+        impl Device for Interrupter<'_> {
+            fn dei(&mut self, _vm: &mut Uxn, _target: u8) {}
+            fn deo(&mut self, _vm: &mut Uxn, _target: u8) -> bool {
+                self.0.store(true, Ordering::Relaxed);
+                true
+            }
+        }
+
+        let flag = AtomicBool::new(false);
+        let mut ram = UxnRam::new();
+        // LIT 00 LIT 10 DEO, then loop forever with JMI
+        ram[0x100..0x108].copy_from_slice(&[
+            op::LIT,
+            0x00,
+            op::LIT,
+            0x10,
+            op::DEO,
+            op::JMI,
+            0xff,
+            0xfd,
+        ]);
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        vm.set_interrupt(Some(&flag));
+        let mut dev = Interrupter(&flag);
+        assert_eq!(vm.run(&mut dev, 0x100), 0x105);
+        assert!(vm.is_interrupted());
+
+        // Resuming with the flag still set returns immediately
+        assert_eq!(vm.run(&mut dev, 0x105), 0x105);
+
+        flag.store(false, Ordering::Relaxed);
+        let r = vm.run_until(&mut dev, 0x105, |_, _, i| i == 10);
+        assert_eq!(r, None);
+    }
+
+    #[test]
+    fn op_info() {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = EmptyDevice;
+        for (i, info) in op::INFO.iter().enumerate() {
+            let i = i as u8;
+            let name = op::NAMES[usize::from(i)];
+            vm.stack.set_len(16);
+            vm.ret.set_len(16);
+            // Use zeros on the stack, so that conditional jumps are not taken
+            vm.stack.data.fill(0);
+            vm.ret.data.fill(0);
+            let next = vm.dispatch(i, &mut dev, 0x100);
+
+            // JMI and JSI have the `r` bit set, but always use the working
+            // stack as their source
+            let ret = i & 0x40 != 0 && !matches!(i, op::JMI | op::JSI);
+            let (src, other) = if ret {
+                (&vm.ret, &vm.stack)
+            } else {
+                (&vm.stack, &vm.ret)
+            };
+            assert_eq!(
+                src.len(),
+                16 - info.pops + info.pushes,
+                "bad source stack effect for {name}"
+            );
+            assert_eq!(
+                other.len(),
+                16 + info.other_pushes,
+                "bad other stack effect for {name}"
+            );
+            if i == op::BRK {
+                assert_eq!(next, None);
+            } else if !info.jump {
+                assert_eq!(
+                    next,
+                    Some(0x100 + u16::from(info.operand)),
+                    "bad operand size for {name}"
+                );
+            }
+        }
+        assert_eq!(op::INFO[usize::from(op::ADD2k)].pops, 0);
+        assert_eq!(op::INFO[usize::from(op::ADD2k)].reads, 4);
+        assert_eq!(op::INFO[usize::from(op::LIT2r)].operand, 2);
+        assert!(op::INFO[usize::from(op::JMP2r)].jump);
+    }
+
+    #[test]
+    fn dispatch() {
+        // #01 #02 INC INC ADD #00 STZ BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x01, op::LIT, 0x02, op::INC, op::INC, op::ADD,
+            op::LIT, 0x00, op::STZ, op::BRK,
+        ];
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = EmptyDevice;
+        let _ = vm.reset(&rom);
+        let expected = vm.run(&mut dev, 0x100);
+        assert_eq!(vm.ram_read_byte(0x00), 0x05);
+
+        // Custom evaluation loop, which fuses pairs of `INC` opcodes
+        let _ = vm.reset(&rom);
+        let mut pc = 0x100;
+        let mut fused = 0;
+        loop {
+            let op = vm.ram_read_byte(pc);
+            pc = pc.wrapping_add(1);
+            let next = if op == op::INC && vm.ram_read_byte(pc) == op::INC {
+                fused += 1;
+                vm.inc::<0>(pc.wrapping_add(1))
+                    .and_then(|pc| vm.inc::<0>(pc))
+            } else {
+                vm.dispatch(op, &mut dev, pc)
+            };
+            match next {
+                Some(next) => pc = next,
+                None => break,
+            }
+        }
+        assert_eq!(fused, 1);
+        assert_eq!(pc, expected);
+        assert_eq!(vm.ram_read_byte(0x00), 0x05);
+    }
+
+    #[test]
+    fn builder() {
+        let mut ram = UxnRam::new();
+        let vm = Uxn::builder(&mut ram).build();
+        assert_eq!(vm.backend(), Backend::default());
+        assert!(!vm.is_interrupted());
+        assert!(!vm.is_instrumented());
+
+        let flag = AtomicBool::new(true);
+        let mut ram = UxnRam::new();
+        let vm = Uxn::builder(&mut ram)
+            .backend(Backend::Interpreter)
+            .interrupt(&flag)
+            .build();
+        assert_eq!(vm.backend(), Backend::Interpreter);
+        assert!(vm.is_interrupted());
+
+        // #01 #02 #03 BRK, with enough fuel for two instructions
+        let rom = [op::LIT, 1, op::LIT, 2, op::LIT, 3, op::BRK];
+        let mut ram = UxnRam::new();
+        let mut vm =
+            Uxn::builder(&mut ram).fuel(2).stack_tracking(true).build();
+        let _ = vm.reset(&rom);
+        assert_eq!(
+            vm.run_halt(&mut EmptyDevice, 0x100),
+            Halt::OutOfCycles { pc: 0x104 }
+        );
+        assert_eq!(vm.stack().high_water(), 2);
+    }
+
+    #[test]
+    fn coverage() {
+        // #01 ?skip #02 @skip BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x01, op::JCI, 0x00, 0x02, op::LIT, 0x02, op::BRK,
+        ];
+        let mut cov = Coverage::new();
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::builder(&mut ram)
+            .backend(Backend::Interpreter)
+            .coverage(&mut cov)
+            .build();
+        let mut dev = EmptyDevice;
+        let _ = vm.reset(&rom);
+        vm.run(&mut dev, 0x100);
+
+        let c = vm.coverage().unwrap();
+        assert_eq!(c.len(), 3);
+        assert_eq!(c.iter().collect::<Vec<_>>(), [0x100, 0x102, 0x107]);
+        assert!(!c.contains(0x105));
+        assert_eq!(c.as_bits()[0x100 / 64], 0b1000_0101);
+
+        vm.coverage_mut().unwrap().clear();
+        assert!(vm.coverage().unwrap().is_empty());
+    }
+
+    /// Counts down from 5, calling a subroutine which increments `zp[2]`
+    #[rustfmt::skip]
+    const AOT_ROM: &[u8] = &[
+        // #05 @loop !body JSI #01 SUB DUP ?loop POP BRK
+        op::LIT, 0x05, op::JSI, 0x00, 0x09, op::LIT, 0x01, op::SUB, op::DUP,
+        op::JCI, 0xff, 0xf6, op::POP, op::BRK,
+        // @body #02 LDZ INC #02 STZ JMP2r
+        op::LIT, 0x02, op::LDZ, op::INC, op::LIT, 0x02, op::STZ, op::JMP2r,
+    ];
+
+    mod aot_example {
+        use crate::{Device, Uxn};
+        include!("aot/example.rs");
+    }
+
+    #[test]
+    fn aot() {
+        // The checked-in example must match the transpiler's output
+        assert_eq!(
+            aot::transpile(AOT_ROM),
+            include_str!("aot/example.rs"),
+            "aot/example.rs is out of date"
+        );
+
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = EmptyDevice;
+        let _ = vm.reset(AOT_ROM);
+        let expected = vm.run(&mut dev, 0x100);
+        assert_eq!(vm.ram_read_byte(0x02), 5);
+
+        let _ = vm.reset(AOT_ROM);
+        let pc = aot_example::run(&mut vm, &mut dev, 0x100);
+        assert_eq!(pc, expected);
+        assert_eq!(vm.ram_read_byte(0x02), 5);
+        assert!(vm.stack.is_empty());
+        assert!(vm.ret.is_empty());
+
+        // Modifying the subroutine makes the compiled code fall back to the
+        // interpreter, so it increments `zp[3]` instead
+        let _ = vm.reset(AOT_ROM);
+        vm.ram_write_byte(0x10f, 0x03);
+        vm.ram_write_byte(0x113, 0x03);
+        let pc = aot_example::run(&mut vm, &mut dev, 0x100);
+        assert_eq!(pc, expected);
+        assert_eq!(vm.ram_read_byte(0x02), 0);
+        assert_eq!(vm.ram_read_byte(0x03), 5);
+    }
+
+    #[test]
+    fn wasm_compile() {
+        // Modules are valid WebAssembly (version 1), and deterministic
+        let out = wasm::compile(AOT_ROM);
+        assert_eq!(out[..8], *b"\0asm\x01\0\0\0");
+        assert_eq!(out, wasm::compile(AOT_ROM));
+
+        // An empty ROM still produces a module, which always falls back
+        let out = wasm::compile(&[]);
+        assert_eq!(out[..4], *b"\0asm");
+    }
+
+    #[test]
+    fn journal() {
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT2, 0x12, 0x34, op::LIT2, 0xff, 0xff, op::STA2,
+            op::LIT2, 0xab, 0xcd, op::LIT2, 0x00, 0x01, op::LIT2, 0x56, 0x78,
+            op::ROT2k, op::STH2, op::LIT, 0x02, op::STR2, op::LIT2, 0x99,
+            0x88, op::LIT, 0x10, op::DEO2, op::STH2r, op::LIT, 0x02, op::DEI,
+            op::BRK, 0x00, 0x00,
+        ];
+        for rom in [&rom, AOT_ROM] {
+            let mut ram = UxnRam::new();
+            let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+            let _ = vm.reset(rom);
+            let mut journal = Journal::new(usize::MAX);
+            let mut pc = Some(0x100);
+            let mut pcs = vec![];
+            while let Some(p) = pc {
+                pcs.push(p);
+                pc = journal.step(&mut vm, &mut EmptyDevice, p);
+            }
+            assert_eq!(journal.history().collect::<Vec<_>>(), pcs);
+
+            // Each step back matches a fresh VM which ran one fewer step
+            for n in (0..pcs.len()).rev() {
+                assert_eq!(journal.step_back(&mut vm), Some(pcs[n]));
+                let mut ram = UxnRam::new();
+                let mut expected = Uxn::new(&mut ram, Backend::Interpreter);
+                let _ = expected.reset(rom);
+                expected.trace_iter(&mut EmptyDevice, 0x100).take(n).count();
+                assert_eq!(test_utils::diff(&expected, &vm), []);
+            }
+            assert_eq!(journal.step_back(&mut vm), None);
+        }
+
+        // Old records are discarded once the journal is full; here, we stop
+        // after returning from the first call to `body`
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let _ = vm.reset(AOT_ROM);
+        let mut journal = Journal::new(2);
+        let pc =
+            journal.run_until(&mut vm, &mut EmptyDevice, 0x100, |_, pc| {
+                pc == 0x105
+            });
+        assert_eq!(pc, Some(0x105));
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal.history().collect::<Vec<_>>(), [0x114, 0x115]);
+    }
+
+    #[test]
+    fn analysis() {
+        // The checked-in AOT example is clean
+        assert_eq!(analyze(AOT_ROM), []);
+
+        // A comparison followed by `JMP` may skip the next byte
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x01, op::LIT, 0x02, op::GTHk, op::JMP, op::SWP, op::POP,
+            op::POP, op::BRK,
+        ];
+        assert_eq!(analyze(&rom), []);
+
+        // Subroutine stack effects are tracked through calls
+        // #01 !sub JSI BRK @sub POP JMP2r
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x01, op::JSI, 0x00, 0x01, op::BRK, op::POP, op::JMP2r,
+        ];
+        assert_eq!(analyze(&rom), []);
+        let mut leaky = rom;
+        leaky[6] = op::DUP;
+        assert_eq!(
+            analyze(&leaky),
+            [Finding::StackLeak {
+                addr: 0x105,
+                depth: 2
+            }]
+        );
+
+        // #00 JCI into the operand of the `LIT`
+        let rom = [op::LIT, 0x00, op::JCI, 0xff, 0xfc, op::BRK];
+        assert_eq!(
+            analyze(&rom),
+            [Finding::JumpIntoOperand {
+                from: 0x102,
+                target: 0x101
+            }]
+        );
+
+        // Code after a `BRK` is unreachable, unless its address is used
+        let rom = [op::BRK, op::LIT, 0x01, op::POP];
+        assert_eq!(
+            analyze(&rom),
+            [Finding::Unreachable {
+                start: 0x101,
+                end: 0x103
+            }]
+        );
+        let rom = [op::LIT2, 0x01, 0x05, op::POP2, op::BRK, op::INC];
+        assert_eq!(analyze(&rom), []);
+
+        // Installed vectors are checked with an empty stack
+        // ;vector .Screen/vector DEO2 BRK @vector POP BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT2, 0x01, 0x07, op::LIT, 0x20, op::DEO2, op::BRK,
+            op::POP, op::BRK,
+        ];
+        assert_eq!(analyze(&rom), [Finding::StackUnderflow { addr: 0x107 }]);
+
+        // @loop #01 !loop JMI
+        let rom = [op::LIT, 0x01, op::JMI, 0xff, 0xfb];
+        assert_eq!(analyze(&rom), [Finding::StackMismatch { addr: 0x100 }]);
+
+        // #01 #10 STZ #11 LDZ POP BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x01, op::LIT, 0x10, op::STZ, op::LIT, 0x11, op::LDZ,
+            op::POP, op::BRK,
+        ];
+        assert_eq!(analyze(&rom), [Finding::UnreadZeroPage { addr: 0x10 }]);
+    }
+
+    #[test]
+    fn cycles() {
+        // #05 @loop #01 SUB DUP ?loop BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x05, op::LIT, 0x01, op::SUB, op::DUP, op::JCI, 0xff,
+            0xf9, op::BRK,
+        ];
+        let mut costs = UNIT_CYCLE_COSTS;
+        costs[usize::from(op::SUB)] = 3;
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::builder(&mut ram)
+            .backend(Backend::Interpreter)
+            .cycle_costs(&costs)
+            .build();
+        let mut dev = EmptyDevice;
+        let _ = vm.reset(&rom);
+        let end = vm.run(&mut dev, 0x100);
+        assert_eq!(end, 0x10a);
+        // LIT + 5 * (LIT + SUB + DUP + JCI) + BRK
+        assert_eq!(vm.cycles(), 1 + 5 * 6 + 1);
+
+        // Run with a limit, resuming until the program terminates
+        let _ = vm.reset(&rom);
+        vm.set_cycle_limit(Some(10));
+        let mut pc = 0x100;
+        let mut stops = 0;
+        let mut total = 0;
+        loop {
+            pc = vm.run(&mut dev, pc);
+            if !vm.is_out_of_cycles() {
+                break;
+            }
+            assert!(vm.cycles() >= 10 && vm.cycles() < 13);
+            total += vm.reset_cycles();
+            stops += 1;
+        }
+        assert_eq!(pc, end);
+        assert!(stops > 1);
+        assert_eq!(total + vm.cycles(), 1 + 5 * 6 + 1);
+    }
+
+    #[test]
+    fn call_vector() {
+        /// Device which runs the vector at `0x200` when port `0x01` is written
+        struct CallDevice;
+        impl Device for CallDevice {
+            fn dei(&mut self, _vm: &mut Uxn, _target: u8) {}
+            fn deo(&mut self, vm: &mut Uxn, target: u8) -> bool {
+                if target == 0x01 {
+                    vm.call_vector(self, 0x200);
+                }
+                true
+            }
+        }
+
+        // #12 #34 #01 DEO #56 BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT2, 0x12, 0x34, op::LIT, 0x01, op::DEO, op::LIT, 0x56,
+            op::BRK,
+        ];
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let _ = vm.reset(&rom);
+        // |0200 #ab #00 STZ #ff BRK
+        #[rustfmt::skip]
+        let vector = [
+            op::LIT, 0xab, op::LIT, 0x00, op::STZ, op::LITr, 0xff, op::BRK,
+        ];
+        for (i, b) in vector.iter().enumerate() {
+            vm.ram_write_byte(0x200 + i as u16, *b);
+        }
+
+        let pc = vm.run(&mut CallDevice, 0x100);
+        assert_eq!(pc, 0x109);
+        assert_eq!(vm.ram_read_byte(0x00), 0xab);
+        assert_eq!(vm.stack.as_slice(), [0x12, 0x56]);
+        assert!(vm.ret.is_empty());
+    }
+
+    #[test]
+    fn backend() {
+        assert!(Backend::Interpreter.is_available());
+        assert_eq!(Backend::Native.is_available(), cfg!(feature = "native"));
+        assert_eq!(
+            Backend::Wasm.is_available(),
+            cfg!(all(feature = "wasm", target_arch = "wasm32"))
+        );
+        assert!(Backend::auto().is_available());
+    }
+
+    #[test]
+    fn trace() {
+        let mut ram = UxnRam::new();
+        ram[0x100..0x106].copy_from_slice(&[
+            op::LIT,
+            0x12,
+            op::INC,
+            op::JMI,
+            0x00,
+            0x00,
+        ]);
+        ram[0x106] = op::BRK;
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = EmptyDevice;
+        let mut t = vm.trace_iter(&mut dev, 0x100);
+        let ops = t.by_ref().collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            [
+                (0x100, op::LIT),
+                (0x102, op::INC),
+                (0x103, op::JMI),
+                (0x106, op::BRK)
+            ]
+        );
+        assert!(t.is_done());
+        assert_eq!(t.pc(), 0x107);
+        assert_eq!(t.vm().stack().as_slice(), &[0x13]);
+        assert_eq!(t.next(), None);
+    }
+
+    #[test]
+    fn cluster() {
+        /// Device which logs the value written to port 0x10
+        #[derive(Default)]
+        struct Log(Vec<u8>);
+        impl Device for Log {
+            fn dei(&mut self, _vm: &mut Uxn, _target: u8) {}
+            fn deo(&mut self, vm: &mut Uxn, target: u8) -> bool {
+                self.0.push(vm.dev[usize::from(target)]);
+                true
+            }
+        }
+
+        let mut rams = [UxnRam::new(), UxnRam::new()];
+        let mut cluster = Cluster::new();
+        for (i, ram) in rams.iter_mut().enumerate() {
+            // LIT [i] LIT 10 DEO BRK, at 0x100
+            ram[0x100..0x106].copy_from_slice(&[
+                op::LIT,
+                i as u8,
+                op::LIT,
+                0x10,
+                op::DEO,
+                op::BRK,
+            ]);
+            // LIT [i + 10] LIT 10 DEO BRK, at 0x200
+            ram[0x200..0x206].copy_from_slice(&[
+                op::LIT,
+                i as u8 + 10,
+                op::LIT,
+                0x10,
+                op::DEO,
+                op::BRK,
+            ]);
+            let vm = Uxn::new(ram, Backend::Interpreter);
+            assert_eq!(cluster.push(vm), i);
+        }
+        assert_eq!(cluster.len(), 2);
+        assert!(cluster.is_idle());
+
+        cluster.queue(0, 0x100);
+        cluster.queue(0, 0x200);
+        cluster.queue(1, 0x100);
+        cluster.queue(1, 0x200);
+
+        let mut dev = Log::default();
+        assert_eq!(cluster.step(&mut dev), Some((0, 0x106)));
+        cluster.run(&mut dev);
+        assert!(cluster.is_idle());
+        assert_eq!(cluster.step(&mut dev), None);
+        assert_eq!(dev.0, [0, 1, 10, 11]);
+    }
+
+    #[test]
+    fn dei2() {
+        /// Device which counts calls to `dei`
+        #[derive(Default)]
+        struct Counter(u8);
+        impl Device for Counter {
+            fn dei(&mut self, vm: &mut Uxn, target: u8) {
+                self.0 += 1;
+                vm.dev[usize::from(target)] = self.0;
+            }
+            fn deo(&mut self, _vm: &mut Uxn, _target: u8) -> bool {
+                true
+            }
+        }
+
+        /// Device which reads both bytes at once
+        #[derive(Default)]
+        struct Atomic(u8);
+        impl Device for Atomic {
+            fn dei(&mut self, _vm: &mut Uxn, _target: u8) {
+                unreachable!()
+            }
+            fn dei2(&mut self, vm: &mut Uxn, target: u8) {
+                self.0 += 1;
+                vm.dev[usize::from(target)] = self.0;
+                vm.dev[usize::from(target.wrapping_add(1))] = self.0;
+            }
+            fn deo(&mut self, _vm: &mut Uxn, _target: u8) -> bool {
+                true
+            }
+        }
+
+        // #ff DEI2 BRK
+        let rom = [op::LIT, 0xff, op::DEI2, op::BRK];
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let _ = vm.reset(&rom);
+
+        let mut dev = Counter::default();
+        vm.run(&mut dev, 0x100);
+        assert_eq!(dev.0, 2);
+        assert_eq!(vm.stack().peek_short_at(0), 0x0102);
+
+        let mut dev = Atomic::default();
+        vm.run(&mut dev, 0x100);
+        assert_eq!(dev.0, 1);
+        assert_eq!(vm.stack().peek_short_at(0), 0x0101);
+    }
+
+    #[test]
+    fn page_boundaries() {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+
+        // #ff LDZ2 reads into the first byte of the program
+        let _ = vm.reset(&[op::LIT, 0xff, op::LDZ2, op::BRK]);
+        vm.ram_write_byte(0xff, 0x12);
+        vm.run(&mut EmptyDevice, 0x100);
+        assert_eq!(
+            vm.stack().peek_short_at(0),
+            u16::from_be_bytes([0x12, op::LIT])
+        );
+
+        // #abcd #ff STZ2 writes to 0xff and 0x100
+        let _ =
+            vm.reset(&[op::LIT2, 0xab, 0xcd, op::LIT, 0xff, op::STZ2, op::BRK]);
+        vm.run(&mut EmptyDevice, 0x100);
+        assert_eq!(vm.ram_read_byte(0xff), 0xab);
+        assert_eq!(vm.ram_read_byte(0x100), 0xcd);
+        assert_eq!(vm.ram_read_byte(0x00), 0x00);
+
+        // #ffff LDA2 and STA2 wrap around to 0x0000
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT2, 0xab, 0xcd, op::LIT2, 0xff, 0xff, op::STA2,
+            op::LIT2, 0xff, 0xff, op::LDA2, op::BRK,
+        ];
+        let _ = vm.reset(&rom);
+        vm.run(&mut EmptyDevice, 0x100);
+        assert_eq!(vm.ram_read_byte(0xffff), 0xab);
+        assert_eq!(vm.ram_read_byte(0x0000), 0xcd);
+        assert_eq!(vm.stack().peek_short_at(0), 0xabcd);
+    }
+
+    #[test]
+    fn equivalence() {
+        use test_utils::{check_equivalence_with, Difference};
+
+        // #1234 #56 STZ BRK
+        let rom = [op::LIT2, 0x12, 0x34, op::LIT, 0x56, op::STZ, op::BRK];
+
+        // Without a native backend, there's nothing to compare against
+        #[cfg(feature = "native")]
+        assert_eq!(test_utils::check_equivalence(&rom, 100), Ok(()));
+
+        let r = check_equivalence_with(&rom, 100, |vm, dev, pc| {
+            let pc = vm.run(dev, pc);
+            vm.ram_write_byte(0x34, 0x78);
+            vm.stack.push_byte(0x9a);
+            pc + 1
+        });
+        let d = r.unwrap_err();
+        assert_eq!(d.len(), 3);
+        assert_eq!(
+            d[0],
+            Difference::Pc {
+                expected: 0x107,
+                actual: 0x108
+            }
+        );
+        assert_eq!(
+            d[1],
+            Difference::Ram {
+                addr: 0x34,
+                expected: 0x00,
+                actual: 0x78
+            }
+        );
+        assert!(matches!(d[2], Difference::Stack { .. }));
+        assert_eq!(d[1].to_string(), "ram mismatch at 0x0034: 0x00 != 0x78");
+
+        // Programs which don't terminate within the fuel limit are skipped
+        let rom = [op::JMI, 0xff, 0xfd];
+        let r = check_equivalence_with(&rom, 100, |_, _, _| unreachable!());
+        assert_eq!(r, Ok(()));
+    }
+
+    #[test]
+    fn high_water() {
+        // #01 #02 #03 POP POP #0405 STH2 POP2r BRK
+        #[rustfmt::skip]
+        let rom = [
+            op::LIT, 0x01, op::LIT, 0x02, op::LIT, 0x03, op::POP, op::POP,
+            op::LIT2, 0x04, 0x05, op::STH2, op::POP2r, op::BRK,
+        ];
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let _ = vm.reset(&rom);
+
+        // High-water marks are only tracked on request
+        vm.run(&mut EmptyDevice, 0x100);
+        assert_eq!(vm.stack().high_water(), 0);
+
+        let _ = vm.reset(&rom);
+        vm.set_stack_tracking(true);
+        vm.run(&mut EmptyDevice, 0x100);
+        assert_eq!(vm.stack().len(), 1);
+        assert_eq!(vm.stack().high_water(), 3);
+        assert!(vm.ret().is_empty());
+        assert_eq!(vm.ret().high_water(), 2);
+
+        vm.stack_mut().reset_high_water();
+        assert_eq!(vm.stack().high_water(), 1);
+
+        let _ = vm.reset(&rom);
+        assert_eq!(vm.stack().high_water(), 0);
+
+        // 256 x LIT 00 BRK
+        let mut rom = [op::LIT, 0].repeat(256);
+        rom.push(op::BRK);
+        let _ = vm.reset(&rom);
+        vm.run(&mut EmptyDevice, 0x100);
+        assert!(vm.stack().is_empty());
+        assert_eq!(vm.stack().high_water(), 255);
+    }
+
+    #[test]
+    fn halt() {
+        struct ExitDevice;
+        impl Device for ExitDevice {
+            fn dei(&mut self, _vm: &mut Uxn, _target: u8) {}
+            fn deo(&mut self, _vm: &mut Uxn, _target: u8) -> bool {
+                false
+            }
+            fn exit_code(&self) -> Option<i32> {
+                Some(3)
+            }
+        }
+
+        // #01 #0f DEO BRK
+        let rom = [op::LIT, 0x01, op::LIT, 0x0f, op::DEO, op::BRK];
+
+        // Every backend reports the same reasons
+        for backend in [Backend::Interpreter, Backend::Native, Backend::Wasm] {
+            if !backend.is_available() {
+                continue;
+            }
+            let mut ram = UxnRam::new();
+            let mut vm = Uxn::new(&mut ram, backend);
+            let _ = vm.reset(&rom);
+            let h = vm.run_halt(&mut EmptyDevice, 0x100);
+            assert_eq!(h, Halt::Break { pc: 0x106 });
+            assert!(!h.is_resumable());
+
+            let h = vm.run_halt(&mut ExitDevice, 0x100);
+            assert_eq!(
+                h,
+                Halt::Exit {
+                    pc: 0x105,
+                    code: Some(3)
+                }
+            );
+            assert_eq!(h.pc(), 0x105);
+
+            vm.set_cycle_costs(Some(&UNIT_CYCLE_COSTS));
+            vm.set_cycle_limit(Some(2));
+            let h = vm.run_halt(&mut EmptyDevice, 0x100);
+            assert_eq!(h, Halt::OutOfCycles { pc: 0x104 }, "{backend:?}");
+            assert!(h.is_resumable());
+
+            let flag = AtomicBool::new(true);
+            vm.set_interrupt(Some(&flag));
+            let h = vm.run_halt(&mut EmptyDevice, 0x104);
+            assert_eq!(h, Halt::Interrupted { pc: 0x104 }, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn owned() {
+        fn build() -> Uxn<'static> {
+            let mut vm = Uxn::new_owned(UxnRam::new(), Backend::Interpreter);
+            let _ = vm.reset(&[op::LIT, 0x12, op::LIT, 0x34, op::BRK]);
+            vm
+        }
+        let mut vm = build();
+        let pc = vm.run(&mut EmptyDevice, 0x100);
+        assert_eq!(pc, 0x105);
+        assert_eq!(vm.stack().peek_short_at(0), 0x1234);
+
+        let ram = vm.into_ram().unwrap();
+        assert_eq!(ram[0x101], 0x12);
+
+        let vm = Uxn::new(UxnRam::new().leak(), Backend::Interpreter);
+        assert!(vm.into_ram().is_none());
+    }
+
+    #[test]
+    fn threads() {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::scope(|s| {
+            let reader = s.spawn(move || rx.iter().collect::<Vec<_>>());
+            s.spawn(move || {
+                for i in 0..3 {
+                    vm.ram_write_byte(0x1234, i);
+                    vm.stack.push_byte(i);
+                    tx.send((vm.ram_read_byte(0x1234), vm.stack().len()))
+                        .unwrap();
+                }
+            });
+            assert_eq!(reader.join().unwrap(), [(0, 1), (1, 2), (2, 3)]);
+        });
+    }
+
+    mod ops {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Builds a stack with random contents and enough room for any opcode
+        fn stack() -> impl Strategy<Value = Stack> {
+            (prop::collection::vec(any::<u8>(), 256), 8u8..=200).prop_map(
+                |(d, len)| {
+                    let mut s = Stack::default();
+                    s.data.copy_from_slice(&d);
+                    s.set_len(len);
+                    s
+                },
+            )
+        }
+
+        /// VM state after executing a single opcode
+        #[derive(Debug, Eq, PartialEq)]
+        struct State {
+            next: Option<u16>,
+            stack: Stack,
+            ret: Stack,
+            dev: [u8; 256],
+            /// `(addr, value)` pairs which differ from the initial RAM pattern
+            ram: Vec<(usize, u8)>,
+        }
+
+        /// Executes every non-immediate opcode from the given stacks
+        ///
+        /// RAM is filled with a pattern before each opcode.  The result is
+        /// indexed by opcode, and is `None` for `BRK`, `LIT`, and immediate
+        /// jumps.
+        fn exec_all(stack: Stack, ret: Stack) -> Vec<Option<State>> {
+            let mut pattern = UxnRam::new();
+            for (i, r) in pattern.iter_mut().enumerate() {
+                *r = (i as u8) ^ (i >> 8) as u8;
+            }
+            let mut ram = UxnRam::new();
+            (0..=255u8)
+                .map(|op| {
+                    if op & 0x1f == 0 {
+                        return None;
+                    }
+                    ram.copy_from_slice(&*pattern);
+                    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+                    vm.stack = stack;
+                    vm.ret = ret;
+                    let next = vm.dispatch(op, &mut EmptyDevice, 0x100);
+                    let mut changed = vec![];
+                    for (p, (a, b)) in vm
+                        .ram()
+                        .chunks(256)
+                        .zip(pattern.chunks(256))
+                        .enumerate()
+                    {
+                        if a != b {
+                            changed.extend(
+                                (0..256)
+                                    .filter(|&i| a[i] != b[i])
+                                    .map(|i| (p * 256 + i, a[i])),
+                            );
+                        }
+                    }
+                    Some(State {
+                        next,
+                        stack: vm.stack,
+                        ret: vm.ret,
+                        dev: vm.dev,
+                        ram: changed,
+                    })
+                })
+                .collect()
+        }
+
+        /// Runs a sequence of opcodes on a stack built from `vals`
+        fn eval(vals: &[u8], ops: &[u8]) -> Stack {
+            let mut ram = UxnRam::new();
+            let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+            for &v in vals {
+                vm.stack.push_byte(v);
+            }
+            for &op in ops {
+                vm.dispatch(op, &mut EmptyDevice, 0x100);
+            }
+            vm.stack
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(32))]
+
+            #[test]
+            fn ret_mirrors_working(a in stack(), b in stack()) {
+                let fwd = exec_all(a, b);
+                let rev = exec_all(b, a);
+                for op in 0..=255u8 {
+                    let i = usize::from(op);
+                    let (Some(f), Some(r)) = (&fwd[i], &rev[i ^ 0x40]) else {
+                        continue;
+                    };
+                    let name = op::NAMES[i];
+                    prop_assert_eq!(f.next, r.next, "{}", name);
+                    prop_assert_eq!(f.stack, r.ret, "{}", name);
+                    prop_assert_eq!(f.ret, r.stack, "{}", name);
+                    prop_assert_eq!(f.dev, r.dev, "{}", name);
+                    prop_assert_eq!(&f.ram, &r.ram, "{}", name);
+                }
+            }
+
+            #[test]
+            fn keep_preserves_inputs(a in stack(), b in stack()) {
+                let states = exec_all(a, b);
+                for op in (0..=255u8).filter(|op| op & 0x80 != 0) {
+                    let i = usize::from(op);
+                    let (Some(k), Some(n)) = (&states[i], &states[i & !0x80])
+                    else {
+                        continue;
+                    };
+                    let name = op::NAMES[i];
+                    let (src, k_src, n_src, k_other, n_other) =
+                        if op & 0x40 != 0 {
+                            (b, k.ret, n.ret, k.stack, n.stack)
+                        } else {
+                            (a, k.stack, n.stack, k.ret, n.ret)
+                        };
+
+                    // The inputs are left in place, with the outputs on top
+                    let pushes = op::INFO[i].pushes;
+                    prop_assert_eq!(k_src.len(), src.len() + pushes, "{}", name);
+                    prop_assert_eq!(
+                        &k_src.data[..usize::from(src.len())],
+                        src.as_slice(),
+                        "{}",
+                        name
+                    );
+                    for j in 0..pushes {
+                        prop_assert_eq!(
+                            k_src.peek_byte_at(j),
+                            n_src.peek_byte_at(j),
+                            "{}",
+                            name
+                        );
+                    }
+
+                    // Everything else matches the non-keep opcode
+                    prop_assert_eq!(k.next, n.next, "{}", name);
+                    prop_assert_eq!(k_other, n_other, "{}", name);
+                    prop_assert_eq!(k.dev, n.dev, "{}", name);
+                    prop_assert_eq!(&k.ram, &n.ram, "{}", name);
+                }
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn add_sub_inverse(a in any::<u16>(), b in any::<u16>()) {
+                let [a0, a1] = a.to_be_bytes();
+                let [b0, b1] = b.to_be_bytes();
+                let s = eval(&[b1, a1, b1], &[op::ADD, op::SWP, op::SUB]);
+                prop_assert_eq!(s.as_slice(), &[a1]);
+
+                let s = eval(
+                    &[b0, b1, a0, a1, b0, b1],
+                    &[op::ADD2, op::SWP2, op::SUB2],
+                );
+                prop_assert_eq!(s.as_slice(), &[a0, a1]);
+                let s = eval(&[a0, a1, b0, b1], &[op::SUB2k, op::ADD2]);
+                prop_assert_eq!(s.as_slice(), &[a0, a1, a0, a1]);
+            }
+
+            #[test]
+            fn sft_identities(a in any::<u16>(), n in 0u8..16) {
+                let [a0, a1] = a.to_be_bytes();
+                let s = eval(&[a1, 0x00], &[op::SFT]);
+                prop_assert_eq!(s.as_slice(), &[a1]);
+                let s = eval(&[a0, a1, 0x00], &[op::SFT2]);
+                prop_assert_eq!(s.as_slice(), &[a0, a1]);
+
+                // Shifting right then left clears the low bits
+                let s = eval(&[a0, a1, (n << 4) | n], &[op::SFT2]);
+                let mask = u16::MAX.checked_shl(u32::from(n)).unwrap_or(0);
+                prop_assert_eq!(s.as_slice(), &(a & mask).to_be_bytes());
+                if n < 8 {
+                    let s = eval(&[a1, (n << 4) | n], &[op::SFT]);
+                    prop_assert_eq!(s.as_slice(), &[a1 & (0xff << n)]);
+                }
+            }
+
+            #[test]
+            fn comparisons(a in any::<u8>(), b in any::<u8>()) {
+                let equ = eval(&[a, b], &[op::EQU]).peek_byte_at(0);
+                let neq = eval(&[a, b], &[op::NEQ]).peek_byte_at(0);
+                prop_assert_eq!(equ ^ neq, 1);
+                let gth = eval(&[a, b], &[op::GTH]).peek_byte_at(0);
+                let lth = eval(&[b, a], &[op::LTH]).peek_byte_at(0);
+                prop_assert_eq!(gth, lth);
+            }
+        }
+    }
+
+    mod stack {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Reference model for the stack, tracking length instead of index
+        struct Model {
+            data: [u8; 256],
+            len: u8,
+        }
+
+        impl Model {
+            fn new(s: &Stack) -> Self {
+                Self {
+                    data: s.data,
+                    len: s.len(),
+                }
+            }
+            fn push(&mut self, v: u8) {
+                self.data[usize::from(self.len)] = v;
+                self.len = self.len.wrapping_add(1);
+            }
+            fn pop(&mut self) -> u8 {
+                self.len = self.len.wrapping_sub(1);
+                self.data[usize::from(self.len)]
+            }
+            fn peek(&self, i: u8) -> u8 {
+                self.data[usize::from(self.len.wrapping_sub(1).wrapping_sub(i))]
+            }
+        }
+
+        #[derive(Clone, Debug)]
+        enum Op {
+            PushByte(u8),
+            PushShort(u16),
+            PopByte,
+            PopShort,
+            Reserve(u8),
+            SetLen(u8),
+        }
+
+        fn op() -> impl Strategy<Value = Op> {
+            prop_oneof![
+                any::<u8>().prop_map(Op::PushByte),
+                any::<u16>().prop_map(Op::PushShort),
+                Just(Op::PopByte),
+                Just(Op::PopShort),
+                any::<u8>().prop_map(Op::Reserve),
+                any::<u8>().prop_map(Op::SetLen),
+            ]
+        }
+
+        fn stack() -> impl Strategy<Value = Stack> {
+            (prop::array::uniform32(any::<u8>()), any::<u8>()).prop_map(
+                |(d, len)| {
+                    let mut s = Stack::default();
+                    for (i, b) in s.data.iter_mut().enumerate() {
+                        *b = d[i % d.len()] ^ i as u8;
+                    }
+                    s.set_len(len);
+                    s
+                },
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn matches_model(
+                mut s in stack(),
+                ops in prop::collection::vec(op(), 0..600),
+            ) {
+                let mut m = Model::new(&s);
+                for op in ops {
+                    match op {
+                        Op::PushByte(v) => {
+                            s.push_byte(v);
+                            m.push(v);
+                        }
+                        Op::PushShort(v) => {
+                            s.push_short(v);
+                            let [lo, hi] = v.to_le_bytes();
+                            m.push(hi);
+                            m.push(lo);
+                        }
+                        Op::PopByte => {
+                            prop_assert_eq!(s.pop_byte(), m.pop());
+                        }
+                        Op::PopShort => {
+                            let lo = m.pop();
+                            let hi = m.pop();
+                            prop_assert_eq!(
+                                s.pop_short(),
+                                u16::from_le_bytes([lo, hi])
+                            );
+                        }
+                        Op::Reserve(n) => {
+                            s.reserve(n);
+                            m.len = m.len.wrapping_add(n);
+                        }
+                        Op::SetLen(n) => {
+                            s.set_len(n);
+                            m.len = n;
+                        }
+                    }
+                    prop_assert_eq!(s.len(), m.len);
+                    prop_assert_eq!(s.is_empty(), m.len == 0);
+                    prop_assert_eq!(s.data, m.data);
+                }
+            }
+
+            #[test]
+            fn peek(s in stack(), i in any::<u8>()) {
+                let m = Model::new(&s);
+                prop_assert_eq!(s.peek_byte_at(i), m.peek(i));
+                prop_assert_eq!(
+                    s.peek_short_at(i),
+                    u16::from_le_bytes([m.peek(i), m.peek(i.wrapping_add(1))])
+                );
+            }
+
+            #[test]
+            fn push_pop_roundtrip(
+                mut s in stack(),
+                vs in prop::collection::vec(any::<u8>(), 0..=256),
+            ) {
+                let len = s.len();
+                for &v in &vs {
+                    s.push_byte(v);
+                }
+                prop_assert_eq!(s.len(), len.wrapping_add(vs.len() as u8));
+                for &v in vs.iter().rev() {
+                    prop_assert_eq!(s.pop_byte(), v);
+                }
+                prop_assert_eq!(s.len(), len);
+            }
+
+            #[test]
+            fn reserve_emplace(
+                mut s in stack(),
+                v in any::<u16>(),
+            ) {
+                let mut t = s;
+                s.push_short(v);
+                t.reserve(2);
+                t.emplace_short(v);
+                prop_assert_eq!(s, t);
+
+                let [b, _] = v.to_le_bytes();
+                s.push_byte(b);
+                t.reserve(1);
+                t.emplace_byte(b);
+                prop_assert_eq!(s, t);
+            }
+        }
+
+        #[test]
+        fn inspect() {
+            let mut s = Stack::default();
+            assert_eq!(s.as_slice(), &[]);
+            assert_eq!(format!("{s}"), "|");
+            s.push_byte(0x12);
+            s.push_short(0x3456);
+            assert_eq!(s.as_slice(), &[0x12, 0x34, 0x56]);
+            assert_eq!(s.iter().rev().collect::<Vec<_>>(), [0x56, 0x34, 0x12]);
+            assert_eq!(s.iter().len(), 3);
+            assert_eq!(format!("{s}"), "12 34 56|");
+            assert_eq!(format!("{s:?}"), "Stack(12 34 56|)");
+            assert_eq!(s.len(), 3);
+        }
+
+        #[test]
+        fn wrapping() {
+            let mut s = Stack::default();
+            assert!(s.is_empty());
+            s.data[255] = 0xab;
+            assert_eq!(s.pop_byte(), 0xab);
+            assert_eq!(s.len(), 255);
+
+            let mut s = Stack::default();
+            for i in 0..=255 {
+                s.push_byte(i);
+            }
+            assert!(s.is_empty());
+            assert_eq!(s.peek_byte_at(0), 255);
+        }
+    }
+
     // The optimizer is not strong enough to eliminate panics in debug builds!
     #[cfg(not(debug_assertions))]
     mod no_panic {
@@ -10,6 +10,20 @@
 // x9-15 - scratch registers
 //
 // We do not use any callee-saved registers (besides x29 / x30)
+//
+// Branch target identification (BTI) and pointer authentication (PAC) are
+// supported: every opcode is a `bti j` landing pad for the indirect branch in
+// `next`, and the entry point is a `bti c` landing pad which signs /
+// authenticates its return address.  These instructions are encoded as hints,
+// so they're no-ops on older cores.  No code is generated or modified at
+// runtime, so the backend also works under strict W^X policies (e.g. the macOS
+// hardened runtime, without `MAP_JIT`).
+
+// Landing pad for indirect branches (`bti j`)
+.macro landing_pad
+    hint #36
+.endm
+
 .macro next
     ldrb w9, [x4, x5]
     add x5, x5, #1
@@ -82,6 +96,8 @@
 .endm
 
 ENTRY aarch64_entry
+    hint #34                    // bti c: landing pad for calls
+    hint #25                    // paciasp: sign the return address in x30
     sub sp, sp, #0x200          // make room in the stack
     stp   x29, x30, [sp, 0x0]   // store stack and frame pointer
     mov   x29, sp
@@ -96,6 +112,7 @@ ENTRY aarch64_entry
     next
 
 _BRK:
+    landing_pad
     // Write index values back through index pointers
     ldp x9, x10, [sp, 0x10]     // restore stack index pointers
     strb w1, [x9]               // save stack index
@@ -103,27 +120,32 @@ _BRK:
 
     ldp   x29, x30, [sp, 0x0]   // Restore stack and frame pointer
     add sp, sp, #0x200  // restore stack pointer
+    hint #29            // autiasp: authenticate the return address in x30
 
     mov x0, x5 // return PC from function
     ret
 
 _INC:
+    landing_pad
     ldrb w9, [x0, x1]
     add w9, w9, #1
     strb w9, [x0, x1]
     next
 
 _POP:
+    landing_pad
     pop
     next
 
 _NIP:
+    landing_pad
     ldrb w9, [x0, x1]   // get the top byte
     pop
     strb w9, [x0, x1]   // overwrite the previous byte
     next
 
 _SWP:
+    landing_pad
     ldrb w10, [x0, x1]   // get the top byte
     peek w11, x9, 1      // get the second-from-top byte
     strb w10, [x0, x9]   // do the swap!
@@ -131,6 +153,7 @@ _SWP:
     next
 
 _ROT:
+    landing_pad
     // a b c -- b c a
     ldrb w10, [x0, x1] // c
     peek w12, x11, 1
@@ -143,11 +166,13 @@ _ROT:
     next
 
 _DUP:
+    landing_pad
     ldrb w10, [x0, x1]   // get the top byte
     push w10
     next
 
 _OVR:
+    landing_pad
     peek w10, x10, 1
     push w10
     next
@@ -163,18 +188,23 @@ _OVR:
 .endm
 
 _EQU:
+    landing_pad
     compare_op eq
 
 _NEQ:
+    landing_pad
     compare_op ne
 
 _GTH:
+    landing_pad
     compare_op hi
 
 _LTH:
+    landing_pad
     compare_op lo
 
 _JMP:
+    landing_pad
     ldrsb x9, [x0, x1]
     pop
     add x5, x5, x9
@@ -182,6 +212,7 @@ _JMP:
     next
 
 _JCN:
+    landing_pad
     ldrsb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -193,6 +224,7 @@ _JCN:
     next
 
 _JSR:
+    landing_pad
     ldrsb w9, [x0, x1]
     pop
     lsr w10, w5, 8
@@ -203,12 +235,14 @@ _JSR:
     next
 
 _STH:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     rpush w9
     next
 
 _LDZ:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w9, [x4, x9]
@@ -216,6 +250,7 @@ _LDZ:
     next
 
 _STZ:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -224,6 +259,7 @@ _STZ:
     next
 
 _LDR:
+    landing_pad
     ldrsb w9, [x0, x1]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -232,6 +268,7 @@ _LDR:
     next
 
 _STR:
+    landing_pad
     ldrsb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -242,6 +279,7 @@ _STR:
     next
 
 _LDA:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -251,6 +289,7 @@ _LDA:
     next
 
 _STA:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -262,15 +301,21 @@ _STA:
     next
 
 _DEI:
+    landing_pad
     precall
     CALL dei_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEO:
+    landing_pad
     precall
-    CALL deo_entry // todo check return value for early exit?
+    CALL deo_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_op op
@@ -283,27 +328,35 @@ _DEO:
 .endm
 
 _ADD:
+    landing_pad
     binary_op add
 
 _SUB:
+    landing_pad
     binary_op sub
 
 _MUL:
+    landing_pad
     binary_op mul
 
 _DIV:
+    landing_pad
     binary_op udiv
 
 _AND:
+    landing_pad
     binary_op and
 
 _ORA:
+    landing_pad
     binary_op orr
 
 _EOR:
+    landing_pad
     binary_op eor
 
 _SFT:
+    landing_pad
     ldrb w10, [x0, x1]
     pop
     ldrb w11, [x0, x1]
@@ -315,6 +368,7 @@ _SFT:
     next
 
 _JCI:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -331,6 +385,7 @@ _JCI:
     next
 
 _INC2:
+    landing_pad
     ldrb w10, [x0, x1]  // get the top byte
     peek w11, x9, 1     // get the second-from-top byte
     orr w12, w10, w11, lsl #8
@@ -342,11 +397,13 @@ _INC2:
     next
 
 _POP2:
+    landing_pad
     sub x1, x1, #2
     and x1, x1, #0xff
     next
 
 _NIP2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -358,6 +415,7 @@ _NIP2:
     next
 
 _SWP2:
+    landing_pad
     ldrb w11, [x0, x1]   // get the top byte
     peek w12, x9, 2       // get the second-from-top byte
     strb w11, [x0, x9]   // do the swap!
@@ -371,6 +429,7 @@ _SWP2:
     next
 
 _ROT2:
+    landing_pad
     ldrb w10, [x0, x1]
     peek w12, x11, 2
     peek w14, x13, 4
@@ -388,6 +447,7 @@ _ROT2:
     next
 
 _DUP2:
+    landing_pad
     ldrb w11, [x0, x1]
     peek w10, x10, 1
     push w10
@@ -395,6 +455,7 @@ _DUP2:
     next
 
 _OVR2:
+    landing_pad
     peek w10, x9, 2
     peek w11, x9, 3
     push w11
@@ -418,18 +479,23 @@ _OVR2:
 .endm
 
 _EQU2:
+    landing_pad
     compare_op2 eq
 
 _NEQ2:
+    landing_pad
     compare_op2 ne
 
 _GTH2:
+    landing_pad
     compare_op2 hi
 
 _LTH2:
+    landing_pad
     compare_op2 lo
 
 _JMP2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -438,6 +504,7 @@ _JMP2:
     next
 
 _JCN2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -450,6 +517,7 @@ _JCN2:
     next
 
 _JSR2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -461,6 +529,7 @@ _JSR2:
     next
 
 _STH2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -470,6 +539,7 @@ _STH2:
     next
 
 _LDZ2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x4, x9]
@@ -481,6 +551,7 @@ _LDZ2:
     next
 
 _STZ2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -494,6 +565,7 @@ _STZ2:
     next
 
 _LDR2:
+    landing_pad
     ldrsb w9, [x0, x1]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -506,6 +578,7 @@ _LDR2:
     next
 
 _STR2:
+    landing_pad
     ldrsb w9, [x0, x1]
     pop
     ldrsb w10, [x0, x1]
@@ -521,6 +594,7 @@ _STR2:
     next
 
 _LDA2:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x12, 1
     orr w9, w9, w10, lsl #8
@@ -534,6 +608,7 @@ _LDA2:
     next
 
 _STA2:
+    landing_pad
     ldrb w9, [x0, x1]
     pop
     ldrb w10, [x0, x1]
@@ -550,15 +625,21 @@ _STA2:
     next
 
 _DEI2:
+    landing_pad
     precall
     CALL dei_2_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEO2:
+    landing_pad
     precall
-    CALL deo_2_entry // todo check return value for early exit?
+    CALL deo_2_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_op2 op
@@ -581,27 +662,35 @@ _DEO2:
 .endm
 
 _ADD2:
+    landing_pad
     binary_op2 add
 
 _SUB2:
+    landing_pad
     binary_op2 sub
 
 _MUL2:
+    landing_pad
     binary_op2 mul
 
 _DIV2:
+    landing_pad
     binary_op2 udiv
 
 _AND2:
+    landing_pad
     binary_op2 and
 
 _ORA2:
+    landing_pad
     binary_op2 orr
 
 _EOR2:
+    landing_pad
     binary_op2 eor
 
 _SFT2:
+    landing_pad
     ldrb w10, [x0, x1]
     pop
     ldrb w11, [x0, x1]
@@ -619,6 +708,7 @@ _SFT2:
     next
 
 _JMI:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -631,23 +721,27 @@ _JMI:
     next
 
 _INCr:
+    landing_pad
     ldrb w9, [x2, x3]
     add w9, w9, #1
     strb w9, [x2, x3]
     next
 
 _POPr:
+    landing_pad
     sub x3, x3, #1
     and x3, x3, #0xff
     next
 
 _NIPr:
+    landing_pad
     ldrb w9, [x2, x3]   // get the top byte
     rpop
     strb w9, [x2, x3]   // overwrite the previous byte
     next
 
 _SWPr:
+    landing_pad
     ldrb w10, [x2, x3]  // get the top byte
     rpeek w11, x9, 1    // get the second-from-top byte
     strb w10, [x2, x9]  // do the swap!
@@ -655,6 +749,7 @@ _SWPr:
     next
 
 _ROTr:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w12, x11, 1
     rpeek w14, x13, 2
@@ -665,11 +760,13 @@ _ROTr:
     next
 
 _DUPr:
+    landing_pad
     ldrb w10, [x2, x3]   // get the top byte
     rpush w10
     next
 
 _OVRr:
+    landing_pad
     rpeek w10, x9, 1
     rpush w10
     next
@@ -685,18 +782,23 @@ _OVRr:
 .endm
 
 _EQUr:
+    landing_pad
     compare_opr eq
 
 _NEQr:
+    landing_pad
     compare_opr ne
 
 _GTHr:
+    landing_pad
     compare_opr hi
 
 _LTHr:
+    landing_pad
     compare_opr lo
 
 _JMPr:
+    landing_pad
     ldrsb x9, [x2, x3]
     rpop
     add x5, x5, x9
@@ -704,6 +806,7 @@ _JMPr:
     next
 
 _JCNr:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -715,6 +818,7 @@ _JCNr:
     next
 
 _JSRr:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpop
     lsr w10, w5, 8
@@ -725,12 +829,14 @@ _JSRr:
     next
 
 _STHr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     push w9
     next
 
 _LDZr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w9, [x4, x9]
@@ -738,6 +844,7 @@ _LDZr:
     next
 
 _STZr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -746,6 +853,7 @@ _STZr:
     next
 
 _LDRr:
+    landing_pad
     ldrsb w9, [x2, x3]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -754,6 +862,7 @@ _LDRr:
     next
 
 _STRr:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -764,6 +873,7 @@ _STRr:
     next
 
 _LDAr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -773,6 +883,7 @@ _LDAr:
     next
 
 _STAr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -784,15 +895,21 @@ _STAr:
     next
 
 _DEIr:
+    landing_pad
     precall
     CALL dei_r_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEOr:
+    landing_pad
     precall
-    CALL deo_r_entry // todo check return value for early exit?
+    CALL deo_r_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_opr op
@@ -805,27 +922,35 @@ _DEOr:
 .endm
 
 _ADDr:
+    landing_pad
     binary_opr add
 
 _SUBr:
+    landing_pad
     binary_opr sub
 
 _MULr:
+    landing_pad
     binary_opr mul
 
 _DIVr:
+    landing_pad
     binary_opr udiv
 
 _ANDr:
+    landing_pad
     binary_opr and
 
 _ORAr:
+    landing_pad
     binary_opr orr
 
 _EORr:
+    landing_pad
     binary_opr eor
 
 _SFTr:
+    landing_pad
     ldrb w10, [x2, x3]
     rpop
     ldrb w11, [x2, x3]
@@ -837,6 +962,7 @@ _SFTr:
     next
 
 _JSI:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -856,6 +982,7 @@ _JSI:
     next
 
 _INC2r:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w11, x9, 1
     orr w12, w10, w11, lsl #8
@@ -867,11 +994,13 @@ _INC2r:
     next
 
 _POP2r:
+    landing_pad
     sub x3, x3, #2
     and x3, x3, #0xff
     next
 
 _NIP2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -883,6 +1012,7 @@ _NIP2r:
     next
 
 _SWP2r:
+    landing_pad
     ldrb w11, [x2, x3]  // get the top byte
     rpeek w12, x9, 2    // get the second-from-top byte
     strb w11, [x2, x9]  // do the swap!
@@ -897,6 +1027,7 @@ _SWP2r:
     next
 
 _ROT2r:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w12, x11, 2
     rpeek w14, x13, 4
@@ -917,6 +1048,7 @@ _ROT2r:
     next
 
 _DUP2r:
+    landing_pad
     ldrb w11, [x2, x3]
     sub w9, w3, #1
     and w9, w9, #0xff
@@ -926,6 +1058,7 @@ _DUP2r:
     next
 
 _OVR2r:
+    landing_pad
     rpeek w10, x9, 2
     rpeek w11, x9, 3
     rpush w11
@@ -949,18 +1082,23 @@ _OVR2r:
 .endm
 
 _EQU2r:
+    landing_pad
     compare_op2r eq
 
 _NEQ2r:
+    landing_pad
     compare_op2r ne
 
 _GTH2r:
+    landing_pad
     compare_op2r hi
 
 _LTH2r:
+    landing_pad
     compare_op2r lo
 
 _JMP2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -969,6 +1107,7 @@ _JMP2r:
     next
 
 _JCN2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -981,6 +1120,7 @@ _JCN2r:
     next
 
 _JSR2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -992,6 +1132,7 @@ _JSR2r:
     next
 
 _STH2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -1001,6 +1142,7 @@ _STH2r:
     next
 
 _LDZ2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x4, x9]
@@ -1012,6 +1154,7 @@ _LDZ2r:
     next
 
 _STZ2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -1025,6 +1168,7 @@ _STZ2r:
     next
 
 _LDR2r:
+    landing_pad
     ldrsb w9, [x2, x3]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -1037,6 +1181,7 @@ _LDR2r:
     next
 
 _STR2r:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpop
     ldrsb w10, [x2, x3]
@@ -1052,6 +1197,7 @@ _STR2r:
     next
 
 _LDA2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -1065,6 +1211,7 @@ _LDA2r:
     next
 
 _STA2r:
+    landing_pad
     ldrb w9, [x2, x3]
     rpop
     ldrb w10, [x2, x3]
@@ -1081,15 +1228,21 @@ _STA2r:
     next
 
 _DEI2r:
+    landing_pad
     precall
     CALL dei_2r_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEO2r:
+    landing_pad
     precall
-    CALL deo_2r_entry // todo check return value for early exit?
+    CALL deo_2r_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_op2r op
@@ -1112,27 +1265,35 @@ _DEO2r:
 .endm
 
 _ADD2r:
+    landing_pad
     binary_op2r add
 
 _SUB2r:
+    landing_pad
     binary_op2r sub
 
 _MUL2r:
+    landing_pad
     binary_op2r mul
 
 _DIV2r:
+    landing_pad
     binary_op2r udiv
 
 _AND2r:
+    landing_pad
     binary_op2r and
 
 _ORA2r:
+    landing_pad
     binary_op2r orr
 
 _EOR2r:
+    landing_pad
     binary_op2r eor
 
 _SFT2r:
+    landing_pad
     ldrb w10, [x2, x3]
     rpop
     ldrb w11, [x2, x3]
@@ -1150,6 +1311,7 @@ _SFT2r:
     next
 
 _LIT:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -1157,20 +1319,24 @@ _LIT:
     next
 
 _INCk:
+    landing_pad
     ldrb w9, [x0, x1]
     add w9, w9, #1
     push w9
     next
 
 _POPk:
+    landing_pad
     next
 
 _NIPk:
+    landing_pad
     ldrb w9, [x0, x1]
     push w9
     next
 
 _SWPk:
+    landing_pad
     ldrb w10, [x0, x1]   // get the top byte
     peek w11, x9, 1      // get the second-from-top byte
     push w10
@@ -1178,6 +1344,7 @@ _SWPk:
     next
 
 _ROTk:
+    landing_pad
     ldrb w13, [x0, x1]
     peek w10, x11, 1
     push w10
@@ -1187,12 +1354,14 @@ _ROTk:
     next
 
 _DUPk:
+    landing_pad
     ldrb w11, [x0, x1]
     push w11
     push w11
     next
 
 _OVRk:
+    landing_pad
     peek w10, x9, 1 // get the second-from-top
     ldrb w11, [x0, x1]
     push w10
@@ -1210,24 +1379,30 @@ _OVRk:
 .endm
 
 _EQUk:
+    landing_pad
     compare_opk eq
 
 _NEQk:
+    landing_pad
     compare_opk ne
 
 _GTHk:
+    landing_pad
     compare_opk hi
 
 _LTHk:
+    landing_pad
     compare_opk lo
 
 _JMPk:
+    landing_pad
     ldrsb x9, [x0, x1]
     add x5, x5, x9
     and x5, x5, 0xffff
     next
 
 _JCNk:
+    landing_pad
     ldrsb w9, [x0, x1]
     peek w10, x10, 1
     cmp w10, #0
@@ -1237,6 +1412,7 @@ _JCNk:
     next
 
 _JSRk:
+    landing_pad
     ldrsb w9, [x0, x1]
     lsr w10, w5, 8
     rpush w10
@@ -1246,23 +1422,27 @@ _JSRk:
     next
 
 _STHk:
+    landing_pad
     ldrb w9, [x0, x1]
     rpush w9
     next
 
 _LDZk:
+    landing_pad
     ldrb w9, [x0, x1]
     ldrb w9, [x4, x9]
     push w9
     next
 
 _STZk:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     strb w10, [x4, x9]
     next
 
 _LDRk:
+    landing_pad
     ldrsb w9, [x0, x1]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -1271,6 +1451,7 @@ _LDRk:
     next
 
 _STRk:
+    landing_pad
     ldrsb w9, [x0, x1]
     peek w10, x10, 1
     add x9, x5, x9
@@ -1279,6 +1460,7 @@ _STRk:
     next
 
 _LDAk:
+    landing_pad
     ldrb w9, [x0, x1]
     sub w10, w1, #1
     and w10, w10, #0xff
@@ -1289,6 +1471,7 @@ _LDAk:
     next
 
 _STAk:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     orr w12, w9, w10, lsl #8
@@ -1297,15 +1480,21 @@ _STAk:
     next
 
 _DEIk:
+    landing_pad
     precall
     CALL dei_k_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEOk:
+    landing_pad
     precall
-    CALL deo_k_entry // todo check return value for early exit?
+    CALL deo_k_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_opk op
@@ -1317,27 +1506,35 @@ _DEOk:
 .endm
 
 _ADDk:
+    landing_pad
     binary_opk add
 
 _SUBk:
+    landing_pad
     binary_opk sub
 
 _MULk:
+    landing_pad
     binary_opk mul
 
 _DIVk:
+    landing_pad
     binary_opk udiv
 
 _ANDk:
+    landing_pad
     binary_opk and
 
 _ORAk:
+    landing_pad
     binary_opk orr
 
 _EORk:
+    landing_pad
     binary_opk eor
 
 _SFTk:
+    landing_pad
     ldrb w10, [x0, x1]
     peek w11, x9, 1
     lsr w12, w10, 4
@@ -1348,6 +1545,7 @@ _SFTk:
     next
 
 _LIT2:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -1359,6 +1557,7 @@ _LIT2:
     next
 
 _INC2k:
+    landing_pad
     ldrb w10, [x0, x1]
     peek w11, x9, 1
     orr w12, w10, w11, lsl #8
@@ -1375,9 +1574,11 @@ _INC2k:
     next
 
 _POP2k:
+    landing_pad
     next
 
 _NIP2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x11, 1
     push w10
@@ -1385,6 +1586,7 @@ _NIP2k:
     next
 
 _SWP2k:
+    landing_pad
     peek w11, x9, 1
     push w11
     peek w11, x9, 1
@@ -1396,6 +1598,7 @@ _SWP2k:
     next
 
 _ROT2k:
+    landing_pad
     peek w11, x9, 3
     push w11
     peek w11, x9, 3
@@ -1411,6 +1614,7 @@ _ROT2k:
     next
 
 _DUP2k:
+    landing_pad
     ldrb w11, [x0, x1]
     sub w9, w1, #1
     and w9, w9, #0xff
@@ -1423,6 +1627,7 @@ _DUP2k:
     next
 
 _OVR2k:
+    landing_pad
     ldrb w10, [x0, x1]
     peek w11, x9, 1
     peek w12, x9, 2
@@ -1451,24 +1656,30 @@ _OVR2k:
 .endm
 
 _EQU2k:
+    landing_pad
     compare_op2k eq
 
 _NEQ2k:
+    landing_pad
     compare_op2k ne
 
 _GTH2k:
+    landing_pad
     compare_op2k hi
 
 _LTH2k:
+    landing_pad
     compare_op2k lo
 
 _JMP2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     orr w5, w9, w10, lsl #8 // update program counter
     next
 
 _JCN2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x12, 1
     peek w11, x12, 2
@@ -1479,6 +1690,7 @@ _JCN2k:
     next
 
 _JSR2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
 
@@ -1490,6 +1702,7 @@ _JSR2k:
     next
 
 _STH2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     rpush w10
@@ -1497,6 +1710,7 @@ _STH2k:
     next
 
 _LDZ2k:
+    landing_pad
     ldrb w9, [x0, x1]
     ldrb w10, [x4, x9]
     push w10
@@ -1507,6 +1721,7 @@ _LDZ2k:
     next
 
 _STZ2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     peek w11, x11, 2
@@ -1518,6 +1733,7 @@ _STZ2k:
     next
 
 _LDR2k:
+    landing_pad
     ldrsb w9, [x0, x1]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -1530,6 +1746,7 @@ _LDR2k:
     next
 
 _STR2k:
+    landing_pad
     ldrsb w9, [x0, x1]
     peek w10, x10, 1
     peek w11, x11, 2
@@ -1543,6 +1760,7 @@ _STR2k:
     next
 
 _LDA2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     orr w12, w9, w10, lsl #8
@@ -1555,6 +1773,7 @@ _LDA2k:
     next
 
 _STA2k:
+    landing_pad
     ldrb w9, [x0, x1]
     peek w10, x10, 1
     orr w12, w9, w10, lsl #8
@@ -1569,15 +1788,21 @@ _STA2k:
     next
 
 _DEI2k:
+    landing_pad
     precall
     CALL dei_2k_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEO2k:
+    landing_pad
     precall
-    CALL deo_2k_entry // todo check return value for early exit?
+    CALL deo_2k_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_op2k op
@@ -1597,27 +1822,35 @@ _DEO2k:
 .endm
 
 _ADD2k:
+    landing_pad
     binary_op2k add
 
 _SUB2k:
+    landing_pad
     binary_op2k sub
 
 _MUL2k:
+    landing_pad
     binary_op2k mul
 
 _DIV2k:
+    landing_pad
     binary_op2k udiv
 
 _AND2k:
+    landing_pad
     binary_op2k and
 
 _ORA2k:
+    landing_pad
     binary_op2k orr
 
 _EOR2k:
+    landing_pad
     binary_op2k eor
 
 _SFT2k:
+    landing_pad
     ldrb w10, [x0, x1]
     peek w11, x9, 1
     peek w12, x9, 2
@@ -1633,6 +1866,7 @@ _SFT2k:
     next
 
 _LITr:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -1640,20 +1874,24 @@ _LITr:
     next
 
 _INCkr:
+    landing_pad
     ldrb w9, [x2, x3]
     add w9, w9, #1
     rpush w9
     next
 
 _POPkr:
+    landing_pad
     next
 
 _NIPkr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpush w9
     next
 
 _SWPkr:
+    landing_pad
     ldrb w10, [x2, x3]   // get the top byte
     rpeek w11, x9, 1
     rpush w10
@@ -1661,6 +1899,7 @@ _SWPkr:
     next
 
 _ROTkr:
+    landing_pad
     ldrb w13, [x2, x3]
     rpeek w10, x11, 1
     rpush w10
@@ -1670,12 +1909,14 @@ _ROTkr:
     next
 
 _DUPkr:
+    landing_pad
     ldrb w11, [x2, x3]
     rpush w11
     rpush w11
     next
 
 _OVRkr:
+    landing_pad
     rpeek w10, x9, 1
     ldrb w11, [x2, x3]
     rpush w10
@@ -1693,24 +1934,30 @@ _OVRkr:
 .endm
 
 _EQUkr:
+    landing_pad
     compare_opkr eq
 
 _NEQkr:
+    landing_pad
     compare_opkr ne
 
 _GTHkr:
+    landing_pad
     compare_opkr hi
 
 _LTHkr:
+    landing_pad
     compare_opkr lo
 
 _JMPkr:
+    landing_pad
     ldrsb x9, [x2, x3]
     add x5, x5, x9
     and x5, x5, 0xffff
     next
 
 _JCNkr:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpeek w10, x10, 1
     cmp w10, #0
@@ -1720,6 +1967,7 @@ _JCNkr:
     next
 
 _JSRkr:
+    landing_pad
     ldrsb w9, [x2, x3]
     lsr w10, w5, 8
     push w10
@@ -1729,23 +1977,27 @@ _JSRkr:
     next
 
 _STHkr:
+    landing_pad
     ldrb w9, [x2, x3]
     push w9
     next
 
 _LDZkr:
+    landing_pad
     ldrb w9, [x2, x3]
     ldrb w9, [x4, x9]
     rpush w9
     next
 
 _STZkr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     strb w10, [x4, x9]
     next
 
 _LDRkr:
+    landing_pad
     ldrsb w9, [x2, x3]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -1754,6 +2006,7 @@ _LDRkr:
     next
 
 _STRkr:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpeek w10, x10, 1
     add x9, x5, x9
@@ -1762,6 +2015,7 @@ _STRkr:
     next
 
 _LDAkr:
+    landing_pad
     ldrb w9, [x2, x3]
     sub w10, w3, #1
     and w10, w10, #0xff
@@ -1772,6 +2026,7 @@ _LDAkr:
     next
 
 _STAkr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     orr w12, w9, w10, lsl #8
@@ -1780,15 +2035,21 @@ _STAkr:
     next
 
 _DEIkr:
+    landing_pad
     precall
     CALL dei_kr_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEOkr:
+    landing_pad
     precall
-    CALL deo_kr_entry // todo check return value for early exit?
+    CALL deo_kr_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_opkr op
@@ -1800,27 +2061,35 @@ _DEOkr:
 .endm
 
 _ADDkr:
+    landing_pad
     binary_opkr add
 
 _SUBkr:
+    landing_pad
     binary_opkr sub
 
 _MULkr:
+    landing_pad
     binary_opkr mul
 
 _DIVkr:
+    landing_pad
     binary_opkr udiv
 
 _ANDkr:
+    landing_pad
     binary_opkr and
 
 _ORAkr:
+    landing_pad
     binary_opkr orr
 
 _EORkr:
+    landing_pad
     binary_opkr eor
 
 _SFTkr:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w11, x9, 1
     lsr w12, w10, 4
@@ -1831,6 +2100,7 @@ _SFTkr:
     next
 
 _LIT2r:
+    landing_pad
     ldrb w9, [x4, x5]
     add x5, x5, #1
     and x5, x5, #0xffff
@@ -1842,6 +2112,7 @@ _LIT2r:
     next
 
 _INC2kr:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w11, x9, 1
     orr w12, w10, w11, lsl #8
@@ -1858,9 +2129,11 @@ _INC2kr:
     next
 
 _POP2kr:
+    landing_pad
     next
 
 _NIP2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x11, 1
     rpush w10
@@ -1868,6 +2141,7 @@ _NIP2kr:
     next
 
 _SWP2kr:
+    landing_pad
     rpeek w11, x9, 1
     rpush w11
     rpeek w11, x9, 1
@@ -1879,6 +2153,7 @@ _SWP2kr:
     next
 
 _ROT2kr:
+    landing_pad
     rpeek w11, x9, 3
     rpush w11
     rpeek w11, x9, 3
@@ -1894,6 +2169,7 @@ _ROT2kr:
     next
 
 _DUP2kr:
+    landing_pad
     ldrb w11, [x2, x3]
     sub w9, w3, #1
     and w9, w9, #0xff
@@ -1906,6 +2182,7 @@ _DUP2kr:
     next
 
 _OVR2kr:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w11, x9, 1
     rpeek w12, x9, 2
@@ -1932,24 +2209,30 @@ _OVR2kr:
 .endm
 
 _EQU2kr:
+    landing_pad
     compare_op2kr eq
 
 _NEQ2kr:
+    landing_pad
     compare_op2kr ne
 
 _GTH2kr:
+    landing_pad
     compare_op2kr hi
 
 _LTH2kr:
+    landing_pad
     compare_op2kr lo
 
 _JMP2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     orr w5, w9, w10, lsl #8 // update program counter
     next
 
 _JCN2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x12, 1
     rpeek w11, x12, 2
@@ -1959,6 +2242,7 @@ _JCN2kr:
     next
 
 _JSR2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     lsr w11, w5, 8
@@ -1968,6 +2252,7 @@ _JSR2kr:
     next
 
 _STH2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x11, 1
     push w10
@@ -1975,6 +2260,7 @@ _STH2kr:
     next
 
 _LDZ2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     ldrb w10, [x4, x9]
     rpush w10
@@ -1985,6 +2271,7 @@ _LDZ2kr:
     next
 
 _STZ2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     rpeek w11, x11, 2
@@ -1996,6 +2283,7 @@ _STZ2kr:
     next
 
 _LDR2kr:
+    landing_pad
     ldrsb w9, [x2, x3]
     add x9, x5, x9
     and x9, x9, #0xffff
@@ -2008,6 +2296,7 @@ _LDR2kr:
     next
 
 _STR2kr:
+    landing_pad
     ldrsb w9, [x2, x3]
     rpeek w10, x10, 1
     rpeek w11, x11, 2
@@ -2021,6 +2310,7 @@ _STR2kr:
     next
 
 _LDA2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     orr w12, w9, w10, lsl #8
@@ -2033,6 +2323,7 @@ _LDA2kr:
     next
 
 _STA2kr:
+    landing_pad
     ldrb w9, [x2, x3]
     rpeek w10, x10, 1
     orr w12, w9, w10, lsl #8
@@ -2047,15 +2338,21 @@ _STA2kr:
     next
 
 _DEI2kr:
+    landing_pad
     precall
     CALL dei_2kr_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 _DEO2kr:
+    landing_pad
     precall
-    CALL deo_2kr_entry // todo check return value for early exit?
+    CALL deo_2kr_entry
+    and w9, w0, #0xff   // save the return value (a bool) across postcall
     postcall
+    cbz w9, _BRK        // exit early if the device or interrupt flag says so
     next
 
 .macro binary_op2kr op
@@ -2075,27 +2372,35 @@ _DEO2kr:
 .endm
 
 _ADD2kr:
+    landing_pad
     binary_op2kr add
 
 _SUB2kr:
+    landing_pad
     binary_op2kr sub
 
 _MUL2kr:
+    landing_pad
     binary_op2kr mul
 
 _DIV2kr:
+    landing_pad
     binary_op2kr udiv
 
 _AND2kr:
+    landing_pad
     binary_op2kr and
 
 _ORA2kr:
+    landing_pad
     binary_op2kr orr
 
 _EOR2kr:
+    landing_pad
     binary_op2kr eor
 
 _SFT2kr:
+    landing_pad
     ldrb w10, [x2, x3]
     rpeek w11, x9, 1
     rpeek w12, x9, 2
@@ -2110,7 +2415,9 @@ _SFT2kr:
     rpush w11
     next
 
-.data
+// The jump table is read-only after relocation, so it can't be used to
+// redirect the indirect branch in `next`
+jump_table_section
 .balign 4096
 .global JUMP_TABLE
 JUMP_TABLE:
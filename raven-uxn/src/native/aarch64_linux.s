@@ -11,3 +11,23 @@
     .global \name
     \name:
 .endm
+
+// Platform-specific section for the jump table, which contains relocated
+// pointers but is read-only at runtime
+.macro jump_table_section
+    .section .data.rel.ro
+.endm
+
+// Mark the object as compatible with BTI and PAC; without this note, the
+// linker disables both protections for the entire binary
+.pushsection .note.gnu.property, "a"
+    .balign 8
+    .long 4                     // name size
+    .long 16                    // descriptor size
+    .long 5                     // NT_GNU_PROPERTY_TYPE_0
+    .asciz "GNU"
+    .long 0xc0000000            // GNU_PROPERTY_AARCH64_FEATURE_1_AND
+    .long 4                     // property size
+    .long 3                     // BTI | PAC
+    .long 0                     // padding
+.popsection
@@ -11,3 +11,9 @@
     .global _\name
     _\name:
 .endm
+
+// Platform-specific section for the jump table, which contains relocated
+// pointers but is read-only at runtime
+.macro jump_table_section
+    .section __DATA_CONST,__const
+.endm
@@ -1,4 +1,4 @@
-use crate::{Device, Uxn};
+use crate::{Device, Halt, Uxn};
 
 #[cfg(not(target_arch = "aarch64"))]
 compile_error!("no native implementation for this platform");
@@ -8,41 +8,41 @@ compile_error!("no native implementation for this platform");
 
 #[no_mangle]
 extern "C" fn deo_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b000>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b000>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn deo_2_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b001>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b001>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn deo_r_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b010>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b010>(d, 0))
 }
 #[no_mangle]
 extern "C" fn deo_2r_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b011>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b011>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn deo_k_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b100>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b100>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn deo_2k_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b101>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b101>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn deo_kr_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b110>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b110>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn deo_2kr_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.deo::<0b111>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.deo::<0b111>(d, 0))
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -50,52 +50,84 @@ extern "C" fn deo_2kr_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
 
 #[no_mangle]
 extern "C" fn dei_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b000>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b000>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn dei_2_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b001>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b001>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn dei_r_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b010>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b010>(d, 0))
 }
 #[no_mangle]
 extern "C" fn dei_2r_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b011>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b011>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn dei_k_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b100>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b100>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn dei_2k_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b101>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b101>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn dei_kr_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b110>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b110>(d, 0))
 }
 
 #[no_mangle]
 extern "C" fn dei_2kr_entry(vm: &mut Uxn, dev: &mut DeviceHandle) -> bool {
-    vm.dei::<0b111>(dev.0, 0).is_some()
+    dev.check(vm, |vm, d| vm.dei::<0b111>(d, 0))
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-struct DeviceHandle<'a>(&'a mut dyn Device);
+struct DeviceHandle<'a> {
+    dev: &'a mut dyn Device,
 
-pub fn entry(vm: &mut Uxn, dev: &mut dyn Device, pc: u16) -> u16 {
-    let mut h = DeviceHandle(dev);
+    /// The device asked the VM to stop (by returning `false` from `deo`)
+    exited: bool,
+
+    /// The interrupt flag was set after a device call
+    interrupted: bool,
+}
+
+impl DeviceHandle<'_> {
+    /// Runs a device operation, returning `true` if evaluation should continue
+    #[inline]
+    fn check<F>(&mut self, vm: &mut Uxn, f: F) -> bool
+    where
+        F: FnOnce(&mut Uxn, &mut dyn Device) -> Option<u16>,
+    {
+        if f(vm, &mut *self.dev).is_none() {
+            self.exited = true;
+        } else if vm.is_interrupted() {
+            self.interrupted = true;
+        }
+        !(self.exited || self.interrupted)
+    }
+}
+
+/// Runs the native backend, returning why it stopped
+///
+/// Device calls are the only places where the backend checks whether it
+/// should stop early, so the interrupt flag is only seen after `DEI` / `DEO`.
+pub fn entry(vm: &mut Uxn, dev: &mut dyn Device, pc: u16) -> Halt {
+    let mut h = DeviceHandle {
+        dev,
+        exited: false,
+        interrupted: false,
+    };
 
     // SAFETY: do you trust me?
-    unsafe {
+    let pc = unsafe {
         aarch64_entry(
             vm.stack.data.as_mut_ptr(),
             &mut vm.stack.index as *mut _,
@@ -106,6 +138,16 @@ pub fn entry(vm: &mut Uxn, dev: &mut dyn Device, pc: u16) -> u16 {
             vm as *mut _,
             &mut h as *mut _,
         )
+    };
+    if h.exited {
+        Halt::Exit {
+            pc,
+            code: h.dev.exit_code(),
+        }
+    } else if h.interrupted {
+        Halt::Interrupted { pc }
+    } else {
+        Halt::Break { pc }
     }
 }
 
@@ -228,27 +270,8 @@ mod test {
         let pc_interp = vm_interp.run(&mut dev, 0x100);
         assert_eq!(pc_native, pc_interp, "{op_name}: pc mismatch");
 
-        assert_eq!(
-            vm_native.dev, vm_interp.dev,
-            "{op_name}: dev memory mismatch"
-        );
-        assert_eq!(vm_native.ram, vm_interp.ram, "{op_name}: ram mismatch");
-        assert_eq!(
-            vm_native.stack.index, vm_interp.stack.index,
-            "{op_name}: stack index mismatch"
-        );
-        assert_eq!(
-            vm_native.stack.data, vm_interp.stack.data,
-            "{op_name}: stack data mismatch"
-        );
-        assert_eq!(
-            vm_native.ret.index, vm_interp.ret.index,
-            "{op_name}: ret index mismatch"
-        );
-        assert_eq!(
-            vm_native.ret.data, vm_interp.ret.data,
-            "{op_name}: ret index mismatch"
-        );
+        let d = crate::test_utils::diff(&vm_interp, &vm_native);
+        assert!(d.is_empty(), "{op_name}: {d:?}");
     }
 
     #[test]
@@ -511,11 +534,13 @@ mod test {
     #[test]
     fn ldz2() {
         run_and_compare_with_ram_r(&[LIT, 0x12, LDZ2]);
+        run_and_compare_with_ram_r(&[LIT, 0xff, LDZ2]);
     }
 
     #[test]
     fn stz2() {
         run_and_compare_r(&[LIT2, 0x12, 0x34, LIT, 0x56, STZ2]);
+        run_and_compare_r(&[LIT2, 0x12, 0x34, LIT, 0xff, STZ2]);
     }
 
     #[test]
@@ -534,12 +559,14 @@ mod test {
     fn lda2() {
         run_and_compare_with_ram_r(&[LIT2, 0x12, 0x34, LDA2]);
         run_and_compare_with_ram_r(&[LIT2, 0x35, 0xff, LDA2]);
+        run_and_compare_with_ram_r(&[LIT2, 0xff, 0xff, LDA2]);
     }
 
     #[test]
     fn sta2() {
         run_and_compare_r(&[LIT2, 0x56, 0x14, LIT2, 0x12, 0x34, STA2]);
         run_and_compare_r(&[LIT2, 0x78, 0x90, LIT2, 0x35, 0xff, STA2]);
+        run_and_compare_r(&[LIT2, 0x78, 0x90, LIT2, 0xff, 0xff, STA2]);
     }
 
     #[test]
@@ -576,4 +603,56 @@ mod test {
         run_and_compare(&[JSI, 0xf6, 0x12]);
         run_and_compare(&[JSI, 0x26, 0xf2]);
     }
+
+    #[test]
+    fn interrupt_deo_loop() {
+        use crate::{Device, Halt};
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        /// Device which sets the interrupt flag (or exits) after 100 `DEO`s
+        struct Counter<'a> {
+            n: usize,
+            flag: &'a AtomicBool,
+            exit: bool,
+        }
+        impl Device for Counter<'_> {
+            fn dei(&mut self, _vm: &mut Uxn, _target: u8) {}
+            fn deo(&mut self, _vm: &mut Uxn, _target: u8) -> bool {
+                self.n += 1;
+                if self.n == 100 && !self.exit {
+                    self.flag.store(true, Ordering::Relaxed);
+                }
+                !(self.n == 100 && self.exit)
+            }
+        }
+
+        for backend in [Backend::Native, Backend::Interpreter] {
+            for exit in [false, true] {
+                let flag = AtomicBool::new(false);
+                let mut ram = UxnRam::new();
+                // @loop LIT 00 LIT 10 DEO !loop
+                ram[0x100..0x108].copy_from_slice(&[
+                    LIT, 0x00, LIT, 0x10, DEO, JMI, 0xff, 0xf8,
+                ]);
+                let mut vm = Uxn::new(&mut ram, backend);
+                vm.set_interrupt(Some(&flag));
+                let mut dev = Counter {
+                    n: 0,
+                    flag: &flag,
+                    exit,
+                };
+                let h = vm.run_halt(&mut dev, 0x100);
+                let expected = if exit {
+                    Halt::Exit {
+                        pc: 0x105,
+                        code: None,
+                    }
+                } else {
+                    Halt::Interrupted { pc: 0x105 }
+                };
+                assert_eq!(h, expected, "{backend:?}");
+                assert_eq!(dev.n, 100, "{backend:?}");
+            }
+        }
+    }
 }
@@ -0,0 +1,119 @@
+//! Registry of mapped device pages
+use crate::{Ports, Uxn, DEV_SIZE};
+
+/// A single mapped page of device memory
+///
+/// Returned by [`Uxn::ports_iter`]
+#[derive(Copy, Clone)]
+pub struct DevicePage<'a> {
+    base: u8,
+    data: &'a [u8; DEV_SIZE],
+}
+
+impl<'a> DevicePage<'a> {
+    /// Returns the base address of the page, of the form `0xA0`
+    pub fn base(&self) -> u8 {
+        self.base
+    }
+
+    /// Returns the raw bytes of the page
+    pub fn bytes(&self) -> &'a [u8; DEV_SIZE] {
+        self.data
+    }
+
+    /// Interprets the page as a [`Ports`] object
+    ///
+    /// This does not check that [`Ports::BASE`] matches the page's address,
+    /// because devices with several identical pages (e.g. audio channels) use
+    /// the same type for each of them; use [`is`](Self::is) to check.
+    pub fn get<P: Ports>(&self) -> &'a P {
+        Uxn::check_dev_size::<P>();
+        P::ref_from(self.data.as_slice()).unwrap()
+    }
+
+    /// Checks whether this is the page at [`P::BASE`](Ports::BASE)
+    pub fn is<P: Ports>(&self) -> bool {
+        self.base == P::BASE
+    }
+}
+
+impl core::fmt::Debug for DevicePage<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:", self.base)?;
+        for b in self.data {
+            write!(f, " {b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Uxn<'a> {
+    /// Marks the device page containing `addr` as mapped
+    ///
+    /// The VM doesn't use this information itself; it lets debugging tools
+    /// find device state (with [`ports_iter`](Self::ports_iter)) without
+    /// knowing the layout of a particular system.  The set of mapped pages is
+    /// kept when the VM is [reset](Self::reset).
+    pub fn map_page(&mut self, addr: u8) {
+        self.mapped_pages |= 1 << (addr >> 4);
+    }
+
+    /// Marks the device page at [`P::BASE`](Ports::BASE) as mapped
+    pub fn map_ports<P: Ports>(&mut self) {
+        self.map_page(P::BASE)
+    }
+
+    /// Marks every device page as unmapped
+    pub fn unmap_pages(&mut self) {
+        self.mapped_pages = 0;
+    }
+
+    /// Checks whether the device page containing `addr` is mapped
+    pub fn is_mapped(&self, addr: u8) -> bool {
+        self.mapped_pages & (1 << (addr >> 4)) != 0
+    }
+
+    /// Iterates over mapped device pages, in order of address
+    ///
+    /// ```
+    /// # use raven_uxn::{Uxn, UxnRam, Backend, Ports};
+    /// # use zerocopy::{AsBytes, FromBytes, FromZeroes};
+    /// #[derive(AsBytes, FromZeroes, FromBytes)]
+    /// #[repr(C)]
+    /// struct Console {
+    ///     vector: [u8; 2],
+    ///     read: u8,
+    ///     _pad: [u8; 5],
+    ///     write: u8,
+    ///     error: u8,
+    ///     _pad2: [u8; 6],
+    /// }
+    /// impl Ports for Console {
+    ///     const BASE: u8 = 0x10;
+    /// }
+    ///
+    /// let mut ram = UxnRam::new();
+    /// let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    /// vm.map_ports::<Console>();
+    /// vm.dev_mut::<Console>().write = b'!';
+    ///
+    /// for page in vm.ports_iter() {
+    ///     if page.is::<Console>() {
+    ///         assert_eq!(page.get::<Console>().write, b'!');
+    ///     }
+    /// }
+    /// ```
+    pub fn ports_iter(&self) -> impl Iterator<Item = DevicePage<'_>> + '_ {
+        (0..16u8)
+            .filter(|i| self.mapped_pages & (1 << i) != 0)
+            .map(|i| {
+                let base = i << 4;
+                DevicePage {
+                    base,
+                    data: self.dev[usize::from(base)..][..DEV_SIZE]
+                        .try_into()
+                        .unwrap(),
+                }
+            })
+    }
+}
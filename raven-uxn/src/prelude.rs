@@ -0,0 +1,43 @@
+//! Types needed to embed the VM in an emulator front-end
+//!
+//! ```
+//! use raven_uxn::prelude::*;
+//!
+//! // LIT 2a LIT 01 ADD BRK
+//! let rom = [0x80, 0x2a, 0x80, 0x01, 0x18, 0x00];
+//!
+//! let mut ram = UxnRam::new();
+//! let mut vm = Uxn::builder(&mut ram)
+//!     .backend(Backend::Interpreter)
+//!     .build();
+//! let extra = vm.reset(&rom);
+//! assert!(extra.is_empty());
+//!
+//! let halt = vm.run_halt(&mut EmptyDevice, 0x100);
+//! assert!(matches!(halt, Halt::Break { .. }));
+//! assert_eq!(vm.stack().peek_byte_at(0), 0x2b);
+//! ```
+//!
+//! Custom devices implement [`Device`], and typically describe their port
+//! layout with a [`Ports`] `struct`.
+//!
+//! # Stability
+//! Everything re-exported here follows semantic versioning: items will not be
+//! removed or renamed, and existing signatures will not change, without a
+//! major version bump.  New items may be added in minor releases, so prefer
+//! importing by name if a glob import could collide with your own types.
+//!
+//! Types which may gain variants or fields (e.g. [`Backend`] and [`Halt`]) are
+//! marked `#[non_exhaustive]`, so adding to them is not a breaking change;
+//! match them with a wildcard arm.
+//!
+//! Other public modules (e.g. [`aot`](crate::aot), [`wasm`](crate::wasm), and
+//! [`test_utils`](crate::test_utils)) are tools for backend authors and may
+//! change between minor releases.
+pub use crate::{
+    Backend, CycleCosts, Device, EmptyDevice, Halt, Ports, Stack, Uxn,
+    UxnBuilder, DEV_SIZE,
+};
+
+#[cfg(feature = "alloc")]
+pub use crate::UxnRam;
@@ -0,0 +1,199 @@
+//! Differential testing between evaluation backends
+//!
+//! These helpers run a ROM with the interpreter (the reference
+//! implementation) and with some other backend, then compare the resulting
+//! VM state.  They're used by the native backend's tests and fuzzer, and can
+//! be reused by other backends (e.g. a JIT or the [`aot`](crate::aot)
+//! transpiler).
+//!
+//! This is only available if the `"alloc"` feature is enabled
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::{Backend, EmptyDevice, Stack, Uxn, UxnRam};
+
+/// A single difference between the reference and candidate VMs
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Difference {
+    /// The final program counters differ
+    Pc {
+        /// Program counter from the interpreter
+        expected: u16,
+        /// Program counter from the candidate backend
+        actual: u16,
+    },
+    /// A byte of RAM differs
+    Ram {
+        /// Address in RAM
+        addr: u16,
+        /// Value from the interpreter
+        expected: u8,
+        /// Value from the candidate backend
+        actual: u8,
+    },
+    /// A byte of device memory differs
+    Dev {
+        /// Address in device memory
+        addr: u8,
+        /// Value from the interpreter
+        expected: u8,
+        /// Value from the candidate backend
+        actual: u8,
+    },
+    /// The working stacks differ
+    Stack {
+        /// Stack from the interpreter
+        expected: Stack,
+        /// Stack from the candidate backend
+        actual: Stack,
+    },
+    /// The return stacks differ
+    Ret {
+        /// Stack from the interpreter
+        expected: Stack,
+        /// Stack from the candidate backend
+        actual: Stack,
+    },
+}
+
+impl core::fmt::Display for Difference {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Difference::Pc { expected, actual } => {
+                write!(f, "pc mismatch: {expected:#06x} != {actual:#06x}")
+            }
+            Difference::Ram {
+                addr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "ram mismatch at {addr:#06x}: {expected:#04x} != {actual:#04x}"
+            ),
+            Difference::Dev {
+                addr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "dev mismatch at {addr:#04x}: {expected:#04x} != {actual:#04x}"
+            ),
+            Difference::Stack { expected, actual } => {
+                write!(f, "stack mismatch: {expected} != {actual}")
+            }
+            Difference::Ret { expected, actual } => {
+                write!(f, "return stack mismatch: {expected} != {actual}")
+            }
+        }
+    }
+}
+
+/// Compares the state (RAM, device memory, and stacks) of two VMs
+///
+/// Returns every difference found, or an empty list if the states match.
+/// Stacks are compared by contents and length (see [`Stack`]'s `PartialEq`).
+pub fn diff(expected: &Uxn, actual: &Uxn) -> Vec<Difference> {
+    let mut out = Vec::new();
+    for (addr, (&e, &a)) in
+        expected.ram.iter().zip(actual.ram.iter()).enumerate()
+    {
+        if e != a {
+            out.push(Difference::Ram {
+                addr: addr as u16,
+                expected: e,
+                actual: a,
+            });
+        }
+    }
+    for (addr, (&e, &a)) in expected.dev.iter().zip(&actual.dev).enumerate() {
+        if e != a {
+            out.push(Difference::Dev {
+                addr: addr as u8,
+                expected: e,
+                actual: a,
+            });
+        }
+    }
+    if expected.stack != actual.stack {
+        out.push(Difference::Stack {
+            expected: expected.stack,
+            actual: actual.stack,
+        });
+    }
+    if expected.ret != actual.ret {
+        out.push(Difference::Ret {
+            expected: expected.ret,
+            actual: actual.ret,
+        });
+    }
+    out
+}
+
+/// Checks that the native backend matches the interpreter on the given ROM
+///
+/// This is [`check_equivalence_with`], running the candidate VM with the
+/// native backend.  It's only available with the `"native"` feature, since
+/// otherwise there's nothing to compare against.
+#[cfg(feature = "native")]
+pub fn check_equivalence(
+    rom: &[u8],
+    fuel: usize,
+) -> Result<(), Vec<Difference>> {
+    check_equivalence_with(rom, fuel, |vm, dev, pc| {
+        crate::native::entry(vm, dev, pc).pc()
+    })
+}
+
+/// Checks that a candidate backend matches the interpreter on the given ROM
+///
+/// The ROM is loaded into two fresh VMs with no devices attached, then run
+/// from `0x100`: first with the interpreter, then by calling `run` with the
+/// second VM.  `run` must evaluate the ROM with the candidate backend (the VM
+/// is built with [`Backend::Interpreter`], so calling [`Uxn::run`] would just
+/// compare the interpreter with itself) and return the final program counter.
+///
+/// The check is skipped (returning `Ok(())`) if the ROM needs expansion
+/// memory or if the interpreter doesn't terminate within `fuel` instructions,
+/// since the candidate backend can't be expected to stop at the same point.
+///
+/// Returns every difference found in the final state; see [`diff`].
+pub fn check_equivalence_with<F>(
+    rom: &[u8],
+    fuel: usize,
+    run: F,
+) -> Result<(), Vec<Difference>>
+where
+    F: FnOnce(&mut Uxn, &mut EmptyDevice, u16) -> u16,
+{
+    let mut ram_v = UxnRam::new();
+    let mut vm_v = Uxn::new(&mut ram_v, Backend::Interpreter);
+    if !vm_v.reset(rom).is_empty() {
+        return Ok(());
+    }
+    let Some(pc_v) =
+        vm_v.run_until(&mut EmptyDevice, 0x100, |_, _, i| i + 1 >= fuel)
+    else {
+        return Ok(());
+    };
+
+    let mut ram_n = UxnRam::new();
+    let mut vm_n = Uxn::new(&mut ram_n, Backend::Interpreter);
+    let _ = vm_n.reset(rom);
+    let pc_n = run(&mut vm_n, &mut EmptyDevice, 0x100);
+
+    let mut out = diff(&vm_v, &vm_n);
+    if pc_v != pc_n {
+        out.insert(
+            0,
+            Difference::Pc {
+                expected: pc_v,
+                actual: pc_n,
+            },
+        );
+    }
+    if out.is_empty() {
+        Ok(())
+    } else {
+        Err(out)
+    }
+}
@@ -0,0 +1,232 @@
+//! Minimal WebAssembly binary encoder
+//!
+//! This only supports what [`compile`](super::compile) needs: a module with
+//! one imported memory and one exported function, whose body is built with
+//! [`Code`].
+extern crate alloc;
+use alloc::vec::Vec;
+
+/// `i32` value type
+const I32: u8 = 0x7f;
+
+/// Appends an unsigned LEB128 value
+fn uleb(out: &mut Vec<u8>, mut v: u32) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+/// Appends a signed LEB128 value
+fn sleb(out: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+/// Appends a length-prefixed string
+fn name(out: &mut Vec<u8>, s: &str) {
+    uleb(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Appends a section with the given id
+fn section(out: &mut Vec<u8>, id: u8, data: &[u8]) {
+    out.push(id);
+    uleb(out, data.len() as u32);
+    out.extend_from_slice(data);
+}
+
+/// Function body under construction
+///
+/// Each method appends a single instruction.  Blocks have no parameters or
+/// results, and memory accesses use the given constant offset with byte
+/// alignment.
+#[derive(Default)]
+pub struct Code(Vec<u8>);
+
+impl Code {
+    fn memory(&mut self, op: u8, offset: u32) {
+        self.0.push(op);
+        uleb(&mut self.0, 0); // alignment
+        uleb(&mut self.0, offset);
+    }
+
+    fn local(&mut self, op: u8, i: u32) {
+        self.0.push(op);
+        uleb(&mut self.0, i);
+    }
+
+    pub fn unreachable(&mut self) {
+        self.0.push(0x00);
+    }
+    pub fn block(&mut self) {
+        self.0.extend([0x02, 0x40]);
+    }
+    pub fn loop_(&mut self) {
+        self.0.extend([0x03, 0x40]);
+    }
+    pub fn end(&mut self) {
+        self.0.push(0x0b);
+    }
+    pub fn br(&mut self, depth: u32) {
+        self.local(0x0c, depth);
+    }
+    pub fn br_if(&mut self, depth: u32) {
+        self.local(0x0d, depth);
+    }
+    pub fn br_table(&mut self, depths: &[u32], default: u32) {
+        self.local(0x0e, depths.len() as u32);
+        for &d in depths {
+            uleb(&mut self.0, d);
+        }
+        uleb(&mut self.0, default);
+    }
+    pub fn return_(&mut self) {
+        self.0.push(0x0f);
+    }
+    pub fn select(&mut self) {
+        self.0.push(0x1b);
+    }
+
+    pub fn local_get(&mut self, i: u32) {
+        self.local(0x20, i);
+    }
+    pub fn local_set(&mut self, i: u32) {
+        self.local(0x21, i);
+    }
+
+    pub fn i64_load(&mut self, offset: u32) {
+        self.memory(0x29, offset);
+    }
+    pub fn i32_load8_u(&mut self, offset: u32) {
+        self.memory(0x2d, offset);
+    }
+    pub fn i32_store8(&mut self, offset: u32) {
+        self.memory(0x3a, offset);
+    }
+
+    pub fn i32_const(&mut self, v: i32) {
+        self.0.push(0x41);
+        sleb(&mut self.0, i64::from(v));
+    }
+    pub fn i64_const(&mut self, v: i64) {
+        self.0.push(0x42);
+        sleb(&mut self.0, v);
+    }
+
+    pub fn i32_eqz(&mut self) {
+        self.0.push(0x45);
+    }
+    pub fn i32_eq(&mut self) {
+        self.0.push(0x46);
+    }
+    pub fn i32_ne(&mut self) {
+        self.0.push(0x47);
+    }
+    pub fn i32_lt_u(&mut self) {
+        self.0.push(0x49);
+    }
+    pub fn i32_gt_u(&mut self) {
+        self.0.push(0x4b);
+    }
+    pub fn i64_ne(&mut self) {
+        self.0.push(0x52);
+    }
+    pub fn i32_add(&mut self) {
+        self.0.push(0x6a);
+    }
+    pub fn i32_sub(&mut self) {
+        self.0.push(0x6b);
+    }
+    pub fn i32_mul(&mut self) {
+        self.0.push(0x6c);
+    }
+    pub fn i32_div_u(&mut self) {
+        self.0.push(0x6e);
+    }
+    pub fn i32_and(&mut self) {
+        self.0.push(0x71);
+    }
+    pub fn i32_or(&mut self) {
+        self.0.push(0x72);
+    }
+    pub fn i32_xor(&mut self) {
+        self.0.push(0x73);
+    }
+    pub fn i32_shl(&mut self) {
+        self.0.push(0x74);
+    }
+    pub fn i32_shr_s(&mut self) {
+        self.0.push(0x75);
+    }
+    pub fn i32_shr_u(&mut self) {
+        self.0.push(0x76);
+    }
+}
+
+/// Builds a module which imports `env.memory` and exports a single function
+///
+/// The function has `params` parameters and `locals` additional locals (all
+/// `i32`), returns an `i32`, and is exported with the given name.
+pub fn module(export: &str, params: u32, locals: u32, code: Code) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend(b"\0asm");
+    out.extend(1u32.to_le_bytes());
+
+    // Type section: a single function type
+    let mut s = Vec::new();
+    uleb(&mut s, 1);
+    s.push(0x60);
+    uleb(&mut s, params);
+    s.extend((0..params).map(|_| I32));
+    uleb(&mut s, 1);
+    s.push(I32);
+    section(&mut out, 1, &s);
+
+    // Import section: memory from the host, with at least one page
+    let mut s = Vec::new();
+    uleb(&mut s, 1);
+    name(&mut s, "env");
+    name(&mut s, "memory");
+    s.extend([0x02, 0x00]);
+    uleb(&mut s, 1);
+    section(&mut out, 2, &s);
+
+    // Function section: one function, using type 0
+    section(&mut out, 3, &[1, 0]);
+
+    // Export section: function 0
+    let mut s = Vec::new();
+    uleb(&mut s, 1);
+    name(&mut s, export);
+    s.extend([0x00, 0x00]);
+    section(&mut out, 7, &s);
+
+    // Code section
+    let mut body = Vec::new();
+    uleb(&mut body, 1); // one local declaration
+    uleb(&mut body, locals);
+    body.push(I32);
+    body.extend(code.0);
+    body.push(0x0b); // end of function
+
+    let mut s = Vec::new();
+    uleb(&mut s, 1);
+    uleb(&mut s, body.len() as u32);
+    s.extend(body);
+    section(&mut out, 10, &s);
+
+    out
+}
@@ -0,0 +1,605 @@
+//! Translation of ROMs into WebAssembly
+//!
+//! [`compile`] finds a ROM's basic blocks (using the same static analysis as
+//! [`aot::transpile`](crate::aot::transpile)) and translates each one into
+//! straight-line WebAssembly, which is dispatched with a single `br_table`
+//! on the program counter.  With the `"wasm"` feature on a `wasm32` target,
+//! [`Backend::Wasm`](crate::Backend::Wasm) compiles the ROM when it's loaded
+//! by [`Uxn::reset`](crate::Uxn::reset), then instantiates the module in the
+//! browser's WebAssembly engine.
+//!
+//! The generated module imports `env.memory` (which must be the memory holding
+//! the VM) and exports a single function
+//!
+//! ```text
+//! run(ram, stack, stack_index, ret, ret_index, pc: i32) -> i32
+//! ```
+//!
+//! where the first five arguments are addresses in that memory.  It returns
+//! the final program counter in the low 16 bits; bit 16 is set if execution
+//! stopped because the code at that address must be run by the interpreter
+//! instead.  This happens for `DEI` and `DEO` instructions, for code which
+//! wasn't found by the static analysis, and for blocks whose bytes in RAM no
+//! longer match the ROM (so self-modifying code remains correct).  The
+//! interpreter runs a single instruction before jumping back into compiled
+//! code.
+//!
+//! Compiled code does not update the stacks' high-water marks.
+extern crate alloc;
+use alloc::vec::Vec;
+
+use crate::aot::{find_blocks, rom_short, Instruction};
+use crate::op;
+
+mod encode;
+use encode::Code;
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod runtime;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub(crate) use runtime::Runtime;
+
+/// Status bit returned when the interpreter must run the next instruction
+pub(crate) const FALLBACK: u32 = 1 << 16;
+
+// Parameters of the generated function
+const RAM: u32 = 0;
+const WST: u32 = 1;
+const WST_INDEX: u32 = 2;
+const RST: u32 = 3;
+const RST_INDEX: u32 = 4;
+const PC: u32 = 5;
+const PARAMS: u32 = 6;
+
+// Additional locals
+const WI: u32 = 6; // working stack index
+const RI: u32 = 7; // return stack index
+const V: u32 = 8; // virtual index, used in `keep` mode
+const A: u32 = 9;
+const B: u32 = 10;
+const C: u32 = 11;
+const LOCALS: u32 = 6;
+
+/// Selects one of the two stacks
+#[derive(Copy, Clone)]
+enum Stack {
+    Working,
+    Return,
+}
+
+impl Stack {
+    fn other(self) -> Self {
+        match self {
+            Stack::Working => Stack::Return,
+            Stack::Return => Stack::Working,
+        }
+    }
+    fn base(self) -> u32 {
+        match self {
+            Stack::Working => WST,
+            Stack::Return => RST,
+        }
+    }
+    fn index(self) -> u32 {
+        match self {
+            Stack::Working => WI,
+            Stack::Return => RI,
+        }
+    }
+}
+
+/// Code generator for a single instruction, with its mode flags
+struct Gen<'a> {
+    c: &'a mut Code,
+    short: bool,
+    keep: bool,
+    src: Stack,
+}
+
+impl Gen<'_> {
+    /// Builds a generator, initializing the virtual index in `keep` mode
+    fn new(c: &mut Code, op: u8) -> Gen<'_> {
+        let src = if op & 0x40 != 0 {
+            Stack::Return
+        } else {
+            Stack::Working
+        };
+        let keep = op & 0x80 != 0;
+        if keep {
+            c.local_get(src.index());
+            c.local_set(V);
+        }
+        Gen {
+            c,
+            short: op & 0x20 != 0,
+            keep,
+            src,
+        }
+    }
+
+    /// Pops a byte from the source stack onto the operand stack
+    fn pop_byte(&mut self) {
+        let i = if self.keep { V } else { self.src.index() };
+        let c = &mut *self.c;
+        c.local_get(self.src.base());
+        c.local_get(i);
+        c.i32_add();
+        c.i32_load8_u(0);
+        c.local_get(i);
+        c.i32_const(1);
+        c.i32_sub();
+        c.i32_const(0xff);
+        c.i32_and();
+        c.local_set(i);
+    }
+
+    /// Pops a byte or short (depending on mode) into a local
+    fn pop(&mut self, local: u32) {
+        self.pop_byte();
+        if self.short {
+            self.pop_byte();
+            self.c.i32_const(8);
+            self.c.i32_shl();
+            self.c.i32_or();
+        }
+        self.c.local_set(local);
+    }
+
+    /// Pops a single byte into a local
+    fn pop_byte_into(&mut self, local: u32) {
+        self.pop_byte();
+        self.c.local_set(local);
+    }
+
+    /// Pops a short into a local
+    fn pop_short_into(&mut self, local: u32) {
+        self.pop_byte();
+        self.pop_byte();
+        self.c.i32_const(8);
+        self.c.i32_shl();
+        self.c.i32_or();
+        self.c.local_set(local);
+    }
+
+    /// Pushes a byte, produced by `value`, onto a stack
+    fn push_byte_with(&mut self, s: Stack, value: impl FnOnce(&mut Code)) {
+        let c = &mut *self.c;
+        c.local_get(s.index());
+        c.i32_const(1);
+        c.i32_add();
+        c.i32_const(0xff);
+        c.i32_and();
+        c.local_set(s.index());
+        c.local_get(s.base());
+        c.local_get(s.index());
+        c.i32_add();
+        value(c);
+        c.i32_store8(0);
+    }
+
+    /// Pushes the value in a local onto a stack, as a byte or short
+    fn push_to(&mut self, s: Stack, short: bool, local: u32) {
+        if short {
+            self.push_byte_with(s, |c| {
+                c.local_get(local);
+                c.i32_const(8);
+                c.i32_shr_u();
+            });
+        }
+        self.push_byte_with(s, |c| c.local_get(local));
+    }
+
+    /// Pushes the value in a local onto the source stack (depending on mode)
+    fn push(&mut self, local: u32) {
+        self.push_to(self.src, self.short, local);
+    }
+
+    /// Reads a byte or short from RAM at the address in a local
+    fn load(&mut self, addr: u32) {
+        let c = &mut *self.c;
+        c.local_get(RAM);
+        c.local_get(addr);
+        c.i32_add();
+        c.i32_load8_u(0);
+        if self.short {
+            c.i32_const(8);
+            c.i32_shl();
+            c.local_get(RAM);
+            c.local_get(addr);
+            c.i32_const(1);
+            c.i32_add();
+            c.i32_const(0xffff);
+            c.i32_and();
+            c.i32_add();
+            c.i32_load8_u(0);
+            c.i32_or();
+        }
+    }
+
+    /// Writes the value in `val` to RAM at the address in `addr`
+    fn store(&mut self, addr: u32, val: u32) {
+        let c = &mut *self.c;
+        c.local_get(RAM);
+        c.local_get(addr);
+        c.i32_add();
+        c.local_get(val);
+        if self.short {
+            c.i32_const(8);
+            c.i32_shr_u();
+            c.i32_store8(0);
+            c.local_get(RAM);
+            c.local_get(addr);
+            c.i32_const(1);
+            c.i32_add();
+            c.i32_const(0xffff);
+            c.i32_and();
+            c.i32_add();
+            c.local_get(val);
+        }
+        c.i32_store8(0);
+    }
+
+    /// Computes `pc + (offset as i8)`, wrapped to 16 bits
+    fn relative(&mut self, pc: u16, offset: u32) {
+        let c = &mut *self.c;
+        c.i32_const(i32::from(pc));
+        c.local_get(offset);
+        c.i32_const(24);
+        c.i32_shl();
+        c.i32_const(24);
+        c.i32_shr_s();
+        c.i32_add();
+        c.i32_const(0xffff);
+        c.i32_and();
+    }
+
+    /// Computes a jump target from the value in a local (see `jump_offset`)
+    fn target(&mut self, pc: u16, local: u32) {
+        if self.short {
+            self.c.local_get(local);
+        } else {
+            self.relative(pc, local);
+        }
+    }
+
+    /// Pops two values, then pushes `A op B` (computed by `f`)
+    fn binary(&mut self, f: impl FnOnce(&mut Code)) {
+        self.pop(B);
+        self.pop(A);
+        self.c.local_get(A);
+        self.c.local_get(B);
+        f(self.c);
+        self.c.local_set(A);
+        self.push(A);
+    }
+
+    /// Pops two values, then pushes the byte `A op B` (computed by `f`)
+    fn compare(&mut self, f: impl FnOnce(&mut Code)) {
+        self.pop(B);
+        self.pop(A);
+        self.c.local_get(A);
+        self.c.local_get(B);
+        f(self.c);
+        self.c.local_set(A);
+        self.push_to(self.src, false, A);
+    }
+}
+
+/// Emits code for an instruction which doesn't end its block
+///
+/// `DEI` and `DEO` are not supported (they're always run by the interpreter).
+fn emit_op(c: &mut Code, rom: &[u8], i: &Instruction) {
+    let pc = i.pc();
+    let mut g = Gen::new(c, i.op);
+    match i.op {
+        op::LIT | op::LITr => {
+            let v = rom[usize::from(pc - crate::aot::ROM_START)];
+            g.c.i32_const(i32::from(v));
+            g.c.local_set(A);
+            g.push(A);
+        }
+        op::LIT2 | op::LIT2r => {
+            let v = rom_short(rom, pc).unwrap();
+            g.c.i32_const(i32::from(v));
+            g.c.local_set(A);
+            g.push(A);
+        }
+        _ => match i.op & 0x1f {
+            op::INC => {
+                g.pop(A);
+                g.c.local_get(A);
+                g.c.i32_const(1);
+                g.c.i32_add();
+                g.c.local_set(A);
+                g.push(A);
+            }
+            op::POP => g.pop(A),
+            op::NIP => {
+                g.pop(B);
+                g.pop(A);
+                g.push(B);
+            }
+            op::SWP => {
+                g.pop(B);
+                g.pop(A);
+                g.push(B);
+                g.push(A);
+            }
+            op::ROT => {
+                g.pop(C);
+                g.pop(B);
+                g.pop(A);
+                g.push(B);
+                g.push(C);
+                g.push(A);
+            }
+            op::DUP => {
+                g.pop(A);
+                g.push(A);
+                g.push(A);
+            }
+            op::OVR => {
+                g.pop(B);
+                g.pop(A);
+                g.push(A);
+                g.push(B);
+                g.push(A);
+            }
+            op::EQU => g.compare(Code::i32_eq),
+            op::NEQ => g.compare(Code::i32_ne),
+            op::GTH => g.compare(Code::i32_gt_u),
+            op::LTH => g.compare(Code::i32_lt_u),
+            op::STH => {
+                g.pop(A);
+                g.push_to(g.src.other(), g.short, A);
+            }
+            op::LDZ => {
+                g.pop_byte_into(A);
+                g.load(A);
+                g.c.local_set(A);
+                g.push(A);
+            }
+            op::STZ => {
+                g.pop_byte_into(A);
+                g.pop(B);
+                g.store(A, B);
+            }
+            op::LDR => {
+                g.pop_byte_into(A);
+                g.relative(pc, A);
+                g.c.local_set(A);
+                g.load(A);
+                g.c.local_set(A);
+                g.push(A);
+            }
+            op::STR => {
+                g.pop_byte_into(A);
+                g.pop(B);
+                g.relative(pc, A);
+                g.c.local_set(A);
+                g.store(A, B);
+            }
+            op::LDA => {
+                g.pop_short_into(A);
+                g.load(A);
+                g.c.local_set(A);
+                g.push(A);
+            }
+            op::STA => {
+                g.pop_short_into(A);
+                g.pop(B);
+                g.store(A, B);
+            }
+            op::ADD => g.binary(Code::i32_add),
+            op::SUB => g.binary(Code::i32_sub),
+            op::MUL => g.binary(Code::i32_mul),
+            op::DIV => g.binary(|c| {
+                // Division by zero returns zero, rather than trapping
+                c.local_get(B);
+                c.i32_eqz();
+                c.i32_or();
+                c.i32_div_u();
+                c.i32_const(0);
+                c.local_get(B);
+                c.select();
+            }),
+            op::AND => g.binary(Code::i32_and),
+            op::ORA => g.binary(Code::i32_or),
+            op::EOR => g.binary(Code::i32_xor),
+            op::SFT => {
+                g.pop_byte_into(B);
+                g.pop(A);
+                g.c.local_get(A);
+                g.c.local_get(B);
+                g.c.i32_const(0xf);
+                g.c.i32_and();
+                g.c.i32_shr_u();
+                g.c.local_get(B);
+                g.c.i32_const(4);
+                g.c.i32_shr_u();
+                g.c.i32_shl();
+                g.c.local_set(A);
+                g.push(A);
+            }
+            _ => unreachable!("invalid opcode {:#04x}", i.op),
+        },
+    }
+}
+
+/// Writes the stack indices back to memory
+fn save_indices(c: &mut Code) {
+    c.local_get(WST_INDEX);
+    c.local_get(WI);
+    c.i32_store8(0);
+    c.local_get(RST_INDEX);
+    c.local_get(RI);
+    c.i32_store8(0);
+}
+
+/// Emits a check that RAM still contains a block's code
+///
+/// Branches to the given depth if any byte differs.
+fn emit_check(c: &mut Code, start: u16, code: &[u8], depth: u32) {
+    let mut offset = u32::from(start);
+    let mut chunks = code.chunks_exact(8);
+    for chunk in &mut chunks {
+        c.local_get(RAM);
+        c.i64_load(offset);
+        c.i64_const(i64::from_le_bytes(chunk.try_into().unwrap()));
+        c.i64_ne();
+        c.br_if(depth);
+        offset += 8;
+    }
+    for &b in chunks.remainder() {
+        c.local_get(RAM);
+        c.i32_load8_u(offset);
+        c.i32_const(i32::from(b));
+        c.i32_ne();
+        c.br_if(depth);
+        offset += 1;
+    }
+}
+
+/// Translates a ROM into a WebAssembly module
+///
+/// The ROM is expected to be loaded at `0x100`; see the [module-level
+/// documentation](self) for the module's interface.
+///
+/// The output depends only on the ROM's bytes, and self-modifying code is
+/// detected when the module runs, so a host may cache it keyed by a hash of
+/// the ROM.  No such cache is provided here: [`Uxn::reset`](crate::Uxn::reset)
+/// compiles the ROM every time it's loaded.
+pub fn compile(rom: &[u8]) -> Vec<u8> {
+    let blocks = find_blocks(rom);
+    let n = blocks.len() as u32;
+    let base = blocks.keys().next().copied().unwrap_or(0);
+    let last = blocks.keys().last().copied().unwrap_or(0);
+
+    let mut c = Code::default();
+    c.local_get(WST_INDEX);
+    c.i32_load8_u(0);
+    c.local_set(WI);
+    c.local_get(RST_INDEX);
+    c.i32_load8_u(0);
+    c.local_set(RI);
+
+    // Dispatch on the program counter.  Block `k` is nested inside blocks
+    // `k + 1..n`, then the fallback block, then the dispatch loop.
+    c.loop_();
+    c.block();
+    for _ in 0..n {
+        c.block();
+    }
+    let mut table = Vec::new();
+    let mut iter = blocks.keys().peekable();
+    let mut k = 0;
+    for addr in base..=last {
+        if iter.peek() == Some(&&addr) {
+            iter.next();
+            table.push(k);
+            k += 1;
+        } else {
+            table.push(n);
+        }
+    }
+    c.local_get(PC);
+    c.i32_const(i32::from(base));
+    c.i32_sub();
+    c.br_table(&table, n);
+
+    for (k, (&start, block)) in blocks.iter().enumerate() {
+        c.end();
+        let k = k as u32;
+        let to_fallback = n - k - 1;
+        let to_dispatch = n - k;
+
+        let last = block.last().unwrap();
+        let len = usize::from(last.next() - start);
+        let code = &rom[usize::from(start - crate::aot::ROM_START)..][..len];
+        emit_check(&mut c, start, code, to_fallback);
+
+        for i in &block[..block.len() - 1] {
+            emit_op(&mut c, rom, i);
+        }
+
+        let pc = last.pc();
+        let next = last.next();
+        match last.op {
+            op::BRK => {
+                save_indices(&mut c);
+                c.i32_const(i32::from(pc));
+                c.return_();
+                continue;
+            }
+            op::JCI => {
+                let mut g = Gen::new(&mut c, op::JCI);
+                g.pop_byte_into(A);
+                let dt = rom_short(rom, pc).unwrap();
+                c.i32_const(i32::from(next.wrapping_add(dt)));
+                c.i32_const(i32::from(next));
+                c.local_get(A);
+                c.select();
+            }
+            op::JMI => {
+                let dt = rom_short(rom, pc).unwrap();
+                c.i32_const(i32::from(next.wrapping_add(dt)));
+            }
+            op::JSI => {
+                let dt = rom_short(rom, pc).unwrap();
+                let mut g = Gen::new(&mut c, op::JSI);
+                g.c.i32_const(i32::from(next));
+                g.c.local_set(A);
+                g.push_to(Stack::Return, true, A);
+                c.i32_const(i32::from(next.wrapping_add(dt)));
+            }
+            o if matches!(o & 0x1f, op::DEI | op::DEO) => {
+                // Run everything up to this instruction, then fall back
+                c.i32_const(i32::from(last.addr));
+                c.local_set(PC);
+                c.br(to_fallback);
+                continue;
+            }
+            o if o & 0x1f == op::JMP => {
+                let mut g = Gen::new(&mut c, o);
+                g.pop(A);
+                g.target(pc, A);
+            }
+            o if o & 0x1f == op::JCN => {
+                let mut g = Gen::new(&mut c, o);
+                g.pop(A);
+                g.pop_byte_into(B);
+                g.target(pc, A);
+                c.i32_const(i32::from(next));
+                c.local_get(B);
+                c.select();
+            }
+            o if o & 0x1f == op::JSR => {
+                let mut g = Gen::new(&mut c, o);
+                g.c.i32_const(i32::from(pc));
+                g.c.local_set(C);
+                g.push_to(g.src.other(), true, C);
+                g.pop(A);
+                g.target(pc, A);
+            }
+            _ => {
+                // Stores which may modify code
+                emit_op(&mut c, rom, last);
+                c.i32_const(i32::from(next));
+            }
+        }
+        c.local_set(PC);
+        c.br(to_dispatch);
+    }
+
+    // Fallback: save state and return to the interpreter
+    c.end();
+    save_indices(&mut c);
+    c.local_get(PC);
+    c.i32_const(FALLBACK as i32);
+    c.i32_or();
+    c.return_();
+    c.end(); // dispatch loop
+    c.unreachable();
+
+    encode::module("run", PARAMS, LOCALS, c)
+}
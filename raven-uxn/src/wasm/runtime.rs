@@ -0,0 +1,58 @@
+//! Instantiation of compiled modules in the host's WebAssembly engine
+//!
+//! This is only meaningful on `wasm32`, where the VM's RAM and stacks live in
+//! the same linear memory that the compiled module imports.
+use js_sys::{Function, Object, Reflect, Uint8Array, WebAssembly};
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Handle to the `run` function of an instantiated module
+pub(crate) struct Runtime(Function);
+
+impl Runtime {
+    /// Compiles and instantiates a module built by [`compile`](super::compile)
+    ///
+    /// Returns `None` if the engine rejects the module, in which case the
+    /// caller should fall back to the interpreter.
+    pub fn new(module: &[u8]) -> Option<Self> {
+        let bytes = Uint8Array::from(module);
+        let module = WebAssembly::Module::new(&bytes).ok()?;
+
+        let env = Object::new();
+        Reflect::set(&env, &"memory".into(), &wasm_bindgen::memory()).ok()?;
+        let imports = Object::new();
+        Reflect::set(&imports, &"env".into(), &env).ok()?;
+
+        let instance = WebAssembly::Instance::new(&module, &imports).ok()?;
+        let run = Reflect::get(&instance.exports(), &"run".into()).ok()?;
+        run.dyn_into().ok().map(Self)
+    }
+
+    /// Runs compiled code from the given program counter
+    ///
+    /// The first five arguments are addresses in linear memory, and the return
+    /// value is the status described in the [module-level
+    /// documentation](super).
+    pub fn call(
+        &self,
+        ram: usize,
+        stack: (usize, usize),
+        ret: (usize, usize),
+        pc: u16,
+    ) -> u32 {
+        let arg = |v: usize| JsValue::from(v as u32);
+        self.0
+            .call6(
+                &JsValue::NULL,
+                &arg(ram),
+                &arg(stack.0),
+                &arg(stack.1),
+                &arg(ret.0),
+                &arg(ret.1),
+                &JsValue::from(pc),
+            )
+            .ok()
+            .and_then(|v| v.as_f64())
+            .map(|v| v as u32)
+            .unwrap_or(super::FALLBACK | u32::from(pc))
+    }
+}
@@ -0,0 +1,107 @@
+//! Runs standard ROMs on each available backend, reporting instructions per
+//! second
+//!
+//! Each ROM has a fixed workload: the reset vector plus a fixed number of
+//! frames.  The number of instructions in that workload is counted once with
+//! the interpreter's cycle accounting (capped at [`FUEL`]), then used as the
+//! throughput for every backend.
+use criterion::{
+    black_box, criterion_group, criterion_main, Criterion, Throughput,
+};
+use raven_varvara::Varvara;
+use uxn::{op, Backend, Uxn, UxnRam, UNIT_CYCLE_COSTS};
+
+/// Maximum number of instructions in a single workload
+const FUEL: u64 = 100_000_000;
+
+/// Recursively computes `fib(24)`, leaving the result on the stack
+#[rustfmt::skip]
+const FIB: &[u8] = &[
+    // |0100 #0018 fib BRK
+    op::LIT2, 0x00, 0x18, op::JSI, 0x00, 0x02, op::BRK, op::BRK,
+    // @fib ( n* -- f* ) DUP2 #0002 LTH2 ?&end
+    op::DUP2, op::LIT2, 0x00, 0x02, op::LTH2, op::JCI, 0x00, 0x11,
+    // DUP2 #0001 SUB2 fib SWP2 #0002 SUB2 fib ADD2
+    op::DUP2, op::LIT2, 0x00, 0x01, op::SUB2, op::JSI, 0xff, 0xf0,
+    op::SWP2, op::LIT2, 0x00, 0x02, op::SUB2, op::JSI, 0xff, 0xe8,
+    op::ADD2,
+    // &end JMP2r
+    op::JMP2r,
+];
+
+/// Draws 4096 2bpp sprites across the screen, using the ROM as sprite data
+#[rustfmt::skip]
+const SPRITES: &[u8] = &[
+    // |0100 #0100 .Screen/addr DEO2 #0000
+    op::LIT2, 0x01, 0x00, op::LIT, 0x2c, op::DEO2, op::LIT2, 0x00, 0x00,
+    // &loop DUP2 #01ff AND2 .Screen/x DEO2 DUP2 #04 SFT2 .Screen/y DEO2
+    op::DUP2, op::LIT2, 0x01, 0xff, op::AND2, op::LIT, 0x28, op::DEO2,
+    op::DUP2, op::LIT, 0x04, op::SFT2, op::LIT, 0x2a, op::DEO2,
+    // #81 .Screen/sprite DEO
+    op::LIT, 0x81, op::LIT, 0x2f, op::DEO,
+    // INC2 DUP2 #1000 NEQ2 ?&loop POP2 BRK
+    op::INC2, op::DUP2, op::LIT2, 0x10, 0x00, op::NEQ2, op::JCI, 0xff, 0xe3,
+    op::POP2, op::BRK,
+];
+
+/// Runs the reset vector, then calls the screen vector `frames` times
+fn run(vm: &mut Uxn, dev: &mut Varvara, rom: &[u8], frames: usize) {
+    let extra = vm.reset(rom);
+    dev.reset(extra);
+    vm.run(dev, 0x100);
+    for _ in 0..frames {
+        dev.redraw(vm);
+    }
+}
+
+/// Counts the instructions executed by a workload
+fn count_ops(rom: &[u8], frames: usize) -> u64 {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::builder(&mut ram)
+        .backend(Backend::Interpreter)
+        .cycle_costs(&UNIT_CYCLE_COSTS)
+        .cycle_limit(FUEL)
+        .build();
+    let mut dev = Varvara::new();
+    run(&mut vm, &mut dev, rom, frames);
+    assert!(!vm.is_out_of_cycles(), "workload exceeded fuel budget");
+    vm.cycles()
+}
+
+fn bench_rom(c: &mut Criterion, name: &str, rom: &[u8], frames: usize) {
+    let mut g = c.benchmark_group(name);
+    g.throughput(Throughput::Elements(count_ops(rom, frames)));
+    for backend in [Backend::Interpreter, Backend::Native] {
+        if !backend.is_available() {
+            continue;
+        }
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, backend);
+        let mut dev = Varvara::new();
+        g.bench_function(format!("{backend:?}").to_lowercase(), |b| {
+            b.iter(|| run(&mut vm, &mut dev, black_box(rom), frames))
+        });
+    }
+
+    g.finish();
+}
+
+fn roms(c: &mut Criterion) {
+    bench_rom(c, "fib", FIB, 0);
+    bench_rom(c, "sprites", SPRITES, 0);
+    bench_rom(
+        c,
+        "mandelbrot",
+        include_bytes!("../../roms/mandelbrot.rom"),
+        0,
+    );
+    bench_rom(
+        c,
+        "bunnymark",
+        include_bytes!("../../roms/bunnymark.rom"),
+        60,
+    );
+}
+
+criterion_group!(benches, roms);
+criterion_main!(benches);
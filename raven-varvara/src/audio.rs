@@ -1,8 +1,12 @@
-use crate::Event;
+use crate::{
+    capture::{AudioSink, Tap},
+    ports::{port_names, PageNames},
+    Event,
+};
 use std::{
     collections::VecDeque,
     mem::offset_of,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
     sync::{Arc, Mutex},
 };
 use uxn::{Ports, Uxn, DEV_SIZE};
@@ -35,6 +39,30 @@ impl AudioPorts {
     const POSITION_L: u8 = Self::POSITION_H + 1;
     const OUTPUT: u8 = offset_of!(Self, output) as u8;
 
+    pub(crate) const NAMES: [PageNames; DEV_COUNT as usize] = {
+        macro_rules! names {
+            ($dev:literal) => {
+                port_names!(Self, $dev, {
+                    vector => "vector",
+                    position => "position",
+                    output => "output",
+                    duration => "duration",
+                    adsr => "adsr",
+                    length => "length",
+                    addr => "addr",
+                    volume => "volume",
+                    pitch => "pitch",
+                })
+            };
+        }
+        [
+            names!("Audio0"),
+            names!("Audio1"),
+            names!("Audio2"),
+            names!("Audio3"),
+        ]
+    };
+
     /// Checks whether the given value is in the audio ports memory space
     pub fn matches(t: u8) -> bool {
         (Self::BASE..Self::BASE + 0x10 * DEV_COUNT).contains(&t)
@@ -173,6 +201,100 @@ struct Stream {
     data: Arc<Mutex<StreamData>>,
 }
 
+/// Method used to read sample data between its points
+///
+/// Samples are usually played at a different rate from the output, so each
+/// output value falls between two sample points.  Higher-quality methods cost
+/// more CPU time, but reduce the aliasing which is audible when playing short
+/// samples at low pitches.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Interpolation {
+    /// Linear interpolation between neighboring points (the default, which
+    /// matches the reference implementation)
+    #[default]
+    Linear,
+    /// Cubic (Catmull-Rom) interpolation over four points
+    Cubic,
+    /// Windowed-sinc (Lanczos) interpolation over eight points
+    Sinc,
+}
+
+impl Interpolation {
+    fn from_u8(i: u8) -> Self {
+        match i {
+            1 => Self::Cubic,
+            2 => Self::Sinc,
+            _ => Self::Linear,
+        }
+    }
+}
+
+/// Per-channel options for playing samples
+///
+/// These are applied whenever a note starts, so changes take effect from the
+/// next note.  The default settings match the reference implementation.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Playback {
+    /// Region of the sample (as a range of byte offsets) which is repeated by
+    /// looping notes
+    ///
+    /// Looping notes play the whole sample once, then repeat only this region;
+    /// `None` repeats the entire sample.  The region is clipped to the length
+    /// of each sample, and ignored if that leaves it empty.
+    pub loop_points: Option<(u16, u16)>,
+
+    /// Plays samples backwards, starting from their last byte
+    pub reverse: bool,
+}
+
+/// Half-width of the Lanczos window, in sample points
+const LANCZOS_A: isize = 4;
+
+/// Evaluates the Lanczos kernel at `x`
+fn lanczos(x: f32) -> f32 {
+    use std::f32::consts::PI;
+    let a = LANCZOS_A as f32;
+    if x == 0.0 {
+        1.0
+    } else if x.abs() >= a {
+        0.0
+    } else {
+        let px = PI * x;
+        a * px.sin() * (px / a).sin() / (px * px)
+    }
+}
+
+/// Mixer flags, shared between the [`Audio`] device and its streams
+#[derive(Default)]
+struct Mixer {
+    /// Global mute flag
+    muted: AtomicBool,
+
+    /// Per-channel mute flags
+    channel_muted: [AtomicBool; DEV_COUNT as usize],
+
+    /// Per-channel solo flags
+    ///
+    /// If any channel is soloed, only soloed channels are audible.
+    solo: [AtomicBool; DEV_COUNT as usize],
+
+    /// Capture of the mixed output, if active
+    tap: Mutex<Option<Tap>>,
+
+    /// Interpolation method, stored as an [`Interpolation`] discriminant
+    interpolation: AtomicU8,
+}
+
+impl Mixer {
+    /// Checks whether the given channel is currently audible
+    fn is_audible(&self, i: usize) -> bool {
+        let any_solo = self.solo.iter().any(|s| s.load(Ordering::Relaxed));
+        !self.muted.load(Ordering::Relaxed)
+            && !self.channel_muted[i].load(Ordering::Relaxed)
+            && (!any_solo || self.solo[i].load(Ordering::Relaxed))
+    }
+}
+
 #[derive(Debug)]
 enum Stage {
     Attack(f32),
@@ -186,6 +308,14 @@ pub struct StreamData {
     samples: Vec<u8>,
     loop_sample: bool,
 
+    /// Region which is repeated when looping, as a half-open range
+    loop_start: usize,
+    loop_end: usize,
+
+    /// Set until the whole sample has been played once; after that, looping
+    /// notes only repeat the loop region
+    first_pass: bool,
+
     /// Computed samples from the previous stream, for crossfading
     crossfade: VecDeque<f32>,
 
@@ -195,7 +325,7 @@ pub struct StreamData {
     /// Absolute position
     megapos: f32,
 
-    /// Amount to increment `pos` on each sample
+    /// Amount to increment `pos` on each sample (negative when reversed)
     inc: f32,
 
     /// Current stage
@@ -219,18 +349,24 @@ pub struct StreamData {
     /// Set in the audio thread when the note is done
     done: Arc<AtomicBool>,
 
-    /// Flag to mute the audio stream from the GUI
+    /// Mute and solo flags, set from the GUI
     ///
-    /// This is read-only in the [`StreamData`] and set by the parent
-    muted: Arc<AtomicBool>,
+    /// These are read-only in the [`StreamData`] and set by the parent
+    mixer: Arc<Mixer>,
+
+    /// Index of this stream's channel, used to look up its mixer flags
+    index: usize,
 }
 
 impl StreamData {
-    fn new(muted: Arc<AtomicBool>) -> Self {
+    fn new(mixer: Arc<Mixer>, index: usize) -> Self {
         Self {
             samples: vec![],
             crossfade: VecDeque::new(),
             loop_sample: false,
+            loop_start: 0,
+            loop_end: 0,
+            first_pass: true,
             pos: 0.0,
             megapos: 0.0,
             inc: 0.0,
@@ -241,7 +377,8 @@ impl StreamData {
             right: 0.0,
             envelope: Envelope(0.into()),
             done: Arc::new(AtomicBool::new(false)),
-            muted,
+            mixer,
+            index,
         }
     }
 
@@ -250,32 +387,128 @@ impl StreamData {
         self.samples.get(f).cloned().unwrap_or(0) as f32
     }
 
+    /// Reads a sample point for a wide interpolation kernel
+    ///
+    /// Looping samples wrap around within their loop region; other samples
+    /// are extended with their first and last points.
+    fn get_point(&self, f: isize) -> f32 {
+        let n = self.samples.len() as isize;
+        if n == 0 {
+            return 0.0;
+        }
+        let (start, end) = (self.loop_start as isize, self.loop_end as isize);
+        let f = if !self.loop_sample {
+            f.clamp(0, n - 1)
+        } else if self.first_pass {
+            // Points past either end of the sample continue into the region
+            if f >= n {
+                start + (f - n).rem_euclid(end - start)
+            } else if f < 0 {
+                start + (f + end - start).rem_euclid(end - start)
+            } else {
+                f
+            }
+        } else if f >= end || f < 0 || (self.inc < 0.0 && f < start) {
+            start + (f - start).rem_euclid(end - start)
+        } else {
+            f
+        };
+        self.get_sample(f as usize)
+    }
+
+    /// Reads the sample data at the current position
+    fn interpolate(&self, mode: Interpolation) -> f32 {
+        let frac = self.pos % 1.0;
+        match mode {
+            Interpolation::Linear => {
+                let lo = self.get_sample(self.pos.floor() as usize);
+                let hi = self.pos.ceil() as usize;
+                let end = if self.first_pass {
+                    self.samples.len()
+                } else {
+                    self.loop_end
+                };
+                let hi = self.get_sample(if hi < end {
+                    hi
+                } else {
+                    self.loop_start
+                });
+                hi * frac + lo * (1.0 - frac)
+            }
+            Interpolation::Cubic => {
+                let i = self.pos.floor() as isize;
+                let [p0, p1, p2, p3] =
+                    [-1, 0, 1, 2].map(|j| self.get_point(i + j));
+                let t = frac;
+                p1 + 0.5
+                    * t
+                    * (p2 - p0
+                        + t * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3
+                            + t * (3.0 * (p1 - p2) + p3 - p0)))
+            }
+            Interpolation::Sinc => {
+                let i = self.pos.floor() as isize;
+                let (sum, weight) = (1 - LANCZOS_A..=LANCZOS_A).fold(
+                    (0.0, 0.0),
+                    |(sum, weight), j| {
+                        let w = lanczos(frac - j as f32);
+                        (sum + w * self.get_point(i + j), weight + w)
+                    },
+                );
+                sum / weight
+            }
+        }
+    }
+
     /// Fills the buffer with stream data
     pub fn next(&mut self, data: &mut [f32]) {
+        self.fill(data);
+        if let Some(t) = self.mixer.tap.lock().unwrap().as_mut() {
+            t.add(self.index, data);
+        }
+    }
+
+    /// Computes stream data, without passing it to the audio capture
+    fn fill(&mut self, data: &mut [f32]) {
         self.duration -= (data.len() / 2) as f32 / SAMPLE_RATE as f32 * 1000.0;
         if self.duration <= 0.0 {
             self.done.store(true, Ordering::Relaxed);
         }
         let mut i = 0;
-        let muted = self.muted.load(Ordering::Relaxed);
+        let muted = !self.mixer.is_audible(self.index);
+        let mode = Interpolation::from_u8(
+            self.mixer.interpolation.load(Ordering::Relaxed),
+        );
 
+        let (start, end) = (self.loop_start as f32, self.loop_end as f32);
+        let len = self.samples.len() as f32;
         while i < data.len() {
-            let wrap = self.samples.len() as f32;
-            let mut valid = true;
-            if self.pos >= wrap {
-                if self.loop_sample {
-                    self.pos %= wrap;
-                } else {
-                    valid = false;
+            if self.loop_sample && end > start && self.first_pass {
+                // Once we run off either end of the sample, move into the loop
+                // region (and stay there)
+                if self.pos >= len {
+                    self.pos = start + (self.pos - len);
+                    self.first_pass = false;
+                } else if self.pos < 0.0 {
+                    self.pos += end;
+                    self.first_pass = false;
+                }
+            }
+            if self.loop_sample
+                && end > start
+                && !self.first_pass
+                && (self.pos >= end || (self.inc < 0.0 && self.pos < start))
+            {
+                self.pos = start + (self.pos - start).rem_euclid(end - start);
+                if self.pos >= end {
+                    // Rounding error when wrapping a tiny negative offset
+                    self.pos = start;
                 }
             }
+            let valid = self.pos >= 0.0 && self.pos < self.samples.len() as f32;
 
             let d = if valid {
-                let lo = self.get_sample(self.pos.floor() as usize);
-                let hi = self.get_sample((self.pos.ceil() % wrap) as usize);
-                let frac = self.pos % 1.0;
-
-                let mut d = hi * frac + lo * (1.0 - frac);
+                let mut d = self.interpolate(mode);
                 d *= self.vol;
                 d = (d).min(u8::MAX as f32);
                 d -= 128.0;
@@ -307,7 +540,7 @@ impl StreamData {
             i += CHANNELS;
 
             self.pos += self.inc;
-            self.megapos += self.inc;
+            self.megapos += self.inc.abs();
             match self.stage {
                 Stage::Attack(a) => {
                     self.vol += a;
@@ -339,51 +572,232 @@ impl StreamData {
     }
 }
 
+/// Mixed output of all four audio streams
+///
+/// Hosts can play this through a single output stream, rather than opening a
+/// device stream for each channel.  Every channel is computed in the same
+/// callback, so they stay in phase regardless of how the host schedules its
+/// audio threads.
+pub struct AudioOutput {
+    streams: [Arc<Mutex<StreamData>>; DEV_COUNT as usize],
+
+    /// Scratch buffer for a single stream's output
+    buf: Vec<f32>,
+}
+
+impl AudioOutput {
+    fn new(streams: [Arc<Mutex<StreamData>>; DEV_COUNT as usize]) -> Self {
+        Self {
+            streams,
+            buf: vec![],
+        }
+    }
+
+    /// Fills the buffer with the sum of every stream
+    pub fn next(&mut self, data: &mut [f32]) {
+        data.fill(0.0);
+        self.buf.resize(data.len(), 0.0);
+        for s in &self.streams {
+            s.lock().unwrap().next(&mut self.buf);
+            for (d, v) in data.iter_mut().zip(&self.buf) {
+                *d += v;
+            }
+        }
+    }
+}
+
+/// Adapts the mixed output to a device's sample rate and channel count
+///
+/// Audio is generated at [`SAMPLE_RATE`] with [`CHANNELS`] channels; this
+/// resamples it (with linear interpolation) for devices which don't support
+/// that configuration.  Mono output mixes the left and right channels; extra
+/// channels (beyond stereo) are left silent.
+///
+/// Samples are pulled from the mixer in blocks sized for each call, so the
+/// resampler adds at most one output buffer of latency.
+pub struct Resampler {
+    output: AudioOutput,
+
+    /// Output channel count
+    channels: usize,
+
+    /// Stream frames per output frame
+    ratio: f64,
+
+    /// Interpolation position between `prev` and `next`
+    frac: f64,
+
+    /// Frames on either side of the current position
+    prev: [f32; CHANNELS],
+    next: [f32; CHANNELS],
+
+    /// Frames pulled from the stream but not yet used
+    pending: VecDeque<f32>,
+}
+
+impl Resampler {
+    /// Builds a resampler for an output device
+    ///
+    /// # Panics
+    /// If `rate` or `channels` is 0
+    pub fn new(output: AudioOutput, rate: u32, channels: usize) -> Self {
+        assert!(rate > 0, "sample rate must be positive");
+        assert!(channels > 0, "channel count must be positive");
+        Self {
+            output,
+            channels,
+            ratio: f64::from(SAMPLE_RATE) / f64::from(rate),
+            // Two steps load the first frame into `prev`
+            frac: 2.0,
+            prev: [0.0; CHANNELS],
+            next: [0.0; CHANNELS],
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Fills an interleaved output buffer
+    pub fn next(&mut self, data: &mut [f32]) {
+        let frames = data.len() / self.channels;
+        if self.ratio == 1.0 && self.channels == CHANNELS {
+            // Fast path when no resampling is needed
+            self.output.next(data);
+            return;
+        }
+
+        // Pull enough frames from the mixer for this buffer
+        let needed = (self.frac + frames as f64 * self.ratio).floor() as usize;
+        let have = self.pending.len() / CHANNELS;
+        if needed > have {
+            let mut buf = vec![0.0; (needed - have) * CHANNELS];
+            self.output.next(&mut buf);
+            self.pending.extend(buf);
+        }
+
+        for out in data.chunks_exact_mut(self.channels) {
+            while self.frac >= 1.0 {
+                self.prev = self.next;
+                for v in &mut self.next {
+                    *v = self.pending.pop_front().unwrap_or(0.0);
+                }
+                self.frac -= 1.0;
+            }
+            let t = self.frac as f32;
+            let frame: [f32; CHANNELS] = std::array::from_fn(|i| {
+                self.prev[i] * (1.0 - t) + self.next[i] * t
+            });
+            if self.channels == 1 {
+                out[0] = frame.iter().sum::<f32>() / CHANNELS as f32;
+            } else if CHANNELS == 1 {
+                out.fill(frame[0]);
+            } else {
+                let n = CHANNELS.min(self.channels);
+                out[..n].copy_from_slice(&frame[..n]);
+                out[n..].fill(0.0);
+            }
+            self.frac += self.ratio;
+        }
+    }
+}
+
+/// Audio devices, which control four output streams
 pub struct Audio {
     streams: [Stream; DEV_COUNT as usize],
 
-    /// Flag to mute the audio stream from the GUI
-    muted: Arc<AtomicBool>,
+    /// Mute and solo flags, shared with each stream
+    mixer: Arc<Mixer>,
+
+    /// Loop and direction settings for each channel
+    playback: [Playback; DEV_COUNT as usize],
 }
 
 impl Audio {
-    pub fn new() -> Self {
-        let muted = Arc::new(AtomicBool::new(false));
-        let stream_data = [(); 4]
-            .map(|_| Arc::new(Mutex::new(StreamData::new(muted.clone()))));
+    pub(crate) fn new() -> Self {
+        let mixer = Arc::new(Mixer::default());
+        let stream_data = [0, 1, 2, 3]
+            .map(|i| Arc::new(Mutex::new(StreamData::new(mixer.clone(), i))));
         let streams = [0, 1, 2, 3].map(|i| Stream {
             done: stream_data[i].lock().unwrap().done.clone(),
             data: stream_data[i].clone(),
         });
 
-        Audio { streams, muted }
+        Audio {
+            streams,
+            mixer,
+            playback: Default::default(),
+        }
     }
 
     /// Sets the global mute flag
-    pub fn set_muted(&mut self, m: bool) {
-        self.muted.store(m, Ordering::Relaxed);
+    pub(crate) fn set_muted(&mut self, m: bool) {
+        self.mixer.muted.store(m, Ordering::Relaxed);
+    }
+
+    /// Checks whether the global mute flag is set
+    pub fn muted(&self) -> bool {
+        self.mixer.muted.load(Ordering::Relaxed)
+    }
+
+    /// Sets the mute flag for a single channel
+    pub(crate) fn set_channel_muted(&mut self, i: usize, m: bool) {
+        self.mixer.channel_muted[i].store(m, Ordering::Relaxed);
+    }
+
+    /// Checks whether a single channel is muted
+    pub fn channel_muted(&self, i: usize) -> bool {
+        self.mixer.channel_muted[i].load(Ordering::Relaxed)
+    }
+
+    /// Sets the solo flag for a single channel
+    pub(crate) fn set_channel_solo(&mut self, i: usize, s: bool) {
+        self.mixer.solo[i].store(s, Ordering::Relaxed);
+    }
+
+    /// Checks whether a single channel is soloed
+    pub fn channel_solo(&self, i: usize) -> bool {
+        self.mixer.solo[i].load(Ordering::Relaxed)
+    }
+
+    /// Sets the interpolation method used by every stream
+    pub(crate) fn set_interpolation(&mut self, i: Interpolation) {
+        self.mixer.interpolation.store(i as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the current interpolation method
+    pub fn interpolation(&self) -> Interpolation {
+        Interpolation::from_u8(self.mixer.interpolation.load(Ordering::Relaxed))
+    }
+
+    /// Checks whether a channel is audible, given the mute and solo flags
+    pub fn channel_audible(&self, i: usize) -> bool {
+        self.mixer.is_audible(i)
     }
 
     /// Resets the audio stream data, preserving the same allocation
-    pub fn reset(&mut self) {
-        for s in &self.streams {
-            *s.data.lock().unwrap() = StreamData::new(self.muted.clone());
+    ///
+    /// Mixer flags (mute and solo) are left unchanged.
+    pub(crate) fn reset(&mut self) {
+        for (i, s) in self.streams.iter().enumerate() {
+            *s.data.lock().unwrap() = StreamData::new(self.mixer.clone(), i);
             s.done.store(false, Ordering::Relaxed);
         }
     }
 
     /// Return the "note done" vector if the given channel is done
-    pub fn update(&self, vm: &Uxn, i: usize) -> Option<Event> {
+    pub(crate) fn update(&self, vm: &Uxn, i: usize) -> Option<Event> {
         if self.streams[i].done.swap(false, Ordering::Relaxed) {
             let p = AudioPorts::dev(vm, i);
             let vector = p.vector.get();
-            Some(Event { data: None, vector })
+            Some(Event {
+                data: None,
+                vector,
+                device: AudioPorts::BASE + (i * DEV_SIZE) as u8,
+            })
         } else {
             None
         }
     }
 
-    pub fn deo(&mut self, vm: &mut Uxn, target: u8) {
+    pub(crate) fn deo(&mut self, vm: &mut Uxn, target: u8) {
         let (i, target) = Self::decode_target(target);
         if target == AudioPorts::PITCH {
             let p = AudioPorts::dev(vm, i);
@@ -407,7 +821,7 @@ impl Audio {
                 // Populate crossfade samples by sampling the previous stream
                 let mut crossfade = std::mem::take(&mut d.crossfade);
                 crossfade.resize(CROSSFADE_COUNT, 0.0f32);
-                d.next(crossfade.make_contiguous());
+                d.fill(crossfade.make_contiguous());
 
                 // Copy the entire sample into RAM, reusing allocation
                 let mut samples = std::mem::take(&mut d.samples);
@@ -416,10 +830,21 @@ impl Audio {
                 for i in 0..len {
                     samples.push(vm.ram_read_byte(base_addr + i));
                 }
+                let playback = self.playback[i];
                 let inc = TUNING[p.pitch.note() as usize] * sample_rate;
+                let (loop_start, loop_end) = match playback.loop_points {
+                    Some((a, b))
+                        if usize::from(a)
+                            < usize::from(b).min(samples.len()) =>
+                    {
+                        (usize::from(a), usize::from(b).min(samples.len()))
+                    }
+                    _ => (0, samples.len()),
+                };
                 let attack = p.adsr.attack();
 
                 let duration = p.duration();
+                let samples_len = samples.len();
 
                 let done = self.streams[i].done.clone();
                 done.store(false, Ordering::Relaxed);
@@ -427,9 +852,16 @@ impl Audio {
                     samples,
                     crossfade,
                     loop_sample: p.pitch.loop_sample(),
-                    pos: 0.0,
+                    loop_start,
+                    loop_end,
+                    first_pass: true,
+                    pos: if playback.reverse {
+                        samples_len.saturating_sub(1) as f32
+                    } else {
+                        0.0
+                    },
                     megapos: 0.0,
-                    inc,
+                    inc: if playback.reverse { -inc } else { inc },
                     duration,
                     done,
 
@@ -446,13 +878,14 @@ impl Audio {
                     } else {
                         Stage::Decay
                     },
-                    muted: self.muted.clone(),
+                    mixer: self.mixer.clone(),
+                    index: i,
                 };
             }
         }
     }
 
-    pub fn dei(&mut self, vm: &mut Uxn, target: u8) {
+    pub(crate) fn dei(&mut self, vm: &mut Uxn, target: u8) {
         let (i, target) = Self::decode_target(target);
         let p = AudioPorts::dev_mut(vm, i);
 
@@ -480,8 +913,37 @@ impl Audio {
         (i, target & 0xF)
     }
 
+    /// Installs or removes the audio capture, returning the previous sink
+    pub(crate) fn set_tap(
+        &mut self,
+        tap: Option<Tap>,
+    ) -> Option<Box<dyn AudioSink>> {
+        let prev = std::mem::replace(&mut *self.mixer.tap.lock().unwrap(), tap);
+        prev.map(Tap::finish)
+    }
+
+    /// Sets the playback options for a single channel
+    pub(crate) fn set_playback(&mut self, i: usize, p: Playback) {
+        self.playback[i] = p;
+    }
+
+    /// Returns the playback options for a single channel
+    pub fn playback(&self, i: usize) -> Playback {
+        self.playback[i]
+    }
+
+    /// Checks whether audio capture is active
+    pub(crate) fn has_tap(&self) -> bool {
+        self.mixer.tap.lock().unwrap().is_some()
+    }
+
     /// Returns a handle to the given stream data
-    pub fn stream(&self, i: usize) -> Arc<Mutex<StreamData>> {
+    pub(crate) fn stream(&self, i: usize) -> Arc<Mutex<StreamData>> {
         self.streams[i].data.clone()
     }
+
+    /// Returns a mixer which sums every stream
+    pub(crate) fn output(&self) -> AudioOutput {
+        AudioOutput::new([0, 1, 2, 3].map(|i| self.stream(i)))
+    }
 }
@@ -0,0 +1,187 @@
+//! Builder for configuring a [`Varvara`] system
+use std::path::PathBuf;
+
+use uxn::Ports;
+
+use crate::{
+    audio::AudioPorts,
+    controller::{Controller, ControllerPorts},
+    file::FilePorts,
+    keymap::KeyMap,
+    mouse::MousePorts,
+    screen::ScreenPorts,
+    Clock, FileBackend, PixelFormat, Varvara,
+};
+
+/// Builder for a [`Varvara`] system, returned by [`Varvara::builder`]
+///
+/// By default, every device is enabled and files are accessed relative to the
+/// current directory (matching [`Varvara::new`]).
+///
+/// A disabled device behaves as if it were not present: the ROM can still
+/// read and write its ports, but nothing happens, its vectors are never
+/// called, and the host's corresponding input methods (e.g.
+/// [`Varvara::mouse`]) do nothing.  The system, console, and datetime devices
+/// cannot be disabled.
+#[must_use]
+pub struct VarvaraBuilder {
+    screen: bool,
+    audio: bool,
+    file: bool,
+    mouse: bool,
+    controller: bool,
+    file_root: Option<PathBuf>,
+    file_backend: Option<Box<dyn FileBackend>>,
+    async_file_io: bool,
+    clock: Option<Box<dyn Clock>>,
+    pixel_format: PixelFormat,
+    key_map: KeyMap,
+}
+
+impl Default for VarvaraBuilder {
+    fn default() -> Self {
+        Self {
+            screen: true,
+            audio: true,
+            file: true,
+            mouse: true,
+            controller: true,
+            file_root: None,
+            file_backend: None,
+            async_file_io: false,
+            clock: None,
+            pixel_format: PixelFormat::default(),
+            key_map: KeyMap::default(),
+        }
+    }
+}
+
+impl VarvaraBuilder {
+    /// Enables or disables the screen device
+    ///
+    /// A disabled screen has a size of 0×0, so no frame buffer is allocated,
+    /// and [`Varvara::redraw`] never calls the screen vector.
+    pub fn screen(mut self, enabled: bool) -> Self {
+        self.screen = enabled;
+        self
+    }
+
+    /// Enables or disables the audio devices
+    ///
+    /// When disabled, [`Varvara::audio_output`] still returns a valid (silent)
+    /// output.
+    pub fn audio(mut self, enabled: bool) -> Self {
+        self.audio = enabled;
+        self
+    }
+
+    /// Enables or disables the file devices
+    pub fn file(mut self, enabled: bool) -> Self {
+        self.file = enabled;
+        self
+    }
+
+    /// Enables or disables the mouse device
+    pub fn mouse(mut self, enabled: bool) -> Self {
+        self.mouse = enabled;
+        self
+    }
+
+    /// Enables or disables the controller device
+    pub fn controller(mut self, enabled: bool) -> Self {
+        self.controller = enabled;
+        self
+    }
+
+    /// Sets the directory in which the file devices operate
+    ///
+    /// Paths from the ROM are resolved relative to this directory (instead of
+    /// the current directory), and cannot escape it.
+    pub fn file_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.file_root = Some(root.into());
+        self
+    }
+
+    /// Sets the storage used by the file devices
+    ///
+    /// See [`Varvara::set_file_backend`] for details.
+    pub fn file_backend<B: FileBackend + 'static>(
+        mut self,
+        backend: B,
+    ) -> Self {
+        self.file_backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Runs file operations on a worker thread
+    ///
+    /// See [`Varvara::set_async_file_io`] for details.
+    pub fn async_file_io(mut self, enabled: bool) -> Self {
+        self.async_file_io = enabled;
+        self
+    }
+
+    /// Sets the clock used by the datetime device
+    ///
+    /// By default, the device reads the host's local time (with
+    /// [`SystemClock`](crate::SystemClock)).  The clock is kept when the
+    /// system is reset.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Sets the byte order of pixels in rendered frames
+    ///
+    /// See [`Varvara::set_pixel_format`] for details.
+    pub fn pixel_format(mut self, format: PixelFormat) -> Self {
+        self.pixel_format = format;
+        self
+    }
+
+    /// Sets the mapping from keys to controller buttons
+    ///
+    /// See [`Varvara::set_key_map`] for details.
+    pub fn key_map(mut self, map: KeyMap) -> Self {
+        self.key_map = map;
+        self
+    }
+
+    /// Builds the system
+    pub fn build(self) -> Varvara {
+        let page = |base: u8, count: u8| {
+            (0..count).fold(0u16, |m, i| m | 1 << ((base >> 4) + i))
+        };
+        let mut disabled = 0;
+        for (enabled, mask) in [
+            (self.screen, page(ScreenPorts::BASE, 1)),
+            (self.audio, page(AudioPorts::BASE, 4)),
+            (self.file, page(FilePorts::BASE, 2)),
+            (self.mouse, page(MousePorts::BASE, 1)),
+            (self.controller, page(ControllerPorts::BASE, 1)),
+        ] {
+            if !enabled {
+                disabled |= mask;
+            }
+        }
+        let mut v = Varvara::with_options(disabled, self.file_root);
+        if let Some(b) = self.file_backend {
+            v.file.set_backend(b);
+        }
+        v.file.set_async(self.async_file_io);
+        if let Some(c) = self.clock {
+            v.datetime.set_clock(c);
+        }
+        v.set_pixel_format(self.pixel_format);
+        v.controller = Controller::new(self.key_map.clone());
+        v.key_map = self.key_map;
+        v
+    }
+}
+
+impl Varvara {
+    /// Returns a builder, to enable or disable individual devices
+    pub fn builder() -> VarvaraBuilder {
+        VarvaraBuilder::default()
+    }
+}
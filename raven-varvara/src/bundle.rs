@@ -0,0 +1,142 @@
+//! ROMs distributed with their data files
+use crate::vfs::{MemoryFs, ReadOnly};
+use std::io::Read;
+
+/// Error returned when a bundle can't be loaded
+#[derive(Debug)]
+pub enum BundleError {
+    /// The data isn't a valid zip archive
+    Zip(zip::result::ZipError),
+    /// The archive has no ROM
+    NoRom,
+    /// The archive has several ROMs at its top level, with these names
+    AmbiguousRom(Vec<String>),
+    /// The archive's files add up to more than [`Bundle::MAX_UNPACKED_SIZE`]
+    TooLarge,
+}
+
+impl std::fmt::Display for BundleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BundleError::Zip(e) => write!(f, "invalid zip archive: {e}"),
+            BundleError::NoRom => write!(f, "no `.rom` file in archive"),
+            BundleError::AmbiguousRom(names) => {
+                write!(f, "multiple ROMs in archive: {}", names.join(", "))
+            }
+            BundleError::TooLarge => write!(
+                f,
+                "archive unpacks to more than {} bytes",
+                Bundle::MAX_UNPACKED_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BundleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BundleError::Zip(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for BundleError {
+    fn from(e: zip::result::ZipError) -> Self {
+        BundleError::Zip(e)
+    }
+}
+
+/// A ROM packaged with its data files
+///
+/// Bundles are zip archives, in one of two layouts:
+///
+/// - A ROM followed by a zip archive (e.g. `cat game.rom assets.zip >
+///   game.rom`).  This still runs as a plain ROM in other emulators, since the
+///   archive is simply extra data at the end of the ROM.
+/// - A plain zip archive with a single `.rom` file at its top level
+///
+/// The archive's contents are served (read-only) by the file devices, e.g.
+/// with [`Varvara::set_file_backend`](crate::Varvara::set_file_backend).
+pub struct Bundle {
+    rom: Vec<u8>,
+    files: MemoryFs,
+}
+
+impl Bundle {
+    /// Maximum total size of the files in a bundle, once unpacked
+    ///
+    /// Files are unpacked into memory, so this stops a small archive from
+    /// expanding to fill it.
+    pub const MAX_UNPACKED_SIZE: u64 = 64 << 20;
+
+    /// Loads a bundle, unpacking its files into memory
+    ///
+    /// Returns [`BundleError::TooLarge`] if the files add up to more than
+    /// [`MAX_UNPACKED_SIZE`](Self::MAX_UNPACKED_SIZE) bytes.
+    pub fn parse(data: &[u8]) -> Result<Self, BundleError> {
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+        let files = MemoryFs::new();
+        let mut roms = vec![];
+        let mut remaining = Self::MAX_UNPACKED_SIZE;
+        for i in 0..archive.len() {
+            let mut f = archive.by_index(i)?;
+            // Skip entries with unsafe names (e.g. absolute paths)
+            let Some(path) = f.enclosed_name() else {
+                continue;
+            };
+            if f.is_dir() {
+                files
+                    .create_dir(&path)
+                    .map_err(zip::result::ZipError::from)?;
+                continue;
+            }
+            if f.size() > remaining {
+                return Err(BundleError::TooLarge);
+            }
+            // The header's size may be wrong, so limit the read as well
+            let mut buf = Vec::with_capacity(f.size() as usize);
+            (&mut f)
+                .take(remaining + 1)
+                .read_to_end(&mut buf)
+                .map_err(zip::result::ZipError::from)?;
+            remaining = remaining
+                .checked_sub(buf.len() as u64)
+                .ok_or(BundleError::TooLarge)?;
+            files
+                .insert(&path, &buf)
+                .map_err(zip::result::ZipError::from)?;
+            if path.parent() == Some(std::path::Path::new(""))
+                && path.extension().is_some_and(|e| e == "rom")
+            {
+                roms.push((path.to_string_lossy().into_owned(), buf));
+            }
+        }
+
+        let offset = archive.offset() as usize;
+        let rom = if offset > 0 {
+            data[..offset].to_vec()
+        } else {
+            match roms.len() {
+                0 => return Err(BundleError::NoRom),
+                1 => roms.pop().unwrap().1,
+                _ => {
+                    return Err(BundleError::AmbiguousRom(
+                        roms.into_iter().map(|(name, _)| name).collect(),
+                    ))
+                }
+            }
+        };
+        Ok(Self { rom, files })
+    }
+
+    /// Returns the ROM's bytes
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Returns a read-only view of the bundled files
+    pub fn files(&self) -> ReadOnly<MemoryFs> {
+        ReadOnly(self.files.clone())
+    }
+}
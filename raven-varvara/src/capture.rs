@@ -0,0 +1,276 @@
+//! Capturing the mixed audio output
+use crate::{
+    audio::{CHANNELS, DEV_COUNT, SAMPLE_RATE},
+    Varvara,
+};
+use log::warn;
+use std::{
+    collections::VecDeque,
+    io::{Seek, SeekFrom, Write},
+    sync::mpsc,
+};
+
+/// Destination for captured audio
+///
+/// Samples are interleaved `f32` values, with
+/// [`AUDIO_CHANNELS`](crate::AUDIO_CHANNELS) channels at
+/// [`AUDIO_SAMPLE_RATE`](crate::AUDIO_SAMPLE_RATE).  Sinks are called from a
+/// dedicated writer thread, so they may block (e.g. on disk I/O) without
+/// stalling audio playback; if a sink falls too far behind, blocks of samples
+/// are dropped rather than delaying the audio threads.
+///
+/// This is implemented for closures, so a host can hand buffers to its own
+/// code, and for [`WavWriter`].
+pub trait AudioSink: Send {
+    /// Receives a block of mixed samples
+    fn write(&mut self, samples: &[f32]);
+
+    /// Finishes capturing, returning the first error encountered (if any)
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&[f32]) + Send> AudioSink for F {
+    fn write(&mut self, samples: &[f32]) {
+        self(samples)
+    }
+}
+
+/// Writes captured audio as a 16-bit PCM WAV file
+///
+/// The header's length fields are filled in by [`AudioSink::finish`], which
+/// is why the output must also implement [`Seek`].
+pub struct WavWriter<W> {
+    out: W,
+    /// Number of bytes of sample data written so far
+    bytes: u32,
+    /// First write error, reported by [`AudioSink::finish`]
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write + Seek + Send> WavWriter<W> {
+    /// Builds a new writer, writing a placeholder header
+    pub fn new(mut out: W) -> std::io::Result<Self> {
+        out.write_all(&wav_header(0))?;
+        Ok(Self {
+            out,
+            bytes: 0,
+            error: None,
+        })
+    }
+}
+
+impl<W: Write + Seek + Send> AudioSink for WavWriter<W> {
+    fn write(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+        let data = samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes())
+            .collect::<Vec<u8>>();
+        match u32::try_from(data.len())
+            .ok()
+            .and_then(|n| self.bytes.checked_add(n))
+            .filter(|n| *n <= u32::MAX - 36)
+        {
+            Some(n) => {
+                if let Err(e) = self.out.write_all(&data) {
+                    self.error = Some(e);
+                }
+                self.bytes = n;
+            }
+            None => {
+                self.error = Some(std::io::Error::other("WAV file is full"));
+            }
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&wav_header(self.bytes))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+/// Builds a 44-byte WAV header for the given amount of sample data
+fn wav_header(bytes: u32) -> [u8; 44] {
+    let channels = CHANNELS as u16;
+    let block = channels * 2;
+    let mut h = [0u8; 44];
+    let fields: [&[u8]; 13] = [
+        b"RIFF",
+        &(36 + bytes).to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        &16u32.to_le_bytes(),
+        &1u16.to_le_bytes(), // PCM
+        &channels.to_le_bytes(),
+        &SAMPLE_RATE.to_le_bytes(),
+        &(SAMPLE_RATE * u32::from(block)).to_le_bytes(),
+        &block.to_le_bytes(),
+        &16u16.to_le_bytes(), // bits per sample
+        b"data",
+        &bytes.to_le_bytes(),
+    ];
+    let mut i = 0;
+    for f in fields {
+        h[i..i + f.len()].copy_from_slice(f);
+        i += f.len();
+    }
+    h
+}
+
+/// Maximum number of samples buffered while waiting for a stream to catch up
+///
+/// If a stream stops being pulled by the host, we'd otherwise buffer samples
+/// forever; past this point, the missing contributions are treated as silence.
+const MAX_PENDING: usize = SAMPLE_RATE as usize * CHANNELS;
+
+/// Maximum number of mixed blocks queued for the writer thread
+///
+/// Once the queue is full, further blocks are dropped (and counted), so a slow
+/// sink never blocks the audio threads.
+const QUEUE_DEPTH: usize = 64;
+
+/// Thread which passes blocks of mixed samples to the sink
+struct Writer {
+    tx: mpsc::SyncSender<Vec<f32>>,
+    thread: std::thread::JoinHandle<Box<dyn AudioSink>>,
+}
+
+impl Writer {
+    fn spawn(mut sink: Box<dyn AudioSink>) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Vec<f32>>(QUEUE_DEPTH);
+        let thread = std::thread::spawn(move || {
+            while let Ok(block) = rx.recv() {
+                sink.write(&block);
+            }
+            sink
+        });
+        Self { tx, thread }
+    }
+
+    /// Waits for queued blocks to be written, then returns the sink
+    fn join(self) -> Box<dyn AudioSink> {
+        drop(self.tx);
+        self.thread.join().expect("audio capture thread panicked")
+    }
+}
+
+/// Mixes the output of every stream, then passes it to a sink
+///
+/// The host pulls each stream separately (and usually from separate
+/// threads), so a block of mixed output is only complete once every stream
+/// has contributed to it.  Completed blocks are handed to a writer thread
+/// through a bounded queue, since this runs in the host's audio callbacks.
+pub(crate) struct Tap {
+    writer: Writer,
+    /// Number of samples dropped because the writer fell behind
+    dropped: u64,
+    /// Mixed samples which haven't yet been passed to the sink
+    pending: VecDeque<f32>,
+    /// Absolute index of the first sample in `pending`
+    start: u64,
+    /// Absolute index of the next sample to be written by each stream
+    pos: [u64; DEV_COUNT as usize],
+}
+
+impl Tap {
+    pub(crate) fn new(sink: Box<dyn AudioSink>) -> Self {
+        Self {
+            writer: Writer::spawn(sink),
+            dropped: 0,
+            pending: VecDeque::new(),
+            start: 0,
+            pos: [0; DEV_COUNT as usize],
+        }
+    }
+
+    /// Adds a block of output from the given stream
+    pub(crate) fn add(&mut self, stream: usize, data: &[f32]) {
+        let offset = (self.pos[stream] - self.start) as usize;
+        if self.pending.len() < offset + data.len() {
+            self.pending.resize(offset + data.len(), 0.0);
+        }
+        for (p, d) in self.pending.range_mut(offset..).zip(data) {
+            *p += d;
+        }
+        self.pos[stream] += data.len() as u64;
+
+        // Send every sample which all of the streams have passed, or enough
+        // to keep the buffer bounded if a stream has stalled
+        let done = self.pos.iter().min().unwrap() - self.start;
+        let n =
+            (done as usize).max(self.pending.len().saturating_sub(MAX_PENDING));
+        self.flush(n);
+    }
+
+    /// Queues the first `n` pending samples for the sink
+    fn flush(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let out = self.pending.drain(..n).collect::<Vec<f32>>();
+        if let Err(mpsc::TrySendError::Full(..)) = self.writer.tx.try_send(out)
+        {
+            self.dropped += n as u64;
+        }
+        self.start += n as u64;
+        for p in &mut self.pos {
+            *p = (*p).max(self.start);
+        }
+    }
+
+    /// Sends any remaining samples, then returns the sink once they're written
+    pub(crate) fn finish(mut self) -> Box<dyn AudioSink> {
+        let n = self.pending.len();
+        let block = self.pending.drain(..n).collect::<Vec<f32>>();
+        // Blocking is fine here, since the capture is being stopped
+        if !block.is_empty() {
+            let _ = self.writer.tx.send(block);
+        }
+        if self.dropped > 0 {
+            warn!(
+                "audio capture dropped {} samples (sink too slow)",
+                self.dropped
+            );
+        }
+        self.writer.join()
+    }
+}
+
+/// # Audio capture
+impl Varvara {
+    /// Starts capturing the mixed audio output
+    ///
+    /// Captured audio is exactly what the host plays (including the effects of
+    /// the mute and solo flags), mixed from all four channels.  Any capture in
+    /// progress is replaced (and returned, so that it can be finished).
+    ///
+    /// The sink runs on its own thread, so this isn't available on
+    /// WebAssembly.
+    pub fn start_audio_capture<S: AudioSink + 'static>(
+        &mut self,
+        sink: S,
+    ) -> Option<Box<dyn AudioSink>> {
+        self.audio.set_tap(Some(Tap::new(Box::new(sink))))
+    }
+
+    /// Stops capturing audio, returning the sink (if one was active)
+    ///
+    /// Call [`AudioSink::finish`] on the result to finish writing the file.
+    pub fn stop_audio_capture(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.audio.set_tap(None)
+    }
+
+    /// Checks whether audio is being captured
+    pub fn is_capturing_audio(&self) -> bool {
+        self.audio.has_tap()
+    }
+}
@@ -1,11 +1,147 @@
-use crate::{Event, EventData};
-use std::mem::offset_of;
+use crate::{
+    ports::{port_names, PageNames},
+    Event, EventData,
+};
+use std::{mem::offset_of, sync::mpsc};
 use uxn::{Ports, Uxn};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
 
+/// Console device, which buffers outgoing characters
 pub struct Console {
     stdout: Vec<u8>,
     stderr: Vec<u8>,
+
+    /// Whether output is buffered for [`Varvara::output`], as `(stdout,
+    /// stderr)`
+    ///
+    /// This is cleared for streams which are consumed by listeners, so that
+    /// the buffers don't grow forever if the host never polls.
+    ///
+    /// [`Varvara::output`]: crate::Varvara::output
+    buffered: (bool, bool),
+
+    /// Host-provided backend, which replaces the buffers if present
+    backend: Option<Box<dyn ConsoleBackend>>,
+}
+
+/// How the process's `stdin` is connected, returned by [`stdin_kind`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StdinKind {
+    /// Interactive terminal
+    Terminal,
+    /// Pipe or file (e.g. `cat data | raven-cli conv.rom`)
+    Piped,
+    /// `stdin` is closed, so there will never be any input
+    Closed,
+}
+
+/// Checks how the process's `stdin` is connected
+///
+/// `stdin` is probed on the first call, and the result is reused afterwards.
+pub fn stdin_kind() -> StdinKind {
+    use std::io::IsTerminal;
+    static KIND: std::sync::OnceLock<StdinKind> = std::sync::OnceLock::new();
+    *KIND.get_or_init(|| {
+        if !stdin_is_open() {
+            StdinKind::Closed
+        } else if std::io::stdin().is_terminal() {
+            StdinKind::Terminal
+        } else {
+            StdinKind::Piped
+        }
+    })
+}
+
+/// Checks whether `stdin` refers to an open file descriptor (or handle)
+fn stdin_is_open() -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+        std::io::stdin().as_fd().try_clone_to_owned().is_ok()
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::AsHandle;
+        std::io::stdin().as_handle().try_clone_to_owned().is_ok()
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        true
+    }
+}
+
+/// Console input from a [`ConsoleBackend`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConsoleInput {
+    /// A character from `stdin`
+    Char(u8),
+    /// The end of input
+    ///
+    /// See [`Varvara::console_end`](crate::Varvara::console_end) for details.
+    End,
+}
+
+/// Host-provided source and sink for console characters
+///
+/// By default, characters written by the ROM are buffered and returned by
+/// [`Varvara::output`](crate::Varvara::output), and the host sends input with
+/// [`Varvara::console`](crate::Varvara::console).  Installing a backend with
+/// [`Varvara::set_console_backend`](crate::Varvara::set_console_backend)
+/// routes output to the backend instead, and polls it for input once per
+/// frame (in [`Varvara::redraw`](crate::Varvara::redraw)).
+pub trait ConsoleBackend: Send {
+    /// Handles a character written to `Console/write`
+    fn write(&mut self, c: u8);
+
+    /// Handles a character written to `Console/error`
+    fn error(&mut self, c: u8);
+
+    /// Returns the next pending input, if any
+    ///
+    /// This must not block.  The default implementation returns `None`.
+    fn read(&mut self) -> Option<ConsoleInput> {
+        None
+    }
+
+    /// Flushes any buffered output
+    ///
+    /// This is called by [`Varvara::output`](crate::Varvara::output), i.e.
+    /// about once per frame.  The default implementation does nothing.
+    fn flush(&mut self) {}
+
+    /// Writes out all buffered output, blocking if necessary
+    ///
+    /// This is called by [`Varvara::output`](crate::Varvara::output) when the
+    /// ROM has requested an exit, since the host may then end the process.
+    /// The default implementation calls [`flush`](Self::flush).
+    fn finish(&mut self) {
+        self.flush()
+    }
+}
+
+/// Console backend which communicates through channels
+///
+/// This is convenient when the console is driven from another thread, e.g. a
+/// GUI panel or an async task.  Closed channels are ignored.
+pub struct ChannelConsole {
+    /// Receives characters written to `Console/write`
+    pub stdout: mpsc::Sender<u8>,
+    /// Receives characters written to `Console/error`
+    pub stderr: mpsc::Sender<u8>,
+    /// Supplies console input
+    pub input: mpsc::Receiver<ConsoleInput>,
+}
+
+impl ConsoleBackend for ChannelConsole {
+    fn write(&mut self, c: u8) {
+        let _ = self.stdout.send(c);
+    }
+    fn error(&mut self, c: u8) {
+        let _ = self.stderr.send(c);
+    }
+    fn read(&mut self) -> Option<ConsoleInput> {
+        self.input.try_recv().ok()
+    }
 }
 
 #[derive(AsBytes, FromZeroes, FromBytes)]
@@ -23,14 +159,28 @@ pub struct ConsolePorts {
     _pad: [u8; 6],
 }
 
-#[derive(Copy, Clone, Debug)]
+/// Value of the `Console/type` port, describing the character in `read`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Type {
-    #[allow(unused)]
+    /// No input has been sent
     NoQueue = 0,
+    /// Character from `stdin`
     Stdin = 1,
+    /// Character from a command-line argument
     Argument = 2,
+    /// Separator between two arguments
     ArgumentSpacer = 3,
-    ArgumentEnd = 4,
+    /// End of the final argument, or end of `stdin`
+    End = 4,
+}
+
+/// Console input, which may be queued when pacing is enabled
+#[derive(Copy, Clone, Debug)]
+pub enum Input {
+    /// Character to send to the console vector
+    Char(u8),
+    /// Change to the `Console/type` port
+    Type(Type),
 }
 
 impl Ports for ConsolePorts {
@@ -39,61 +189,151 @@ impl Ports for ConsolePorts {
 
 impl ConsolePorts {
     const READ: u8 = Self::BASE | offset_of!(Self, read) as u8;
-    const WRITE: u8 = Self::BASE | offset_of!(Self, write) as u8;
-    const ERROR: u8 = Self::BASE | offset_of!(Self, error) as u8;
+    pub(crate) const WRITE: u8 = Self::BASE | offset_of!(Self, write) as u8;
+    pub(crate) const ERROR: u8 = Self::BASE | offset_of!(Self, error) as u8;
+
+    pub(crate) const NAMES: PageNames = port_names!(Self, "Console", {
+        vector => "vector",
+        read => "read",
+        _exec => "exec",
+        _mode => "mode",
+        _dead => "dead",
+        _exit => "exit",
+        type_ => "type",
+        write => "write",
+        error => "error",
+    });
 }
 
 /// Spawns a worker thread that listens on `stdin` and emits characters
 ///
+/// Input is passed through byte-by-byte, without any decoding.  The worker
+/// stops (dropping `tx`) when `stdin` reaches end-of-file or can't be read.
+///
+/// Use [`spawn_worker_with_eof`] to be told explicitly when input ends.
+///
 /// # Panics
 /// If threads are not available on the system (e.g. in WebAssembly)
 pub fn spawn_worker<F, E>(mut tx: F)
 where
     F: FnMut(u8) -> Result<(), E> + Send + 'static,
+{
+    spawn_worker_with_eof(move |c| match c {
+        Some(c) => tx(c),
+        None => Ok(()),
+    })
+}
+
+/// Spawns a worker thread that listens on `stdin` and emits characters
+///
+/// This is like [`spawn_worker`], but `tx` is called with `None` once `stdin`
+/// reaches end-of-file (or can't be read), before the worker stops.  The host
+/// should then call [`Varvara::console_end`](crate::Varvara::console_end).
+///
+/// If `stdin` is closed (see [`stdin_kind`]), no thread is spawned; `tx` is
+/// called with `None` before this function returns.
+///
+/// # Panics
+/// If threads are not available on the system (e.g. in WebAssembly)
+pub fn spawn_worker_with_eof<F, E>(mut tx: F)
+where
+    F: FnMut(Option<u8>) -> Result<(), E> + Send + 'static,
 {
     use std::io::Read;
+    if stdin_kind() == StdinKind::Closed {
+        let _ = tx(None);
+        return;
+    }
     std::thread::spawn(move || {
         let mut i = std::io::stdin().lock();
         let mut buf = [0u8; 32];
         loop {
-            let n = i.read(&mut buf).unwrap();
+            let n = match i.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    continue
+                }
+                Err(e) => {
+                    log::warn!("could not read stdin: {e}");
+                    break;
+                }
+            };
             for &c in &buf[..n] {
-                if tx(c).is_err() {
+                if tx(Some(c)).is_err() {
                     return;
                 }
             }
         }
+        let _ = tx(None);
     });
 }
 
 impl Console {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             stdout: vec![],
             stderr: vec![],
+            buffered: (true, true),
+            backend: None,
+        }
+    }
+
+    /// Clears buffered output, keeping the backend
+    pub(crate) fn reset(&mut self) {
+        self.stdout.clear();
+        self.stderr.clear();
+    }
+
+    /// Sets the backend, returning the previous one
+    pub(crate) fn set_backend(
+        &mut self,
+        b: Option<Box<dyn ConsoleBackend>>,
+    ) -> Option<Box<dyn ConsoleBackend>> {
+        std::mem::replace(&mut self.backend, b)
+    }
+
+    /// Polls the backend for input
+    pub(crate) fn read(&mut self) -> Option<ConsoleInput> {
+        self.backend.as_mut()?.read()
+    }
+
+    /// Flushes the backend, if present
+    pub(crate) fn flush(&mut self) {
+        if let Some(b) = &mut self.backend {
+            b.flush();
+        }
+    }
+
+    /// Writes out all of the backend's output, if present
+    pub(crate) fn finish(&mut self) {
+        if let Some(b) = &mut self.backend {
+            b.finish();
         }
     }
 
-    pub fn deo(&mut self, vm: &mut Uxn, target: u8) {
+    pub(crate) fn deo(&mut self, vm: &mut Uxn, target: u8) {
         let v = vm.dev::<ConsolePorts>();
-        match target {
-            ConsolePorts::WRITE => {
-                self.stdout.push(v.write);
+        match (target, &mut self.backend) {
+            (ConsolePorts::WRITE, Some(b)) => b.write(v.write),
+            (ConsolePorts::WRITE, None) if self.buffered.0 => {
+                self.stdout.push(v.write)
             }
-            ConsolePorts::ERROR => {
-                self.stderr.push(v.error);
+            (ConsolePorts::ERROR, Some(b)) => b.error(v.error),
+            (ConsolePorts::ERROR, None) if self.buffered.1 => {
+                self.stderr.push(v.error)
             }
             _ => (),
         }
     }
-    pub fn dei(&mut self, _vm: &mut Uxn, _target: u8) {
+    pub(crate) fn dei(&mut self, _vm: &mut Uxn, _target: u8) {
         // Nothing to do here; data is pre-populated in `vm.dev` memory
     }
 
     /// Sets the appropriate type value if there are arguments to be parsed
     ///
     /// This should be called before running the reset vector
-    pub fn set_has_args(&mut self, vm: &mut Uxn, has_args: bool) {
+    pub(crate) fn set_has_args(&mut self, vm: &mut Uxn, has_args: bool) {
         if has_args {
             let p = vm.dev_mut::<ConsolePorts>();
             p.type_ = 1;
@@ -103,7 +343,7 @@ impl Console {
     /// Sets the current character type
     ///
     /// This should be called before sending a console event
-    pub fn set_type(&mut self, vm: &mut Uxn, ty: Type) {
+    pub(crate) fn set_type(&mut self, vm: &mut Uxn, ty: Type) {
         let p = vm.dev_mut::<ConsolePorts>();
         p.type_ = ty as u8;
     }
@@ -112,11 +352,12 @@ impl Console {
     ///
     /// Note that this function does not set the type, which should be
     /// configured by calling [`Self::set_type`] before firing the vector.
-    pub fn update(&self, vm: &Uxn, c: u8) -> Event {
+    pub(crate) fn update(&self, vm: &Uxn, c: u8) -> Event {
         let p = vm.dev::<ConsolePorts>();
         let vector = p.vector.get();
         Event {
             vector,
+            device: ConsolePorts::BASE,
             data: Some(EventData {
                 addr: ConsolePorts::READ,
                 value: c,
@@ -125,13 +366,36 @@ impl Console {
         }
     }
 
-    /// Takes the `stderr` buffer, leaving it empty
-    pub fn stdout(&mut self) -> Vec<u8> {
+    /// Sets whether `stdout` and `stderr` are buffered
+    pub(crate) fn set_buffered(&mut self, stdout: bool, stderr: bool) {
+        self.buffered = (stdout, stderr);
+        if !stdout {
+            self.stdout.clear();
+        }
+        if !stderr {
+            self.stderr.clear();
+        }
+    }
+
+    /// Returns characters written to `Console/write` which have not yet been
+    /// returned by [`Varvara::output`](crate::Varvara::output)
+    pub fn pending_stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// Returns characters written to `Console/error` which have not yet been
+    /// returned by [`Varvara::output`](crate::Varvara::output)
+    pub fn pending_stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Takes the `stdout` buffer, leaving it empty
+    pub(crate) fn stdout(&mut self) -> Vec<u8> {
         std::mem::take(&mut self.stdout)
     }
 
     /// Takes the `stderr` buffer, leaving it empty
-    pub fn stderr(&mut self) -> Vec<u8> {
+    pub(crate) fn stderr(&mut self) -> Vec<u8> {
         std::mem::take(&mut self.stderr)
     }
 }
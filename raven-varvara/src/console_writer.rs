@@ -0,0 +1,229 @@
+//! Incremental console output, written from a background thread
+use crate::ConsoleBackend;
+use log::warn;
+use std::{
+    io::Write,
+    sync::mpsc::{self, TrySendError},
+    thread::JoinHandle,
+};
+
+/// Pending bytes are sent to the worker once a stream has this many
+const CHUNK_SIZE: usize = 4096;
+
+/// Maximum number of chunks queued for the worker
+const QUEUE_SIZE: usize = 64;
+
+/// Default limit on pending bytes per stream
+pub const DEFAULT_LIMIT: usize = 1 << 20;
+
+/// Message sent to the worker thread
+enum Chunk {
+    /// Block of output for one stream (`0` for `stdout`, `1` for `stderr`)
+    Data(usize, Vec<u8>),
+    /// Request to reply once all previous output has been written
+    Sync(mpsc::Sender<()>),
+}
+
+/// Console backend which writes output incrementally
+///
+/// Characters from `Console/write` and `Console/error` are gathered into
+/// chunks, which are sent to a worker thread at each newline (or once a chunk
+/// is large enough), and whenever [`Varvara::output`](crate::Varvara::output)
+/// is called.  The worker writes them to the underlying streams, so a slow
+/// terminal or pipe never blocks the VM.
+///
+/// Output is passed through as raw bytes, so invalid UTF-8 is tolerated.
+/// However, a chunk never ends partway through a multi-byte UTF-8 sequence;
+/// a host which decodes each chunk separately (e.g. to display it in a text
+/// panel) won't see spurious replacement characters.
+///
+/// # Backpressure
+/// The worker's queue is bounded.  If it falls behind, output accumulates in
+/// a per-stream buffer of up to `limit` bytes (see
+/// [`ConsoleWriter::with_limit`]); beyond that, further characters are
+/// dropped (and counted by [`ConsoleWriter::dropped`]) until the worker
+/// catches up.  A warning is logged when output starts being dropped.
+///
+/// When the ROM requests an exit, and when the writer is dropped, all pending
+/// output is written before returning.
+pub struct ConsoleWriter {
+    tx: Option<mpsc::SyncSender<Chunk>>,
+    worker: Option<JoinHandle<()>>,
+
+    /// Bytes which have not yet been sent to the worker, for each stream
+    pending: [Vec<u8>; 2],
+
+    /// Maximum length of each pending buffer
+    limit: usize,
+
+    /// Number of bytes dropped
+    dropped: u64,
+
+    /// Whether we're currently dropping output, to avoid repeated warnings
+    dropping: bool,
+}
+
+impl ConsoleWriter {
+    /// Builds a writer for the process's `stdout` and `stderr`
+    pub fn stdio() -> Self {
+        Self::new(std::io::stdout(), std::io::stderr())
+    }
+
+    /// Builds a writer with the default limit of [`DEFAULT_LIMIT`] bytes
+    pub fn new<O, E>(stdout: O, stderr: E) -> Self
+    where
+        O: Write + Send + 'static,
+        E: Write + Send + 'static,
+    {
+        Self::with_limit(stdout, stderr, DEFAULT_LIMIT)
+    }
+
+    /// Builds a writer which buffers at most `limit` bytes per stream
+    pub fn with_limit<O, E>(mut stdout: O, mut stderr: E, limit: usize) -> Self
+    where
+        O: Write + Send + 'static,
+        E: Write + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel::<Chunk>(QUEUE_SIZE);
+        let worker = std::thread::spawn(move || {
+            for c in rx {
+                let (stream, data) = match c {
+                    Chunk::Data(stream, data) => (stream, data),
+                    Chunk::Sync(tx) => {
+                        let _ = tx.send(());
+                        continue;
+                    }
+                };
+                let w: &mut dyn Write = match stream {
+                    0 => &mut stdout,
+                    _ => &mut stderr,
+                };
+                if let Err(e) = w.write_all(&data).and_then(|_| w.flush()) {
+                    warn!("could not write console output: {e}");
+                }
+            }
+        });
+        Self {
+            tx: Some(tx),
+            worker: Some(worker),
+            pending: [vec![], vec![]],
+            limit,
+            dropped: 0,
+            dropping: false,
+        }
+    }
+
+    /// Returns the number of bytes which have been dropped
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn push(&mut self, stream: usize, c: u8) {
+        if self.pending[stream].len() >= self.limit {
+            self.send(stream);
+        }
+        let p = &mut self.pending[stream];
+        if p.len() >= self.limit {
+            if !self.dropping {
+                warn!("console output is backed up; dropping characters");
+                self.dropping = true;
+            }
+            self.dropped += 1;
+            return;
+        }
+        self.dropping = false;
+        p.push(c);
+        if c == b'\n' || p.len() >= CHUNK_SIZE {
+            self.send(stream);
+        }
+    }
+
+    /// Tries to send pending bytes to the worker, without blocking
+    ///
+    /// Bytes at the end of the buffer which may be the start of a multi-byte
+    /// UTF-8 sequence are held back.
+    fn send(&mut self, stream: usize) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let p = &mut self.pending[stream];
+        let n = utf8_boundary(p);
+        if n == 0 {
+            return;
+        }
+        let rest = p.split_off(n);
+        let data = std::mem::replace(p, rest);
+        match tx.try_send(Chunk::Data(stream, data)) {
+            Ok(()) => (),
+            Err(TrySendError::Full(c) | TrySendError::Disconnected(c)) => {
+                // Put the data back, to try again later
+                let Chunk::Data(_, mut data) = c else {
+                    unreachable!()
+                };
+                data.append(p);
+                *p = data;
+            }
+        }
+    }
+
+    /// Sends all pending bytes, blocking until the worker has written them
+    fn sync(&mut self) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        for (stream, data) in self.pending.iter_mut().enumerate() {
+            let data = std::mem::take(data);
+            if !data.is_empty() {
+                let _ = tx.send(Chunk::Data(stream, data));
+            }
+        }
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if tx.send(Chunk::Sync(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+impl ConsoleBackend for ConsoleWriter {
+    fn write(&mut self, c: u8) {
+        self.push(0, c)
+    }
+    fn error(&mut self, c: u8) {
+        self.push(1, c)
+    }
+    fn flush(&mut self) {
+        self.send(0);
+        self.send(1);
+    }
+    fn finish(&mut self) {
+        self.sync();
+    }
+}
+
+impl Drop for ConsoleWriter {
+    fn drop(&mut self) {
+        self.sync();
+        self.tx = None;
+        if let Some(w) = self.worker.take() {
+            let _ = w.join();
+        }
+    }
+}
+
+/// Returns the length of the longest prefix which doesn't end partway through
+/// a UTF-8 sequence
+///
+/// Invalid bytes are treated as complete, so they're never held back.
+fn utf8_boundary(data: &[u8]) -> usize {
+    for (i, &b) in data.iter().enumerate().rev().take(3) {
+        let len = match b {
+            0b1100_0000..=0b1101_1111 => 2,
+            0b1110_0000..=0b1110_1111 => 3,
+            0b1111_0000..=0b1111_0111 => 4,
+            0b1000_0000..=0b1011_1111 => continue, // continuation byte
+            _ => return data.len(),
+        };
+        return if i + len > data.len() { i } else { data.len() };
+    }
+    data.len()
+}
@@ -1,4 +1,8 @@
-use crate::{Event, EventData};
+use crate::{
+    keymap::{self, Button, KeyMap},
+    ports::{port_names, PageNames},
+    Event, EventData,
+};
 use std::{collections::HashSet, mem::offset_of};
 use uxn::{Ports, Uxn};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
@@ -9,7 +13,11 @@ pub struct ControllerPorts {
     vector: U16<BigEndian>,
     button: u8,
     key: u8,
-    _pad: [u8; 12],
+    _pad1: u8,
+    p2: u8,
+    p3: u8,
+    p4: u8,
+    _pad2: [u8; 8],
 }
 
 impl Ports for ControllerPorts {
@@ -18,15 +26,142 @@ impl Ports for ControllerPorts {
 
 impl ControllerPorts {
     const KEY: u8 = Self::BASE | offset_of!(Self, key) as u8;
+
+    pub(crate) const NAMES: PageNames = port_names!(Self, "Controller", {
+        vector => "vector",
+        button => "button",
+        key => "key",
+        p2 => "p2",
+        p3 => "p3",
+        p4 => "p4",
+    });
 }
 
-#[derive(Default)]
+/// Number of players supported by the controller device
+///
+/// Player 0 uses `Controller/button`, and players 1–3 use `Controller/p2`
+/// through `Controller/p4`.
+pub const PLAYERS: usize = 4;
+
+/// Controller (keyboard) device
 pub struct Controller {
-    /// Keys that are currently held down
-    down: HashSet<Key>,
+    /// Mapping from keys to buttons
+    map: KeyMap,
+
+    /// Keys that are currently held down, for each player
+    down: [HashSet<Key>; PLAYERS],
+
+    /// Current button state, for each player
+    buttons: [u8; PLAYERS],
+
+    /// Buttons held on each player's gamepad
+    pads: [u8; PLAYERS],
+
+    /// Policy for repeating held keys
+    repeat: KeyRepeat,
+
+    /// Most recently pressed key (with its player), and the number of frames
+    /// for which it has been held
+    held: Option<(usize, Key, u32)>,
+}
+
+/// Timing for key repeats generated by the controller, in frames (at 60 Hz)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RepeatTiming {
+    /// Frames between the initial press and the first repeat
+    pub delay: u32,
+    /// Frames between subsequent repeats (at least 1)
+    pub interval: u32,
+}
+
+impl Default for RepeatTiming {
+    /// 500 ms delay, then 30 repeats per second
+    fn default() -> Self {
+        Self {
+            delay: 30,
+            interval: 2,
+        }
+    }
+}
+
+/// Policy for repeating held keys
+///
+/// By default, repeats are taken from the host (the `repeat` argument to
+/// [`Varvara::pressed`](crate::Varvara::pressed)), so their timing depends on
+/// the platform and toolkit.  With [`KeyRepeat::timing`] set, host repeats
+/// are ignored and the controller generates its own from
+/// [`Varvara::redraw`](crate::Varvara::redraw), so that every front-end
+/// behaves the same way.  Only the most recently pressed key repeats.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KeyRepeat {
+    /// Timing for generated repeats, or `None` to use the host's repeats
+    pub timing: Option<RepeatTiming>,
+    /// Repeat characters sent to `Controller/key`
+    pub chars: bool,
+    /// Repeat button presses, calling the vector with unchanged buttons
+    pub buttons: bool,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> Self {
+        Self {
+            timing: None,
+            chars: true,
+            buttons: true,
+        }
+    }
+}
+
+/// Stick deflection beyond which it counts as a direction
+const STICK_THRESHOLD: f32 = 0.5;
 
-    /// Current button state
-    buttons: u8,
+/// State of a gamepad with the standard layout
+///
+/// Buttons are named by position, so that this doesn't depend on the labels
+/// of a particular controller.  The south face button is mapped to A, the
+/// east face button to B, and the d-pad and left stick to the directions.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GamepadState {
+    /// Player number (below [`CONTROLLER_PLAYERS`](crate::CONTROLLER_PLAYERS))
+    pub player: usize,
+    /// Bottom face button (A on Xbox-style controllers)
+    pub south: bool,
+    /// Right face button (B on Xbox-style controllers)
+    pub east: bool,
+    /// Select / back / share button
+    pub select: bool,
+    /// Start / menu / options button
+    pub start: bool,
+    /// D-pad up
+    pub up: bool,
+    /// D-pad down
+    pub down: bool,
+    /// D-pad left
+    pub left: bool,
+    /// D-pad right
+    pub right: bool,
+    /// Left stick position, from -1 to 1 (positive is right and up)
+    pub stick: (f32, f32),
+}
+
+impl GamepadState {
+    /// Returns the equivalent `Controller/button` bitfield
+    pub fn buttons(&self) -> u8 {
+        let (x, y) = self.stick;
+        [
+            (self.south, Button::A),
+            (self.east, Button::B),
+            (self.select, Button::Select),
+            (self.start, Button::Start),
+            (self.up || y > STICK_THRESHOLD, Button::Up),
+            (self.down || y < -STICK_THRESHOLD, Button::Down),
+            (self.left || x < -STICK_THRESHOLD, Button::Left),
+            (self.right || x > STICK_THRESHOLD, Button::Right),
+        ]
+        .into_iter()
+        .filter(|(held, _)| *held)
+        .fold(0, |acc, (_, b)| acc | b.bit())
+    }
 }
 
 /// Key input to the controller device
@@ -47,15 +182,77 @@ pub enum Key {
 
 impl Controller {
     /// Builds a new controller with no keys held
-    pub fn new() -> Self {
-        Self::default()
+    pub(crate) fn new(map: KeyMap) -> Self {
+        Self {
+            map,
+            down: Default::default(),
+            buttons: [0; PLAYERS],
+            pads: [0; PLAYERS],
+            repeat: KeyRepeat::default(),
+            held: None,
+        }
+    }
+
+    /// Returns the mapping from keys to buttons
+    pub fn key_map(&self) -> &KeyMap {
+        &self.map
+    }
+
+    /// Sets the key repeat policy
+    pub(crate) fn set_key_repeat(&mut self, repeat: KeyRepeat) {
+        self.repeat = repeat;
+    }
+
+    /// Replaces the key map, returning events for any changed buttons
+    ///
+    /// Held characters are released, since they may no longer be mapped.
+    pub(crate) fn set_key_map(
+        &mut self,
+        vm: &mut Uxn,
+        map: KeyMap,
+    ) -> Vec<Event> {
+        self.map = map;
+        for down in &mut self.down {
+            down.retain(|k| !matches!(k, Key::Char(..)));
+        }
+        (0..PLAYERS)
+            .filter_map(|player| self.check_buttons(vm, player, false))
+            .collect()
+    }
+
+    /// Checks whether the given key is held down by player 0
+    pub fn is_down(&self, k: Key) -> bool {
+        self.is_down_for(0, k)
+    }
+
+    /// Checks whether the given key is held down by the given player
+    ///
+    /// Returns `false` if the player is out of range.
+    pub fn is_down_for(&self, player: usize, k: Key) -> bool {
+        self.down
+            .get(player)
+            .is_some_and(|d| d.contains(&keymap::normalize(k)))
+    }
+
+    /// Returns the current `Controller/button` bitfield
+    pub fn buttons(&self) -> u8 {
+        self.buttons[0]
+    }
+
+    /// Returns the current button bitfield for the given player
+    ///
+    /// This is `Controller/button` for player 0, and `Controller/p2` through
+    /// `Controller/p4` for players 1–3; it's 0 if the player is out of range.
+    pub fn buttons_for(&self, player: usize) -> u8 {
+        self.buttons.get(player).copied().unwrap_or(0)
     }
 
     /// Sends a single character event
-    pub fn char(&mut self, vm: &mut Uxn, c: u8) -> Event {
+    pub(crate) fn char(&mut self, vm: &mut Uxn, c: u8) -> Event {
         let p = vm.dev::<ControllerPorts>();
         Event {
             vector: p.vector.get(),
+            device: ControllerPorts::BASE,
             data: Some(EventData {
                 addr: ControllerPorts::KEY,
                 value: c,
@@ -64,61 +261,137 @@ impl Controller {
         }
     }
 
-    /// Send the given key event, returning an event if needed
-    pub fn pressed(
+    /// Send the given key event for a player, returning an event if needed
+    ///
+    /// Characters are only sent for player 0, since there's a single
+    /// `Controller/key` port.  A character which is assigned to a button in
+    /// the key map also updates the button state.
+    pub(crate) fn pressed(
         &mut self,
         vm: &mut Uxn,
+        player: usize,
         k: Key,
         repeat: bool,
     ) -> Option<Event> {
-        if let Key::Char(k) = k {
-            Some(self.char(vm, k))
-        } else {
-            self.down.insert(k);
-            self.check_buttons(vm, repeat)
+        if player >= PLAYERS {
+            log::warn!("ignoring key for invalid player {player}");
+            return None;
         }
+        if repeat && self.repeat.timing.is_some() {
+            return None;
+        }
+        let is_char = matches!(k, Key::Char(..));
+        let norm = keymap::normalize(k);
+        if !repeat && !self.down[player].contains(&norm) {
+            self.held = Some((player, k, 0));
+        }
+        if !is_char || self.map.get(k).is_some() {
+            self.down[player].insert(norm);
+        }
+        self.key_event(vm, player, k, repeat)
     }
 
-    /// Indicate that the given key has been released
-    ///
-    /// This may change our button state and return an event
-    pub fn released(&mut self, vm: &mut Uxn, k: Key) -> Option<Event> {
-        if !matches!(k, Key::Char(..)) {
-            self.down.remove(&k);
-            self.check_buttons(vm, false)
+    /// Sends the event for a key press, which may be a repeat
+    fn key_event(
+        &mut self,
+        vm: &mut Uxn,
+        player: usize,
+        k: Key,
+        repeat: bool,
+    ) -> Option<Event> {
+        let is_char = matches!(k, Key::Char(..));
+        let e = self.check_buttons(
+            vm,
+            player,
+            repeat && !is_char && self.repeat.buttons,
+        );
+        match k {
+            Key::Char(c) if player == 0 && (!repeat || self.repeat.chars) => {
+                Some(self.char(vm, c))
+            }
+            _ => e,
+        }
+    }
+
+    /// Advances key repeat by one frame, returning an event if a held key
+    /// repeats
+    pub(crate) fn tick(&mut self, vm: &mut Uxn) -> Option<Event> {
+        let t = self.repeat.timing?;
+        let (player, k, frames) = self.held.as_mut()?;
+        *frames = frames.saturating_add(1);
+        let due =
+            *frames >= t.delay && (*frames - t.delay) % t.interval.max(1) == 0;
+        let (player, k) = (*player, *k);
+        if due {
+            self.key_event(vm, player, k, true)
         } else {
             None
         }
     }
 
-    fn check_buttons(&mut self, vm: &mut Uxn, repeat: bool) -> Option<Event> {
-        let mut buttons = 0;
-        for (i, k) in [
-            Key::Ctrl,
-            Key::Alt,
-            Key::Shift,
-            Key::Home,
-            Key::Up,
-            Key::Down,
-            Key::Left,
-            Key::Right,
-        ]
-        .iter()
-        .enumerate()
-        {
-            if self.down.contains(k) {
-                buttons |= 1 << i;
+    /// Indicate that the given key has been released by a player
+    ///
+    /// This may change our button state and return an event
+    pub(crate) fn released(
+        &mut self,
+        vm: &mut Uxn,
+        player: usize,
+        k: Key,
+    ) -> Option<Event> {
+        if player >= PLAYERS {
+            log::warn!("ignoring key for invalid player {player}");
+            None
+        } else {
+            let k = keymap::normalize(k);
+            if self.held.is_some_and(|(p, h, _)| {
+                p == player && keymap::normalize(h) == k
+            }) {
+                self.held = None;
+            }
+            if self.down[player].remove(&k) {
+                self.check_buttons(vm, player, false)
+            } else {
+                None
             }
         }
+    }
+
+    /// Updates a player's gamepad, returning an event if buttons changed
+    ///
+    /// Gamepad buttons are combined with the player's held keys.
+    pub(crate) fn gamepad(
+        &mut self,
+        vm: &mut Uxn,
+        state: GamepadState,
+    ) -> Option<Event> {
+        let Some(pad) = self.pads.get_mut(state.player) else {
+            log::warn!("ignoring gamepad for invalid player {}", state.player);
+            return None;
+        };
+        *pad = state.buttons();
+        self.check_buttons(vm, state.player, false)
+    }
+
+    fn check_buttons(
+        &mut self,
+        vm: &mut Uxn,
+        player: usize,
+        repeat: bool,
+    ) -> Option<Event> {
+        let buttons = self.down[player]
+            .iter()
+            .filter_map(|k| self.map.get(*k))
+            .fold(self.pads[player], |acc, b| acc | b.bit());
 
         // We'll return this event in case we don't have a keypress event;
         // otherwise, the keypress event will call the vector (at least once)
-        if buttons != self.buttons || repeat {
+        if buttons != self.buttons[player] || repeat {
             let p = vm.dev_mut::<ControllerPorts>();
-            self.buttons = buttons;
-            p.button = buttons;
+            self.buttons[player] = buttons;
+            *[&mut p.button, &mut p.p2, &mut p.p3, &mut p.p4][player] = buttons;
             Some(Event {
                 vector: p.vector.get(),
+                device: ControllerPorts::BASE,
                 data: None,
             })
         } else {
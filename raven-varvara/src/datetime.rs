@@ -1,4 +1,8 @@
-use chrono::{Datelike, Timelike};
+use crate::ports::{port_names, PageNames};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset,
+    TimeZone, Timelike,
+};
 use std::mem::offset_of;
 use uxn::{Ports, Uxn};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
@@ -32,35 +36,179 @@ impl DatetimePorts {
     const DAY_OF_WEEK: u8 = Self::BASE | offset_of!(Self, day_of_week) as u8;
     const DAY_OF_YEAR: u8 = Self::BASE | offset_of!(Self, day_of_year) as u8;
     const IS_DST: u8 = Self::BASE | offset_of!(Self, is_dst) as u8;
+
+    pub(crate) const NAMES: PageNames = port_names!(Self, "DateTime", {
+        year => "year",
+        month => "month",
+        day => "day",
+        hour => "hour",
+        minute => "minute",
+        second => "second",
+        day_of_week => "dotw",
+        day_of_year => "doty",
+        is_dst => "isdst",
+    });
+}
+
+/// Source of the current time for the datetime device
+///
+/// The system uses [`SystemClock`] by default; a different clock can be
+/// installed with [`VarvaraBuilder::clock`](crate::VarvaraBuilder::clock),
+/// e.g. [`OffsetClock::utc`] to behave the same way on every machine, or a
+/// [`FixedClock`] to make screenshot tests deterministic.
+///
+/// Any `FnMut() -> NaiveDateTime` closure is also a clock, which is
+/// convenient for scripted sequences of times.
+pub trait Clock: Send {
+    /// Returns the current local time
+    fn now(&mut self) -> NaiveDateTime;
+
+    /// Checks whether daylight saving time was in effect at the time most
+    /// recently returned by [`now`](Self::now)
+    ///
+    /// The default implementation returns `false`.
+    fn is_dst(&mut self) -> bool {
+        false
+    }
+}
+
+/// Clock which reads the host's local time
+///
+/// The timezone is the host's (e.g. from the `TZ` environment variable on
+/// Unix), including its daylight saving rules.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock {
+    /// Time returned by the most recent call to [`Clock::now`]
+    last: Option<DateTime<Local>>,
+}
+
+impl Clock for SystemClock {
+    fn now(&mut self) -> NaiveDateTime {
+        let t = Local::now();
+        self.last = Some(t);
+        t.naive_local()
+    }
+
+    /// Compares the UTC offset with the timezone's standard offset
+    ///
+    /// Daylight saving time always moves clocks forward, so the standard
+    /// offset is the smaller of the offsets in January and July (which works
+    /// in both hemispheres) of the same year.  This is computed from the time
+    /// reported by [`now`](Clock::now), so it's correct on either side of a
+    /// transition, including the repeated hour when clocks are turned back
+    /// and the turn of the year.
+    fn is_dst(&mut self) -> bool {
+        let Some(t) = self.last else {
+            return false;
+        };
+        let offset = t.offset().fix();
+        let year = t.year();
+        let standard = [1, 7]
+            .into_iter()
+            .filter_map(|month| {
+                let t = NaiveDate::from_ymd_opt(year, month, 1)?
+                    .and_hms_opt(12, 0, 0)?;
+                let o = Local.offset_from_utc_datetime(&t);
+                Some(o.fix().local_minus_utc())
+            })
+            .min();
+        standard.is_some_and(|s| offset.local_minus_utc() > s)
+    }
+}
+
+/// Clock which reads the host's time with a fixed UTC offset
+///
+/// This ignores the host's timezone, so ROMs see the same time on every
+/// machine; there is no daylight saving time.
+#[derive(Copy, Clone, Debug)]
+pub struct OffsetClock(pub FixedOffset);
+
+impl OffsetClock {
+    /// Builds a clock which reads UTC
+    pub fn utc() -> Self {
+        Self(chrono::Utc.fix())
+    }
+}
+
+impl Clock for OffsetClock {
+    fn now(&mut self) -> NaiveDateTime {
+        chrono::Utc::now().with_timezone(&self.0).naive_local()
+    }
 }
 
-pub struct Datetime;
+/// Clock which always returns the same time
+#[derive(Copy, Clone, Debug)]
+pub struct FixedClock(pub NaiveDateTime);
+
+impl Clock for FixedClock {
+    fn now(&mut self) -> NaiveDateTime {
+        self.0
+    }
+}
+
+impl<F: FnMut() -> NaiveDateTime + Send> Clock for F {
+    fn now(&mut self) -> NaiveDateTime {
+        self()
+    }
+}
+
+pub struct Datetime {
+    clock: Box<dyn Clock>,
+}
 
 impl Datetime {
+    pub fn new() -> Self {
+        Self {
+            clock: Box::<SystemClock>::default(),
+        }
+    }
+
+    /// Replaces the clock
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
     pub fn deo(&mut self, _vm: &mut Uxn, _target: u8) {
         // Time in Varvara, just like in real live, cannot be changed
     }
     pub fn dei(&mut self, vm: &mut Uxn, target: u8) {
+        let t = self.clock.now();
+        self.read(vm, target, &t);
+    }
+
+    /// Reads two adjacent ports from a single timestamp
+    ///
+    /// This means that a `DEI2` of e.g. `hour` and `minute` can't straddle a
+    /// rollover between the two bytes.
+    pub fn dei2(&mut self, vm: &mut Uxn, target: u8) {
+        let t = self.clock.now();
+        self.read(vm, target, &t);
+        self.read(vm, target.wrapping_add(1), &t);
+    }
+
+    /// Writes a single port
+    ///
+    /// As in the reference implementation, months and days of the year count
+    /// from 0, and days of the week count from Sunday.
+    fn read(&mut self, vm: &mut Uxn, target: u8, t: &NaiveDateTime) {
         let d = vm.dev_mut::<DatetimePorts>();
-        let t = chrono::Local::now();
         match target {
-            DatetimePorts::YEAR => d.year.set(t.year().try_into().unwrap()),
-            DatetimePorts::MONTH => d.month = t.month().try_into().unwrap(),
-            DatetimePorts::DAY => d.day = t.day().try_into().unwrap(),
-            DatetimePorts::HOUR => d.hour = t.hour().try_into().unwrap(),
-            DatetimePorts::MINUTE => d.minute = t.minute().try_into().unwrap(),
-            DatetimePorts::SECOND => d.second = t.second().try_into().unwrap(),
+            // Clocks may be set to any year, so clamp it to the port's range
+            DatetimePorts::YEAR => {
+                d.year.set(t.year().clamp(0, i32::from(u16::MAX)) as u16)
+            }
+            DatetimePorts::MONTH => d.month = t.month0() as u8,
+            DatetimePorts::DAY => d.day = t.day() as u8,
+            DatetimePorts::HOUR => d.hour = t.hour() as u8,
+            DatetimePorts::MINUTE => d.minute = t.minute() as u8,
+            DatetimePorts::SECOND => d.second = t.second() as u8,
             DatetimePorts::DAY_OF_WEEK => {
-                d.day_of_week =
-                    t.weekday().num_days_from_sunday().try_into().unwrap()
+                d.day_of_week = t.weekday().num_days_from_sunday() as u8
             }
             DatetimePorts::DAY_OF_YEAR => {
-                d.day_of_year.set(t.ordinal().try_into().unwrap())
-            }
-            DatetimePorts::IS_DST => {
-                // https://github.com/chronotope/chrono/issues/1562
-                d.is_dst = 0u8; // TODO this is not correct
+                d.day_of_year.set(t.ordinal0() as u16)
             }
+            DatetimePorts::IS_DST => d.is_dst = self.clock.is_dst().into(),
 
             _ => (),
         }
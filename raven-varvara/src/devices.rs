@@ -0,0 +1,44 @@
+//! Read-only access to individual Varvara devices
+//!
+//! These are returned by [`Varvara::devices`], so that hosts can inspect
+//! device state (e.g. the open file or the effective palette) without going
+//! through device memory.
+pub use crate::{
+    audio::Audio, console::Console, controller::Controller, file::File,
+    mouse::Mouse, screen::Screen, system::System,
+};
+
+use crate::Varvara;
+
+/// References to each device in a [`Varvara`] system
+pub struct Devices<'a> {
+    /// System device
+    pub system: &'a System,
+    /// Console device
+    pub console: &'a Console,
+    /// Screen device
+    pub screen: &'a Screen,
+    /// Audio devices
+    pub audio: &'a Audio,
+    /// Controller device
+    pub controller: &'a Controller,
+    /// Mouse device
+    pub mouse: &'a Mouse,
+    /// File devices
+    pub file: &'a File,
+}
+
+impl Varvara {
+    /// Returns references to each device, for inspecting their state
+    pub fn devices(&self) -> Devices<'_> {
+        Devices {
+            system: &self.system,
+            console: &self.console,
+            screen: &self.screen,
+            audio: &self.audio,
+            controller: &self.controller,
+            mouse: &self.mouse,
+            file: &self.file,
+        }
+    }
+}
@@ -1,8 +1,15 @@
+use crate::{
+    ports::{port_names, PageNames},
+    vfs::{DirEntries, FileBackend, FileWriter},
+    Event,
+};
 use log::{error, trace, warn};
 use std::{
     collections::{HashSet, VecDeque},
     io::{Read, Write},
     mem::offset_of,
+    path::{Path, PathBuf},
+    sync::mpsc,
 };
 use uxn::{Ports, Uxn, DEV_SIZE};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
@@ -10,7 +17,7 @@ use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
 #[derive(AsBytes, FromZeroes, FromBytes)]
 #[repr(C)]
 pub struct FilePorts {
-    _vector: U16<BigEndian>,
+    vector: U16<BigEndian>,
     success: U16<BigEndian>,
     stat: U16<BigEndian>,
     delete: u8,
@@ -64,6 +71,8 @@ impl FilePorts {
 }
 
 impl FilePorts {
+    const STAT_H: u8 = offset_of!(Self, stat) as u8;
+    const STAT_L: u8 = Self::STAT_H + 1;
     const NAME_H: u8 = offset_of!(Self, name) as u8;
     const NAME_L: u8 = Self::NAME_H + 1;
     const LENGTH_H: u8 = offset_of!(Self, length) as u8;
@@ -74,326 +83,1004 @@ impl FilePorts {
     const WRITE_L: u8 = Self::WRITE_H + 1;
     const APPEND: u8 = offset_of!(Self, append) as u8;
     const DELETE: u8 = offset_of!(Self, delete) as u8;
+
+    pub(crate) const NAMES: [PageNames; 2] = {
+        macro_rules! names {
+            ($dev:literal) => {
+                port_names!(Self, $dev, {
+                    vector => "vector",
+                    success => "success",
+                    stat => "stat",
+                    delete => "delete",
+                    append => "append",
+                    name => "name",
+                    length => "length",
+                    read => "read",
+                    write => "write",
+                })
+            };
+        }
+        [names!("File0"), names!("File1")]
+    };
 }
 
-#[cfg_attr(target_os = "windows", allow(clippy::large_enum_variant))]
 enum Handle {
     File {
-        path: std::path::PathBuf,
-        file: std::fs::File,
+        path: PathBuf,
+        file: Box<dyn Read + Send>,
+
+        /// Number of bytes read so far
+        pos: u64,
+
+        /// Size of the file when it was opened
+        len: u64,
     },
     Dir {
-        path: std::path::PathBuf,
-        dir: std::fs::ReadDir, // weirdly huge (616 bytes) on Windows!
+        path: PathBuf,
+        dir: DirEntries,
 
         /// Buffer of left-over characters to write
         scratch: VecDeque<u8>,
+
+        /// Number of bytes of the listing read so far
+        pos: u64,
     },
     Write {
-        path: std::path::PathBuf,
-        file: std::fs::File,
+        path: PathBuf,
+        file: Box<dyn FileWriter>,
+
+        /// Number of bytes written so far
+        pos: u64,
     },
 }
 
-pub struct File {
-    f: Option<Handle>,
+impl Handle {
+    /// Closes the handle, finishing any file being written
+    fn close(self) {
+        if let Handle::Write { path, file, .. } = self {
+            if let Err(e) = file.finish() {
+                error!("could not finish writing {path:?}: {e}");
+            }
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Handle::File { path, .. }
+            | Handle::Dir { path, .. }
+            | Handle::Write { path, .. } => path,
+        }
+    }
+
+    fn status(&self) -> Status {
+        let (writing, pos, len) = match self {
+            Handle::File { pos, len, .. } => (false, *pos, Some(*len)),
+            Handle::Dir { pos, .. } => (false, *pos, None),
+            Handle::Write { pos, .. } => (true, *pos, None),
+        };
+        Status {
+            path: self.path().to_owned(),
+            writing,
+            pos,
+            len,
+        }
+    }
+}
+
+/// Snapshot of a device's open handle, which hosts can inspect
+struct Status {
+    path: PathBuf,
+    writing: bool,
+
+    /// Number of bytes transferred so far
+    pos: u64,
 
-    /// Scratch buffer
+    /// Size of the file being read, if any
+    len: Option<u64>,
+}
+
+/// File operation, with its data copied out of VM memory
+///
+/// Paths are resolved when the request is made, and are `None` if the ROM's
+/// filename is invalid (which is only an error if a handle must be opened).
+enum Request {
+    Read {
+        index: usize,
+        path: Option<PathBuf>,
+        addr: u16,
+        buf: Vec<u8>,
+    },
+    Write {
+        index: usize,
+        path: Option<PathBuf>,
+        append: bool,
+        atomic: bool,
+        create_dirs: bool,
+        buf: Vec<u8>,
+    },
+    Stat {
+        index: usize,
+        path: Option<PathBuf>,
+        addr: u16,
+        buf: Vec<u8>,
+    },
+    Delete {
+        index: usize,
+        path: Option<PathBuf>,
+    },
+    /// Closes one device's handle, or every handle (if `None`)
+    Close(Option<usize>),
+    /// Closes every handle, then replaces the storage
+    SetBackend(Box<dyn FileBackend>),
+}
+
+/// Result of a [`Request`]
+struct Reply {
+    /// Operation to be finished in VM memory, if the request came from the ROM
+    done: Option<Done>,
+
+    /// State of each device's handle after the request
+    status: [Option<Status>; DEV_COUNT],
+}
+
+/// Completed operation from the ROM
+struct Done {
+    index: usize,
+
+    /// Value for the success port
+    success: u16,
+
+    /// Address at which the first `success` bytes of `buf` are copied, if any
+    addr: Option<u16>,
+
+    /// Buffer used by the operation, which is recycled afterwards
     buf: Vec<u8>,
+}
+
+/// Storage and open handles, which do the actual I/O
+///
+/// When file I/O is asynchronous, this lives on a worker thread.
+struct Io {
+    f: [Option<Handle>; DEV_COUNT],
 
     /// Log of missing files, to avoid spamming warnings
-    missing_files: HashSet<String>,
+    missing_files: HashSet<PathBuf>,
+
+    /// Storage for files
+    backend: Box<dyn FileBackend>,
 }
 
-impl File {
-    pub fn new() -> Self {
+impl Io {
+    fn new(backend: Box<dyn FileBackend>) -> Self {
         Self {
-            f: None,
-            buf: vec![],
+            f: [None, None],
             missing_files: HashSet::new(),
+            backend,
         }
     }
 
-    /// Decodes a port address into an `(index, offset)` tuple
-    fn decode_target(target: u8) -> (usize, u8) {
-        let i = usize::from(target - FilePorts::BASE) / DEV_SIZE;
-        (i, target & 0xF)
-    }
-
-    pub fn deo(&mut self, vm: &mut Uxn, target: u8) {
-        let (i, target) = Self::decode_target(target);
-        match target {
-            FilePorts::DELETE => self.delete(vm, i),
-            FilePorts::APPEND => (), // Ignored, this sets the append flag
-            FilePorts::NAME_H | FilePorts::NAME_L => {
-                self.f = None;
+    fn run(&mut self, r: Request) -> Reply {
+        let done = match r {
+            Request::Read {
+                index,
+                path,
+                addr,
+                mut buf,
+            } => {
+                let success = self.read(index, path, &mut buf);
+                Some(Done {
+                    index,
+                    success,
+                    addr: Some(addr),
+                    buf,
+                })
             }
-            FilePorts::LENGTH_H | FilePorts::LENGTH_L => {
-                // Ignored, this sets the buffer length
+            Request::Write {
+                index,
+                path,
+                append,
+                atomic,
+                create_dirs,
+                buf,
+            } => {
+                let success =
+                    self.write(index, path, append, atomic, create_dirs, &buf);
+                Some(Done {
+                    index,
+                    success,
+                    addr: None,
+                    buf,
+                })
             }
-            FilePorts::READ_H => (), // ignored, action is on READ_L
-            FilePorts::READ_L => self.read(vm, i),
-            FilePorts::WRITE_H => (), // ignored, action is on WRITE_L
-            FilePorts::WRITE_L => self.write(vm, i),
+            Request::Stat {
+                index,
+                path,
+                addr,
+                mut buf,
+            } => {
+                let success = self.stat(path, &mut buf);
+                Some(Done {
+                    index,
+                    success,
+                    addr: Some(addr),
+                    buf,
+                })
+            }
+            Request::Delete { index, path } => {
+                let success = self.delete(index, path);
+                Some(Done {
+                    index,
+                    success,
+                    addr: None,
+                    buf: vec![],
+                })
+            }
+            Request::Close(Some(index)) => {
+                self.close_one(index);
+                None
+            }
+            Request::Close(None) => {
+                self.close();
+                self.missing_files.clear();
+                None
+            }
+            Request::SetBackend(backend) => {
+                self.close();
+                self.backend = backend;
+                None
+            }
+        };
+        Reply {
+            done,
+            status: std::array::from_fn(|i| {
+                self.f[i].as_ref().map(Handle::status)
+            }),
+        }
+    }
 
-            _ => warn!("unknown file deo: {target:2x}"),
+    fn close(&mut self) {
+        for i in 0..DEV_COUNT {
+            self.close_one(i);
         }
     }
 
-    /// Checks that the given path is local and does not escape our working dir
-    ///
-    /// Note that this simply checks depth; symlinks must be examined separately
-    fn is_path_local(path: &std::path::Path) -> bool {
-        let mut depth = 0;
-        for component in path.components() {
-            match component {
-                std::path::Component::Prefix(..)
-                | std::path::Component::RootDir => {
-                    error!("path {path:?} is not relative");
-                    return false;
-                }
-                std::path::Component::CurDir => {}
-                std::path::Component::ParentDir => {
-                    if depth == 0 {
-                        error!("path {path:?} escapes working directory");
-                        return false;
-                    } else {
-                        depth -= 1;
-                    }
-                }
-                std::path::Component::Normal(..) => {
-                    depth += 1;
-                }
-            }
+    fn close_one(&mut self, index: usize) {
+        if let Some(h) = self.f[index].take() {
+            h.close();
         }
-        true
     }
 
-    fn delete(&mut self, vm: &mut Uxn, index: usize) {
+    /// Deletes a file or empty directory, returning the success flag
+    fn delete(&mut self, index: usize, path: Option<PathBuf>) -> u16 {
         // Close the file, if it happens to be open
-        self.f = None;
-
-        // Set the return flag to -1
-        FilePorts::dev_mut(vm, index).success.set(u16::MAX);
-
-        let ports = FilePorts::dev(vm, index);
-        let Some(filename) = ports.filename(vm) else {
-            return;
+        self.close_one(index);
+        let Some(path) = path else {
+            return u16::MAX;
         };
-        let path = std::path::PathBuf::from(&filename);
-        if !Self::is_path_local(&path) {
-            return;
+        // The other device may also have the file open
+        for i in 0..DEV_COUNT {
+            if self.f[i].as_ref().is_some_and(|h| h.path() == path) {
+                self.close_one(i);
+            }
+        }
+        // Directories are only removed if they're empty
+        if self.backend.remove(&path).is_ok() {
+            0
+        } else {
+            u16::MAX
         }
-        if std::fs::remove_file(&path).is_ok() {
-            FilePorts::dev_mut(vm, index).success.set(0);
-        };
     }
 
-    fn write(&mut self, vm: &mut Uxn, index: usize) {
-        // Clear the success flag
-        let ports = FilePorts::dev_mut(vm, index);
-        ports.success.set(0);
+    /// Writes a description of the given file into `buf`
+    ///
+    /// The buffer is filled with the file's size as hex digits, or entirely
+    /// with `-` for a directory, `?` for a file too large to describe, or `!`
+    /// for a missing file.  Returns the number of bytes written, which is 0 if
+    /// the path is invalid.
+    fn stat(&mut self, path: Option<PathBuf>, buf: &mut [u8]) -> u16 {
+        let Some(path) = path else {
+            return 0;
+        };
+        let len = buf.len();
+        match self.backend.info(&path) {
+            Ok(m) if m.is_dir => buf.fill(b'-'),
+            Ok(m) if len < 16 && m.len >= 1 << (len * 4) => buf.fill(b'?'),
+            Ok(m) => {
+                let digits = format!("{:0len$x}", m.len);
+                buf.copy_from_slice(&digits.as_bytes()[digits.len() - len..]);
+            }
+            Err(_) => buf.fill(b'!'),
+        }
+        len as u16
+    }
 
-        let ports = FilePorts::dev(vm, index);
-        if !matches!(self.f, Some(Handle::Write { .. })) {
-            let Some(filename) = ports.filename(vm) else {
-                return;
+    /// Writes data to the device's file, returning the success flag
+    fn write(
+        &mut self,
+        index: usize,
+        path: Option<PathBuf>,
+        append: bool,
+        atomic: bool,
+        create_dirs: bool,
+        data: &[u8],
+    ) -> u16 {
+        if !matches!(self.f[index], Some(Handle::Write { .. })) {
+            // Close any read handle, which may be open on the same file
+            self.close_one(index);
+            let Some(path) = path else {
+                return 0;
             };
-            let path = std::path::PathBuf::from(&filename);
-            if !Self::is_path_local(&path) {
-                error!("path {path:?} escapes working directory");
-                return;
+
+            if create_dirs {
+                if let Some(parent) = path.parent() {
+                    if let Err(e) = self.backend.create_dir_all(parent) {
+                        error!("could not create {parent:?}: {e}");
+                        return 0;
+                    }
+                }
+            }
+            if self.backend.info(&path).is_ok_and(|m| m.is_dir) {
+                warn!("{path:?} is a directory; skipping");
+                return 0;
             }
 
-            let file = std::fs::OpenOptions::new()
-                .write(true)
-                .create(true)
-                .append(ports.append == 0x1)
-                .open(&path);
-            let file = match file {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("could not open {path:?}: {e}");
-                    return;
+            match self.backend.create(&path, append, atomic) {
+                Ok(file) => {
+                    trace!("opened {path:?} as file for writing");
+                    self.f[index] = Some(Handle::Write { path, file, pos: 0 });
                 }
-            };
-            let m = match file.metadata() {
-                Ok(m) => m,
                 Err(e) => {
-                    error!("could not check metadata for {path:?}: {e}");
-                    return;
+                    error!("could not open {path:?} for writing: {e}");
+                    return 0;
                 }
-            };
-            if m.is_symlink() {
-                warn!("{path:?} is a symlink; skipping");
-                return;
-            } else if m.is_dir() {
-                warn!("{path:?} is a directory; skipping");
-                return;
-            } else {
-                trace!("opened {path:?} as file for writing");
-                self.f = Some(Handle::Write { path, file });
             }
         }
 
-        self.buf.resize(usize::from(ports.length.get()), 0u8);
-        self.buf.fill(0u8);
-        let Some(Handle::Write { path, file }) = self.f.as_mut() else {
+        let Some(Handle::Write { path, file, pos }) = self.f[index].as_mut()
+        else {
             unreachable!();
         };
-
-        // Copy data out of the VM
-        self.buf.resize(usize::from(ports.length.get()), 0u8);
-        let mut addr = ports.write.get();
-        for b in self.buf.iter_mut() {
-            *b = vm.ram_read_byte(addr);
-            addr = addr.wrapping_add(1);
-        }
-
-        let n = match file.write(&self.buf) {
-            Ok(n) => n,
-            Err(e) => {
-                error!("could not write to {path:?}: {e}");
-                return;
-            }
-        };
-        if n != self.buf.len() {
-            error!("could not write all bytes to file");
-            return;
+        if let Err(e) = file.write_all(data) {
+            error!("could not write to {path:?}: {e}");
+            return 0;
         }
-        let ports = FilePorts::dev_mut(vm, index);
-        ports.success.set(n as u16);
+        *pos += data.len() as u64;
+        data.len() as u16
     }
 
-    fn read(&mut self, vm: &mut Uxn, index: usize) {
-        // Clear the success flag
-        let ports = FilePorts::dev_mut(vm, index);
-        ports.success.set(0);
-
-        if !matches!(self.f, Some(Handle::File { .. } | Handle::Dir { .. })) {
-            let ports = FilePorts::dev(vm, index);
-            let Some(filename) = ports.filename(vm) else {
-                return;
-            };
-            let path = std::path::PathBuf::from(&filename);
-            if !path.exists() {
-                if self.missing_files.insert(filename.to_owned()) {
-                    error!("{filename:?} is missing");
-                }
-                return;
-            }
-            let path = std::path::PathBuf::from(&filename);
-            if !Self::is_path_local(&path) {
-                error!("path {path:?} escapes working directory");
-                return;
-            }
-
-            let file = match std::fs::File::open(&path) {
-                Ok(f) => f,
-                Err(e) => {
-                    error!("could not open {path:?}: {e}");
-                    return;
-                }
+    /// Reads from the device's file or directory listing into `buf`
+    ///
+    /// Returns the success flag, which is the number of bytes read.
+    fn read(
+        &mut self,
+        index: usize,
+        path: Option<PathBuf>,
+        buf: &mut [u8],
+    ) -> u16 {
+        if !matches!(
+            self.f[index],
+            Some(Handle::File { .. } | Handle::Dir { .. })
+        ) {
+            // Close any write handle, saving data before we try to read it
+            self.close_one(index);
+            let Some(path) = path else {
+                return 0;
             };
-            let m = match file.metadata() {
+            let m = match self.backend.info(&path) {
                 Ok(m) => m,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    if self.missing_files.insert(path.clone()) {
+                        error!("{path:?} is missing");
+                    }
+                    return 0;
+                }
                 Err(e) => {
                     error!("could not check metadata for {path:?}: {e}");
-                    return;
+                    return 0;
                 }
             };
-            if m.is_symlink() {
-                warn!("{path:?} is a symlink; skipping");
-                return;
-            } else if m.is_dir() {
-                let dir = match std::fs::read_dir(&path) {
+            if m.is_dir {
+                let dir = match self.backend.read_dir(&path) {
                     Ok(d) => d,
                     Err(e) => {
                         error!("could not open dir for {path:?}: {e}");
-                        return;
+                        return 0;
                     }
                 };
                 trace!("opened {path:?} as dir for reading");
-                self.f = Some(Handle::Dir {
+                self.f[index] = Some(Handle::Dir {
                     path,
                     dir,
                     scratch: Default::default(),
+                    pos: 0,
                 });
             } else {
+                let file = match self.backend.open(&path) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        error!("could not open {path:?}: {e}");
+                        return 0;
+                    }
+                };
                 trace!("opened {path:?} as file for reading");
-                self.f = Some(Handle::File { path, file });
+                self.f[index] = Some(Handle::File {
+                    path,
+                    file,
+                    pos: 0,
+                    len: m.len,
+                });
             }
         }
 
-        let ports = FilePorts::dev_mut(vm, index);
-        self.buf.resize(usize::from(ports.length.get()), 0u8);
-        let n = match self.f.as_mut().unwrap() {
+        let n = match self.f[index].as_mut().unwrap() {
             Handle::Write { .. } => unreachable!(),
-            Handle::File { path, file } => match file.read(&mut self.buf) {
-                Ok(n) => n,
+            Handle::File {
+                path, file, pos, ..
+            } => match read_full(file, buf) {
+                Ok(n) => {
+                    *pos += n as u64;
+                    n
+                }
                 Err(e) => {
                     error!("failed to read file at {path:?}: {e}");
-                    return;
+                    return 0;
                 }
             },
-            Handle::Dir { path, dir, scratch } => {
+            Handle::Dir {
+                path,
+                dir,
+                scratch,
+                pos,
+            } => {
                 let mut n = 0;
-                while n != self.buf.len() {
+                while n != buf.len() {
                     // Send any pending characters
-                    while n < self.buf.len() {
+                    while n < buf.len() {
                         let Some(c) = scratch.pop_front() else {
                             break;
                         };
-                        self.buf[n] = c;
+                        buf[n] = c;
                         n += 1;
                     }
                     // Preload new data into the buffer
-                    if n < self.buf.len() && scratch.is_empty() {
+                    if n < buf.len() && scratch.is_empty() {
                         let Some(next) = dir.next() else {
                             break;
                         };
                         match next {
                             Ok(d) => {
-                                let m = match d.metadata() {
-                                    Ok(m) => m,
-                                    Err(e) => {
-                                        error!(
-                                            "could not get entry metadata: {e}"
-                                        );
-                                        return;
-                                    }
-                                };
-                                let size = if m.is_dir() {
+                                let size = if d.info.is_dir {
                                     "----".to_owned()
-                                } else if m.len() < u16::MAX as u64 {
-                                    format!("{:04x}", m.len())
+                                } else if d.info.len < u16::MAX as u64 {
+                                    format!("{:04x}", d.info.len)
                                 } else {
                                     "????".to_owned()
                                 };
                                 scratch.extend(size.bytes());
                                 scratch.push_back(b' ');
-                                let name = d.file_name();
-                                scratch.extend(name.as_encoded_bytes());
+                                scratch.extend(d.name.as_encoded_bytes());
                                 scratch.push_back(b'\n');
                             }
                             Err(e) => {
                                 error!(
                                     "error while iterating over {path:?}: {e}"
                                 );
-                                return;
+                                return 0;
                             }
                         }
                     }
                 }
+                *pos += n as u64;
                 n
             }
         };
+        n as u16
+    }
+}
 
-        ports.success.set(n as u16);
-        let mut addr = ports.read.get();
-        for &b in &self.buf {
-            vm.ram_write_byte(addr, b);
-            addr = addr.wrapping_add(1);
+/// Worker thread which runs file operations in the background
+struct Worker {
+    tx: mpsc::Sender<Request>,
+    rx: mpsc::Receiver<Reply>,
+    thread: std::thread::JoinHandle<Io>,
+}
+
+impl Worker {
+    fn spawn(mut io: Io) -> Self {
+        let (tx, requests) = mpsc::channel();
+        let (replies, rx) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            while let Ok(r) = requests.recv() {
+                if replies.send(io.run(r)).is_err() {
+                    break;
+                }
+            }
+            io
+        });
+        Self { tx, rx, thread }
+    }
+
+    /// Stops the worker, returning its storage and handles
+    fn join(self) -> Io {
+        drop(self.tx);
+        self.thread.join().expect("file worker thread panicked")
+    }
+}
+
+/// How file operations are run
+enum Mode {
+    /// Operations finish during the `DEO` which requests them
+    Sync(Io),
+    /// Operations run on a worker thread, and are finished by [`File::update`]
+    Async(Worker),
+}
+
+/// Number of file devices
+const DEV_COUNT: usize = 2;
+
+/// Maximum number of buffers kept for reuse
+const MAX_SPARE: usize = 4;
+
+/// File devices, each of which has its own open handle
+pub struct File {
+    mode: Mode,
+
+    /// State of each device's handle, as of its last finished operation
+    status: [Option<Status>; DEV_COUNT],
+
+    /// Number of requests sent to the worker which haven't been finished
+    pending: usize,
+
+    /// Buffers to reuse for later operations
+    spare: Vec<Vec<u8>>,
+
+    /// Write to a temporary file, which replaces the target when closed
+    atomic_writes: bool,
+
+    /// Create missing parent directories when writing a file
+    create_dirs: bool,
+
+    /// Directory in which paths are resolved (the current directory if unset)
+    root: Option<PathBuf>,
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl File {
+    pub(crate) fn new() -> Self {
+        Self {
+            mode: Mode::Sync(Io::new(default_backend())),
+            status: [None, None],
+            pending: 0,
+            spare: vec![],
+            atomic_writes: true,
+            create_dirs: false,
+            root: None,
+        }
+    }
+
+    /// Closes any open handles and clears internal state
+    ///
+    /// The atomic writes, directory creation, and asynchronous I/O settings
+    /// are preserved.
+    pub(crate) fn reset(&mut self) {
+        self.close();
+        self.spare.clear();
+    }
+
+    /// Closes every open handle
+    ///
+    /// If atomic writes are enabled, this moves temporary files into place.
+    /// When I/O is asynchronous, this waits for pending operations to finish,
+    /// discarding their results.
+    pub(crate) fn close(&mut self) {
+        self.run_blocking(Request::Close(None));
+    }
+
+    /// Enables or disables atomic writes
+    ///
+    /// When enabled, files are written to a temporary file in the same
+    /// directory, which is renamed to the target when the handle is closed
+    /// (i.e. when the ROM changes `File/name`, deletes the file, or the system
+    /// is reset or dropped).  This means that a crash in the middle of saving
+    /// leaves the original file intact.
+    pub(crate) fn set_atomic_writes(&mut self, atomic: bool) {
+        self.atomic_writes = atomic;
+    }
+
+    /// Enables or disables creating parent directories when writing a file
+    pub(crate) fn set_create_dirs(&mut self, create: bool) {
+        self.create_dirs = create;
+    }
+
+    /// Runs file operations on a worker thread (or during the `DEO`)
+    ///
+    /// Switching modes closes every open handle.  WebAssembly doesn't have
+    /// threads, so operations there are always synchronous.
+    pub(crate) fn set_async(&mut self, enabled: bool) {
+        if enabled == self.is_async() {
+            return;
+        }
+        if cfg!(target_arch = "wasm32") {
+            warn!("asynchronous file I/O isn't supported on WebAssembly");
+            return;
+        }
+        self.close();
+        // Swap in a placeholder, so that we can take ownership of the old mode
+        let empty = Mode::Sync(Io::new(Box::new(crate::vfs::MemoryFs::new())));
+        self.mode = match std::mem::replace(&mut self.mode, empty) {
+            Mode::Sync(io) => Mode::Async(Worker::spawn(io)),
+            Mode::Async(w) => Mode::Sync(w.join()),
+        };
+    }
+
+    /// Checks whether file operations run on a worker thread
+    pub fn is_async(&self) -> bool {
+        matches!(self.mode, Mode::Async(..))
+    }
+
+    /// Checks whether missing parent directories are created when writing
+    pub fn create_dirs(&self) -> bool {
+        self.create_dirs
+    }
+
+    /// Returns the path of the given device's open file or directory, if any
+    ///
+    /// When I/O is asynchronous, this reflects the device's last finished
+    /// operation.
+    pub fn open_path(&self, index: usize) -> Option<&Path> {
+        self.status(index).map(|s| s.path.as_path())
+    }
+
+    /// Returns the number of bytes transferred through the given device's
+    /// open handle
+    ///
+    /// Large files are read and written in chunks of up to 64 KiB, so this
+    /// lets a host report progress (alongside [`File::open_len`]).
+    pub fn position(&self, index: usize) -> Option<u64> {
+        self.status(index).map(|s| s.pos)
+    }
+
+    /// Returns the size of the file being read by the given device, if any
+    pub fn open_len(&self, index: usize) -> Option<u64> {
+        self.status(index).and_then(|s| s.len)
+    }
+
+    /// Checks whether the given device's open handle is writing to a file
+    pub fn is_writing(&self, index: usize) -> bool {
+        self.status(index).is_some_and(|s| s.writing)
+    }
+
+    fn status(&self, index: usize) -> Option<&Status> {
+        self.status.get(index).and_then(Option::as_ref)
+    }
+
+    /// Returns the number of operations running in the background
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Checks whether atomic writes are enabled
+    pub fn atomic_writes(&self) -> bool {
+        self.atomic_writes
+    }
+
+    /// Returns the directory in which paths are resolved, if set
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Sets the directory in which paths are resolved
+    ///
+    /// If this is `None`, paths are resolved relative to the current directory.
+    /// Open handles are closed, since their paths may be outside the new root.
+    pub(crate) fn set_root(&mut self, root: Option<PathBuf>) {
+        self.close();
+        self.root = root;
+    }
+
+    /// Replaces the storage for files, closing any open handles
+    pub(crate) fn set_backend(&mut self, backend: Box<dyn FileBackend>) {
+        self.run_blocking(Request::SetBackend(backend));
+    }
+
+    /// Runs a request which doesn't touch VM memory, waiting for it to finish
+    ///
+    /// Operations which were already pending are finished without applying
+    /// their results, since we don't have the VM.
+    fn run_blocking(&mut self, r: Request) {
+        match &mut self.mode {
+            Mode::Sync(io) => {
+                let reply = io.run(r);
+                self.status = reply.status;
+            }
+            Mode::Async(w) => {
+                if w.tx.send(r).is_ok() {
+                    self.pending += 1;
+                }
+                while self.pending > 0 {
+                    let Ok(reply) = w.rx.recv() else {
+                        break;
+                    };
+                    self.pending -= 1;
+                    self.status = reply.status;
+                    if let Some(d) = reply.done {
+                        recycle(&mut self.spare, d.buf);
+                    }
+                }
+                self.pending = 0;
+            }
+        }
+    }
+
+    /// Runs a request, finishing it immediately if I/O is synchronous
+    fn request(&mut self, vm: &mut Uxn, r: Request) {
+        match &mut self.mode {
+            Mode::Sync(io) => {
+                let reply = io.run(r);
+                self.status = reply.status;
+                if let Some(d) = reply.done {
+                    self.finish(vm, d);
+                }
+            }
+            Mode::Async(w) => {
+                if w.tx.send(r).is_ok() {
+                    self.pending += 1;
+                } else {
+                    error!("file worker thread has stopped");
+                }
+            }
+        }
+    }
+
+    /// Copies an operation's results into VM memory and the success port
+    fn finish(&mut self, vm: &mut Uxn, d: Done) {
+        if let Some(addr) = d.addr {
+            let n = usize::from(d.success);
+            let (head, tail) = vm
+                .ram_slice_mut(addr, n)
+                .expect("buffer length is limited to 16 bits");
+            let (a, b) = d.buf[..n].split_at(head.len());
+            head.copy_from_slice(a);
+            tail.copy_from_slice(b);
+        }
+        FilePorts::dev_mut(vm, d.index).success.set(d.success);
+        recycle(&mut self.spare, d.buf);
+    }
+
+    /// Finishes an operation from the worker thread, if one is complete
+    ///
+    /// Returns an event for the device's vector, which is called to tell the
+    /// ROM that its operation is done.
+    pub(crate) fn update(&mut self, vm: &mut Uxn) -> Option<Event> {
+        let Mode::Async(w) = &self.mode else {
+            return None;
+        };
+        loop {
+            let reply = w.rx.try_recv().ok()?;
+            self.pending -= 1;
+            self.status = reply.status;
+            if let Some(d) = reply.done {
+                let index = d.index;
+                self.finish(vm, d);
+                let vector = FilePorts::dev(vm, index).vector.get();
+                return Some(Event {
+                    data: None,
+                    vector,
+                    device: FilePorts::BASE + (index * DEV_SIZE) as u8,
+                });
+            }
         }
     }
+
+    /// Returns a buffer for an operation, reusing an old one if possible
+    fn buffer(&mut self) -> Vec<u8> {
+        let mut buf = self.spare.pop().unwrap_or_default();
+        buf.clear();
+        buf
+    }
+
+    /// Converts a filename from the ROM into a path within our root directory
+    ///
+    /// Returns `None` if the filename would escape the root directory.
+    fn resolve(&self, filename: &str) -> Option<PathBuf> {
+        let path = Path::new(filename);
+        if !Self::is_path_local(path) {
+            return None;
+        }
+        Some(match &self.root {
+            Some(root) => root.join(path),
+            None => path.to_owned(),
+        })
+    }
+
+    /// Reads and resolves the device's filename
+    fn path(&self, vm: &Uxn, index: usize) -> Option<PathBuf> {
+        let filename = FilePorts::dev(vm, index).filename(vm)?;
+        self.resolve(&filename)
+    }
+
+    /// Decodes a port address into an `(index, offset)` tuple
+    fn decode_target(target: u8) -> (usize, u8) {
+        let i = usize::from(target - FilePorts::BASE) / DEV_SIZE;
+        (i, target & 0xF)
+    }
+
+    pub(crate) fn deo(&mut self, vm: &mut Uxn, addr: u8) {
+        let (i, target) = Self::decode_target(addr);
+        match target {
+            FilePorts::STAT_H => (), // ignored, action is on STAT_L
+            FilePorts::STAT_L => self.stat(vm, i),
+            FilePorts::DELETE => self.delete(vm, i),
+            FilePorts::APPEND => (), // Ignored, this sets the append flag
+            FilePorts::NAME_H | FilePorts::NAME_L => {
+                self.request(vm, Request::Close(Some(i)))
+            }
+            FilePorts::LENGTH_H | FilePorts::LENGTH_L => {
+                // Ignored, this sets the buffer length
+            }
+            FilePorts::READ_H => (), // ignored, action is on READ_L
+            FilePorts::READ_L => self.read(vm, i),
+            FilePorts::WRITE_H => (), // ignored, action is on WRITE_L
+            FilePorts::WRITE_L => self.write(vm, i),
+
+            _ => warn!(
+                "unknown file deo: {addr:02x} ({})",
+                crate::ports::name_of(addr).unwrap_or("unused")
+            ),
+        }
+    }
+
+    /// Checks that the given path is local and does not escape our working dir
+    ///
+    /// Note that this simply checks depth; symlinks must be examined separately
+    fn is_path_local(path: &Path) -> bool {
+        let mut depth = 0;
+        for component in path.components() {
+            match component {
+                std::path::Component::Prefix(..)
+                | std::path::Component::RootDir => {
+                    error!("path {path:?} is not relative");
+                    return false;
+                }
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    if depth == 0 {
+                        error!("path {path:?} escapes working directory");
+                        return false;
+                    } else {
+                        depth -= 1;
+                    }
+                }
+                std::path::Component::Normal(..) => {
+                    depth += 1;
+                }
+            }
+        }
+        true
+    }
+
+    fn delete(&mut self, vm: &mut Uxn, index: usize) {
+        // Set the return flag to -1
+        FilePorts::dev_mut(vm, index).success.set(u16::MAX);
+
+        let path = self.path(vm, index);
+        self.request(vm, Request::Delete { index, path });
+    }
+
+    /// Writes a description of the named file into the `stat` buffer
+    ///
+    /// See [`Io::stat`] for the format.  The success flag is set to the number
+    /// of bytes written.
+    fn stat(&mut self, vm: &mut Uxn, index: usize) {
+        // Clear the success flag
+        FilePorts::dev_mut(vm, index).success.set(0);
+
+        let path = self.path(vm, index);
+        let ports = FilePorts::dev(vm, index);
+        let addr = ports.stat.get();
+        let mut buf = self.buffer();
+        buf.resize(usize::from(ports.length.get()), 0u8);
+        self.request(
+            vm,
+            Request::Stat {
+                index,
+                path,
+                addr,
+                buf,
+            },
+        );
+    }
+
+    fn write(&mut self, vm: &mut Uxn, index: usize) {
+        // Clear the success flag
+        FilePorts::dev_mut(vm, index).success.set(0);
+
+        let path = self.path(vm, index);
+        let ports = FilePorts::dev(vm, index);
+        let append = ports.append == 0x1;
+
+        // Copy data out of the VM (which may wrap around)
+        let mut buf = self.buffer();
+        let (head, tail) = vm
+            .ram_slice(ports.write.get(), usize::from(ports.length.get()))
+            .expect("buffer length is limited to 16 bits");
+        buf.extend_from_slice(head);
+        buf.extend_from_slice(tail);
+        self.request(
+            vm,
+            Request::Write {
+                index,
+                path,
+                append,
+                atomic: self.atomic_writes,
+                create_dirs: self.create_dirs,
+                buf,
+            },
+        );
+    }
+
+    fn read(&mut self, vm: &mut Uxn, index: usize) {
+        // Clear the success flag
+        FilePorts::dev_mut(vm, index).success.set(0);
+
+        let path = self.path(vm, index);
+        let ports = FilePorts::dev(vm, index);
+        let addr = ports.read.get();
+        let mut buf = self.buffer();
+        buf.resize(usize::from(ports.length.get()), 0u8);
+        self.request(
+            vm,
+            Request::Read {
+                index,
+                path,
+                addr,
+                buf,
+            },
+        );
+    }
+}
+
+/// Keeps a buffer for later operations, if we don't have enough already
+fn recycle(spare: &mut Vec<Vec<u8>>, buf: Vec<u8>) {
+    if buf.capacity() > 0 && spare.len() < MAX_SPARE {
+        spare.push(buf);
+    }
+}
+
+/// Reads until the buffer is full or the reader is exhausted
+///
+/// A single call to [`Read::read`] may return fewer bytes than requested
+/// (e.g. when decompressing), which the ROM would mistake for the end of the
+/// file.
+fn read_full(r: &mut dyn Read, mut buf: &mut [u8]) -> std::io::Result<usize> {
+    let size = buf.len();
+    while !buf.is_empty() {
+        match r.read(buf) {
+            Ok(0) => break,
+            Ok(n) => buf = &mut buf[n..],
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(size - buf.len())
+}
+
+/// Returns the default storage for files
+///
+/// This is the host filesystem, except on WebAssembly (which doesn't have
+/// one), where files are kept in memory.
+fn default_backend() -> Box<dyn FileBackend> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Box::new(crate::vfs::StdFs)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        Box::new(crate::vfs::MemoryFs::new())
+    }
 }
@@ -0,0 +1,138 @@
+//! One-call helper for running a ROM without a GUI
+use crate::Varvara;
+use uxn::{Backend, Uxn, UxnRam, UNIT_CYCLE_COSTS};
+
+/// Limits on a headless run, used by [`run_headless`]
+#[derive(Copy, Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct HeadlessLimits {
+    /// Number of screen frames to render and capture after input is consumed
+    pub frames: usize,
+
+    /// Maximum number of instructions to execute, across the whole run
+    ///
+    /// This stops ROMs which never return from a vector (e.g. an infinite
+    /// loop in the reset vector); `None` means there's no limit.
+    pub fuel: Option<u64>,
+}
+
+impl HeadlessLimits {
+    /// Builds a set of limits which captures the given number of frames
+    pub fn new(frames: usize) -> Self {
+        Self { frames, fuel: None }
+    }
+
+    /// Sets the instruction budget (see [`fuel`](Self::fuel))
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+}
+
+/// A single captured screen frame
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Frame {
+    /// Screen size, as a `(width, height)` tuple
+    pub size: (u16, u16),
+
+    /// Screen contents (see [`Output::frame`](crate::Output::frame))
+    pub data: Vec<u8>,
+}
+
+/// Result of a headless run, returned by [`run_headless`]
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct HeadlessResult {
+    /// Characters sent to the console's `write` port
+    pub stdout: Vec<u8>,
+
+    /// Characters sent to the console's `error` port
+    pub stderr: Vec<u8>,
+
+    /// Exit code requested by the VM, if any
+    pub exit: Option<i32>,
+
+    /// Captured frames, one per redraw
+    pub frames: Vec<Frame>,
+
+    /// The run was stopped because it used up [`HeadlessLimits::fuel`]
+    pub out_of_fuel: bool,
+}
+
+impl HeadlessResult {
+    /// Accumulates output from the system, returning `true` if the run
+    /// should stop (because of an exit or running out of fuel)
+    fn collect(&mut self, dev: &mut Varvara, vm: &Uxn) -> bool {
+        let out = dev.output(vm);
+        self.stdout.extend(out.stdout);
+        self.stderr.extend(out.stderr);
+        self.exit = out.exit;
+        self.out_of_fuel = vm.is_out_of_cycles();
+        self.exit.is_some() || self.out_of_fuel
+    }
+}
+
+/// Runs a ROM to completion without a GUI
+///
+/// The reset vector is run, then each byte of `stdin` is sent to the console
+/// device, followed by an end-of-input event (see [`Varvara::console_end`]).
+/// Then the screen vector is called [`limits.frames`] times, capturing
+/// the frame after each call.  Execution stops early if the VM requests an
+/// exit, or if it runs out of [fuel](HeadlessLimits::fuel) (in which case the
+/// vector which was running is abandoned).
+///
+/// This always uses the interpreter backend.
+///
+/// [`limits.frames`]: HeadlessLimits::frames
+pub fn run_headless(
+    rom: &[u8],
+    stdin: &[u8],
+    limits: HeadlessLimits,
+) -> HeadlessResult {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.load_rom(&mut vm, rom);
+    if let Some(fuel) = limits.fuel {
+        vm.set_cycle_costs(Some(&UNIT_CYCLE_COSTS));
+        vm.set_cycle_limit(Some(fuel));
+    }
+    dev.init_args(&mut vm, &[]);
+
+    let mut out = HeadlessResult::default();
+    vm.run(&mut dev, 0x100);
+    if out.collect(&mut dev, &vm) {
+        return out;
+    }
+
+    for &c in stdin {
+        dev.console(&mut vm, c);
+        if out.collect(&mut dev, &vm) {
+            return out;
+        }
+    }
+    dev.console_end(&mut vm);
+    if out.collect(&mut dev, &vm) {
+        return out;
+    }
+
+    for _ in 0..limits.frames {
+        dev.redraw(&mut vm);
+        let o = dev.output(&vm);
+        let (w, h) = o.size;
+        let n = usize::from(w) * usize::from(h) * 4;
+        out.frames.push(Frame {
+            size: o.size,
+            data: o.frame[..n].to_vec(),
+        });
+        out.stdout.extend(o.stdout);
+        out.stderr.extend(o.stderr);
+        out.exit = o.exit;
+        out.out_of_fuel = vm.is_out_of_cycles();
+        if out.exit.is_some() || out.out_of_fuel {
+            break;
+        }
+    }
+    out
+}
@@ -0,0 +1,208 @@
+//! Mapping from host keys to controller buttons
+//!
+//! The controller's button byte has eight bits, which are conventionally
+//! named after a gamepad: A, B, Select, Start, and the four directions.  By
+//! default, these are driven by Ctrl, Alt, Shift, Home, and the arrow keys;
+//! a [`KeyMap`] lets players choose a different layout (e.g. WASD).
+//!
+//! Key maps can be loaded from a text file with one button per line, followed
+//! by `=` and a comma-separated list of keys:
+//!
+//! ```text
+//! # WASD for movement, with J and K as A and B
+//! up = w
+//! left = a
+//! down = s
+//! right = d
+//! a = j, ctrl
+//! b = k, alt
+//! select = shift
+//! start = home
+//! ```
+//!
+//! Keys are either a single printable character (matched without regard to
+//! case) or one of `shift`, `ctrl`, `alt`, `up`, `down`, `left`, `right`,
+//! `home`, `end`, or `space` (`#`, `=`, and `,` can't be used, because
+//! they're part of the file syntax).  A loaded file replaces the entire default
+//! mapping, so buttons which aren't listed have no keys.
+use crate::Key;
+use std::collections::HashMap;
+
+/// A single button in the controller's button byte
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// Every button, in bit order
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    /// Returns this button's bit in `Controller/button`
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let b = match s {
+            "a" => Button::A,
+            "b" => Button::B,
+            "select" => Button::Select,
+            "start" => Button::Start,
+            "up" => Button::Up,
+            "down" => Button::Down,
+            "left" => Button::Left,
+            "right" => Button::Right,
+            _ => return None,
+        };
+        Some(b)
+    }
+}
+
+/// Parses a key name, as used in key map files
+fn parse_key(s: &str) -> Option<Key> {
+    let k = match s {
+        "shift" => Key::Shift,
+        "ctrl" => Key::Ctrl,
+        "alt" => Key::Alt,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "space" => Key::Char(b' '),
+        _ => match s.as_bytes() {
+            [c] if c.is_ascii_graphic() => Key::Char(*c),
+            _ => return None,
+        },
+    };
+    Some(k)
+}
+
+/// Error returned when a key map file can't be parsed
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyMapError {
+    /// Line number (starting from 1)
+    pub line: usize,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl std::fmt::Display for KeyMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for KeyMapError {}
+
+/// Table from host keys to controller buttons
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyMap {
+    buttons: HashMap<Key, Button>,
+}
+
+impl Default for KeyMap {
+    /// Builds the standard mapping (Ctrl, Alt, Shift, Home, and arrows)
+    fn default() -> Self {
+        let mut m = Self::empty();
+        for (k, b) in [
+            (Key::Ctrl, Button::A),
+            (Key::Alt, Button::B),
+            (Key::Shift, Button::Select),
+            (Key::Home, Button::Start),
+            (Key::Up, Button::Up),
+            (Key::Down, Button::Down),
+            (Key::Left, Button::Left),
+            (Key::Right, Button::Right),
+        ] {
+            m.set(k, b);
+        }
+        m
+    }
+}
+
+impl KeyMap {
+    /// Builds a key map with no keys assigned
+    pub fn empty() -> Self {
+        Self {
+            buttons: HashMap::new(),
+        }
+    }
+
+    /// Assigns a key to a button, replacing its previous assignment
+    ///
+    /// Several keys may drive the same button.  Characters are matched
+    /// without regard to case.
+    pub fn set(&mut self, k: Key, b: Button) {
+        self.buttons.insert(normalize(k), b);
+    }
+
+    /// Removes a key's assignment, returning its previous button
+    pub fn remove(&mut self, k: Key) -> Option<Button> {
+        self.buttons.remove(&normalize(k))
+    }
+
+    /// Looks up the button assigned to a key
+    pub fn get(&self, k: Key) -> Option<Button> {
+        self.buttons.get(&normalize(k)).copied()
+    }
+
+    /// Parses a key map from a text file
+    pub fn parse(text: &str) -> Result<Self, KeyMapError> {
+        let mut m = Self::empty();
+        for (i, line) in text.lines().enumerate() {
+            let err = |message: String| KeyMapError {
+                line: i + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((button, keys)) = line.split_once('=') else {
+                return Err(err("expected `button = keys`".to_owned()));
+            };
+            let button = button.trim().to_ascii_lowercase();
+            let b = Button::parse(&button)
+                .ok_or_else(|| err(format!("unknown button `{button}`")))?;
+            for key in keys.split(',').map(str::trim) {
+                let name = if key.len() == 1 {
+                    key.to_owned()
+                } else {
+                    key.to_ascii_lowercase()
+                };
+                let k = parse_key(&name)
+                    .ok_or_else(|| err(format!("unknown key `{key}`")))?;
+                m.set(k, b);
+            }
+        }
+        Ok(m)
+    }
+}
+
+/// Converts characters to lowercase, so that Shift doesn't change the mapping
+pub(crate) fn normalize(k: Key) -> Key {
+    match k {
+        Key::Char(c) => Key::Char(c.to_ascii_lowercase()),
+        k => k,
+    }
+}
@@ -1,70 +1,175 @@
 //! The Varvara computer system
 #![warn(missing_docs)]
-use log::warn;
+use log::{trace, warn};
 use std::{
+    collections::VecDeque,
     io::Write,
-    sync::{Arc, Mutex},
+    num::NonZeroUsize,
+    sync::{mpsc, Arc, Mutex},
 };
 
+mod capture;
 mod console;
+#[cfg(not(target_arch = "wasm32"))]
+mod console_writer;
 mod controller;
 mod datetime;
 mod file;
+mod headless;
+mod listeners;
 mod mouse;
+mod recorder;
 mod screen;
+#[cfg(feature = "png")]
+mod screenshot;
 mod system;
+mod timer;
+
+pub mod devices;
+pub mod keymap;
+pub mod ports;
+pub mod prelude;
+pub mod rom;
+pub mod theme;
+pub mod vfs;
 
 /// Audio handler implementation
 mod audio;
 
-pub use audio::StreamData;
+#[cfg(feature = "zip")]
+mod bundle;
+#[cfg(feature = "zip")]
+pub use bundle::{Bundle, BundleError};
+
+mod builder;
+pub use builder::VarvaraBuilder;
+
 pub use audio::CHANNELS as AUDIO_CHANNELS;
 pub use audio::SAMPLE_RATE as AUDIO_SAMPLE_RATE;
+pub use audio::{AudioOutput, Interpolation, Playback, Resampler, StreamData};
 
-pub use controller::Key;
-pub use mouse::MouseState;
+pub use capture::{AudioSink, WavWriter};
+pub use controller::{
+    GamepadState, Key, KeyRepeat, RepeatTiming, PLAYERS as CONTROLLER_PLAYERS,
+};
+pub use datetime::{Clock, FixedClock, OffsetClock, SystemClock};
+pub use mouse::{MouseState, Touch, TouchPhase, SCROLL_PIXELS_PER_LINE};
+pub use recorder::{Recorder, RECORD_FPS};
+pub use screen::{Layer, PixelFormat, Region};
+pub use screen::{MAX_SIZE as SCREEN_MAX_SIZE, MIN_SIZE as SCREEN_MIN_SIZE};
+pub use timer::{FrameTimer, FRAME_PERIOD};
+pub use vfs::{FileBackend, MemoryFs, StdFs};
 
-pub use console::spawn_worker as spawn_console_worker;
+pub use console::{
+    spawn_worker as spawn_console_worker,
+    spawn_worker_with_eof as spawn_console_worker_with_eof,
+};
+pub use console::{
+    stdin_kind, ChannelConsole, ConsoleBackend, ConsoleInput, StdinKind,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use console_writer::{
+    ConsoleWriter, DEFAULT_LIMIT as CONSOLE_WRITER_LIMIT,
+};
 
-use uxn::{Device, Ports, Uxn};
+pub use headless::{run_headless, Frame, HeadlessLimits, HeadlessResult};
+
+use uxn::{Device, Halt, Ports, Uxn};
 
 /// Write to execute before calling the event vector
-#[derive(Copy, Clone, Debug)]
-struct EventData {
-    addr: u8,
-    value: u8,
-    clear: bool,
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct EventData {
+    /// Address in device memory
+    pub addr: u8,
+    /// Value written to `addr` before calling the vector
+    pub value: u8,
+    /// Whether `addr` is reset to 0 after the vector returns
+    pub clear: bool,
 }
 
-/// Internal events, accumulated by devices then applied to the CPU
-#[derive(Copy, Clone, Debug)]
-struct Event {
-    /// Tuple of `(address, value)` to write in in device memory
+/// Vector dispatch, accumulated by devices then applied to the CPU
+///
+/// Hosts can observe these events with [`Varvara::subscribe`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// Write to device memory which triggered the vector, if any
     pub data: Option<EventData>,
 
     /// Vector to trigger
     pub vector: u16,
+
+    /// Base address of the device which owns the vector
+    pub device: u8,
 }
 
+/// Number of events queued for each [`Varvara::subscribe`] receiver, beyond
+/// which newer events are dropped
+pub const SUBSCRIBE_CAPACITY: usize = 1024;
+
 /// Output from [`Varvara::update`], which may modify the GUI
+#[non_exhaustive]
 pub struct Output<'a> {
     /// Current window size
     pub size: (u16, u16),
 
-    /// Current screen contents, as RGBA values
+    /// New window size, if the screen has been resized
+    ///
+    /// This is reported by the first output after a reset, then whenever the
+    /// ROM changes the screen size (which clears the screen).  The ROM may
+    /// request any size up to [`SCREEN_MAX_SIZE`] in each dimension, at any
+    /// time.
+    pub resized: Option<(u16, u16)>,
+
+    /// Current screen contents, with 4 bytes per pixel
+    ///
+    /// Pixels are in the format set by [`Varvara::set_pixel_format`] (BGRA by
+    /// default).
     pub frame: &'a [u8],
 
+    /// Byte order of each pixel in `frame`
+    pub pixel_format: PixelFormat,
+
+    /// Region of `frame` which has changed since the previous output
+    ///
+    /// This is `None` if the frame is unchanged.  The first output after a
+    /// reset, a resize, or a palette change reports the entire screen.  A
+    /// host which keeps its own copy of the frame (e.g. a GPU texture) only
+    /// needs to update this region.
+    pub dirty: Option<Region>,
+
     /// The system's mouse cursor should be hidden
     pub hide_mouse: bool,
 
+    /// The host should grab (and hide) the pointer, then report relative
+    /// motion in [`MouseState::delta`]
+    ///
+    /// This is set by [`Varvara::set_mouse_capture`].
+    pub capture_mouse: bool,
+
     /// Outgoing console characters sent to the `write` port
+    ///
+    /// This is always empty while [`Varvara::on_stdout`] listeners are
+    /// registered.
     pub stdout: Vec<u8>,
 
     /// Outgoing console characters sent to the `error` port
+    ///
+    /// This is always empty while [`Varvara::on_stderr`] listeners are
+    /// registered.
     pub stderr: Vec<u8>,
 
     /// Request to exit with the given error code
     pub exit: Option<i32>,
+
+    /// Most recent vector dispatched by the system
+    pub last_vector: Option<VectorInfo>,
+
+    /// Current colors (as `0xAARRGGBB` values), if they have changed
+    ///
+    /// This is reported by the first output after a reset, then whenever the
+    /// ROM writes new colors to the system device (or the host changes the
+    /// default palette).
+    pub palette: Option<[u32; 4]>,
 }
 
 impl Output<'_> {
@@ -91,6 +196,9 @@ impl Output<'_> {
         self.print()?;
         if let Some(e) = self.exit {
             log::info!("requested exit ({e})");
+            if let (true, Some(v)) = (e != 0, self.last_vector) {
+                log::info!("last vector: {v}");
+            }
 
             #[cfg(not(target_arch = "wasm32"))]
             std::process::exit(e);
@@ -105,7 +213,63 @@ impl Output<'_> {
     }
 }
 
+/// Information about a vector dispatched by the system
+///
+/// This is returned by [`Varvara::last_vector`], and is useful for diagnosing
+/// a ROM which has hung or failed.
+#[derive(Copy, Clone, Debug)]
+pub struct VectorInfo {
+    /// Base address of the device which owns the vector
+    pub device: u8,
+
+    /// Address of the vector
+    pub vector: u16,
+
+    /// Time spent executing the vector
+    ///
+    /// This is `None` on WebAssembly, where timers are not available.
+    pub elapsed: Option<std::time::Duration>,
+
+    /// Why execution stopped
+    ///
+    /// This is [`Halt::Break`] if the vector finished normally; hosts can
+    /// tell an interrupt ([`Halt::Interrupted`]) apart from running out of
+    /// cycles ([`Halt::OutOfCycles`]).
+    pub halt: Halt,
+}
+
+impl std::fmt::Display for VectorInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = ports::name_of(self.device).unwrap_or("?");
+        write!(f, "{name} #{:04x}", self.vector)?;
+        if let Some(t) = self.elapsed {
+            write!(f, " ({t:?})")?;
+        }
+        match self.halt {
+            Halt::Break { .. } => Ok(()),
+            Halt::Exit { code: Some(c), .. } => write!(f, " [exit {c}]"),
+            Halt::Exit { code: None, .. } => write!(f, " [exit]"),
+            Halt::Interrupted { .. } => write!(f, " [interrupted]"),
+            Halt::OutOfCycles { .. } => write!(f, " [out of cycles]"),
+            h => write!(f, " [{h:?}]"),
+        }
+    }
+}
+
 /// Handle to the Varvara system
+///
+/// # Device memory during vectors
+/// Before calling a vector, the system writes new input into the device's
+/// ports (e.g. `Console/read` and `Console/type`, or the mouse position and
+/// buttons).  These values don't change while the vector runs, except through
+/// the ROM's own `DEO` writes (and ports which are refreshed on `DEI`, like
+/// the date and time).
+///
+/// Some ports are transient, and only hold a value while the vector which
+/// reports it is running: `Controller/key`, `Mouse/scrollx`, and
+/// `Mouse/scrolly` are reset to 0 once the vector returns, unless the vector
+/// itself wrote to them.  Other ports keep their values (e.g. `Console/read`
+/// holds the most recent character).
 pub struct Varvara {
     system: system::System,
     console: console::Console,
@@ -118,6 +282,48 @@ pub struct Varvara {
 
     /// Flags indicating if we've already printed a warning about a missing dev
     already_warned: [bool; 16],
+
+    /// Most recent vector dispatched by [`Varvara::process_event`]
+    last_vector: Option<VectorInfo>,
+
+    /// Colors reported by the most recent [`Output`]
+    last_palette: Option<[u32; 4]>,
+
+    /// Byte order of rendered frames
+    pixel_format: PixelFormat,
+
+    /// Key map, which is kept when the system is reset
+    key_map: keymap::KeyMap,
+
+    /// Key repeat policy, which is kept when the system is reset
+    key_repeat: KeyRepeat,
+
+    /// Active recording, which captures a frame on each redraw
+    recorder: Option<recorder::Recorder>,
+
+    /// Maximum number of console bytes to deliver per frame
+    console_pacing: Option<NonZeroUsize>,
+
+    /// Console input which has not yet been delivered
+    console_queue: VecDeque<console::Input>,
+
+    /// Most recent `Console/type`, including queued input
+    console_type: console::Type,
+
+    /// Channels which receive every dispatched [`Event`]
+    subscribers: Vec<mpsc::SyncSender<Event>>,
+
+    /// Number of events processed, used to track port writes
+    epoch: u64,
+
+    /// Epoch in which each port was last written by the ROM (with `DEO`)
+    written: [u64; 256],
+
+    /// Bitfield of disabled device pages, one bit per page
+    disabled: u16,
+
+    /// Callbacks for device output
+    listeners: listeners::Listeners,
 }
 
 impl Default for Varvara {
@@ -128,7 +334,15 @@ impl Default for Varvara {
 
 impl Device for Varvara {
     fn deo(&mut self, vm: &mut Uxn, target: u8) -> bool {
+        trace!(
+            "deo {target:02x} ({})",
+            ports::name_of(target).unwrap_or("?")
+        );
+        self.written[usize::from(target)] = self.epoch;
+        let size = self.screen.size();
+        let exiting = self.system.should_exit();
         match target & 0xF0 {
+            t if !self.is_enabled(t) => self.warn_missing(t),
             system::SystemPorts::BASE => self.system.deo(vm, target),
             console::ConsolePorts::BASE => self.console.deo(vm, target),
             datetime::DatetimePorts::BASE => self.datetime.deo(vm, target),
@@ -141,10 +355,16 @@ impl Device for Varvara {
             // Default case
             t => self.warn_missing(t),
         }
+        self.notify_deo(vm, target, size, exiting);
         !self.system.should_exit()
     }
     fn dei(&mut self, vm: &mut Uxn, target: u8) {
+        trace!(
+            "dei {target:02x} ({})",
+            ports::name_of(target).unwrap_or("?")
+        );
         match target & 0xF0 {
+            t if !self.is_enabled(t) => self.warn_missing(t),
             system::SystemPorts::BASE => self.system.dei(vm, target),
             console::ConsolePorts::BASE => self.console.dei(vm, target),
             datetime::DatetimePorts::BASE => self.datetime.dei(vm, target),
@@ -158,23 +378,87 @@ impl Device for Varvara {
             t => self.warn_missing(t),
         }
     }
+    fn dei2(&mut self, vm: &mut Uxn, target: u8) {
+        match target & 0xF0 {
+            // Only the datetime device has values which may change between
+            // the two reads; other devices use the default per-byte reads.
+            datetime::DatetimePorts::BASE if target & 0x0F != 0x0F => {
+                trace!(
+                    "dei2 {target:02x} ({})",
+                    ports::name_of(target).unwrap_or("?")
+                );
+                self.datetime.dei2(vm, target)
+            }
+            _ => {
+                self.dei(vm, target);
+                self.dei(vm, target.wrapping_add(1));
+            }
+        }
+    }
+    fn exit_code(&self) -> Option<i32> {
+        self.system.exit_code()
+    }
 }
 
 impl Varvara {
     /// Builds a new instance of the Varvara peripherals
+    ///
+    /// Every device is enabled; use [`Varvara::builder`] to configure them.
     pub fn new() -> Self {
-        Self {
+        Self::builder().build()
+    }
+
+    /// Builds a system with the given disabled device pages
+    fn with_options(
+        disabled: u16,
+        file_root: Option<std::path::PathBuf>,
+    ) -> Self {
+        let mut file = file::File::new();
+        file.set_root(file_root);
+        let mut out = Self {
             console: console::Console::new(),
             system: system::System::new(),
-            datetime: datetime::Datetime,
+            datetime: datetime::Datetime::new(),
             audio: audio::Audio::new(),
-            screen: screen::Screen::new(),
+            screen: screen::Screen::empty(),
             mouse: mouse::Mouse::new(),
-            file: file::File::new(),
-            controller: controller::Controller::new(),
+            file,
+            controller: controller::Controller::new(Default::default()),
 
             already_warned: [false; 16],
-        }
+            last_vector: None,
+            last_palette: None,
+            pixel_format: PixelFormat::default(),
+            key_map: keymap::KeyMap::default(),
+            key_repeat: KeyRepeat::default(),
+            recorder: None,
+            console_pacing: None,
+            console_queue: VecDeque::new(),
+            console_type: console::Type::NoQueue,
+            subscribers: vec![],
+            epoch: 0,
+            written: [0; 256],
+            disabled,
+            listeners: Default::default(),
+        };
+        out.screen = out.new_screen();
+        out
+    }
+
+    /// Checks whether the device at the given address is enabled
+    fn is_enabled(&self, addr: u8) -> bool {
+        self.disabled & (1 << (addr >> 4)) == 0
+    }
+
+    /// Builds a screen, which is empty if the screen device is disabled
+    fn new_screen(&self) -> screen::Screen {
+        let mut s = if self.is_enabled(screen::ScreenPorts::BASE) {
+            screen::Screen::new()
+        } else {
+            screen::Screen::empty()
+        };
+        s.set_format(self.pixel_format);
+        s
     }
 
     /// Resets the CPU, loading extra data into expansion memory
@@ -183,13 +467,56 @@ impl Varvara {
     /// threads can continue to run.
     pub fn reset(&mut self, extra: &[u8]) {
         self.system.reset(extra);
-        self.console = console::Console::new();
+        self.console.reset();
         self.audio.reset();
-        self.screen = screen::Screen::new();
+        self.screen = self.new_screen();
         self.mouse = mouse::Mouse::new();
-        self.file = file::File::new();
-        self.controller = controller::Controller::new();
+        self.file.reset();
+        self.controller = controller::Controller::new(self.key_map.clone());
+        self.controller.set_key_repeat(self.key_repeat);
         self.already_warned.fill(false);
+        self.last_vector = None;
+        self.last_palette = None;
+        self.console_queue.clear();
+        self.console_type = console::Type::NoQueue;
+    }
+
+    /// Resets the CPU and peripherals, loading a ROM file
+    ///
+    /// This is equivalent to calling [`Uxn::reset`] followed by
+    /// [`Varvara::reset`], except that expansion memory is not copied up
+    /// front: each 64 KiB bank is copied from `rom` when the ROM first
+    /// accesses it.  Combined with [`RomFile::open`](rom::RomFile::open),
+    /// this means that only the parts of a large ROM which are used are ever
+    /// read from disk.
+    ///
+    /// Like [`Varvara::load_rom`], this sets the VM's mapped device pages.
+    pub fn reset_from(&mut self, vm: &mut Uxn, rom: Arc<rom::RomFile>) {
+        let offset = rom.len() - self.reset_vm(vm, &rom).len();
+        self.reset(&[]);
+        self.system.reset_from(rom, offset);
+    }
+
+    /// Resets the CPU and peripherals, loading a ROM from memory
+    ///
+    /// This is equivalent to calling [`Uxn::reset`] followed by
+    /// [`Varvara::reset`], and also sets the VM's mapped device pages (see
+    /// [`Uxn::ports_iter`]) to this system's enabled devices.  Hosts should
+    /// load every ROM through this or [`Varvara::reset_from`], so that the
+    /// pages are always mapped.
+    pub fn load_rom(&mut self, vm: &mut Uxn, rom: &[u8]) {
+        let extra = self.reset_vm(vm, rom);
+        self.reset(extra);
+    }
+
+    /// Resets the VM with the given ROM and maps this system's device pages
+    ///
+    /// Returns the data which didn't fit into VM memory.
+    fn reset_vm<'r>(&self, vm: &mut Uxn, rom: &'r [u8]) -> &'r [u8] {
+        let extra = vm.reset(rom);
+        vm.unmap_pages();
+        ports::map_pages_where(vm, |p| self.is_enabled(p));
+        extra
     }
 
     /// Checks whether the SHIFT key is currently down
@@ -202,10 +529,181 @@ impl Varvara {
 
     /// Calls the screen vector
     ///
-    /// This function must be called at 60 Hz
-    pub fn redraw(&mut self, vm: &mut Uxn) {
+    /// This function must be called at 60 Hz; [`Varvara::tick`] does so with a
+    /// [`FrameTimer`].
+    ///
+    /// If console pacing is enabled, queued console input is delivered before
+    /// calling the screen vector (see [`Varvara::set_console_pacing`]), as are
+    /// key repeats generated by the controller (see
+    /// [`Varvara::set_key_repeat`]).
+    ///
+    /// Returns `true` if the screen contents may have changed since the
+    /// previous call (because the ROM wrote to the screen device or changed
+    /// the palette, from any vector).  If this returns `false`, then the host
+    /// can skip re-rendering the frame.
+    ///
+    /// If a recording is in progress, the frame is captured (see
+    /// [`Varvara::start_recording`]).
+    pub fn redraw(&mut self, vm: &mut Uxn) -> bool {
+        self.pump_console(vm);
+        if self.is_enabled(controller::ControllerPorts::BASE) {
+            if let Some(e) = self.controller.tick(vm) {
+                self.process_event(vm, e);
+            }
+        }
+        if !self.is_enabled(screen::ScreenPorts::BASE) {
+            return false;
+        }
         let e = self.screen.update(vm);
         self.process_event(vm, e);
+        let colors = self.system.colors(vm);
+        let dirty = self.screen.take_dirty(colors);
+        if let Some(r) = &mut self.recorder {
+            r.capture(self.screen.size(), self.screen.indices(), colors);
+        }
+        if dirty && !self.listeners.frame.is_empty() {
+            let size = self.screen.size();
+            let frame = self.screen.frame(colors);
+            for f in &mut self.listeners.frame {
+                f(size, frame);
+            }
+        }
+        dirty
+    }
+
+    /// Renders a rectangular region of the screen
+    ///
+    /// The result is `w * h * 4` bytes, in the same format as
+    /// [`Output::frame`]; pixels outside the screen are left as zeros.  This
+    /// is cheaper than rendering the full frame when only a small area is
+    /// needed (e.g. when checking a ROM's output in tests).
+    pub fn copy_region(
+        &self,
+        vm: &Uxn,
+        x: u16,
+        y: u16,
+        w: u16,
+        h: u16,
+    ) -> Vec<u8> {
+        self.screen.copy_region(self.system.colors(vm), x, y, w, h)
+    }
+
+    /// Renders a single layer of the screen
+    ///
+    /// The result has the same size and format as [`Output::frame`], except
+    /// that transparent pixels in the foreground (color 0) are left as zeros.
+    /// Drawing the foreground over the background (with alpha blending)
+    /// produces the usual frame; this lets hosts composite the layers
+    /// themselves, e.g. to process them separately.  To read color indices
+    /// instead of rendered colors, see [`Screen::layer`](devices::Screen::layer).
+    pub fn render_layer(&self, vm: &Uxn, layer: Layer) -> Vec<u8> {
+        self.screen.render_layer(self.system.colors(vm), layer)
+    }
+
+    /// Sets the byte order of pixels in rendered frames
+    ///
+    /// This applies to [`Output::frame`], [`Varvara::copy_region`], and frame
+    /// listeners, so that a host can pass frames directly to its graphics
+    /// API.  The format is kept when the system is reset.
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+        self.screen.set_format(format);
+    }
+
+    /// Sets the palette to use before the ROM writes the color registers
+    ///
+    /// This lets the host pick colors (e.g. to match a dark theme) which are
+    /// shown while a ROM boots; reading the color registers with `DEI` returns
+    /// these values until the ROM overwrites them.  The setting persists
+    /// across calls to [`Varvara::reset`].
+    pub fn set_default_palette(&mut self, r: u16, g: u16, b: u16) {
+        self.system.set_default_palette([r, g, b]);
+    }
+
+    /// Uses the given theme as the default palette
+    ///
+    /// This is equivalent to [`Varvara::set_default_palette`]; colors written
+    /// by the ROM take priority over the theme.
+    pub fn set_theme(&mut self, theme: theme::Theme) {
+        self.set_default_palette(theme.r, theme.g, theme.b);
+    }
+
+    /// Enables or disables atomic writes in the file device (on by default)
+    ///
+    /// When enabled, writes go to a temporary file in the same directory,
+    /// which replaces the target file once the ROM is done writing it; a crash
+    /// mid-save then leaves the original file intact.  The setting persists
+    /// across calls to [`Varvara::reset`].
+    pub fn set_atomic_file_writes(&mut self, atomic: bool) {
+        self.file.set_atomic_writes(atomic);
+    }
+
+    /// Sets the directory in which the file devices operate
+    ///
+    /// Paths from the ROM are resolved relative to this directory and cannot
+    /// escape it; if `root` is `None`, the current directory is used.  Any
+    /// open file is closed.  The setting persists across calls to
+    /// [`Varvara::reset`]; see also [`VarvaraBuilder::file_root`].
+    pub fn set_file_root<P: Into<std::path::PathBuf>>(
+        &mut self,
+        root: Option<P>,
+    ) {
+        self.file.set_root(root.map(Into::into));
+    }
+
+    /// Replaces the storage used by the file devices
+    ///
+    /// By default, files are read from and written to the host's filesystem
+    /// ([`StdFs`]), except on WebAssembly, where they're kept in memory
+    /// ([`MemoryFs`]).  Any open file is closed.  The backend persists across
+    /// calls to [`Varvara::reset`].
+    pub fn set_file_backend<B: FileBackend + 'static>(&mut self, backend: B) {
+        self.file.set_backend(Box::new(backend));
+    }
+
+    /// Enables or disables creating directories in the file device (off by
+    /// default)
+    ///
+    /// When enabled, writing to a path whose parent directories don't exist
+    /// creates them (within the file root), so ROMs can save into new project
+    /// folders.  The setting persists across calls to [`Varvara::reset`].
+    pub fn set_create_file_dirs(&mut self, create: bool) {
+        self.file.set_create_dirs(create);
+    }
+
+    /// Runs file operations on a worker thread (off by default)
+    ///
+    /// Normally, reads and writes happen during the ROM's `DEO`, so slow
+    /// storage stalls the whole system.  When enabled, operations run in the
+    /// background: the success flag is cleared immediately, then set (and the
+    /// file device's vector called) when [`Varvara::file`] sees that the
+    /// operation has finished.  ROMs which expect results straight after the
+    /// `DEO` won't work in this mode.
+    ///
+    /// Switching modes closes any open files.  The setting persists across
+    /// calls to [`Varvara::reset`], and is ignored on WebAssembly.
+    pub fn set_async_file_io(&mut self, enabled: bool) {
+        self.file.set_async(enabled);
+    }
+
+    /// Limits the number of console bytes delivered per frame
+    ///
+    /// Some ROMs mishandle bursts of console input (e.g. a large paste or long
+    /// arguments).  With pacing enabled, bytes from [`Varvara::console`] and
+    /// [`Varvara::send_args`] are queued, and at most `n` of them are sent to
+    /// the console vector on each call to [`Varvara::redraw`].  When `n` is
+    /// `None` (the default), bytes are delivered immediately.  The setting
+    /// persists across calls to [`Varvara::reset`].
+    pub fn set_console_pacing(&mut self, n: Option<NonZeroUsize>) {
+        self.console_pacing = n;
+    }
+
+    /// Returns the number of console bytes waiting to be delivered
+    pub fn console_backlog(&self) -> usize {
+        self.console_queue
+            .iter()
+            .filter(|i| matches!(i, console::Input::Char(..)))
+            .count()
     }
 
     /// Sets initial value for `Console/type` based on the presense of arguments
@@ -215,111 +713,535 @@ impl Varvara {
         self.console.set_has_args(vm, !args.is_empty());
     }
 
+    /// Sets labels from a symbol file, used to annotate debug output
+    ///
+    /// When the ROM writes to `System/debug`, return addresses are then shown
+    /// relative to the nearest label (see [`Varvara::debug_dump`]).  Symbols
+    /// are cleared on reset, since they belong to a particular ROM.
+    pub fn set_symbols(&mut self, symbols: Option<rom::Symbols>) {
+        self.system.set_symbols(symbols);
+    }
+
+    /// Returns the stack dump which is printed when the ROM writes to
+    /// `System/debug`
+    pub fn debug_dump(&self, vm: &Uxn) -> String {
+        self.system.debug_dump(vm)
+    }
+
+    /// Returns the metadata which the ROM published, if any
+    ///
+    /// ROMs conventionally write the address of a metadata block to
+    /// `System/metadata` at startup; the block is parsed at that point (see
+    /// [`rom`] for its format).  The metadata is cleared on reset.
+    pub fn metadata(&self) -> Option<rom::RomMetadata> {
+        self.system.metadata().cloned()
+    }
+
     /// Returns the current output state of the system
     ///
     /// This is not idempotent; the output is taken from various accumulators
     /// and will be empty if this is called multiple times.
     #[must_use]
-    pub fn output(&mut self, vm: &Uxn) -> Output {
+    pub fn output(&mut self, vm: &Uxn) -> Output<'_> {
+        if self.system.should_exit() {
+            // Make sure that any in-progress writes are saved before exiting
+            self.file.close();
+            self.console.finish();
+        } else {
+            self.console.flush();
+        }
+        let colors = self.system.colors(vm);
+        let palette = (self.last_palette != Some(colors)).then_some(colors);
+        self.last_palette = Some(colors);
+        self.screen.frame(colors);
+        let dirty = self.screen.take_region();
         Output {
             size: self.screen.size(),
-            frame: self.screen.frame(vm),
+            resized: self.screen.take_resized().then(|| self.screen.size()),
+            frame: self.screen.buffer(),
+            pixel_format: self.pixel_format,
+            dirty,
             hide_mouse: self.mouse.active(),
+            capture_mouse: self.mouse.captured(),
             stdout: self.console.stdout(),
             stderr: self.console.stderr(),
             exit: self.system.exit(),
+            last_vector: self.last_vector,
+            palette,
         }
     }
 
     /// Sends arguments to the console device
     ///
     /// Leaves the console type set to `stdin`, and returns the current output
-    /// state of the system
-    pub fn send_args(&mut self, vm: &mut Uxn, args: &[String]) -> Output {
+    /// state of the system.  If console pacing is enabled, the arguments are
+    /// queued and delivered by subsequent calls to [`Varvara::redraw`].
+    pub fn send_args(&mut self, vm: &mut Uxn, args: &[String]) -> Output<'_> {
+        use console::{Input, Type};
         for (i, a) in args.iter().enumerate() {
-            self.console.set_type(vm, console::Type::Argument);
+            self.console_input(vm, Input::Type(Type::Argument));
             for c in a.bytes() {
-                self.process_event(vm, self.console.update(vm, c));
+                self.console_input(vm, Input::Char(c));
             }
 
             let ty = if i == args.len() - 1 {
-                console::Type::ArgumentEnd
+                Type::End
             } else {
-                console::Type::ArgumentSpacer
+                Type::ArgumentSpacer
             };
-            self.console.set_type(vm, ty);
-            self.process_event(vm, self.console.update(vm, b'\n'));
+            self.console_input(vm, Input::Type(ty));
+            self.console_input(vm, Input::Char(b'\n'));
         }
-        self.console.set_type(vm, console::Type::Stdin);
+        self.console_input(vm, Input::Type(Type::Stdin));
         self.output(vm)
     }
 
     /// Send a character from the keyboard (controller) device
     pub fn char(&mut self, vm: &mut Uxn, k: u8) {
+        if !self.is_enabled(controller::ControllerPorts::BASE) {
+            return;
+        }
         let e = self.controller.char(vm, k);
         self.process_event(vm, e);
     }
 
-    /// Press a key on the controller device
-    pub fn pressed(&mut self, vm: &mut Uxn, k: Key, repeat: bool) {
-        if let Some(e) = self.controller.pressed(vm, k, repeat) {
+    /// Updates a gamepad's state on the controller device
+    ///
+    /// The gamepad's buttons are combined with keys held by the same player
+    /// (see [`Varvara::pressed_for`]); if the result changes, the controller
+    /// vector is called.
+    pub fn gamepad(&mut self, vm: &mut Uxn, state: GamepadState) {
+        if !self.is_enabled(controller::ControllerPorts::BASE) {
+            return;
+        }
+        if let Some(e) = self.controller.gamepad(vm, state) {
+            self.process_event(vm, e);
+        }
+    }
+
+    /// Sets the policy for repeating held keys
+    ///
+    /// The policy is kept when the system is reset.
+    pub fn set_key_repeat(&mut self, repeat: KeyRepeat) {
+        self.key_repeat = repeat;
+        self.controller.set_key_repeat(repeat);
+    }
+
+    /// Sets the mapping from keys to controller buttons
+    ///
+    /// The map is kept when the system is reset.  Any buttons which change
+    /// as a result call the controller vector.
+    pub fn set_key_map(&mut self, vm: &mut Uxn, map: keymap::KeyMap) {
+        self.key_map = map.clone();
+        for e in self.controller.set_key_map(vm, map) {
             self.process_event(vm, e);
         }
     }
 
+    /// Press a key on the controller device
+    pub fn pressed(&mut self, vm: &mut Uxn, k: Key, repeat: bool) {
+        self.pressed_for(vm, 0, k, repeat)
+    }
+
     /// Release a key on the controller device
     pub fn released(&mut self, vm: &mut Uxn, k: Key) {
-        if let Some(e) = self.controller.released(vm, k) {
+        self.released_for(vm, 0, k)
+    }
+
+    /// Press a key on the controller device for the given player
+    ///
+    /// Player 0 is the same as [`Varvara::pressed`]; players 1 through 3
+    /// (up to [`CONTROLLER_PLAYERS`]) update `Controller/p2` through
+    /// `Controller/p4`.  Characters are only sent for player 0.
+    pub fn pressed_for(
+        &mut self,
+        vm: &mut Uxn,
+        player: usize,
+        k: Key,
+        repeat: bool,
+    ) {
+        if !self.is_enabled(controller::ControllerPorts::BASE) {
+            return;
+        }
+        if let Some(e) = self.controller.pressed(vm, player, k, repeat) {
             self.process_event(vm, e);
         }
     }
 
+    /// Release a key on the controller device for the given player
+    pub fn released_for(&mut self, vm: &mut Uxn, player: usize, k: Key) {
+        if !self.is_enabled(controller::ControllerPorts::BASE) {
+            return;
+        }
+        if let Some(e) = self.controller.released(vm, player, k) {
+            self.process_event(vm, e);
+        }
+    }
+
+    /// Sets the console backend, returning the previous one
+    ///
+    /// Pass `None` to go back to buffering output for [`Varvara::output`].
+    /// The backend is kept when the system is reset.
+    pub fn set_console_backend(
+        &mut self,
+        b: Option<Box<dyn ConsoleBackend>>,
+    ) -> Option<Box<dyn ConsoleBackend>> {
+        self.console.set_backend(b)
+    }
+
     /// Send a character from the console device
+    ///
+    /// The character is sent with `Console/type` set to 1 (stdin).  If console
+    /// pacing is enabled, it is queued and delivered by a subsequent call to
+    /// [`Varvara::redraw`].
     pub fn console(&mut self, vm: &mut Uxn, c: u8) {
-        let e = self.console.update(vm, c);
-        self.process_event(vm, e);
+        use console::{Input, Type};
+        if self.console_type != Type::Stdin {
+            self.console_input(vm, Input::Type(Type::Stdin));
+        }
+        self.console_input(vm, Input::Char(c));
+    }
+
+    /// Signals the end of console input (e.g. `stdin` reaching end-of-file)
+    ///
+    /// This sends a null character with `Console/type` set to 4 (end), which
+    /// lets a ROM flush its output and exit.
+    pub fn console_end(&mut self, vm: &mut Uxn) {
+        use console::{Input, Type};
+        self.console_input(vm, Input::Type(Type::End));
+        self.console_input(vm, Input::Char(0));
+    }
+
+    /// Applies console input, or queues it if pacing is enabled
+    ///
+    /// Input is also queued if earlier input is still pending, so that it is
+    /// always delivered in order.
+    fn console_input(&mut self, vm: &mut Uxn, i: console::Input) {
+        if let console::Input::Type(ty) = i {
+            self.console_type = ty;
+        }
+        if self.console_pacing.is_some() || !self.console_queue.is_empty() {
+            self.console_queue.push_back(i);
+        } else {
+            self.apply_console_input(vm, i);
+        }
+    }
+
+    fn apply_console_input(&mut self, vm: &mut Uxn, i: console::Input) {
+        match i {
+            console::Input::Type(ty) => self.console.set_type(vm, ty),
+            console::Input::Char(c) => {
+                let e = self.console.update(vm, c);
+                self.process_event(vm, e);
+            }
+        }
+    }
+
+    /// Delivers queued console input, up to the pacing limit
+    ///
+    /// Input from the console backend (if any) is queued first.
+    fn pump_console(&mut self, vm: &mut Uxn) {
+        while let Some(i) = self.console.read() {
+            match i {
+                ConsoleInput::Char(c) => self.console(vm, c),
+                ConsoleInput::End => self.console_end(vm),
+            }
+        }
+        let mut budget =
+            self.console_pacing.map_or(usize::MAX, NonZeroUsize::get);
+        while let Some(&i) = self.console_queue.front() {
+            if let console::Input::Char(..) = i {
+                if budget == 0 || self.system.should_exit() {
+                    break;
+                }
+                budget -= 1;
+            }
+            self.console_queue.pop_front();
+            self.apply_console_input(vm, i);
+        }
     }
 
     /// Updates the mouse state
     pub fn mouse(&mut self, vm: &mut Uxn, m: MouseState) {
+        if !self.is_enabled(mouse::MousePorts::BASE) {
+            return;
+        }
         if let Some(e) = self.mouse.update(vm, m) {
             self.process_event(vm, e);
         }
     }
 
+    /// Updates the mouse state from a touch event
+    ///
+    /// Touches are translated into mouse events, so that ROMs can be used
+    /// without a physical mouse: the first finger down moves the cursor and
+    /// holds the left button, while a second finger switches to the right
+    /// button and a third to the middle button.
+    pub fn touch(&mut self, vm: &mut Uxn, t: Touch) {
+        if !self.is_enabled(mouse::MousePorts::BASE) {
+            return;
+        }
+        let m = self.mouse.touch(t);
+        self.mouse(vm, m);
+    }
+
+    /// Enables or disables relative-motion (pointer capture) mode
+    ///
+    /// While enabled, `Mouse/x` and `Mouse/y` are driven by
+    /// [`MouseState::delta`] rather than the absolute position, and wrap
+    /// around instead of stopping at the screen edges; this suits ROMs which
+    /// want unbounded motion, such as first-person views or drawing tools.
+    /// [`Output::capture_mouse`] asks the host to grab the pointer.
+    pub fn set_mouse_capture(&mut self, captured: bool) {
+        self.mouse.set_captured(captured);
+    }
+
     /// Processes pending audio events
-    pub fn audio(&mut self, vm: &mut Uxn) {
+    ///
+    /// Returns `true` if any audio channel finished playing a note since the
+    /// previous call.
+    pub fn audio(&mut self, vm: &mut Uxn) -> bool {
+        if !self.is_enabled(audio::AudioPorts::BASE) {
+            return false;
+        }
+        let mut any = false;
         for i in 0..audio::DEV_COUNT {
             if let Some(e) = self.audio.update(vm, usize::from(i)) {
+                listeners::Listeners::call(
+                    &mut self.listeners.note_end,
+                    usize::from(i),
+                );
                 self.process_event(vm, e);
+                any = true;
             }
         }
+        any
+    }
+
+    /// Finishes file operations which have completed in the background
+    ///
+    /// This only does anything if [asynchronous file
+    /// I/O](Varvara::set_async_file_io) is enabled.  Each finished operation
+    /// writes its data and success flag, then calls its device's vector.
+    /// Returns `true` if any operations were pending, so the host should keep
+    /// calling this function.
+    pub fn file(&mut self, vm: &mut Uxn) -> bool {
+        if !self.is_enabled(file::FilePorts::BASE) {
+            return false;
+        }
+        // Only finish operations which were already pending, so that a ROM
+        // which requests more data from its vector can't stall the host
+        let pending = self.file.pending();
+        for _ in 0..pending {
+            let Some(e) = self.file.update(vm) else {
+                break;
+            };
+            self.process_event(vm, e);
+        }
+        pending > 0
     }
 
     /// Processes a single vector event
     ///
-    /// Events with an unassigned vector (i.e. 0) are ignored
+    /// Events with an unassigned vector (i.e. 0) don't write their data, but
+    /// transient ports are still cleared (see [`Varvara`]).
     fn process_event(&mut self, vm: &mut Uxn, e: Event) {
+        // Start a new epoch, so that we can see which ports the vector writes
+        self.epoch += 1;
         if e.vector != 0 {
             if let Some(d) = e.data {
                 vm.write_dev_mem(d.addr, d.value);
             }
-            vm.run(self, e.vector);
+
+            #[cfg(not(target_arch = "wasm32"))]
+            let start = std::time::Instant::now();
+            let halt = vm.run_halt(self, e.vector);
+            #[cfg(not(target_arch = "wasm32"))]
+            let elapsed = Some(start.elapsed());
+            #[cfg(target_arch = "wasm32")]
+            let elapsed = None;
+
+            // Events are dropped if a subscriber falls behind, so that the
+            // queue can't grow without bound
+            self.subscribers.retain(|tx| {
+                !matches!(
+                    tx.try_send(e),
+                    Err(mpsc::TrySendError::Disconnected(..))
+                )
+            });
+            self.last_vector = Some(VectorInfo {
+                device: e.device,
+                vector: e.vector,
+                elapsed,
+                halt,
+            });
             if let Some(d) = e.data {
                 if d.clear {
-                    vm.write_dev_mem(d.addr, 0);
+                    self.clear_port(vm, d.addr);
                 }
             }
         }
+        if e.device == mouse::MousePorts::BASE {
+            for addr in mouse::MousePorts::SCROLL {
+                self.clear_port(vm, addr);
+            }
+        }
+    }
+
+    /// Invokes listeners for the effects of a `DEO`
+    ///
+    /// `size` and `exiting` are the screen size and exit flag before the
+    /// `DEO` was handled.
+    fn notify_deo(
+        &mut self,
+        vm: &Uxn,
+        target: u8,
+        size: (u16, u16),
+        exiting: bool,
+    ) {
+        use listeners::Listeners;
+        use zerocopy::AsBytes;
+        let l = &mut self.listeners;
+        let c = || {
+            vm.dev::<console::ConsolePorts>().as_bytes()
+                [usize::from(target & 0x0F)]
+        };
+        match target {
+            console::ConsolePorts::WRITE => Listeners::call(&mut l.stdout, c()),
+            console::ConsolePorts::ERROR => Listeners::call(&mut l.stderr, c()),
+            _ => (),
+        }
+        if self.screen.size() != size {
+            Listeners::call(&mut l.resize, self.screen.size());
+        }
+        if let (false, Some(code)) = (exiting, self.system.exit_code()) {
+            Listeners::call(&mut l.exit, code);
+        }
+    }
+
+    /// Resets a transient port to 0, unless the ROM wrote to it in this epoch
+    fn clear_port(&mut self, vm: &mut Uxn, addr: u8) {
+        if self.written[usize::from(addr)] != self.epoch {
+            vm.write_dev_mem(addr, 0);
+        }
+    }
+
+    /// Subscribes to vector dispatches
+    ///
+    /// Every event which calls a vector (i.e. with a non-zero vector address)
+    /// is sent to the returned channel once the vector has finished running.
+    /// This includes the screen vector but not the reset vector, which is run
+    /// by the host.  Dropping the receiver ends the subscription.
+    /// Subscriptions persist across calls to [`Varvara::reset`].
+    ///
+    /// The channel holds up to [`SUBSCRIBE_CAPACITY`] events.  If the receiver
+    /// falls behind and the channel is full, new events are dropped (for that
+    /// receiver only) until it catches up; the VM never blocks on a slow
+    /// subscriber.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBE_CAPACITY);
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Returns the most recent vector dispatched by the system
+    ///
+    /// This does not include the reset vector, which is run by the host.
+    pub fn last_vector(&self) -> Option<VectorInfo> {
+        self.last_vector
     }
 
     /// Returns the set of audio stream data handles
+    ///
+    /// Most hosts should use [`Varvara::audio_output`] instead, which mixes
+    /// the streams into a single output.
     pub fn audio_streams(&self) -> [Arc<Mutex<audio::StreamData>>; 4] {
         [0, 1, 2, 3].map(|i| self.audio.stream(i))
     }
 
+    /// Returns the mixed output of all four audio streams
+    pub fn audio_output(&self) -> AudioOutput {
+        self.audio.output()
+    }
+
     /// Sets the global mute flag for audio
     pub fn audio_set_muted(&mut self, m: bool) {
         self.audio.set_muted(m)
     }
+
+    /// Mutes or unmutes a single audio channel
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_set_channel_muted(&mut self, i: usize, m: bool) {
+        self.audio.set_channel_muted(i, m)
+    }
+
+    /// Checks whether a single audio channel is muted
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_channel_muted(&self, i: usize) -> bool {
+        self.audio.channel_muted(i)
+    }
+
+    /// Solos (or un-solos) a single audio channel
+    ///
+    /// While any channel is soloed, only soloed channels are audible.
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_set_channel_solo(&mut self, i: usize, s: bool) {
+        self.audio.set_channel_solo(i, s)
+    }
+
+    /// Checks whether a single audio channel is soloed
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_channel_solo(&self, i: usize) -> bool {
+        self.audio.channel_solo(i)
+    }
+
+    /// Sets the interpolation method used when playing samples
+    ///
+    /// Like the mixer flags, this is kept when the system is reset.
+    pub fn audio_set_interpolation(&mut self, i: audio::Interpolation) {
+        self.audio.set_interpolation(i)
+    }
+
+    /// Returns the interpolation method used when playing samples
+    pub fn audio_interpolation(&self) -> audio::Interpolation {
+        self.audio.interpolation()
+    }
+
+    /// Sets loop points and playback direction for a single audio channel
+    ///
+    /// The new settings apply from the channel's next note, and are kept when
+    /// the system is reset.
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_set_playback(&mut self, i: usize, p: audio::Playback) {
+        self.audio.set_playback(i, p)
+    }
+
+    /// Returns the loop points and playback direction for an audio channel
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_playback(&self, i: usize) -> audio::Playback {
+        self.audio.playback(i)
+    }
+
+    /// Checks whether an audio channel is audible
+    ///
+    /// A channel is audible if neither it nor the global output is muted, and
+    /// either it is soloed or no channel is soloed.
+    ///
+    /// # Panics
+    /// If `i` is not a valid channel index (0-3)
+    pub fn audio_channel_audible(&self, i: usize) -> bool {
+        self.audio.channel_audible(i)
+    }
 }
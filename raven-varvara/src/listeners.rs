@@ -0,0 +1,104 @@
+//! Callbacks which are invoked as devices produce output
+use crate::Varvara;
+
+type Callback<T> = Box<dyn FnMut(T) + Send>;
+type FrameCallback = Box<dyn FnMut((u16, u16), &[u8]) + Send>;
+
+/// Registered callbacks, grouped by kind
+#[derive(Default)]
+pub(crate) struct Listeners {
+    pub stdout: Vec<Callback<u8>>,
+    pub stderr: Vec<Callback<u8>>,
+    pub frame: Vec<FrameCallback>,
+    pub resize: Vec<Callback<(u16, u16)>>,
+    pub exit: Vec<Callback<i32>>,
+    pub note_end: Vec<Callback<usize>>,
+}
+
+impl Listeners {
+    /// Calls every callback in the list with the given value
+    pub fn call<T: Copy>(list: &mut [Callback<T>], v: T) {
+        for f in list {
+            f(v)
+        }
+    }
+}
+
+/// # Listeners
+///
+/// Listeners are an alternative to polling [`Varvara::output`]: each callback
+/// is invoked as soon as the corresponding activity happens, from within
+/// whichever method caused it (e.g. while a vector is running).  Console
+/// output which is sent to listeners is no longer accumulated for
+/// [`Varvara::output`], so that it doesn't pile up in hosts which never poll;
+/// other output is still reported there as usual.
+///
+/// Callbacks must not block for long, since the VM is paused while they run.
+/// Listeners persist across calls to [`Varvara::reset`], and are removed with
+/// [`Varvara::clear_listeners`].
+impl Varvara {
+    /// Registers a callback for each character written to `Console/write`
+    pub fn on_stdout<F: FnMut(u8) + Send + 'static>(&mut self, f: F) {
+        self.listeners.stdout.push(Box::new(f));
+        self.update_console_buffering();
+    }
+
+    /// Registers a callback for each character written to `Console/error`
+    pub fn on_stderr<F: FnMut(u8) + Send + 'static>(&mut self, f: F) {
+        self.listeners.stderr.push(Box::new(f));
+        self.update_console_buffering();
+    }
+
+    /// Registers a callback for each new frame
+    ///
+    /// The callback is invoked by [`Varvara::redraw`] when the screen contents
+    /// have changed, with the screen size and frame in the configured pixel
+    /// format (see [`Varvara::set_pixel_format`]).
+    pub fn on_frame<F: FnMut((u16, u16), &[u8]) + Send + 'static>(
+        &mut self,
+        f: F,
+    ) {
+        self.listeners.frame.push(Box::new(f));
+    }
+
+    /// Registers a callback for when the ROM resizes the screen
+    ///
+    /// The callback receives the new `(width, height)`.
+    pub fn on_resize<F: FnMut((u16, u16)) + Send + 'static>(&mut self, f: F) {
+        self.listeners.resize.push(Box::new(f));
+    }
+
+    /// Registers a callback for when the ROM requests an exit
+    ///
+    /// The callback receives the exit code.  The exit is still reported by
+    /// [`Output::exit`](crate::Output::exit), and the host is responsible for
+    /// stopping.
+    pub fn on_exit<F: FnMut(i32) + Send + 'static>(&mut self, f: F) {
+        self.listeners.exit.push(Box::new(f));
+    }
+
+    /// Registers a callback for when an audio channel finishes a note
+    ///
+    /// The callback receives the channel index (0-3), and is invoked from
+    /// [`Varvara::audio`] before the channel's vector is called.
+    pub fn on_audio_note_end<F: FnMut(usize) + Send + 'static>(
+        &mut self,
+        f: F,
+    ) {
+        self.listeners.note_end.push(Box::new(f));
+    }
+
+    /// Removes every registered listener
+    pub fn clear_listeners(&mut self) {
+        self.listeners = Listeners::default();
+        self.update_console_buffering();
+    }
+
+    /// Only buffers console streams which don't have listeners
+    fn update_console_buffering(&mut self) {
+        self.console.set_buffered(
+            self.listeners.stdout.is_empty(),
+            self.listeners.stderr.is_empty(),
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use crate::Event;
+use crate::{
+    ports::{port_names, PageNames},
+    Event,
+};
+use std::mem::offset_of;
 use uxn::{Ports, Uxn};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
 
@@ -20,42 +24,165 @@ impl Ports for MousePorts {
     const BASE: u8 = 0x90;
 }
 
-/// Stored mouse state
+impl MousePorts {
+    /// Addresses of the `scrollx` and `scrolly` ports
+    ///
+    /// These are reset to 0 after the mouse vector returns.
+    pub(crate) const SCROLL: [u8; 4] = {
+        let x = Self::BASE | offset_of!(Self, scroll_x) as u8;
+        let y = Self::BASE | offset_of!(Self, scroll_y) as u8;
+        [x, x + 1, y, y + 1]
+    };
+
+    pub(crate) const NAMES: PageNames = port_names!(Self, "Mouse", {
+        vector => "vector",
+        x => "x",
+        y => "y",
+        state => "state",
+        scroll_x => "scrollx",
+        scroll_y => "scrolly",
+    });
+}
+
+/// Number of pixels of scrolling which are treated as a single line
+///
+/// This is applied to [`MouseState::scroll`]; callers which know the platform's
+/// scroll units should normalize them and use [`MouseState::scroll_lines`]
+/// instead.
+pub const SCROLL_PIXELS_PER_LINE: f32 = 5.0;
+
+/// Mouse device
 #[derive(Default)]
-pub(crate) struct Mouse {
+pub struct Mouse {
     /// Current position
     pos: (f32, f32),
 
     /// Accumulated scroll values, used for fractional scrolling
     scroll: (f32, f32),
 
-    /// Bitfield of button state (bit 0: left, bit 1: middle, bit 2: right)
+    /// Bitfield of button state (see [`MouseState::buttons`])
     buttons: u8,
 
     /// Set as true when a mouse DEI / DEO operator is called
     active: bool,
+
+    /// Position is driven by relative motion, rather than absolute position
+    captured: bool,
+
+    /// Active touches, in the order that they started
+    touches: Vec<(u64, (f32, f32))>,
 }
 
 /// Update to mouse state
+///
+/// Build this with [`MouseState::new`] (or [`Default`]) and the `with_*`
+/// methods, since fields may be added in future releases.
 #[derive(Default, Debug)]
+#[non_exhaustive]
 pub struct MouseState {
     /// Current position
+    ///
+    /// This is ignored while the mouse is captured (see
+    /// [`Varvara::set_mouse_capture`](crate::Varvara::set_mouse_capture)).
     pub pos: (f32, f32),
 
-    /// Accumulated scroll values, used for fractional scrolling
+    /// Relative motion since the previous update, in screen pixels
+    ///
+    /// This is only used while the mouse is captured, and should be taken
+    /// from raw device motion (which isn't bounded by the window edges).
+    pub delta: (f32, f32),
+
+    /// Scroll amount in pixels
+    ///
+    /// This is divided by [`SCROLL_PIXELS_PER_LINE`] to get a number of lines
     pub scroll: (f32, f32),
 
-    /// Bitfield of button state (bit 0: left, bit 1: middle, bit 2: right)
+    /// Scroll amount in lines (i.e. mouse wheel notches)
+    ///
+    /// This is added to the normalized value of [`scroll`](Self::scroll);
+    /// fractional lines are accumulated until they add up to a full line.
+    pub scroll_lines: (f32, f32),
+
+    /// Bitfield of button state
+    ///
+    /// Bit 0 is the left button, bit 1 the middle, and bit 2 the right; bits
+    /// 3 and 4 are the extra "back" and "forward" buttons (buttons 4 and 5),
+    /// matching the reference emulator.  Chorded buttons set multiple bits.
     pub buttons: u8,
 }
 
+impl MouseState {
+    /// Button bits which are passed through to `Mouse/state`
+    pub const BUTTON_MASK: u8 = 0b11111;
+
+    /// Builds a state at the given position, with no motion or buttons
+    pub const fn new(pos: (f32, f32)) -> Self {
+        Self {
+            pos,
+            delta: (0.0, 0.0),
+            scroll: (0.0, 0.0),
+            scroll_lines: (0.0, 0.0),
+            buttons: 0,
+        }
+    }
+
+    /// Sets the relative motion (see [`delta`](Self::delta))
+    pub const fn with_delta(mut self, delta: (f32, f32)) -> Self {
+        self.delta = delta;
+        self
+    }
+
+    /// Sets the scroll amount in pixels (see [`scroll`](Self::scroll))
+    pub const fn with_scroll(mut self, scroll: (f32, f32)) -> Self {
+        self.scroll = scroll;
+        self
+    }
+
+    /// Sets the scroll amount in lines (see
+    /// [`scroll_lines`](Self::scroll_lines))
+    pub const fn with_scroll_lines(mut self, lines: (f32, f32)) -> Self {
+        self.scroll_lines = lines;
+        self
+    }
+
+    /// Sets the button bitfield (see [`buttons`](Self::buttons))
+    pub const fn with_buttons(mut self, buttons: u8) -> Self {
+        self.buttons = buttons;
+        self
+    }
+}
+
+/// Phase of a touch event
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TouchPhase {
+    /// A finger was placed on the surface
+    Start,
+    /// A finger moved
+    Move,
+    /// A finger was lifted from the surface
+    End,
+    /// The touch was interrupted by the platform
+    Cancel,
+}
+
+/// A single touch event, for [`Varvara::touch`](crate::Varvara::touch)
+#[derive(Copy, Clone, Debug)]
+pub struct Touch {
+    /// Identifier for the finger, which is stable from start to end
+    pub id: u64,
+    /// Phase of this event
+    pub phase: TouchPhase,
+    /// Position, in screen pixels
+    pub pos: (f32, f32),
+}
+
 impl Mouse {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Mouse::default()
     }
 
     /// Sets the active flag
-    pub fn set_active(&mut self) {
+    pub(crate) fn set_active(&mut self) {
         self.active = true
     }
 
@@ -64,55 +191,141 @@ impl Mouse {
         self.active
     }
 
-    /// Updates the internal mouse state, pushing an event if it has changed
-    pub fn update(&mut self, vm: &mut Uxn, state: MouseState) -> Option<Event> {
-        let mut changed = false;
-        let m = vm.dev_mut::<MousePorts>();
+    /// Checks whether the mouse is in relative-motion (captured) mode
+    pub fn captured(&self) -> bool {
+        self.captured
+    }
 
-        if state.pos != self.pos {
-            m.x.set(state.pos.0 as u16);
-            m.y.set(state.pos.1 as u16);
-            changed = true;
-            self.pos = state.pos;
-        }
+    /// Enters or leaves relative-motion mode
+    pub(crate) fn set_captured(&mut self, captured: bool) {
+        self.captured = captured;
+    }
 
-        self.scroll.0 += state.scroll.0 / 5.0;
-        self.scroll.1 += state.scroll.1 / 5.0;
+    /// Returns the number of touches currently in progress
+    pub fn touches(&self) -> usize {
+        self.touches.len()
+    }
 
-        // Send scrolls as one-tick updates on a per-frame basis
-        if self.scroll.0.abs() > 1.0 {
-            changed = true;
-            let amount = self.scroll.0.abs().min(i16::MAX as f32)
-                * self.scroll.0.signum();
-            m.scroll_x.set((amount as i16) as u16);
-            self.scroll.0 -= (amount as i16) as f32;
-        } else {
-            m.scroll_x.set(0);
+    /// Applies a touch event, returning the equivalent mouse state
+    ///
+    /// The first finger down drives the position.  One finger holds the left
+    /// button, two fingers the right button, and three or more the middle
+    /// button; lifting every finger releases all buttons.
+    pub(crate) fn touch(&mut self, t: Touch) -> MouseState {
+        let i = self.touches.iter().position(|(id, _)| *id == t.id);
+        match (t.phase, i) {
+            (TouchPhase::Start, None) => self.touches.push((t.id, t.pos)),
+            (TouchPhase::Start | TouchPhase::Move, Some(i)) => {
+                self.touches[i].1 = t.pos
+            }
+            (TouchPhase::End | TouchPhase::Cancel, Some(i)) => {
+                self.touches.remove(i);
+            }
+            (TouchPhase::Move | TouchPhase::End | TouchPhase::Cancel, None) => {
+                log::warn!("ignoring {:?} for unknown touch {}", t.phase, t.id)
+            }
         }
+        let buttons = match self.touches.len() {
+            0 => 0,
+            1 => 0b001,
+            2 => 0b100,
+            _ => 0b010,
+        };
+        let pos = self.touches.first().map(|t| t.1).unwrap_or(self.pos);
+        MouseState {
+            pos,
+            buttons,
+            ..MouseState::default()
+        }
+    }
 
-        if self.scroll.1.abs() > 1.0 {
-            changed = true;
-            let amount = self.scroll.1.abs().min(i16::MAX as f32)
-                * self.scroll.1.signum();
-            m.scroll_y.set((amount as i16) as u16);
-            self.scroll.1 -= (amount as i16) as f32;
+    /// Returns the most recent position, in screen pixels
+    pub fn position(&self) -> (f32, f32) {
+        self.pos
+    }
+
+    /// Returns the bitfield of held buttons
+    ///
+    /// See [`MouseState::buttons`] for the meaning of each bit.
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    /// Updates the internal mouse state, pushing an event if it has changed
+    pub(crate) fn update(
+        &mut self,
+        vm: &mut Uxn,
+        state: MouseState,
+    ) -> Option<Event> {
+        let mut changed = false;
+        let m = vm.dev_mut::<MousePorts>();
+
+        // In relative mode, the position wraps around at the edges of the
+        // 16-bit coordinate space, so ROMs can track unbounded motion by
+        // subtracting consecutive values.
+        let pos = if self.captured {
+            let (dx, dy) = state.delta;
+            if dx.is_finite() && dy.is_finite() {
+                let wrap = |p: f32| p.rem_euclid(65536.0);
+                (wrap(self.pos.0 + dx), wrap(self.pos.1 + dy))
+            } else {
+                self.pos
+            }
         } else {
-            m.scroll_y.set(0);
+            state.pos
+        };
+        if pos != self.pos {
+            m.x.set(pos.0 as u16);
+            m.y.set(pos.1 as u16);
+            changed = true;
+            self.pos = pos;
         }
 
-        if state.buttons != self.buttons {
-            m.state = state.buttons;
+        // Send scrolls as one-tick updates on a per-frame basis, keeping the
+        // fractional part for later
+        let dx = accumulate(
+            &mut self.scroll.0,
+            state.scroll.0,
+            state.scroll_lines.0,
+        );
+        let dy = accumulate(
+            &mut self.scroll.1,
+            state.scroll.1,
+            state.scroll_lines.1,
+        );
+        m.scroll_x.set(dx as u16);
+        m.scroll_y.set(dy as u16);
+        changed |= dx != 0 || dy != 0;
+
+        let buttons = state.buttons & MouseState::BUTTON_MASK;
+        if buttons != self.buttons {
+            m.state = buttons;
             changed = true;
-            self.buttons = state.buttons;
+            self.buttons = buttons;
         }
 
         if changed {
             Some(Event {
                 data: None,
                 vector: m.vector.get(),
+                device: MousePorts::BASE,
             })
         } else {
             None
         }
     }
 }
+
+/// Adds a scroll delta to an accumulator, returning the whole lines to send
+///
+/// Non-finite deltas are ignored, so that a single bad event from the host
+/// can't poison the accumulator.
+fn accumulate(acc: &mut f32, pixels: f32, lines: f32) -> i16 {
+    let delta = pixels / SCROLL_PIXELS_PER_LINE + lines;
+    if delta.is_finite() {
+        *acc += delta;
+    }
+    let amount = acc.trunc().clamp(-(i16::MAX as f32), i16::MAX as f32);
+    *acc -= amount;
+    amount as i16
+}
@@ -0,0 +1,99 @@
+//! Human-readable names for Varvara device ports
+use uxn::{Ports, Uxn, DEV_SIZE};
+
+use crate::{
+    audio::AudioPorts, console::ConsolePorts, controller::ControllerPorts,
+    datetime::DatetimePorts, file::FilePorts, mouse::MousePorts,
+    screen::ScreenPorts, system::SystemPorts,
+};
+
+/// Names for a single device page, indexed by offset within the page
+pub(crate) type PageNames = [Option<&'static str>; DEV_SIZE];
+
+/// Returns the size of a struct field, given an accessor function
+///
+/// The accessor is never called; it's only used for type inference.
+pub(crate) const fn field_size<T, F>(_f: fn(&T) -> &F) -> usize {
+    std::mem::size_of::<F>()
+}
+
+/// Builds a [`PageNames`] table from a `Ports` struct
+///
+/// Each field is given as `field => "name"`; multi-byte fields assign the same
+/// name to every byte that they cover.  This must be invoked in a module where
+/// the struct's fields are visible.
+macro_rules! port_names {
+    ($ty:ty, $dev:literal, { $($field:ident => $name:literal),* $(,)? }) => {{
+        let mut out: $crate::ports::PageNames = [None; uxn::DEV_SIZE];
+        $(
+            let start = std::mem::offset_of!($ty, $field);
+            let size = $crate::ports::field_size(|p: &$ty| &p.$field);
+            let mut i = 0;
+            while i < size {
+                out[start + i] = Some(concat!($dev, "/", $name));
+                i += 1;
+            }
+        )*
+        out
+    }};
+}
+pub(crate) use port_names;
+
+/// Every mapped device page, as `(base address, names)` tuples
+const PAGES: [(u8, &PageNames); 12] = [
+    (SystemPorts::BASE, &SystemPorts::NAMES),
+    (ConsolePorts::BASE, &ConsolePorts::NAMES),
+    (ScreenPorts::BASE, &ScreenPorts::NAMES),
+    (AudioPorts::BASE, &AudioPorts::NAMES[0]),
+    (AudioPorts::BASE + 0x10, &AudioPorts::NAMES[1]),
+    (AudioPorts::BASE + 0x20, &AudioPorts::NAMES[2]),
+    (AudioPorts::BASE + 0x30, &AudioPorts::NAMES[3]),
+    (ControllerPorts::BASE, &ControllerPorts::NAMES),
+    (MousePorts::BASE, &MousePorts::NAMES),
+    (FilePorts::BASE, &FilePorts::NAMES[0]),
+    (FilePorts::BASE + 0x10, &FilePorts::NAMES[1]),
+    (DatetimePorts::BASE, &DatetimePorts::NAMES),
+];
+
+/// Table of every port name, indexed by absolute address
+static NAMES: [Option<&str>; 256] = {
+    let mut out = [None; 256];
+    let mut p = 0;
+    while p < PAGES.len() {
+        let (base, names) = PAGES[p];
+        let mut i = 0;
+        while i < DEV_SIZE {
+            out[base as usize + i] = names[i];
+            i += 1;
+        }
+        p += 1;
+    }
+    out
+};
+
+/// Looks up the canonical name of a Varvara port, e.g. `"Screen/width"`
+///
+/// Both bytes of a short port share the same name.  Returns `None` for
+/// addresses which are unused or belong to unmapped devices.
+pub fn name_of(addr: u8) -> Option<&'static str> {
+    NAMES[usize::from(addr)]
+}
+
+/// Marks every Varvara device page as mapped in the VM
+///
+/// This lets generic tools enumerate device state with [`Uxn::ports_iter`].
+/// [`Varvara::load_rom`](crate::Varvara::load_rom) and
+/// [`Varvara::reset_from`](crate::Varvara::reset_from) do this for the
+/// system's enabled devices.
+pub fn map_pages(vm: &mut Uxn) {
+    map_pages_where(vm, |_| true)
+}
+
+/// Marks Varvara device pages as mapped, if they pass the given filter
+pub(crate) fn map_pages_where<F: Fn(u8) -> bool>(vm: &mut Uxn, f: F) {
+    for (base, _) in PAGES {
+        if f(base) {
+            vm.map_page(base);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Types needed to embed the Varvara system in an emulator front-end
+//!
+//! This also re-exports everything from the [`uxn` prelude](uxn::prelude), so
+//! a front-end usually needs a single glob import.
+//!
+//! ```
+//! use raven_varvara::prelude::*;
+//!
+//! // LIT "h LIT 18 DEO LIT "i LIT 18 DEO BRK
+//! let rom = [0x80, b'h', 0x80, 0x18, 0x17, 0x80, b'i', 0x80, 0x18, 0x17, 0x00];
+//!
+//! let mut ram = UxnRam::new();
+//! let mut vm = Uxn::builder(&mut ram)
+//!     .backend(Backend::Interpreter)
+//!     .build();
+//! let mut dev = Varvara::new();
+//! dev.load_rom(&mut vm, &rom);
+//! dev.init_args(&mut vm, &[]);
+//!
+//! vm.run(&mut dev, 0x100);
+//! let out = dev.output(&vm);
+//! assert_eq!(out.stdout, b"hi");
+//! assert_eq!(out.exit, None);
+//! ```
+//!
+//! Front-ends then forward input with methods like [`Varvara::char`],
+//! [`Varvara::pressed`], and [`Varvara::mouse`], call [`Varvara::redraw`]
+//! once per frame, and present each [`Output`].  For batch use without a GUI,
+//! see [`run_headless`].
+//!
+//! # Stability
+//! Everything re-exported here follows semantic versioning, with the same
+//! guarantees as the [`uxn` prelude](uxn::prelude): items will not be removed
+//! or renamed, and existing signatures will not change, without a major
+//! version bump.  New items may be added in minor releases.
+//!
+//! Structs which may gain fields (e.g. [`Output`], [`HeadlessLimits`], and
+//! [`MouseState`]) are marked `#[non_exhaustive]`; build them with their
+//! constructors or [`Default`] instead of struct literals.  Other structs
+//! with public fields (e.g. [`Region`]) are plain data, and their fields are
+//! part of the stable API.
+pub use uxn::prelude::*;
+
+pub use crate::{
+    keymap::KeyMap, run_headless, theme::Theme, AudioOutput, AudioSink, Event,
+    EventData, Frame, FrameTimer, GamepadState, HeadlessLimits, HeadlessResult,
+    Key, KeyRepeat, Layer, MouseState, Output, PixelFormat, Recorder, Region,
+    RepeatTiming, Resampler, StreamData, Touch, TouchPhase, Varvara,
+    VarvaraBuilder, AUDIO_CHANNELS, AUDIO_SAMPLE_RATE, CONTROLLER_PLAYERS,
+};
@@ -0,0 +1,262 @@
+//! Recording screen frames as animations
+use crate::Varvara;
+use std::io::Write;
+
+/// Frame rate of recordings, matching the usual rate of [`Varvara::redraw`]
+pub const RECORD_FPS: u16 = 60;
+
+/// Records screen frames, for saving animations
+///
+/// A recorder is armed with [`Varvara::start_recording`], then captures one
+/// frame per call to [`Varvara::redraw`] (which hosts call at 60 Hz) until
+/// it's disarmed with [`Varvara::stop_recording`].  Frames are captured with
+/// exact pixels, without the scaling or filtering of a screen recording.
+///
+/// Every frame in a recording has the size of the first frame; if the ROM
+/// resizes the screen, later frames are cropped or padded with color 0.
+///
+/// Call [`Recorder::finish`] once recording is done, to finish writing the
+/// file and check for errors.
+pub struct Recorder {
+    sink: Sink,
+    /// Size of the recording, set by the first frame
+    size: Option<(u16, u16)>,
+    /// Number of frames captured
+    frames: u64,
+    /// First write error, reported by [`Recorder::finish`]
+    error: Option<std::io::Error>,
+}
+
+enum Sink {
+    /// Uncompressed YUV frames, written as they're captured
+    Y4m(Box<dyn Write + Send>),
+    /// Animated PNG, written when the recording is finished
+    #[cfg(feature = "png")]
+    Apng(Box<dyn Write + Send>, Vec<ApngFrame>),
+}
+
+/// A single frame of an animated PNG, stored at 2 bits per pixel
+#[cfg(feature = "png")]
+struct ApngFrame {
+    indices: Vec<u8>,
+    colors: [u32; 4],
+    /// Number of captured frames for which this frame is shown
+    duration: u16,
+}
+
+impl Recorder {
+    /// Builds a recorder which writes uncompressed video in YUV4MPEG2 format
+    ///
+    /// Frames are written as they're captured (in 4:4:4 YUV), so the output
+    /// grows by `3 * width * height` bytes per frame; it's intended to be
+    /// converted with a separate encoder (e.g. `ffmpeg -i out.y4m ...`).
+    pub fn y4m<W: Write + Send + 'static>(out: W) -> Self {
+        Self::new(Sink::Y4m(Box::new(out)))
+    }
+
+    /// Builds a recorder which writes an animated PNG
+    ///
+    /// Frames are kept in memory (at 2 bits per pixel, with consecutive
+    /// identical frames merged) and written by [`Recorder::finish`].
+    #[cfg(feature = "png")]
+    pub fn apng<W: Write + Send + 'static>(out: W) -> Self {
+        Self::new(Sink::Apng(Box::new(out), vec![]))
+    }
+
+    fn new(sink: Sink) -> Self {
+        Self {
+            sink,
+            size: None,
+            frames: 0,
+            error: None,
+        }
+    }
+
+    /// Returns the number of frames captured so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Captures a frame, given the color index of each pixel
+    pub(crate) fn capture<I: Iterator<Item = u8>>(
+        &mut self,
+        size: (u16, u16),
+        pixels: I,
+        colors: [u32; 4],
+    ) {
+        let (w, h) = *self.size.get_or_insert(size);
+        self.frames += 1;
+        if self.error.is_some() {
+            return;
+        }
+
+        // Crop or pad the frame to the recording's size
+        let mut indices = vec![0u8; usize::from(w) * usize::from(h)];
+        if size.0 > 0 {
+            let rows = indices.chunks_mut(usize::from(w));
+            let src = pixels.collect::<Vec<u8>>();
+            for (row, src) in rows.zip(src.chunks(usize::from(size.0))) {
+                let n = row.len().min(src.len());
+                row[..n].copy_from_slice(&src[..n]);
+            }
+        }
+
+        match &mut self.sink {
+            Sink::Y4m(out) => {
+                let yuv = colors.map(yuv);
+                let mut data = Vec::with_capacity(indices.len() * 3 + 6);
+                if self.frames == 1 {
+                    let header = format!(
+                        "YUV4MPEG2 W{w} H{h} F{RECORD_FPS}:1 Ip A1:1 C444\n"
+                    );
+                    data.extend_from_slice(header.as_bytes());
+                }
+                data.extend_from_slice(b"FRAME\n");
+                for plane in 0..3 {
+                    let yuv = yuv.map(|c| c[plane]);
+                    data.extend(indices.iter().map(|&i| yuv[usize::from(i)]));
+                }
+                if let Err(e) = out.write_all(&data) {
+                    self.error.get_or_insert(e);
+                }
+            }
+            #[cfg(feature = "png")]
+            Sink::Apng(_, frames) => {
+                // Pack four pixels into each byte
+                let packed = indices
+                    .chunks(4)
+                    .map(|c| {
+                        c.iter()
+                            .enumerate()
+                            .fold(0, |acc, (i, &p)| acc | (p & 0b11) << (i * 2))
+                    })
+                    .collect::<Vec<u8>>();
+                match frames.last_mut() {
+                    Some(f)
+                        if f.indices == packed
+                            && f.colors == colors
+                            && f.duration < u16::MAX =>
+                    {
+                        f.duration += 1
+                    }
+                    _ => frames.push(ApngFrame {
+                        indices: packed,
+                        colors,
+                        duration: 1,
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Finishes writing the recording
+    ///
+    /// Returns the first error encountered while writing, if any.
+    pub fn finish(self) -> std::io::Result<()> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        match self.sink {
+            Sink::Y4m(mut out) => out.flush(),
+            #[cfg(feature = "png")]
+            Sink::Apng(out, frames) => {
+                let size = self.size.unwrap_or((0, 0));
+                write_apng(out, size, &frames).map_err(std::io::Error::other)
+            }
+        }
+    }
+}
+
+/// Converts a `0xAARRGGBB` color to `[Y, U, V]` values (BT.601, limited range)
+fn yuv(color: u32) -> [u8; 3] {
+    let [_a, r, g, b] = color.to_be_bytes().map(i32::from);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y, u, v].map(|c| c.clamp(0, 255) as u8)
+}
+
+/// Writes frames as an animated PNG with a shared 8-bit palette
+#[cfg(feature = "png")]
+fn write_apng(
+    out: Box<dyn Write + Send>,
+    (w, h): (u16, u16),
+    frames: &[ApngFrame],
+) -> Result<(), png::EncodingError> {
+    if frames.is_empty() || w == 0 || h == 0 {
+        log::warn!("no frames recorded; not writing animation");
+        return Ok(());
+    }
+
+    // Build a shared palette from every frame's colors
+    let mut palette: Vec<u32> = vec![];
+    let lookup = frames
+        .iter()
+        .map(|f| {
+            f.colors.map(|c| {
+                let c = c & 0xffffff;
+                match palette.iter().position(|p| *p == c) {
+                    Some(i) => i as u8,
+                    None if palette.len() < 256 => {
+                        palette.push(c);
+                        (palette.len() - 1) as u8
+                    }
+                    None => {
+                        log::warn!("too many colors; reusing color 0");
+                        0
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    let rgb = palette
+        .iter()
+        .flat_map(|c| {
+            let [_a, r, g, b] = c.to_be_bytes();
+            [r, g, b]
+        })
+        .collect::<Vec<u8>>();
+
+    let mut enc = png::Encoder::new(out, u32::from(w), u32::from(h));
+    enc.set_color(png::ColorType::Indexed);
+    enc.set_depth(png::BitDepth::Eight);
+    enc.set_palette(rgb);
+    enc.set_animated(frames.len() as u32, 0)?;
+    let mut writer = enc.write_header()?;
+    let n = usize::from(w) * usize::from(h);
+    for (f, lookup) in frames.iter().zip(&lookup) {
+        let data = f
+            .indices
+            .iter()
+            .flat_map(|b| (0..4).map(move |i| (b >> (i * 2)) & 0b11))
+            .take(n)
+            .map(|i| lookup[usize::from(i)])
+            .collect::<Vec<u8>>();
+        writer.set_frame_delay(f.duration, RECORD_FPS)?;
+        writer.write_image_data(&data)?;
+    }
+    writer.finish()
+}
+
+/// # Recording
+impl Varvara {
+    /// Starts recording screen frames
+    ///
+    /// Any recording in progress is replaced (and returned, so that it can
+    /// be finished).
+    pub fn start_recording(&mut self, rec: Recorder) -> Option<Recorder> {
+        self.recorder.replace(rec)
+    }
+
+    /// Stops recording, returning the recorder (if one was active)
+    ///
+    /// Call [`Recorder::finish`] on the result to finish writing the file.
+    pub fn stop_recording(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    /// Checks whether a recording is in progress
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+}
@@ -0,0 +1,262 @@
+//! ROM loading and metadata parsing
+//!
+//! By convention, a Varvara ROM begins by writing the address of a metadata
+//! block to `System/metadata`, i.e. `;meta .System/metadata DEO2`.  The block
+//! is a version byte, followed by null-terminated text whose lines are the
+//! ROM's name, a short description, its author, and a date.
+
+/// Address at which ROMs are loaded
+const ROM_START: u16 = 0x100;
+
+/// Metadata extracted from a ROM's header
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RomInfo<'a> {
+    /// Metadata format version
+    pub version: u8,
+
+    /// Full text of the metadata block
+    pub text: &'a str,
+
+    /// ROM name (the first line of text)
+    pub name: &'a str,
+
+    /// Description (the second line of text)
+    pub details: Option<&'a str>,
+
+    /// Author (the third line of text)
+    pub author: Option<&'a str>,
+
+    /// Date (the fourth line of text)
+    pub date: Option<&'a str>,
+}
+
+impl<'a> RomInfo<'a> {
+    /// Parses metadata from a ROM, which is expected to start at `0x100`
+    ///
+    /// Returns `None` if the ROM does not begin with the metadata header, or
+    /// if the metadata block is out of range or not valid UTF-8.
+    pub fn parse(rom: &'a [u8]) -> Option<Self> {
+        // LIT2 [addr] LIT 06 DEO2
+        let [0xa0, hi, lo, 0x80, 0x06, 0x37, ..] = *rom else {
+            return None;
+        };
+        let addr = u16::from_be_bytes([hi, lo]).checked_sub(ROM_START)?;
+        Self::parse_block(rom.get(usize::from(addr)..)?)
+    }
+
+    /// Parses a metadata block, i.e. a version byte followed by text
+    ///
+    /// The text ends at the first null byte (or the end of the slice).
+    /// Returns `None` if the block is empty or the text is not valid UTF-8.
+    pub fn parse_block(block: &'a [u8]) -> Option<Self> {
+        let (&version, block) = block.split_first()?;
+        let end = block.iter().position(|&c| c == 0).unwrap_or(block.len());
+        let text = std::str::from_utf8(&block[..end]).ok()?;
+
+        let mut lines = text.lines();
+        Some(Self {
+            version,
+            text,
+            name: lines.next().unwrap_or(""),
+            details: lines.next(),
+            author: lines.next(),
+            date: lines.next(),
+        })
+    }
+}
+
+/// Owned copy of a ROM's metadata
+///
+/// This is returned by [`Varvara::metadata`](crate::Varvara::metadata), and
+/// has the same fields as [`RomInfo`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RomMetadata {
+    /// Metadata format version
+    pub version: u8,
+
+    /// Full text of the metadata block
+    pub text: String,
+
+    /// ROM name (the first line of text)
+    pub name: String,
+
+    /// Description (the second line of text)
+    pub details: Option<String>,
+
+    /// Author (the third line of text)
+    pub author: Option<String>,
+
+    /// Date (the fourth line of text)
+    pub date: Option<String>,
+}
+
+impl From<RomInfo<'_>> for RomMetadata {
+    fn from(info: RomInfo) -> Self {
+        Self {
+            version: info.version,
+            text: info.text.to_owned(),
+            name: info.name.to_owned(),
+            details: info.details.map(str::to_owned),
+            author: info.author.map(str::to_owned),
+            date: info.date.map(str::to_owned),
+        }
+    }
+}
+
+/// Contents of a ROM file
+///
+/// This dereferences to the ROM's bytes, and can be shared (in an [`Arc`])
+/// with [`Varvara::reset_from`](crate::Varvara::reset_from), which copies
+/// expansion memory out of it lazily.
+///
+/// [`Arc`]: std::sync::Arc
+pub struct RomFile(RomData);
+
+enum RomData {
+    Owned(Vec<u8>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+}
+
+impl RomFile {
+    /// Opens a ROM file by memory-mapping it
+    ///
+    /// Pages of the file are only read from disk when they are first used, so
+    /// this is cheap even for multi-megabyte ROMs.  Empty files (which cannot
+    /// be mapped on every platform) are returned as an empty ROM.
+    ///
+    /// See [`RomFile::read`] for a safe alternative, which reads the whole
+    /// file into memory.
+    ///
+    /// # Safety
+    /// The file must not be modified (by this or any other process) while the
+    /// `RomFile` is alive.  If it is truncated, reading the missing pages
+    /// crashes the process; if it is rewritten, the ROM's bytes may change
+    /// underneath the VM.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub unsafe fn open<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> std::io::Result<Self> {
+        let f = std::fs::File::open(path)?;
+        if f.metadata()?.len() == 0 {
+            return Ok(Self(RomData::Owned(vec![])));
+        }
+        // SAFETY: the file is only read, and the caller promises not to
+        // modify it while it's mapped
+        let m = unsafe { memmap2::Mmap::map(&f)? };
+        Ok(Self(RomData::Mapped(m)))
+    }
+
+    /// Reads a ROM file into memory
+    pub fn read<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        std::fs::read(path).map(Self::from)
+    }
+}
+
+impl From<Vec<u8>> for RomFile {
+    fn from(data: Vec<u8>) -> Self {
+        Self(RomData::Owned(data))
+    }
+}
+
+impl std::ops::Deref for RomFile {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            RomData::Owned(v) => v,
+            #[cfg(not(target_arch = "wasm32"))]
+            RomData::Mapped(m) => m,
+        }
+    }
+}
+
+/// Labels from a symbol file, used to annotate addresses
+///
+/// Symbol files are written by the assembler next to the ROM (e.g.
+/// `orca.rom.sym`), and are a sequence of entries, each of which is a
+/// big-endian address followed by a null-terminated label.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols(Vec<(u16, String)>);
+
+impl Symbols {
+    /// Parses a symbol file
+    ///
+    /// A truncated entry at the end of the file is ignored.
+    pub fn parse(mut data: &[u8]) -> Self {
+        let mut out = vec![];
+        while let [hi, lo, rest @ ..] = data {
+            let Some(end) = rest.iter().position(|&c| c == 0) else {
+                break;
+            };
+            let name = String::from_utf8_lossy(&rest[..end]).into_owned();
+            out.push((u16::from_be_bytes([*hi, *lo]), name));
+            data = &rest[end + 1..];
+        }
+        out.sort_by_key(|(addr, _)| *addr);
+        Self(out)
+    }
+
+    /// Loads the symbol file for a ROM, i.e. `foo.rom.sym` for `foo.rom`
+    ///
+    /// Returns `Ok(None)` if there is no such file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_for<P: AsRef<std::path::Path>>(
+        rom: P,
+    ) -> std::io::Result<Option<Self>> {
+        let mut path = rom.as_ref().as_os_str().to_owned();
+        path.push(".sym");
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(Self::parse(&data))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Loads symbols from an explicit file, or else from the ROM's own
+    ///
+    /// With `path` set, the file must exist; otherwise this falls back to
+    /// [`Symbols::load_for`].  Errors name the file that failed to load.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(
+        path: Option<&std::path::Path>,
+        rom: &std::path::Path,
+    ) -> std::io::Result<Option<Self>> {
+        let with_path = |e: std::io::Error, p: &std::path::Path| {
+            std::io::Error::new(e.kind(), format!("{}: {e}", p.display()))
+        };
+        match path {
+            Some(path) => {
+                let data =
+                    std::fs::read(path).map_err(|e| with_path(e, path))?;
+                Ok(Some(Self::parse(&data)))
+            }
+            None => Self::load_for(rom).map_err(|e| with_path(e, rom)),
+        }
+    }
+
+    /// Returns the number of labels
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Checks whether there are no labels
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the label at exactly the given address
+    ///
+    /// If several labels share an address, the last one in the file is used.
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.nearest(addr)
+            .and_then(|(name, offset)| (offset == 0).then_some(name))
+    }
+
+    /// Returns the closest label at or before the given address, along with
+    /// the offset from that label
+    pub fn nearest(&self, addr: u16) -> Option<(&str, u16)> {
+        let i = self.0.partition_point(|(a, _)| *a <= addr);
+        let (a, name) = self.0.get(i.checked_sub(1)?)?;
+        Some((name, addr - a))
+    }
+}
@@ -1,4 +1,8 @@
-use crate::Event;
+use crate::{
+    ports::{port_names, PageNames},
+    Event,
+};
+use log::warn;
 use std::mem::offset_of;
 use uxn::{Ports, Uxn};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
@@ -32,6 +36,18 @@ impl ScreenPorts {
     const HEIGHT_W: u8 = Self::HEIGHT_R + 1;
     const PIXEL: u8 = Self::BASE | offset_of!(Self, pixel) as u8;
     const SPRITE: u8 = Self::BASE | offset_of!(Self, sprite) as u8;
+
+    pub(crate) const NAMES: PageNames = port_names!(Self, "Screen", {
+        vector => "vector",
+        width => "width",
+        height => "height",
+        auto => "auto",
+        x => "x",
+        y => "y",
+        addr => "addr",
+        pixel => "pixel",
+        sprite => "sprite",
+    });
 }
 
 #[derive(Copy, Clone, Default)]
@@ -48,10 +64,46 @@ impl ScreenPixel {
             self.bg
         }
     }
+
+    /// Sets the color in one layer, returning `true` if the visible color
+    /// has changed
+    #[inline]
+    fn set(&mut self, layer: Layer, color: u8) -> bool {
+        let prev = self.get();
+        match layer {
+            Layer::Foreground => self.fg = color,
+            Layer::Background => self.bg = color,
+        };
+        prev != self.get()
+    }
 }
 
-enum Layer {
+/// Spreads the bits of a byte into the bytes of a `u64`, stored so that
+/// `to_le_bytes` returns them in order from the most significant bit
+const EXPAND: [u64; 256] = {
+    let mut out = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut b = 0;
+        while b < 8 {
+            if i & (0x80 >> b) != 0 {
+                out[i] |= 1 << (b * 8);
+            }
+            b += 1;
+        }
+        i += 1;
+    }
+    out
+};
+
+/// One of the screen's two layers
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Layer {
+    /// Foreground layer, which is drawn over the background
+    ///
+    /// Color 0 is transparent in this layer.
     Foreground,
+    /// Background layer
     Background,
 }
 
@@ -127,28 +179,179 @@ impl Auto {
     }
 }
 
+/// Byte order of each pixel in a rendered frame
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum PixelFormat {
+    /// Blue, green, red, alpha
+    ///
+    /// This is the in-memory layout of `0xAARRGGBB` values on little-endian
+    /// systems, and matches many window systems' framebuffers.
+    #[default]
+    Bgra,
+    /// Red, green, blue, alpha
+    ///
+    /// This is the usual format for GPU textures and image files.
+    Rgba,
+    /// Alpha, red, green, blue
+    Argb,
+}
+
+impl PixelFormat {
+    /// Converts a `0xAARRGGBB` color into bytes in this format
+    pub fn bytes(self, color: u32) -> [u8; 4] {
+        let [a, r, g, b] = color.to_be_bytes();
+        match self {
+            PixelFormat::Bgra => [b, g, r, a],
+            PixelFormat::Rgba => [r, g, b, a],
+            PixelFormat::Argb => [a, r, g, b],
+        }
+    }
+
+    /// Converts bytes in this format into a `0xAARRGGBB` color
+    pub fn color(self, bytes: [u8; 4]) -> u32 {
+        let [a, r, g, b] = match self {
+            PixelFormat::Bgra => {
+                let [b, g, r, a] = bytes;
+                [a, r, g, b]
+            }
+            PixelFormat::Rgba => {
+                let [r, g, b, a] = bytes;
+                [a, r, g, b]
+            }
+            PixelFormat::Argb => bytes,
+        };
+        u32::from_be_bytes([a, r, g, b])
+    }
+}
+
+/// Rectangular region of the screen, in pixels
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    /// Left edge
+    pub x: u16,
+    /// Top edge
+    pub y: u16,
+    /// Width
+    pub w: u16,
+    /// Height
+    pub h: u16,
+}
+
+impl Region {
+    /// Returns the smallest region which contains both regions
+    pub fn union(self, other: Region) -> Region {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let x_end = (self.x + self.w).max(other.x + other.w);
+        let y_end = (self.y + self.h).max(other.y + other.h);
+        Region {
+            x,
+            y,
+            w: x_end - x,
+            h: y_end - y,
+        }
+    }
+}
+
+/// Bounding box of modified pixels, with exclusive upper bounds
+///
+/// The box is empty if `x_end <= x_start` (or likewise in `y`).
+#[derive(Copy, Clone)]
+struct Damage {
+    x_start: u16,
+    y_start: u16,
+    x_end: u16,
+    y_end: u16,
+}
+
+impl Damage {
+    const NONE: Self = Self {
+        x_start: u16::MAX,
+        y_start: u16::MAX,
+        x_end: 0,
+        y_end: 0,
+    };
+
+    /// Builds a box covering the entire screen
+    fn all(width: u16, height: u16) -> Self {
+        Self {
+            x_start: 0,
+            y_start: 0,
+            x_end: width,
+            y_end: height,
+        }
+    }
+
+    /// Expands the box to include the given pixel
+    #[inline]
+    fn mark(&mut self, x: u16, y: u16) {
+        self.x_start = self.x_start.min(x);
+        self.y_start = self.y_start.min(y);
+        self.x_end = self.x_end.max(x + 1);
+        self.y_end = self.y_end.max(y + 1);
+    }
+
+    /// Resets the box, returning its previous value (clipped to the screen)
+    fn take(&mut self, width: u16, height: u16) -> Option<Region> {
+        let d = std::mem::replace(self, Self::NONE);
+        let x_end = d.x_end.min(width);
+        let y_end = d.y_end.min(height);
+        (d.x_start < x_end && d.y_start < y_end).then(|| Region {
+            x: d.x_start,
+            y: d.y_start,
+            w: x_end - d.x_start,
+            h: y_end - d.y_start,
+        })
+    }
+}
+
+/// Largest screen width or height that a ROM can request, in pixels
+pub const MAX_SIZE: u16 = 4096;
+
+/// Smallest screen width or height that a ROM can request, in pixels
+pub const MIN_SIZE: u16 = 8;
+
+/// Screen device, which draws into an internal frame buffer
 pub struct Screen {
     /// Screen buffer
     pixels: Vec<ScreenPixel>,
 
-    /// Local buffer for rendered RGBA values
+    /// Local buffer for the rendered frame, in the configured pixel format
+    /// (see `Varvara::set_pixel_format`)
     buffer: Vec<u8>,
 
     width: u16,
     height: u16,
 
-    /// Flag indicating whether `buffer` should be recalculated
-    changed: bool,
+    /// Pixels which have changed since `buffer` was last rendered
+    damage: Damage,
+
+    /// Region of `buffer` which has been re-rendered since the last call to
+    /// [`take_region`](Self::take_region)
+    rendered: Option<Region>,
+
+    /// Flag indicating whether the screen has changed since the last redraw
+    dirty: bool,
+
+    /// Flag indicating whether the screen has been resized since the last
+    /// call to [`take_resized`](Self::take_resized)
+    resized: bool,
+
+    /// Palette at the time of the last redraw
+    redraw_colors: [u32; 4],
 
     /// Color palette
     colors: [u32; 4],
+
+    /// Byte order of rendered pixels
+    format: PixelFormat,
 }
 
 impl Screen {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         const WIDTH: u16 = 512;
         const HEIGHT: u16 = 320;
-        let size = WIDTH as usize * WIDTH as usize;
+        let size = WIDTH as usize * HEIGHT as usize;
         let buffer = vec![0; size * 4];
         let pixels = vec![ScreenPixel::default(); size];
         Self {
@@ -156,22 +359,64 @@ impl Screen {
             pixels,
             width: WIDTH,
             height: HEIGHT,
-            changed: true,
+            damage: Damage::all(WIDTH, HEIGHT),
+            rendered: None,
+            dirty: true,
+            resized: true,
+            redraw_colors: [0; 4],
             colors: [0; 4],
+            format: PixelFormat::default(),
+        }
+    }
+
+    /// Builds a screen with a size of 0×0, which allocates nothing
+    pub(crate) fn empty() -> Self {
+        Self {
+            buffer: vec![],
+            pixels: vec![],
+            width: 0,
+            height: 0,
+            damage: Damage::NONE,
+            rendered: None,
+            dirty: true,
+            resized: false,
+            redraw_colors: [0; 4],
+            colors: [0; 4],
+            format: PixelFormat::default(),
         }
     }
 
     /// Resizes our internal buffers to the new width and height
+    ///
+    /// As in the reference implementation, the screen is cleared when its
+    /// size changes, and sizes outside of [`MIN_SIZE`]..=[`MAX_SIZE`] are
+    /// ignored.
     fn resize(&mut self, width: u16, height: u16) {
         if width == self.width && height == self.height {
             return;
         }
+        if !(MIN_SIZE..=MAX_SIZE).contains(&width)
+            || !(MIN_SIZE..=MAX_SIZE).contains(&height)
+        {
+            warn!("ignoring invalid screen size {width}x{height}");
+            return;
+        }
         self.width = width;
         self.height = height;
 
         let size = self.width as usize * self.height as usize;
-        self.pixels.resize(size, ScreenPixel::default());
-        self.buffer.resize(size * 4, 0u8);
+        self.pixels = vec![ScreenPixel::default(); size];
+        self.buffer = vec![0u8; size * 4];
+        self.damage = Damage::all(width, height);
+        // The old rendered region may lie outside the new buffer, and the
+        // damage above already covers the whole screen
+        self.rendered = None;
+        self.resized = true;
+    }
+
+    /// Checks whether the screen has been resized since the last call
+    pub(crate) fn take_resized(&mut self) -> bool {
+        std::mem::take(&mut self.resized)
     }
 
     /// Returns the current size as a `(width, height)` tuple
@@ -179,23 +424,133 @@ impl Screen {
         (self.width, self.height)
     }
 
-    /// Gets the current frame, returning a `(buffer, width, height)` tuple
-    pub fn frame(&mut self, vm: &Uxn) -> &[u8] {
+    /// Returns the color index (0-3) of each pixel in a single layer
+    ///
+    /// Pixels are in row-major order, starting at the top left.
+    pub fn layer(
+        &self,
+        layer: Layer,
+    ) -> impl ExactSizeIterator<Item = u8> + '_ {
+        let n = usize::from(self.width) * usize::from(self.height);
+        self.pixels[..n].iter().map(move |p| match layer {
+            Layer::Foreground => p.fg,
+            Layer::Background => p.bg,
+        })
+    }
+
+    /// Returns the color index (0-3) of each visible pixel, in row-major order
+    pub(crate) fn indices(&self) -> impl Iterator<Item = u8> + '_ {
+        let n = usize::from(self.width) * usize::from(self.height);
+        self.pixels[..n].iter().map(|p| p.get() & 0b11)
+    }
+
+    /// Renders the whole screen as RGBA values, ignoring the pixel format
+    #[cfg(feature = "png")]
+    pub(crate) fn rgba(&self, colors: [u32; 4]) -> Vec<u8> {
+        let bytes = colors.map(|c| PixelFormat::Rgba.bytes(c));
+        let n = usize::from(self.width) * usize::from(self.height);
+        self.pixels[..n]
+            .iter()
+            .flat_map(|p| bytes[usize::from(p.get() & 0b11)])
+            .collect()
+    }
+
+    /// Renders a single layer with the given colors
+    ///
+    /// Transparent pixels in the foreground are left as zeros.
+    pub(crate) fn render_layer(
+        &self,
+        colors: [u32; 4],
+        layer: Layer,
+    ) -> Vec<u8> {
+        let mut bytes = colors.map(|c| self.format.bytes(c));
+        if layer == Layer::Foreground {
+            bytes[0] = [0; 4];
+        }
+        self.layer(layer)
+            .flat_map(|c| bytes[usize::from(c & 0b11)])
+            .collect()
+    }
+
+    /// Gets the current frame, rendered with the given colors
+    pub(crate) fn frame(&mut self, colors: [u32; 4]) -> &[u8] {
         let prev_colors = self.colors;
-        let sys = vm.dev::<crate::system::SystemPorts>();
-        self.colors = [0, 1, 2, 3].map(|i| sys.color(i));
-        self.changed |= prev_colors != self.colors;
-
-        if std::mem::take(&mut self.changed) {
-            for (p, o) in self.pixels.iter().zip(self.buffer.chunks_mut(4)) {
-                o.copy_from_slice(
-                    &self.colors[(p.get() & 0b11) as usize].to_le_bytes(),
-                );
+        self.colors = colors;
+        if prev_colors != self.colors {
+            self.damage = Damage::all(self.width, self.height);
+        }
+
+        if let Some(r) = self.damage.take(self.width, self.height) {
+            let bytes = self.colors.map(|c| self.format.bytes(c));
+            let width = usize::from(self.width);
+            let (x, w) = (usize::from(r.x), usize::from(r.w));
+            for y in usize::from(r.y)..usize::from(r.y + r.h) {
+                let start = y * width + x;
+                let src = &self.pixels[start..][..w];
+                let dst = &mut self.buffer[start * 4..][..w * 4];
+                for (p, o) in src.iter().zip(dst.chunks_mut(4)) {
+                    o.copy_from_slice(&bytes[(p.get() & 0b11) as usize]);
+                }
             }
+            self.rendered = Some(match self.rendered {
+                Some(prev) => prev.union(r),
+                None => r,
+            });
+        }
+        &self.buffer
+    }
+
+    /// Sets the byte order of rendered pixels
+    pub(crate) fn set_format(&mut self, format: PixelFormat) {
+        if format != self.format {
+            self.format = format;
+            self.damage = Damage::all(self.width, self.height);
         }
+    }
+
+    /// Returns the frame as of the most recent call to [`frame`](Self::frame)
+    pub(crate) fn buffer(&self) -> &[u8] {
         &self.buffer
     }
 
+    /// Returns the region re-rendered by [`frame`](Self::frame) since the
+    /// previous call, then resets it
+    pub(crate) fn take_region(&mut self) -> Option<Region> {
+        self.rendered.take()
+    }
+
+    /// Renders a rectangular region of the screen with the given colors
+    ///
+    /// The result is `w * h * 4` bytes, in the same format as
+    /// [`frame`](Self::frame); pixels outside the screen are left as zeros.
+    pub(crate) fn copy_region(
+        &self,
+        colors: [u32; 4],
+        x: u16,
+        y: u16,
+        w: u16,
+        h: u16,
+    ) -> Vec<u8> {
+        let mut out = vec![0u8; usize::from(w) * usize::from(h) * 4];
+        let x_end = x.saturating_add(w).min(self.width);
+        let y_end = y.saturating_add(h).min(self.height);
+        if x >= x_end {
+            return out;
+        }
+        let bytes = colors.map(|c| self.format.bytes(c));
+        let width = usize::from(self.width);
+        let cols = usize::from(x_end - x);
+        for (row, py) in (y..y_end).enumerate() {
+            let start = usize::from(py) * width + usize::from(x);
+            let src = &self.pixels[start..][..cols];
+            let dst = &mut out[row * usize::from(w) * 4..][..cols * 4];
+            for (p, o) in src.iter().zip(dst.chunks_mut(4)) {
+                o.copy_from_slice(&bytes[(p.get() & 0b11) as usize]);
+            }
+        }
+        out
+    }
+
     fn set_pixel(&mut self, layer: Layer, x: u16, y: u16, color: u8) {
         if x >= self.width || y >= self.height {
             return;
@@ -203,10 +558,46 @@ impl Screen {
         let i = x as usize + y as usize * self.width as usize;
         // This should always be true, but we check to avoid a panic site
         if let Some(o) = self.pixels.get_mut(i) {
-            match layer {
-                Layer::Foreground => o.fg = color,
-                Layer::Background => o.bg = color,
-            };
+            if o.set(layer, color) {
+                self.damage.mark(x, y);
+            }
+        }
+    }
+
+    /// Draws one row of a sprite, starting at `(x, y)`
+    ///
+    /// `row` contains the 2-bit value of each pixel from left to right, and
+    /// `colors` maps each value to a color (or `None` if it is transparent).
+    fn blit_row(
+        &mut self,
+        layer: Layer,
+        x: u16,
+        y: u16,
+        row: [u8; 8],
+        colors: &[Option<u8>; 4],
+    ) {
+        if y >= self.height {
+            return;
+        }
+        if usize::from(x) + 8 > usize::from(self.width) {
+            // Slow path for rows which are clipped or wrap around
+            for (dx, d) in row.into_iter().enumerate() {
+                if let Some(c) = colors[usize::from(d)] {
+                    self.set_pixel(layer, x.wrapping_add(dx as u16), y, c);
+                }
+            }
+            return;
+        }
+        let i = usize::from(y) * usize::from(self.width) + usize::from(x);
+        let Some(pixels) = self.pixels.get_mut(i..i + 8) else {
+            return;
+        };
+        for (dx, (p, d)) in pixels.iter_mut().zip(row).enumerate() {
+            if let Some(c) = colors[usize::from(d)] {
+                if p.set(layer, c) {
+                    self.damage.mark(x + dx as u16, y);
+                }
+            }
         }
     }
 
@@ -255,6 +646,10 @@ impl Screen {
         ];
 
         let auto = v.auto;
+        let color = usize::from(s.color());
+        let colors: [Option<u8>; 4] = std::array::from_fn(|d| {
+            (d != 0 || OPAQUE[color]).then_some(BLENDING[d][color])
+        });
 
         // XXX THIS IS NOT A PLACE OF HONOR
         //
@@ -277,25 +672,12 @@ impl Screen {
                 addr = addr.wrapping_add(1);
 
                 let y = y.wrapping_add(if s.flip_y() { 7 - dy } else { dy });
-                if y >= self.height {
-                    continue;
-                }
-                for dx in 0..8 {
-                    let x =
-                        x.wrapping_add(if s.flip_x() { 7 - dx } else { dx });
-                    if x >= self.width {
-                        continue;
-                    }
-
-                    let lo_bit = (lo >> (7 - dx)) & 0b1;
-                    let hi_bit = (hi >> (7 - dx)) & 0b1; // 0 if !two_bpp
-                    let data = (lo_bit | (hi_bit << 1)) as usize;
-                    let color = s.color() as usize;
-                    if data != 0 || OPAQUE[color] {
-                        let c = BLENDING[data][color];
-                        self.set_pixel(s.layer(), x, y, c);
-                    }
-                }
+
+                // Expand the whole row at once (hi is 0 if !two_bpp)
+                let row =
+                    EXPAND[usize::from(lo)] | EXPAND[usize::from(hi)] << 1;
+                let row = if s.flip_x() { row.swap_bytes() } else { row };
+                self.blit_row(s.layer(), x, y, row.to_le_bytes(), &colors);
             }
             // Update position within the loop.  Note that we don't update the
             // ports here; they're updated outside the loop below.
@@ -343,9 +725,9 @@ impl Screen {
     }
 
     /// Executes a DEO command against the screen
-    pub fn deo(&mut self, vm: &mut Uxn, target: u8) {
+    pub(crate) fn deo(&mut self, vm: &mut Uxn, target: u8) {
         let v = vm.dev::<ScreenPorts>();
-        self.changed = true;
+        self.dirty = true;
         match target {
             ScreenPorts::WIDTH_W => {
                 let new_width = v.width.get();
@@ -366,7 +748,7 @@ impl Screen {
     }
 
     /// Executes a DEI command against the screen
-    pub fn dei(&mut self, vm: &mut Uxn, target: u8) {
+    pub(crate) fn dei(&mut self, vm: &mut Uxn, target: u8) {
         let v = vm.dev_mut::<ScreenPorts>();
         match target {
             ScreenPorts::WIDTH_R => {
@@ -379,10 +761,23 @@ impl Screen {
         }
     }
 
+    /// Checks whether the screen has changed since the last call
+    ///
+    /// This includes both writes to the screen device and changes to the
+    /// palette.
+    pub(crate) fn take_dirty(&mut self, colors: [u32; 4]) -> bool {
+        let prev_colors = std::mem::replace(&mut self.redraw_colors, colors);
+        std::mem::take(&mut self.dirty) || prev_colors != colors
+    }
+
     /// Called on screen update; returns the screen vector
-    pub fn update(&mut self, vm: &mut Uxn) -> Event {
+    pub(crate) fn update(&mut self, vm: &mut Uxn) -> Event {
         // Nothing to do here, but return the screen vector
         let vector = vm.dev::<ScreenPorts>().vector.get();
-        Event { data: None, vector }
+        Event {
+            data: None,
+            vector,
+            device: ScreenPorts::BASE,
+        }
     }
 }
@@ -0,0 +1,53 @@
+//! Screenshots, encoded as PNG images
+use crate::{Output, PixelFormat, Varvara};
+use image::{codecs::png::PngEncoder, ExtendedColorType, ImageEncoder};
+use log::warn;
+use uxn::Uxn;
+
+/// Encodes RGBA pixels as a PNG image
+///
+/// Returns an empty vector if encoding fails (e.g. for a 0×0 screen).
+fn encode_png((width, height): (u16, u16), rgba: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let r = PngEncoder::new(&mut out).write_image(
+        rgba,
+        u32::from(width),
+        u32::from(height),
+        ExtendedColorType::Rgba8,
+    );
+    if let Err(e) = r {
+        warn!("could not encode screenshot: {e}");
+        out.clear();
+    }
+    out
+}
+
+impl Varvara {
+    /// Renders the screen as a PNG image
+    ///
+    /// The image uses the current palette, and is empty if the screen is
+    /// disabled.
+    pub fn screenshot(&self, vm: &Uxn) -> Vec<u8> {
+        let size = self.screen.size();
+        encode_png(size, &self.screen.rgba(self.system.colors(vm)))
+    }
+}
+
+impl Output<'_> {
+    /// Encodes [`frame`](Self::frame) as a PNG image
+    ///
+    /// This is equivalent to [`Varvara::screenshot`], for hosts which only
+    /// keep the most recent output.
+    pub fn screenshot(&self) -> Vec<u8> {
+        let (w, h) = self.size;
+        let n = usize::from(w) * usize::from(h) * 4;
+        let rgba: Vec<u8> = self.frame[..n]
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let c = self.pixel_format.color(p.try_into().unwrap());
+                PixelFormat::Rgba.bytes(c)
+            })
+            .collect();
+        encode_png(self.size, &rgba)
+    }
+}
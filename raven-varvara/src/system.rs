@@ -1,11 +1,33 @@
+use crate::{
+    ports::{port_names, PageNames},
+    rom::{RomFile, RomInfo, RomMetadata, Symbols},
+};
 use log::warn;
-use std::mem::offset_of;
+use std::{fmt::Write, mem::offset_of, sync::Arc};
 use uxn::{Ports, Uxn};
 use zerocopy::{AsBytes, BigEndian, FromBytes, FromZeroes, U16};
 
+/// System device, which owns expansion memory and the palette
 pub struct System {
     exit: Option<i32>,
-    banks: [Box<[u8; 65536]>; 15],
+
+    /// Expansion memory, allocated when first used
+    banks: [Option<Box<[u8; 65536]>>; 15],
+
+    /// ROM file and offset from which unallocated banks are loaded
+    source: Option<(Arc<RomFile>, usize)>,
+
+    /// Host-provided palette, used until the ROM writes the color registers
+    default_palette: [u16; 3],
+
+    /// Bitfield of color register bytes which the ROM has written
+    palette_written: u8,
+
+    /// Metadata published by the ROM through `System/metadata`
+    metadata: Option<RomMetadata>,
+
+    /// Labels used to annotate the debug output
+    symbols: Option<Symbols>,
 }
 
 impl Default for System {
@@ -57,44 +79,132 @@ impl SystemPorts {
     const EXPANSION: u8 = (offset_of!(Self, expansion) + 1) as u8;
     const WST: u8 = offset_of!(Self, wst) as u8;
     const RST: u8 = offset_of!(Self, rst) as u8;
+    const METADATA: u8 = (offset_of!(Self, metadata) + 1) as u8;
+    const RED: u8 = offset_of!(Self, red) as u8;
+    const BLUE_L: u8 = offset_of!(Self, blue) as u8 + 1;
     const DEBUG: u8 = offset_of!(Self, debug) as u8;
     const STATE: u8 = offset_of!(Self, state) as u8;
 
-    /// Looks up the color for the given index
-    pub fn color(&self, i: u8) -> u32 {
-        let i = 3 - i;
-        let r = u32::from(self.red.get() >> (i * 4)) & 0xF;
-        let g = u32::from(self.green.get() >> (i * 4)) & 0xF;
-        let b = u32::from(self.blue.get() >> (i * 4)) & 0xF;
-        let color = 0x0F000000 | (r << 16) | (g << 8) | b;
-        color | (color << 4)
-    }
+    pub(crate) const NAMES: PageNames = port_names!(Self, "System", {
+        expansion => "expansion",
+        wst => "wst",
+        rst => "rst",
+        metadata => "metadata",
+        red => "r",
+        green => "g",
+        blue => "b",
+        debug => "debug",
+        state => "state",
+    });
+}
+
+/// Looks up the color for the given index, given `[r, g, b]` registers
+fn color(palette: [u16; 3], i: u8) -> u32 {
+    let i = 3 - i;
+    let [r, g, b] = palette.map(|c| u32::from(c >> (i * 4)) & 0xF);
+    let color = 0x0F000000 | (r << 16) | (g << 8) | b;
+    color | (color << 4)
 }
 
 mod expansion {
     pub const FILL: u8 = 0x00;
     pub const CPYL: u8 = 0x01;
     pub const CPYR: u8 = 0x02;
+
+    /// Number of banks, including main memory (bank 0)
+    pub const BANKS: u16 = 16;
 }
 
 impl System {
-    pub fn new() -> Self {
-        let banks = [(); 15].map(|_| Box::new([0u8; 65536]));
-        Self { banks, exit: None }
+    pub(crate) fn new() -> Self {
+        Self {
+            banks: Default::default(),
+            source: None,
+            exit: None,
+            default_palette: [0; 3],
+            palette_written: 0,
+            metadata: None,
+            symbols: None,
+        }
+    }
+
+    /// Sets the palette used before the ROM writes the color registers
+    ///
+    /// Each color register is treated as two independent bytes, so a ROM
+    /// which only writes one byte of a register sees the host's value in the
+    /// other byte.
+    pub(crate) fn set_default_palette(&mut self, rgb: [u16; 3]) {
+        self.default_palette = rgb;
+    }
+
+    /// Returns the effective `[r, g, b]` color registers
+    ///
+    /// Bytes which the ROM has not written use the host's default palette.
+    pub fn palette(&self, vm: &Uxn) -> [u16; 3] {
+        let v = vm.dev::<SystemPorts>().as_bytes();
+        let mut out = [0u16; 3];
+        for (i, c) in out.iter_mut().enumerate() {
+            let [hi, lo] = [0, 1].map(|j| {
+                let k = i * 2 + j;
+                if self.palette_written & (1 << k) != 0 {
+                    v[usize::from(SystemPorts::RED) + k]
+                } else {
+                    self.default_palette[i].to_be_bytes()[j]
+                }
+            });
+            *c = u16::from_be_bytes([hi, lo]);
+        }
+        out
+    }
+
+    /// Returns the effective colors, as `0xAARRGGBB` values
+    pub fn colors(&self, vm: &Uxn) -> [u32; 4] {
+        let palette = self.palette(vm);
+        [0, 1, 2, 3].map(|i| color(palette, i))
     }
 
     /// Resets the peripheral, loading the given data into expansion memory
-    pub fn reset(&mut self, mut mem: &[u8]) {
+    pub(crate) fn reset(&mut self, mut mem: &[u8]) {
         for b in &mut self.banks {
-            let n = mem.len().min(b.len());
-            b[..n].copy_from_slice(&mem[..n]);
+            let n = mem.len().min(65536);
+            *b = (n > 0).then(|| {
+                let mut bank = Box::new([0u8; 65536]);
+                bank[..n].copy_from_slice(&mem[..n]);
+                bank
+            });
             mem = &mem[n..];
-            b[n..].fill(0u8);
         }
+        self.source = None;
         self.exit = None;
+        self.palette_written = 0;
+        self.metadata = None;
+        self.symbols = None;
     }
 
-    pub fn deo(&mut self, vm: &mut Uxn, target: u8) {
+    /// Resets the peripheral, loading expansion memory from a ROM on demand
+    ///
+    /// Expansion memory begins at `offset` within the ROM; each bank is copied
+    /// when it is first accessed.
+    pub(crate) fn reset_from(&mut self, rom: Arc<RomFile>, offset: usize) {
+        self.reset(&[]);
+        self.source = Some((rom, offset));
+    }
+
+    /// Returns the given expansion bank (0-14), loading it if necessary
+    fn bank(&mut self, b: usize) -> &mut [u8; 65536] {
+        let source = &self.source;
+        self.banks[b].get_or_insert_with(|| {
+            let mut bank = Box::new([0u8; 65536]);
+            if let Some((rom, offset)) = source {
+                let data = rom.get(offset + b * 65536..).unwrap_or(&[]);
+                let n = data.len().min(65536);
+                bank[..n].copy_from_slice(&data[..n]);
+            }
+            bank
+        })
+    }
+
+    pub(crate) fn deo(&mut self, vm: &mut Uxn, target: u8) {
         let v = vm.dev::<SystemPorts>();
         match target {
             SystemPorts::EXPANSION => {
@@ -109,13 +219,17 @@ impl System {
                             );
                         }
                         let bank = f.bank.get();
+                        if bank >= expansion::BANKS {
+                            warn!("invalid expansion bank {bank}");
+                            return;
+                        }
                         let addr = f.addr.get();
                         for i in 0..f.length.get() {
                             let j = addr.wrapping_add(i);
                             match usize::from(bank).checked_sub(1) {
                                 None => vm.ram_write_byte(j, f.value),
                                 Some(b) => {
-                                    self.banks[b][usize::from(j)] = f.value
+                                    self.bank(b)[usize::from(j)] = f.value
                                 }
                             }
                         }
@@ -137,6 +251,12 @@ impl System {
                                     .wrapping_sub(i)
                             }
                         };
+                        for bank in [c.src_bank.get(), c.dst_bank.get()] {
+                            if bank >= expansion::BANKS {
+                                warn!("invalid expansion bank {bank}");
+                                return;
+                            }
+                        }
 
                         for i in 0..c.length.get() {
                             let src_addr = offset(i, c.src_addr);
@@ -144,14 +264,14 @@ impl System {
                                 .checked_sub(1)
                             {
                                 None => vm.ram_read_byte(src_addr),
-                                Some(b) => self.banks[b][usize::from(src_addr)],
+                                Some(b) => self.bank(b)[usize::from(src_addr)],
                             };
 
                             let dst_addr = offset(i, c.dst_addr);
                             match usize::from(c.dst_bank.get()).checked_sub(1) {
                                 None => vm.ram_write_byte(dst_addr, v),
                                 Some(b) => {
-                                    self.banks[b][usize::from(dst_addr)] = v
+                                    self.bank(b)[usize::from(dst_addr)] = v
                                 }
                             }
                         }
@@ -168,50 +288,122 @@ impl System {
                 vm.ret_mut().set_len(rst)
             }
             SystemPorts::DEBUG => {
-                for (name, st) in [("WST", vm.stack()), ("RST", vm.ret())] {
-                    print!("{name} ");
-                    let n = st.len();
-                    for i in (0..8).rev() {
-                        print!("{:02x}", st.peek_byte_at(i));
-                        if i == n {
-                            print!("|")
-                        } else {
-                            print!(" ");
-                        }
-                    }
-                    println!("<");
-                }
+                // This goes to stderr, so that it isn't mixed into the
+                // console's output (which may be piped elsewhere)
+                eprint!("{}", self.debug_dump(vm));
             }
-            SystemPorts::STATE => {
-                if v.state != 0 {
-                    self.exit = Some((v.state & !0x80) as i32);
+            SystemPorts::METADATA => {
+                let addr = usize::from(v.metadata.get());
+                self.metadata =
+                    RomInfo::parse_block(&vm.ram()[addr..]).map(Into::into);
+                if self.metadata.is_none() {
+                    warn!("invalid metadata block at {addr:#06x}");
                 }
             }
+            SystemPorts::STATE if v.state != 0 => {
+                self.exit = Some((v.state & !0x80) as i32);
+            }
+            SystemPorts::RED..=SystemPorts::BLUE_L => {
+                self.palette_written |= 1 << (target - SystemPorts::RED);
+            }
             _ => (),
         }
     }
 
-    pub fn dei(&mut self, vm: &mut Uxn, target: u8) {
+    pub(crate) fn dei(&mut self, vm: &mut Uxn, target: u8) {
         match target & 0x0F {
             SystemPorts::WST => {
                 let wst = vm.stack().len();
                 vm.dev_mut::<SystemPorts>().wst = wst;
             }
             SystemPorts::RST => {
-                let rst = vm.stack().len();
+                let rst = vm.ret().len();
                 vm.dev_mut::<SystemPorts>().rst = rst;
             }
+            t @ SystemPorts::RED..=SystemPorts::BLUE_L => {
+                let i = usize::from(t - SystemPorts::RED);
+                let [r, g, b] = self.palette(vm);
+                let bytes = [r, g, b].map(u16::to_be_bytes);
+                vm.dev_mut::<SystemPorts>().as_bytes_mut()[usize::from(t)] =
+                    bytes[i / 2][i % 2];
+            }
             _ => (),
         }
     }
 
     /// Returns `true` if the exit flag is set
-    pub fn should_exit(&self) -> bool {
+    pub(crate) fn should_exit(&self) -> bool {
         self.exit.is_some()
     }
 
+    /// Sets the labels used to annotate the debug output
+    pub(crate) fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    /// Formats the stacks, as printed when the ROM writes `System/debug`
+    ///
+    /// Each stack shows its top 8 bytes, with `|` marking its length.  If
+    /// symbols are loaded, the top of the working stack is labelled (if it is
+    /// a known address), and return addresses are shown as offsets from the
+    /// nearest label, from innermost to outermost.
+    pub(crate) fn debug_dump(&self, vm: &Uxn) -> String {
+        let mut out = String::new();
+        for (name, st) in [("WST", vm.stack()), ("RST", vm.ret())] {
+            write!(out, "{name} ").unwrap();
+            let n = st.len();
+            for i in (0..8).rev() {
+                write!(out, "{:02x}", st.peek_byte_at(i)).unwrap();
+                out.push(if i == n { '|' } else { ' ' });
+            }
+            out.push_str("<\n");
+
+            let Some(symbols) = &self.symbols else {
+                continue;
+            };
+            let short = |i: u8| {
+                u16::from_be_bytes([
+                    st.peek_byte_at(i * 2 + 1),
+                    st.peek_byte_at(i * 2),
+                ])
+            };
+            if name == "WST" {
+                if n >= 2 {
+                    let v = short(0);
+                    if let Some(label) = symbols.get(v) {
+                        writeln!(out, "    #{v:04x} @{label}").unwrap();
+                    }
+                }
+            } else {
+                for v in (0..n / 2).map(short) {
+                    match symbols.nearest(v) {
+                        Some((label, 0)) => {
+                            writeln!(out, "    #{v:04x} @{label}")
+                        }
+                        Some((label, i)) => {
+                            writeln!(out, "    #{v:04x} @{label}+{i:x}")
+                        }
+                        None => writeln!(out, "    #{v:04x}"),
+                    }
+                    .unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    /// Returns the metadata published by the ROM, if any
+    pub fn metadata(&self) -> Option<&RomMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Returns the exit code (if present), without clearing it
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
     /// Clears and returns the exit code (if present)
-    pub fn exit(&mut self) -> Option<i32> {
+    pub(crate) fn exit(&mut self) -> Option<i32> {
         self.exit.take()
     }
 }
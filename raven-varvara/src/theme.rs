@@ -0,0 +1,32 @@
+//! Palette themes shared between Varvara ROMs
+//!
+//! A `.theme` file is six bytes: the System device's red, green, and blue
+//! color registers, each stored as a big-endian short (i.e. exactly what a ROM
+//! would write to `System/r`, `System/g`, and `System/b`).
+
+/// System palette loaded from a `.theme` file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Theme {
+    /// Red color register
+    pub r: u16,
+    /// Green color register
+    pub g: u16,
+    /// Blue color register
+    pub b: u16,
+}
+
+impl Theme {
+    /// Parses a theme from the contents of a `.theme` file
+    ///
+    /// Returns `None` if the data is not exactly six bytes long.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let [r0, r1, g0, g1, b0, b1] = *data else {
+            return None;
+        };
+        Some(Self {
+            r: u16::from_be_bytes([r0, r1]),
+            g: u16::from_be_bytes([g0, g1]),
+            b: u16::from_be_bytes([b0, b1]),
+        })
+    }
+}
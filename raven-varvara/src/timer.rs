@@ -0,0 +1,97 @@
+//! Pacing for the screen vector
+use crate::Varvara;
+use std::time::Duration;
+use uxn::Uxn;
+
+/// Time between frames (60 Hz), rounded up to the nearest nanosecond
+pub const FRAME_PERIOD: Duration = Duration::from_nanos(16_666_667);
+
+/// Default limit on the number of frames run to catch up
+const MAX_CATCHUP: u32 = 4;
+
+/// Tracks when the screen vector should next be called
+///
+/// The screen vector must be called at 60 Hz, but hosts are woken up at
+/// irregular intervals (by vsync, input events, or timers).  On each update,
+/// a host passes the current time to [`FrameTimer::due`] (or
+/// [`Varvara::tick`]), and runs the screen vector that many times.
+///
+/// Deadlines are tracked in absolute terms, so rounding in the host's wakeups
+/// doesn't accumulate as drift.  If the host falls far behind (e.g. because
+/// it was suspended), at most a few frames are run to catch up, then the
+/// timer starts again from the current time.
+///
+/// Times are given as a [`Duration`] since an arbitrary origin (for example,
+/// `Instant::elapsed` on a fixed `Instant`), since [`std::time::Instant`] is
+/// not available on every platform.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTimer {
+    /// Deadline for the next frame, or `None` before the first update
+    next: Option<Duration>,
+    /// Maximum number of frames returned by a single call to [`due`]
+    ///
+    /// [`due`]: FrameTimer::due
+    max_catchup: u32,
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTimer {
+    /// Builds a new timer, which is due immediately
+    pub fn new() -> Self {
+        Self {
+            next: None,
+            max_catchup: MAX_CATCHUP,
+        }
+    }
+
+    /// Sets the maximum number of frames run to catch up (at least 1)
+    pub fn with_max_catchup(mut self, n: u32) -> Self {
+        self.max_catchup = n.max(1);
+        self
+    }
+
+    /// Returns the number of frames which are due at time `now`
+    ///
+    /// This advances the timer, so each frame is only reported once.
+    pub fn due(&mut self, now: Duration) -> u32 {
+        let next = *self.next.get_or_insert(now);
+        if now < next {
+            return 0;
+        }
+        let behind = (now - next).as_nanos() / FRAME_PERIOD.as_nanos();
+        match u32::try_from(behind + 1) {
+            Ok(n) if n <= self.max_catchup => {
+                self.next = Some(next + FRAME_PERIOD * n);
+                n
+            }
+            _ => {
+                self.next = Some(now + FRAME_PERIOD);
+                self.max_catchup
+            }
+        }
+    }
+}
+
+impl Varvara {
+    /// Calls the screen vector for each frame that is due at time `now`
+    ///
+    /// Returns `true` if any call to [`Varvara::redraw`] reported a change to
+    /// the screen.
+    pub fn tick(
+        &mut self,
+        vm: &mut Uxn,
+        timer: &mut FrameTimer,
+        now: Duration,
+    ) -> bool {
+        let mut changed = false;
+        for _ in 0..timer.due(now) {
+            changed |= self.redraw(vm);
+        }
+        changed
+    }
+}
@@ -0,0 +1,532 @@
+//! Storage behind the file devices
+//!
+//! The file devices resolve paths from the ROM (relative to their root
+//! directory), then pass them to a [`FileBackend`].  By default, this is
+//! [`StdFs`], which uses the host's filesystem; [`MemoryFs`] keeps files in
+//! memory instead, which is useful on platforms without a filesystem (e.g.
+//! WebAssembly), in tests, and for hosts which bundle assets with a ROM.
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Information about a file or directory
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FileInfo {
+    /// Whether this is a directory
+    pub is_dir: bool,
+    /// Length in bytes (0 for directories)
+    pub len: u64,
+}
+
+/// Single entry in a directory listing
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirEntry {
+    /// File name, without its directory
+    pub name: OsString,
+    /// Information about the entry
+    pub info: FileInfo,
+}
+
+/// Iterator over the entries in a directory
+pub type DirEntries =
+    Box<dyn Iterator<Item = std::io::Result<DirEntry>> + Send>;
+
+/// Handle to a file which is being written
+pub trait FileWriter: Write + Send {
+    /// Finishes writing, e.g. moving a temporary file into place
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Storage used by the file devices
+///
+/// Paths have already been checked to stay within the file root, so backends
+/// don't need to sandbox them again.
+pub trait FileBackend: Send {
+    /// Returns information about a path
+    ///
+    /// This must return an error of kind [`std::io::ErrorKind::NotFound`] if
+    /// nothing exists at the path.
+    fn info(&self, path: &Path) -> std::io::Result<FileInfo>;
+
+    /// Opens a file for reading
+    fn open(&mut self, path: &Path) -> std::io::Result<Box<dyn Read + Send>>;
+
+    /// Lists the entries in a directory
+    fn read_dir(&mut self, path: &Path) -> std::io::Result<DirEntries>;
+
+    /// Opens a file for writing, creating it if necessary
+    ///
+    /// If `append` is set, data is added to the end of the existing file;
+    /// otherwise, the file is truncated.  If `atomic` is set, the file should
+    /// be left unchanged until [`FileWriter::finish`] is called.
+    fn create(
+        &mut self,
+        path: &Path,
+        append: bool,
+        atomic: bool,
+    ) -> std::io::Result<Box<dyn FileWriter>>;
+
+    /// Removes a file or an empty directory
+    fn remove(&mut self, path: &Path) -> std::io::Result<()>;
+
+    /// Creates a directory and all of its missing parents
+    fn create_dir_all(&mut self, path: &Path) -> std::io::Result<()>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Backend which uses the host's filesystem
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdFs;
+
+/// File being written directly or through a temporary file
+struct StdWriter {
+    path: PathBuf,
+    file: std::fs::File,
+
+    /// Temporary file being written, which is renamed to `path` when finished
+    tmp: Option<PathBuf>,
+}
+
+impl Write for StdWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl FileWriter for StdWriter {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        let StdWriter { path, file, tmp } = *self;
+        if let Some(tmp) = tmp {
+            file.sync_all()?;
+            drop(file);
+            std::fs::rename(&tmp, &path)?;
+            log::trace!("moved {tmp:?} to {path:?}");
+        }
+        Ok(())
+    }
+}
+
+impl StdFs {
+    /// Returns the temporary path used for atomic writes to the given path
+    fn tmp_path(path: &Path) -> PathBuf {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(".raven-tmp");
+        path.with_file_name(name)
+    }
+}
+
+impl FileBackend for StdFs {
+    fn info(&self, path: &Path) -> std::io::Result<FileInfo> {
+        let m = std::fs::metadata(path)?;
+        Ok(FileInfo {
+            is_dir: m.is_dir(),
+            len: if m.is_dir() { 0 } else { m.len() },
+        })
+    }
+
+    fn open(&mut self, path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(std::fs::File::open(path)?))
+    }
+
+    fn read_dir(&mut self, path: &Path) -> std::io::Result<DirEntries> {
+        let dir = std::fs::read_dir(path)?;
+        Ok(Box::new(dir.map(|d| {
+            let d = d?;
+            let m = d.metadata()?;
+            Ok(DirEntry {
+                name: d.file_name(),
+                info: FileInfo {
+                    is_dir: m.is_dir(),
+                    len: if m.is_dir() { 0 } else { m.len() },
+                },
+            })
+        })))
+    }
+
+    fn create(
+        &mut self,
+        path: &Path,
+        append: bool,
+        atomic: bool,
+    ) -> std::io::Result<Box<dyn FileWriter>> {
+        let tmp = atomic.then(|| Self::tmp_path(path));
+        if let Some(tmp) = &tmp {
+            if append && path.exists() {
+                std::fs::copy(path, tmp)?;
+            }
+        }
+        let target = tmp.as_deref().unwrap_or(path);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(target)?;
+        if file.metadata()?.is_dir() {
+            return Err(std::io::Error::other("is a directory"));
+        }
+        Ok(Box::new(StdWriter {
+            path: path.to_owned(),
+            file,
+            tmp,
+        }))
+    }
+
+    fn remove(&mut self, path: &Path) -> std::io::Result<()> {
+        match std::fs::symlink_metadata(path) {
+            Ok(m) if m.is_dir() => std::fs::remove_dir(path),
+            _ => std::fs::remove_file(path),
+        }
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Node in an in-memory filesystem
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+/// Map from normalized paths to nodes
+///
+/// The root directory (an empty path) always exists, and isn't stored.
+type Tree = BTreeMap<PathBuf, Node>;
+
+/// Backend which stores files in memory
+///
+/// Clones of a `MemoryFs` share the same files, so a host can keep a handle
+/// to pre-populate assets or read back what the ROM saved.
+///
+/// ```
+/// use raven_varvara::{MemoryFs, Varvara};
+///
+/// let fs = MemoryFs::new();
+/// fs.insert("levels/1.txt", b"...").unwrap();
+/// let mut dev = Varvara::new();
+/// dev.set_file_backend(fs.clone());
+/// assert_eq!(fs.get("levels/1.txt").unwrap(), b"...");
+/// ```
+#[derive(Clone, Default)]
+pub struct MemoryFs {
+    tree: Arc<Mutex<Tree>>,
+}
+
+/// Converts a path into a key in the tree
+///
+/// Paths are checked before reaching the backend, so `..` and absolute
+/// components are simply clamped to the root.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(c) => out.push(c),
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(..) => {}
+        }
+    }
+    out
+}
+
+fn not_found() -> std::io::Error {
+    std::io::ErrorKind::NotFound.into()
+}
+
+/// Checks that the parent of the given (normalized) path is a directory
+fn check_parent(tree: &Tree, path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        None => Err(std::io::Error::other("invalid path")),
+        Some(p) if p.as_os_str().is_empty() => Ok(()),
+        Some(p) => match tree.get(p) {
+            Some(Node::Dir) => Ok(()),
+            Some(Node::File(..)) => {
+                Err(std::io::Error::other("not a directory"))
+            }
+            None => Err(not_found()),
+        },
+    }
+}
+
+impl MemoryFs {
+    /// Builds an empty filesystem
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, creating its parent directories (if missing)
+    ///
+    /// Any existing file at the path is replaced.  This returns an error if
+    /// the path is a directory, or one of its parents is a file.
+    pub fn insert<P: AsRef<Path>>(
+        &self,
+        path: P,
+        data: &[u8],
+    ) -> std::io::Result<()> {
+        let path = normalize(path.as_ref());
+        if path.as_os_str().is_empty() {
+            return Err(std::io::Error::other("is a directory"));
+        }
+        let mut tree = self.tree.lock().unwrap();
+        if let Some(parent) = path.parent() {
+            create_dirs(&mut tree, parent)?;
+        }
+        if matches!(tree.get(&path), Some(Node::Dir)) {
+            return Err(std::io::Error::other("is a directory"));
+        }
+        tree.insert(path, Node::File(data.to_vec()));
+        Ok(())
+    }
+
+    /// Adds a directory, creating its parent directories (if missing)
+    ///
+    /// This returns an error if the path or one of its parents is a file.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        create_dirs(&mut self.tree.lock().unwrap(), &normalize(path.as_ref()))
+    }
+
+    /// Returns the contents of a file, if present
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<Vec<u8>> {
+        match self.tree.lock().unwrap().get(&normalize(path.as_ref())) {
+            Some(Node::File(data)) => Some(data.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Creates a (normalized) directory and all of its parents
+fn create_dirs(tree: &mut Tree, path: &Path) -> std::io::Result<()> {
+    for p in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+        match tree.get(p) {
+            Some(Node::Dir) => break,
+            Some(Node::File(..)) => {
+                return Err(std::io::Error::other("not a directory"))
+            }
+            None => (),
+        }
+    }
+    let mut p = PathBuf::new();
+    for c in path.components() {
+        p.push(c);
+        tree.entry(p.clone()).or_insert(Node::Dir);
+    }
+    Ok(())
+}
+
+/// File being written into a [`MemoryFs`]
+struct MemoryWriter {
+    tree: Arc<Mutex<Tree>>,
+    path: PathBuf,
+
+    /// Pending contents for an atomic write, stored when finished
+    pending: Option<Vec<u8>>,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(p) = &mut self.pending {
+            p.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+        let mut tree = self.tree.lock().unwrap();
+        match tree.get_mut(&self.path) {
+            Some(Node::File(data)) => {
+                data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileWriter for MemoryWriter {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        if let Some(data) = self.pending {
+            let mut tree = self.tree.lock().unwrap();
+            check_parent(&tree, &self.path)?;
+            tree.insert(self.path, Node::File(data));
+        }
+        Ok(())
+    }
+}
+
+impl FileBackend for MemoryFs {
+    fn info(&self, path: &Path) -> std::io::Result<FileInfo> {
+        let path = normalize(path);
+        if path.as_os_str().is_empty() {
+            return Ok(FileInfo {
+                is_dir: true,
+                len: 0,
+            });
+        }
+        match self.tree.lock().unwrap().get(&path) {
+            Some(Node::Dir) => Ok(FileInfo {
+                is_dir: true,
+                len: 0,
+            }),
+            Some(Node::File(data)) => Ok(FileInfo {
+                is_dir: false,
+                len: data.len() as u64,
+            }),
+            None => Err(not_found()),
+        }
+    }
+
+    fn open(&mut self, path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
+        match self.tree.lock().unwrap().get(&normalize(path)) {
+            Some(Node::File(data)) => {
+                Ok(Box::new(std::io::Cursor::new(data.clone())))
+            }
+            Some(Node::Dir) => Err(std::io::Error::other("is a directory")),
+            None => Err(not_found()),
+        }
+    }
+
+    fn read_dir(&mut self, path: &Path) -> std::io::Result<DirEntries> {
+        let path = normalize(path);
+        let tree = self.tree.lock().unwrap();
+        if !path.as_os_str().is_empty() {
+            match tree.get(&path) {
+                Some(Node::Dir) => (),
+                Some(Node::File(..)) => {
+                    return Err(std::io::Error::other("not a directory"))
+                }
+                None => return Err(not_found()),
+            }
+        }
+        let entries = tree
+            .iter()
+            .filter(|(p, _)| p.parent() == Some(path.as_path()))
+            .map(|(p, n)| {
+                Ok(DirEntry {
+                    name: p.file_name().unwrap_or_default().to_owned(),
+                    info: match n {
+                        Node::Dir => FileInfo {
+                            is_dir: true,
+                            len: 0,
+                        },
+                        Node::File(data) => FileInfo {
+                            is_dir: false,
+                            len: data.len() as u64,
+                        },
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn create(
+        &mut self,
+        path: &Path,
+        append: bool,
+        atomic: bool,
+    ) -> std::io::Result<Box<dyn FileWriter>> {
+        let path = normalize(path);
+        let mut tree = self.tree.lock().unwrap();
+        check_parent(&tree, &path)?;
+        let prev = match tree.get(&path) {
+            Some(Node::Dir) => {
+                return Err(std::io::Error::other("is a directory"))
+            }
+            Some(Node::File(data)) if append => data.clone(),
+            _ => vec![],
+        };
+        let pending = if atomic {
+            Some(prev)
+        } else {
+            tree.insert(path.clone(), Node::File(prev));
+            None
+        };
+        Ok(Box::new(MemoryWriter {
+            tree: self.tree.clone(),
+            path,
+            pending,
+        }))
+    }
+
+    fn remove(&mut self, path: &Path) -> std::io::Result<()> {
+        let path = normalize(path);
+        let mut tree = self.tree.lock().unwrap();
+        match tree.get(&path) {
+            Some(Node::File(..)) => (),
+            Some(Node::Dir) => {
+                if tree.keys().any(|p| p.parent() == Some(path.as_path())) {
+                    return Err(std::io::Error::other("directory not empty"));
+                }
+            }
+            None => return Err(not_found()),
+        }
+        tree.remove(&path);
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> std::io::Result<()> {
+        create_dirs(&mut self.tree.lock().unwrap(), &normalize(path))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Wrapper which rejects any attempt to modify a backend
+///
+/// Files can be listed and read as usual; writing, creating directories, and
+/// deleting fail with [`std::io::ErrorKind::PermissionDenied`].
+#[derive(Clone, Default)]
+pub struct ReadOnly<B>(pub B);
+
+fn read_only() -> std::io::Error {
+    std::io::ErrorKind::PermissionDenied.into()
+}
+
+impl<B: FileBackend> FileBackend for ReadOnly<B> {
+    fn info(&self, path: &Path) -> std::io::Result<FileInfo> {
+        self.0.info(path)
+    }
+
+    fn open(&mut self, path: &Path) -> std::io::Result<Box<dyn Read + Send>> {
+        self.0.open(path)
+    }
+
+    fn read_dir(&mut self, path: &Path) -> std::io::Result<DirEntries> {
+        self.0.read_dir(path)
+    }
+
+    fn create(
+        &mut self,
+        _path: &Path,
+        _append: bool,
+        _atomic: bool,
+    ) -> std::io::Result<Box<dyn FileWriter>> {
+        Err(read_only())
+    }
+
+    fn remove(&mut self, _path: &Path) -> std::io::Result<()> {
+        Err(read_only())
+    }
+
+    fn create_dir_all(&mut self, _path: &Path) -> std::io::Result<()> {
+        Err(read_only())
+    }
+}
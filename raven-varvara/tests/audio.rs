@@ -0,0 +1,357 @@
+use raven_varvara::{
+    Interpolation, Playback, Resampler, StreamData, Varvara, WavWriter,
+    AUDIO_CHANNELS, AUDIO_SAMPLE_RATE,
+};
+use std::sync::{Arc, Mutex};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Plays a looping note on `Audio0` and `Audio1`
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // #0010 .Audio0/length DEO2 #ff .Audio0/volume DEO #3c .Audio0/pitch DEO
+    op::LIT2, 0x00, 0x10, op::LIT, 0x3a, op::DEO2,
+    op::LIT, 0xff, op::LIT, 0x3e, op::DEO,
+    op::LIT, 0x3c, op::LIT, 0x3f, op::DEO,
+    // #0010 .Audio1/length DEO2 #ff .Audio1/volume DEO #3c .Audio1/pitch DEO
+    op::LIT2, 0x00, 0x10, op::LIT, 0x4a, op::DEO2,
+    op::LIT, 0xff, op::LIT, 0x4e, op::DEO,
+    op::LIT, 0x3c, op::LIT, 0x4f, op::DEO,
+    op::BRK,
+];
+
+/// Checks whether the stream produces any sound
+fn is_playing(stream: &Arc<Mutex<StreamData>>) -> bool {
+    let mut buf = [0f32; 512];
+    stream.lock().unwrap().next(&mut buf);
+    buf.iter().any(|&v| v != 0.0)
+}
+
+#[test]
+fn mute_and_solo() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let [a, b, ..] = dev.audio_streams();
+    assert!(is_playing(&a));
+    assert!(is_playing(&b));
+
+    dev.audio_set_channel_muted(0, true);
+    assert!(dev.audio_channel_muted(0));
+    assert!(!dev.audio_channel_audible(0));
+    assert!(!is_playing(&a));
+    assert!(is_playing(&b));
+
+    // Soloing a channel silences every other channel
+    dev.audio_set_channel_muted(0, false);
+    dev.audio_set_channel_solo(1, true);
+    assert!(dev.audio_channel_solo(1));
+    assert!(!is_playing(&a));
+    assert!(is_playing(&b));
+
+    // Muting takes priority over soloing
+    dev.audio_set_channel_solo(0, true);
+    dev.audio_set_channel_muted(1, true);
+    assert!(is_playing(&a));
+    assert!(!is_playing(&b));
+
+    // The global mute flag silences everything
+    dev.audio_set_muted(true);
+    assert!(!is_playing(&a));
+    assert!((0..4).all(|i| !dev.audio_channel_audible(i)));
+    dev.audio_set_muted(false);
+
+    // Mixer flags are preserved when the system is reset
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert!(dev.audio_channel_solo(0));
+    assert!(dev.audio_channel_muted(1));
+    assert!(is_playing(&a));
+    assert!(!is_playing(&b));
+}
+
+#[test]
+fn capture() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let captured = Arc::new(Mutex::new(vec![]));
+    let c = captured.clone();
+    assert!(!dev.is_capturing_audio());
+    dev.start_audio_capture(move |s: &[f32]| {
+        c.lock().unwrap().extend_from_slice(s)
+    });
+    assert!(dev.is_capturing_audio());
+
+    // Output is only captured once every stream has been pulled
+    let streams = dev.audio_streams();
+    let mut mixed = vec![0f32; 512];
+    for (i, s) in streams.iter().enumerate() {
+        assert!(captured.lock().unwrap().is_empty());
+        let mut buf = [0f32; 512];
+        s.lock().unwrap().next(&mut buf);
+        assert_eq!(buf.iter().any(|&v| v != 0.0), i < 2);
+        for (m, b) in mixed.iter_mut().zip(buf) {
+            *m += b;
+        }
+    }
+
+    // Stopping the capture sends anything which is still pending, and waits
+    // for the writer thread to pass it to the sink
+    let mut buf = [0f32; 256];
+    streams[0].lock().unwrap().next(&mut buf);
+    let sink = dev.stop_audio_capture().unwrap();
+    sink.finish().unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 768);
+    assert_eq!(captured[..512], mixed);
+    assert_eq!(captured[512..], buf);
+    assert!(!dev.is_capturing_audio());
+
+    // WAV files have their header filled in when finished
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.wav");
+    let f = std::fs::File::create(&path).unwrap();
+    dev.start_audio_capture(WavWriter::new(f).unwrap());
+    for s in &streams {
+        s.lock().unwrap().next(&mut [0f32; 100]);
+    }
+    dev.stop_audio_capture().unwrap().finish().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!(data.len(), 44 + 200);
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(data[4..8], (36u32 + 200).to_le_bytes());
+    assert_eq!(&data[8..16], b"WAVEfmt ");
+    assert_eq!(data[22..24], (AUDIO_CHANNELS as u16).to_le_bytes());
+    assert_eq!(data[24..28], AUDIO_SAMPLE_RATE.to_le_bytes());
+    assert_eq!(&data[36..40], b"data");
+    assert_eq!(data[40..44], 200u32.to_le_bytes());
+}
+
+#[test]
+fn interpolation() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    assert_eq!(dev.audio_interpolation(), Interpolation::Linear);
+
+    // Play a square wave, which rings differently with each method
+    let mut render = |mode| {
+        let extra = vm.reset(ROM);
+        dev.reset(extra);
+        for i in 0..16 {
+            vm.ram_write_byte(i, if i < 8 { 0x20 } else { 0xe0 });
+        }
+        vm.write_dev_mem(0x39, 0xf0); // Audio0/adsr: full sustain
+        dev.audio_set_interpolation(mode);
+        vm.run(&mut dev, 0x100);
+        let mut buf = vec![0f32; 2048];
+        dev.audio_streams()[0].lock().unwrap().next(&mut buf);
+        assert!(buf.iter().all(|v| v.is_finite()));
+        assert!(buf.iter().any(|&v| v != 0.0));
+        buf
+    };
+    let linear = render(Interpolation::Linear);
+    let cubic = render(Interpolation::Cubic);
+    let sinc = render(Interpolation::Sinc);
+    assert_ne!(linear, cubic);
+    assert_ne!(linear, sinc);
+    assert_ne!(cubic, sinc);
+
+    // The setting is kept when the system is reset
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    assert_eq!(dev.audio_interpolation(), Interpolation::Sinc);
+}
+
+#[test]
+fn mixed_output() {
+    let run = || {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = Varvara::new();
+        let extra = vm.reset(ROM);
+        dev.reset(extra);
+        vm.run(&mut dev, 0x100);
+        dev
+    };
+
+    // Render each stream separately, then sum them
+    let dev = run();
+    let mut expected = vec![0f32; 512];
+    for s in dev.audio_streams() {
+        let mut buf = vec![0f32; 512];
+        s.lock().unwrap().next(&mut buf);
+        for (e, v) in expected.iter_mut().zip(&buf) {
+            *e += v;
+        }
+    }
+    assert!(expected.iter().any(|&v| v != 0.0));
+
+    let dev = run();
+    let mut out = dev.audio_output();
+    let mut buf = vec![1f32; 512];
+    out.next(&mut buf);
+    assert_eq!(buf, expected);
+
+    // Mute flags still apply to the mixed output
+    let mut dev = run();
+    dev.audio_set_muted(true);
+    let mut out = dev.audio_output();
+    out.next(&mut buf);
+    assert!(buf.iter().all(|&v| v == 0.0));
+}
+
+#[test]
+fn resample() {
+    // Builds a fresh mixer playing the test ROM
+    let output = || {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = Varvara::new();
+        let extra = vm.reset(ROM);
+        dev.reset(extra);
+        vm.run(&mut dev, 0x100);
+        dev.audio_output()
+    };
+    let mut direct = vec![0f32; 1024 * AUDIO_CHANNELS];
+    output().next(&mut direct);
+    assert!(direct.iter().any(|&v| v != 0.0));
+
+    // At the native configuration, samples pass through unchanged
+    let mut r = Resampler::new(output(), AUDIO_SAMPLE_RATE, AUDIO_CHANNELS);
+    let mut buf = vec![0f32; 1024 * AUDIO_CHANNELS];
+    r.next(&mut buf);
+    assert_eq!(buf, direct);
+
+    // At half the sample rate, every other frame is used, and mono output
+    // mixes the channels together
+    let mono = |f: &[f32]| f.iter().sum::<f32>() / AUDIO_CHANNELS as f32;
+    let mut r = Resampler::new(output(), AUDIO_SAMPLE_RATE / 2, 1);
+    let mut buf = vec![0f32; 512];
+    for chunk in buf.chunks_mut(100) {
+        r.next(chunk);
+    }
+    for (i, v) in buf.iter().enumerate() {
+        let frame = &direct[i * 2 * AUDIO_CHANNELS..][..AUDIO_CHANNELS];
+        assert_eq!(*v, mono(frame), "mismatch at frame {i}");
+    }
+
+    // Extra output channels are silent
+    let mut r = Resampler::new(output(), AUDIO_SAMPLE_RATE * 2, 4);
+    let mut buf = vec![0f32; 4 * 512];
+    r.next(&mut buf);
+    assert!(buf.iter().all(|v| v.is_finite()));
+    assert!(buf.chunks(4).any(|f| f[0] != 0.0));
+    assert!(buf.chunks(4).all(|f| f[2] == 0.0 && f[3] == 0.0));
+}
+
+#[test]
+fn loop_points_and_reverse() {
+    /// Plays a looping 16-byte note on `Audio0`, then (at 0x116) reads back
+    /// its position into 0x80
+    #[rustfmt::skip]
+    const ROM: &[u8] = &[
+        // #0010 .Audio0/length DEO2 #f0 .Audio0/adsr+1 DEO
+        op::LIT2, 0x00, 0x10, op::LIT, 0x3a, op::DEO2,
+        op::LIT, 0xf0, op::LIT, 0x39, op::DEO,
+        // #ff .Audio0/volume DEO #3c .Audio0/pitch DEO BRK
+        op::LIT, 0xff, op::LIT, 0x3e, op::DEO,
+        op::LIT, 0x3c, op::LIT, 0x3f, op::DEO,
+        op::BRK,
+        // @position .Audio0/position DEI2 #80 STZ2 BRK
+        op::LIT, 0x32, op::DEI2, op::LIT, 0x80, op::STZ2,
+        op::BRK,
+    ];
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    assert_eq!(dev.audio_playback(0), Playback::default());
+
+    // Starts a note, returning its output and a list of positions
+    let mut play = |p: Playback| {
+        let extra = vm.reset(ROM);
+        dev.reset(extra);
+        dev.audio_set_playback(0, p);
+        vm.run(&mut dev, 0x100);
+        let stream = dev.audio_streams()[0].clone();
+        let mut out = vec![];
+        let mut pos = vec![];
+        for _ in 0..64 {
+            let mut buf = [0f32; 64];
+            stream.lock().unwrap().next(&mut buf);
+            out.extend(buf);
+            vm.run(&mut dev, 0x116);
+            pos.push(u16::from_be_bytes([
+                vm.ram_read_byte(0x80),
+                vm.ram_read_byte(0x81),
+            ]));
+        }
+        (out, pos)
+    };
+
+    // By default, the whole sample is repeated
+    let (out, pos) = play(Playback::default());
+    assert!(pos.iter().all(|&p| p <= 16));
+    assert!(pos.iter().any(|&p| p < 4));
+
+    // Looping over the entire sample is the same as the default behavior
+    let (full, _) = play(Playback {
+        loop_points: Some((0, 16)),
+        reverse: false,
+    });
+    assert_eq!(out, full);
+
+    // The note plays the whole sample once, then only repeats the loop region.
+    // Like the reference implementation, the position wraps before computing
+    // each point, so it may be read back one step past the region.
+    let (_, pos) = play(Playback {
+        loop_points: Some((4, 8)),
+        reverse: false,
+    });
+    assert!(pos[..5].windows(2).all(|w| w[0] < w[1]), "{pos:?}");
+    assert!(pos[4] > 8, "{pos:?}");
+    assert!(pos[16..].iter().all(|&p| (4..=8).contains(&p)), "{pos:?}");
+
+    // Reversed notes start at the end of the sample
+    let (_, pos) = play(Playback {
+        loop_points: None,
+        reverse: true,
+    });
+    assert!(pos[0] > pos[1]);
+    assert!(pos.iter().all(|&p| p < 16));
+    let (_, pos) = play(Playback {
+        loop_points: Some((4, 8)),
+        reverse: true,
+    });
+    assert!(pos[..4].windows(2).all(|w| w[0] > w[1]), "{pos:?}");
+    assert!(pos[0] > 8 && pos[3] < 4, "{pos:?}");
+    assert!(pos[16..].iter().all(|&p| (3..8).contains(&p)), "{pos:?}");
+
+    // Loop points beyond the end of the sample are clipped
+    let (_, pos) = play(Playback {
+        loop_points: Some((12, 1000)),
+        reverse: false,
+    });
+    assert!(pos[16..].iter().all(|&p| (12..=16).contains(&p)), "{pos:?}");
+
+    // Settings are kept when the system is reset
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    assert_eq!(
+        dev.audio_playback(0),
+        Playback {
+            loop_points: Some((12, 1000)),
+            reverse: false,
+        }
+    );
+}
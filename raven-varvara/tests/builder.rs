@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use raven_varvara::{MouseState, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Writes `hi` to `out.txt`, then stops
+#[rustfmt::skip]
+const FILE_ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x15, op::LIT, 0xa8, op::DEO2,
+    // #0002 .File0/length DEO2
+    op::LIT2, 0x00, 0x02, op::LIT, 0xaa, op::DEO2,
+    // ;data .File0/write DEO2 BRK
+    op::LIT2, 0x01, 0x13, op::LIT, 0xae, op::DEO2, op::BRK,
+    // @data "hi @name "out.txt 00
+    b'h', b'i',
+    b'o', b'u', b't', b'.', b't', b'x', b't', 0,
+];
+
+/// Counts screen and mouse vectors in the zero page
+#[rustfmt::skip]
+const VECTOR_ROM: &[u8] = &[
+    // |0100 ;on-screen .Screen/vector DEO2
+    op::LIT2, 0x01, 0x0d, op::LIT, 0x20, op::DEO2,
+    // ;on-mouse .Mouse/vector DEO2 BRK
+    op::LIT2, 0x01, 0x14, op::LIT, 0x90, op::DEO2, op::BRK,
+    // @on-screen #00 LDZk INC SWP STZ BRK
+    op::LIT, 0x00, op::LDZ | 0x80, op::INC, op::SWP, op::STZ, op::BRK,
+    // @on-mouse #01 LDZk INC SWP STZ BRK
+    op::LIT, 0x01, op::LDZ | 0x80, op::INC, op::SWP, op::STZ, op::BRK,
+];
+
+const MOVED: MouseState = MouseState::new((10.0, 20.0));
+
+#[test]
+fn file_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_root(dir.path()).build();
+    dev.reset_from(&mut vm, Arc::new(FILE_ROM.to_vec().into()));
+    vm.run(&mut dev, 0x100);
+    drop(dev);
+    assert_eq!(std::fs::read(dir.path().join("out.txt")).unwrap(), b"hi");
+}
+
+#[test]
+fn disabled_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_root(dir.path()).file(false).build();
+    dev.reset_from(&mut vm, Arc::new(FILE_ROM.to_vec().into()));
+    vm.run(&mut dev, 0x100);
+    drop(dev);
+    assert!(!dir.path().join("out.txt").exists());
+    assert!(!vm.is_mapped(0xa0));
+    assert!(!vm.is_mapped(0xb0));
+    assert!(vm.is_mapped(0x10));
+}
+
+#[test]
+fn disabled_devices() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.reset_from(&mut vm, Arc::new(VECTOR_ROM.to_vec().into()));
+    vm.run(&mut dev, 0x100);
+    assert!(dev.redraw(&mut vm));
+    dev.mouse(&mut vm, MOVED);
+    assert_eq!(vm.ram()[..2], [1, 1]);
+
+    let mut dev = Varvara::builder()
+        .screen(false)
+        .mouse(false)
+        .audio(false)
+        .controller(false)
+        .build();
+    dev.reset_from(&mut vm, Arc::new(VECTOR_ROM.to_vec().into()));
+    vm.run(&mut dev, 0x100);
+    assert!(!dev.redraw(&mut vm));
+    dev.mouse(&mut vm, MOVED);
+    assert_eq!(vm.ram()[..2], [0, 0]);
+    assert!(!dev.audio(&mut vm));
+
+    let out = dev.output(&vm);
+    assert_eq!(out.size, (0, 0));
+    assert!(out.frame.is_empty());
+    let bases: Vec<u8> = vm.ports_iter().map(|p| p.base()).collect();
+    assert_eq!(bases, [0x00, 0x10, 0xa0, 0xb0, 0xc0]);
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "zip")]
+use raven_varvara::{Bundle, BundleError, FileBackend, Varvara};
+use std::io::{Cursor, Write};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Reads a byte from `data.bin` into address 0x80, then stops
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x13, op::LIT, 0xa8, op::DEO2,
+    // #0001 .File0/length DEO2
+    op::LIT2, 0x00, 0x01, op::LIT, 0xaa, op::DEO2,
+    // #0080 .File0/read DEO2 BRK
+    op::LIT2, 0x00, 0x80, op::LIT, 0xac, op::DEO2, op::BRK,
+    // @name "data.bin 00
+    b'd', b'a', b't', b'a', b'.', b'b', b'i', b'n', 0,
+];
+
+/// Builds a zip archive with the given files
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut w = zip::ZipWriter::new(Cursor::new(vec![]));
+    for (name, data) in files {
+        w.start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        w.write_all(data).unwrap();
+    }
+    w.finish().unwrap().into_inner()
+}
+
+fn run(bundle: &Bundle) -> u8 {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_backend(bundle.files()).build();
+    let extra = vm.reset(bundle.rom());
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    vm.ram_read_byte(0x80)
+}
+
+#[test]
+fn zip_with_rom() {
+    let data = zip(&[("game.rom", ROM), ("data.bin", &[0x2a])]);
+    let bundle = Bundle::parse(&data).unwrap();
+    assert_eq!(bundle.rom(), ROM);
+    assert_eq!(run(&bundle), 0x2a);
+}
+
+#[test]
+fn rom_with_appended_zip() {
+    let mut data = ROM.to_vec();
+    data.extend(zip(&[("data.bin", &[0x2b])]));
+    let bundle = Bundle::parse(&data).unwrap();
+    assert_eq!(bundle.rom(), ROM);
+    assert_eq!(run(&bundle), 0x2b);
+}
+
+#[test]
+fn bad_bundles() {
+    assert!(matches!(Bundle::parse(ROM), Err(BundleError::Zip(..))));
+
+    let data = zip(&[("data.bin", &[0x2a])]);
+    assert!(matches!(Bundle::parse(&data), Err(BundleError::NoRom)));
+
+    // ROMs in subdirectories don't count
+    let data = zip(&[("a.rom", ROM), ("b.rom", ROM), ("c/d.rom", ROM)]);
+    match Bundle::parse(&data) {
+        Err(BundleError::AmbiguousRom(names)) => {
+            assert_eq!(names, ["a.rom", "b.rom"])
+        }
+        _ => panic!("expected an ambiguous ROM"),
+    }
+}
+
+#[test]
+fn too_large() {
+    // Files are stored uncompressed, to keep the test fast
+    let mut w = zip::ZipWriter::new(Cursor::new(vec![]));
+    let opts = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+    w.start_file("game.rom", opts).unwrap();
+    w.write_all(ROM).unwrap();
+    w.start_file("big.bin", opts).unwrap();
+    let n = Bundle::MAX_UNPACKED_SIZE as usize - ROM.len() + 1;
+    w.write_all(&vec![0; n]).unwrap();
+    let data = w.finish().unwrap().into_inner();
+    assert!(matches!(Bundle::parse(&data), Err(BundleError::TooLarge)));
+}
+
+#[test]
+fn read_only() {
+    let data = zip(&[("game.rom", ROM), ("data.bin", &[0x2a])]);
+    let bundle = Bundle::parse(&data).unwrap();
+    let mut files = bundle.files();
+    let path = std::path::Path::new("data.bin");
+    assert_eq!(files.info(path).unwrap().len, 1);
+    let err = files.create(path, false, false).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(files.remove(path).is_err());
+}
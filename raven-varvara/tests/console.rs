@@ -0,0 +1,303 @@
+use raven_varvara::{
+    ChannelConsole, ConsoleBackend, ConsoleInput, ConsoleWriter, Varvara,
+};
+use std::{
+    io::Write,
+    num::NonZeroUsize,
+    sync::{mpsc, Arc, Mutex},
+};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Records each console event as a `(type, byte)` pair in the zero page
+///
+/// The byte at address 0 is the offset of the next pair.
+#[rustfmt::skip]
+const RECORD: &[u8] = &[
+    // |0100 #02 #00 STZ
+    op::LIT, 0x02, op::LIT, 0x00, op::STZ,
+    // ;on-console .Console/vector DEO2 BRK
+    op::LIT2, 0x01, 0x0c, op::LIT, 0x10, op::DEO2, op::BRK,
+    // @on-console .Console/type DEI #00 LDZ STZ
+    op::LIT, 0x17, op::DEI, op::LIT, 0x00, op::LDZ, op::STZ,
+    // .Console/read DEI #00 LDZ INC STZ
+    op::LIT, 0x12, op::DEI, op::LIT, 0x00, op::LDZ, op::INC, op::STZ,
+    // #00 LDZ INC INC #00 STZ BRK
+    op::LIT, 0x00, op::LDZ, op::INC, op::INC, op::LIT, 0x00, op::STZ,
+    op::BRK,
+];
+
+/// Sends arguments and console input, returning the zero page afterwards
+fn record(pacing: Option<NonZeroUsize>) -> Vec<u8> {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_console_pacing(pacing);
+
+    let args = ["ab".to_owned(), "c".to_owned()];
+    let extra = vm.reset(RECORD);
+    dev.reset(extra);
+    dev.init_args(&mut vm, &args);
+    vm.run(&mut dev, 0x100);
+    let _ = dev.send_args(&mut vm, &args);
+    dev.console(&mut vm, b'x');
+    dev.console(&mut vm, b'y');
+    for _ in 0..8 {
+        dev.redraw(&mut vm);
+    }
+    assert_eq!(dev.console_backlog(), 0);
+    vm.ram()[..0x100].to_vec()
+}
+
+#[test]
+fn pacing_final_state() {
+    let expected = record(None);
+    #[rustfmt::skip]
+    assert_eq!(
+        expected[..16],
+        [16, 0,
+         2, b'a', 2, b'b', 3, b'\n', 2, b'c', 4, b'\n',
+         1, b'x', 1, b'y']
+    );
+    for n in [1, 2, 3, 100] {
+        assert_eq!(record(NonZeroUsize::new(n)), expected, "pacing {n}");
+    }
+}
+
+#[test]
+fn pacing_per_frame() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_console_pacing(NonZeroUsize::new(3));
+
+    let extra = vm.reset(RECORD);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    for &c in b"hello" {
+        dev.console(&mut vm, c);
+    }
+    assert_eq!(dev.console_backlog(), 5);
+    assert_eq!(vm.ram_read_byte(0x00), 2);
+
+    dev.redraw(&mut vm);
+    assert_eq!(dev.console_backlog(), 2);
+    assert_eq!(vm.ram_read_byte(0x00), 8);
+
+    dev.redraw(&mut vm);
+    assert_eq!(dev.console_backlog(), 0);
+    assert_eq!(vm.ram_read_byte(0x00), 12);
+    assert_eq!(vm.ram_read_byte(0x0b), b'o');
+}
+
+#[test]
+fn end_of_input() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+
+    let extra = vm.reset(RECORD);
+    dev.reset(extra);
+    dev.init_args(&mut vm, &[]);
+    vm.run(&mut dev, 0x100);
+    let _ = dev.send_args(&mut vm, &[]);
+    dev.console(&mut vm, 0xff);
+    dev.console_end(&mut vm);
+    assert_eq!(vm.ram()[..6], [6, 0, 1, 0xff, 4, 0]);
+}
+
+#[test]
+fn stdin_type() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+
+    // Without arguments, stdin is still reported as such
+    let extra = vm.reset(RECORD);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    dev.console(&mut vm, b'x');
+    dev.console_end(&mut vm);
+    dev.console(&mut vm, b'y');
+    assert_eq!(vm.ram()[..8], [8, 0, 1, b'x', 4, 0, 1, b'y']);
+}
+
+/// Records `Controller/key` from its vector, with a probe to read ports later
+#[rustfmt::skip]
+const KEYS: &[u8] = &[
+    // |0100 ;on-key .Controller/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x80, op::DEO2, op::BRK,
+    // @on-key .Controller/key DEI #00 STZ BRK
+    op::LIT, 0x83, op::DEI, op::LIT, 0x00, op::STZ, op::BRK,
+    // @probe .Controller/key DEI #01 STZ .Console/read DEI #02 STZ BRK
+    op::LIT, 0x83, op::DEI, op::LIT, 0x01, op::STZ,
+    op::LIT, 0x12, op::DEI, op::LIT, 0x02, op::STZ, op::BRK,
+    // @on-key-inc .Controller/key DEI INC .Controller/key DEO BRK
+    op::LIT, 0x83, op::DEI, op::INC, op::LIT, 0x83, op::DEO, op::BRK,
+];
+
+#[test]
+fn char_and_clear() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(KEYS);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The key is visible to the vector, then cleared; the console character
+    // isn't written, because there's no console vector
+    dev.char(&mut vm, b'k');
+    dev.console(&mut vm, b'c');
+    vm.run(&mut dev, 0x10e);
+    assert_eq!(vm.ram()[..3], [b'k', 0, 0]);
+
+    // With a console vector, the character persists after the vector returns
+    vm.write_dev_mem(0x10, 0x01);
+    vm.write_dev_mem(0x11, 0x1a);
+    dev.console(&mut vm, b'c');
+    vm.run(&mut dev, 0x10e);
+    assert_eq!(vm.ram()[..3], [b'k', 0, b'c']);
+
+    // A vector which writes the key itself keeps its value
+    vm.write_dev_mem(0x80, 0x01);
+    vm.write_dev_mem(0x81, 0x1b);
+    dev.char(&mut vm, b'a');
+    vm.run(&mut dev, 0x10e);
+    assert_eq!(vm.ram()[..3], [b'k', b'b', b'c']);
+}
+
+/// Echoes console input to `Console/write` and its type to `Console/error`
+#[rustfmt::skip]
+const ECHO: &[u8] = &[
+    // |0100 ;on-console .Console/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x10, op::DEO2, op::BRK,
+    // @on-console .Console/read DEI .Console/write DEO
+    op::LIT, 0x12, op::DEI, op::LIT, 0x18, op::DEO,
+    // .Console/type DEI .Console/error DEO BRK
+    op::LIT, 0x17, op::DEI, op::LIT, 0x19, op::DEO, op::BRK,
+];
+
+#[test]
+fn channel_backend() {
+    let (stdout_tx, stdout_rx) = mpsc::channel();
+    let (stderr_tx, stderr_rx) = mpsc::channel();
+    let (input_tx, input_rx) = mpsc::channel();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_console_backend(Some(Box::new(ChannelConsole {
+        stdout: stdout_tx,
+        stderr: stderr_tx,
+        input: input_rx,
+    })));
+    let extra = vm.reset(ECHO);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Input is polled once per frame
+    input_tx.send(ConsoleInput::Char(b'h')).unwrap();
+    input_tx.send(ConsoleInput::Char(b'i')).unwrap();
+    input_tx.send(ConsoleInput::End).unwrap();
+    assert!(stdout_rx.try_recv().is_err());
+    dev.redraw(&mut vm);
+    assert_eq!(stdout_rx.try_iter().collect::<Vec<_>>(), b"hi\0");
+    assert_eq!(stderr_rx.try_iter().collect::<Vec<_>>(), [1, 1, 4]);
+
+    // Output goes to the backend instead of being buffered
+    let out = dev.output(&vm);
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
+
+    // Removing the backend goes back to buffering
+    assert!(dev.set_console_backend(None).is_some());
+    dev.console(&mut vm, b'!');
+    assert_eq!(dev.output(&vm).stdout, b"!");
+    assert!(stdout_rx.try_recv().is_err());
+}
+
+/// Records each call to `write` as a separate chunk
+#[derive(Clone, Default)]
+struct Chunks(Arc<Mutex<Vec<Vec<u8>>>>);
+
+impl Write for Chunks {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().push(buf.to_vec());
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Blocks each write until the gate is opened (by dropping its sender)
+struct Gated(mpsc::Receiver<()>);
+
+impl Write for Gated {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let _ = self.0.recv();
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn writer_utf8() {
+    let out = Chunks::default();
+    let err = Chunks::default();
+    let mut w = ConsoleWriter::new(out.clone(), err.clone());
+
+    // A partial UTF-8 sequence is held back when flushing
+    w.write(b'a');
+    w.write(0xc3);
+    w.flush();
+    w.write(0xa9);
+    w.write(b'\n');
+    w.error(b'!');
+    drop(w);
+
+    let out = out.0.lock().unwrap();
+    assert_eq!(*out, [b"a".to_vec(), "\u{e9}\n".as_bytes().to_vec()]);
+    assert_eq!(*err.0.lock().unwrap(), [b"!".to_vec()]);
+}
+
+#[test]
+fn writer_backpressure() {
+    let (gate, rx) = mpsc::channel();
+    let mut w = ConsoleWriter::with_limit(Gated(rx), Chunks::default(), 16);
+    for _ in 0..1000 {
+        w.write(b'x');
+        w.write(b'\n');
+    }
+    assert!(w.dropped() > 0);
+
+    // Opening the gate lets the writer finish
+    drop(gate);
+    drop(w);
+}
+
+#[test]
+fn writer_exit() {
+    #[rustfmt::skip]
+    const EXIT: &[u8] = &[
+        // |0100 LIT "h .Console/write DEO
+        op::LIT, b'h', op::LIT, 0x18, op::DEO,
+        // #81 .System/state DEO BRK
+        op::LIT, 0x81, op::LIT, 0x0f, op::DEO, op::BRK,
+    ];
+    let out = Chunks::default();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let w = ConsoleWriter::new(out.clone(), Chunks::default());
+    dev.set_console_backend(Some(Box::new(w)));
+    let extra = vm.reset(EXIT);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Output is written before the exit is reported
+    assert_eq!(dev.output(&vm).exit, Some(1));
+    assert_eq!(*out.0.lock().unwrap(), [b"h".to_vec()]);
+}
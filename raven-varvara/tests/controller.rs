@@ -0,0 +1,205 @@
+use raven_varvara::{
+    keymap::{Button, KeyMap},
+    GamepadState, Key, KeyRepeat, RepeatTiming, Varvara, CONTROLLER_PLAYERS,
+};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Stores `Controller/p2` and counts vector calls in the zero page
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // |0100 ;on-controller .Controller/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x80, op::DEO2, op::BRK,
+    // @on-controller .Controller/p2 DEI #00 STZ
+    op::LIT, 0x85, op::DEI, op::LIT, 0x00, op::STZ,
+    // #01 LDZ INC #01 STZ BRK
+    op::LIT, 0x01, op::LDZ, op::INC, op::LIT, 0x01, op::STZ, op::BRK,
+];
+
+#[test]
+fn players() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Player 1 writes to Controller/p2, leaving Controller/button alone
+    dev.pressed_for(&mut vm, 1, Key::Up, false);
+    assert_eq!(vm.ram_read_byte(0x00), 0x10);
+    assert_eq!(vm.ram_read_byte(0x01), 1);
+    assert_eq!(dev.devices().controller.buttons(), 0);
+    assert!(dev.devices().controller.is_down_for(1, Key::Up));
+    assert!(!dev.devices().controller.is_down(Key::Up));
+
+    // Each player's buttons are independent
+    dev.pressed(&mut vm, Key::Ctrl, false);
+    dev.pressed_for(&mut vm, 3, Key::Left, false);
+    assert_eq!(vm.ram_read_byte(0x01), 3);
+    let c = dev.devices().controller;
+    let buttons: Vec<u8> =
+        (0..CONTROLLER_PLAYERS).map(|p| c.buttons_for(p)).collect();
+    assert_eq!(buttons, [0x01, 0x10, 0x00, 0x40]);
+
+    dev.released_for(&mut vm, 1, Key::Up);
+    assert_eq!(vm.ram_read_byte(0x00), 0);
+    assert_eq!(vm.ram_read_byte(0x01), 4);
+
+    // Characters only come from player 0, and invalid players are ignored
+    dev.pressed_for(&mut vm, 2, Key::Char(b'a'), false);
+    dev.pressed_for(&mut vm, 4, Key::Up, false);
+    assert_eq!(vm.ram_read_byte(0x01), 4);
+    assert_eq!(dev.devices().controller.buttons_for(4), 0);
+}
+
+#[test]
+fn key_map() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let map = KeyMap::parse(
+        "# WASD\n\
+         up = w\n\
+         LEFT = a, Left  # both keys work\n\
+         a = space\n",
+    )
+    .unwrap();
+    assert_eq!(map.get(Key::Char(b'W')), Some(Button::Up));
+    assert_eq!(map.get(Key::Left), Some(Button::Left));
+    assert_eq!(map.get(Key::Ctrl), None);
+    dev.set_key_map(&mut vm, map);
+
+    // Mapped characters drive buttons (regardless of case), and are still
+    // sent as characters
+    let c = |dev: &Varvara| dev.devices().controller.buttons();
+    dev.pressed(&mut vm, Key::Char(b'w'), false);
+    assert_eq!(c(&dev), Button::Up.bit());
+    dev.pressed(&mut vm, Key::Char(b'A'), false);
+    assert_eq!(c(&dev), Button::Up.bit() | Button::Left.bit());
+    dev.released(&mut vm, Key::Char(b'W'));
+    assert_eq!(c(&dev), Button::Left.bit());
+    dev.released(&mut vm, Key::Char(b'a'));
+    assert_eq!(c(&dev), 0);
+
+    // Unmapped keys no longer count
+    dev.pressed(&mut vm, Key::Ctrl, false);
+    assert_eq!(c(&dev), 0);
+    dev.pressed(&mut vm, Key::Char(b' '), false);
+    assert_eq!(c(&dev), Button::A.bit());
+
+    // The map is kept across a reset
+    dev.reset(extra);
+    dev.pressed(&mut vm, Key::Char(b'w'), false);
+    assert_eq!(c(&dev), Button::Up.bit());
+
+    // Errors report the line number
+    let err = KeyMap::parse("up = w\njump = space\n").unwrap_err();
+    assert_eq!(err.line, 2);
+    assert!(KeyMap::parse("up = ctl").is_err());
+    assert!(KeyMap::parse("up").is_err());
+}
+
+#[test]
+fn gamepad() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Player 1's gamepad is visible in Controller/p2
+    let pad = GamepadState {
+        player: 1,
+        south: true,
+        stick: (0.9, -0.1),
+        ..GamepadState::default()
+    };
+    dev.gamepad(&mut vm, pad);
+    let expected = Button::A.bit() | Button::Right.bit();
+    assert_eq!(vm.ram_read_byte(0x00), expected);
+    assert_eq!(vm.ram_read_byte(0x01), 1);
+
+    // Unchanged state doesn't call the vector
+    dev.gamepad(
+        &mut vm,
+        GamepadState {
+            stick: (0.8, 0.2),
+            ..pad
+        },
+    );
+    assert_eq!(vm.ram_read_byte(0x01), 1);
+
+    // Gamepad buttons are combined with the player's keys
+    dev.pressed_for(&mut vm, 1, Key::Up, false);
+    assert_eq!(vm.ram_read_byte(0x00), expected | Button::Up.bit());
+    dev.gamepad(
+        &mut vm,
+        GamepadState {
+            player: 1,
+            ..Default::default()
+        },
+    );
+    assert_eq!(vm.ram_read_byte(0x00), Button::Up.bit());
+    assert_eq!(dev.devices().controller.buttons(), 0);
+}
+
+#[test]
+fn key_repeat() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    let calls = |vm: &Uxn| vm.ram_read_byte(0x01);
+
+    // Generated repeats ignore the host, and follow the configured timing
+    dev.set_key_repeat(KeyRepeat {
+        timing: Some(RepeatTiming {
+            delay: 3,
+            interval: 2,
+        }),
+        buttons: false,
+        ..KeyRepeat::default()
+    });
+    dev.pressed(&mut vm, Key::Char(b'x'), false);
+    dev.pressed(&mut vm, Key::Char(b'x'), true);
+    assert_eq!(calls(&vm), 1);
+    let mut counts = vec![];
+    for _ in 0..6 {
+        dev.redraw(&mut vm);
+        counts.push(calls(&vm));
+    }
+    assert_eq!(counts, [1, 1, 2, 2, 3, 3]);
+
+    // Buttons don't repeat, and releasing a key stops its repeats
+    dev.pressed(&mut vm, Key::Up, false);
+    assert_eq!(calls(&vm), 4);
+    for _ in 0..10 {
+        dev.redraw(&mut vm);
+    }
+    assert_eq!(calls(&vm), 4);
+    dev.released(&mut vm, Key::Up);
+    dev.pressed(&mut vm, Key::Char(b'y'), false);
+    dev.released(&mut vm, Key::Char(b'y'));
+    for _ in 0..10 {
+        dev.redraw(&mut vm);
+    }
+    assert_eq!(calls(&vm), 6);
+
+    // With host repeats, characters and buttons can be filtered separately
+    dev.set_key_repeat(KeyRepeat {
+        chars: false,
+        ..KeyRepeat::default()
+    });
+    dev.pressed(&mut vm, Key::Char(b'x'), true);
+    assert_eq!(calls(&vm), 6);
+    dev.pressed(&mut vm, Key::Up, false);
+    dev.pressed(&mut vm, Key::Up, true);
+    assert_eq!(calls(&vm), 8);
+}
@@ -0,0 +1,121 @@
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime, Timelike};
+use raven_varvara::{FixedClock, OffsetClock, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+#[test]
+fn dei2() {
+    // .DateTime/year DEI2 .DateTime/hour DEI2 BRK
+    #[rustfmt::skip]
+    let rom = [
+        op::LIT, 0xc0, op::DEI2, op::LIT, 0xc4, op::DEI2, op::BRK,
+    ];
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let s = vm.stack().as_slice();
+    assert_eq!(s.len(), 4);
+    assert!(u16::from_be_bytes([s[0], s[1]]) >= 2024);
+    assert!(s[2] < 24);
+    assert!(s[3] < 60);
+}
+
+/// Reads every datetime port into the zero page
+#[rustfmt::skip]
+const READ_ALL: &[u8] = &[
+    // .DateTime/year DEI2 #00 STZ2
+    op::LIT, 0xc0, op::DEI2, op::LIT, 0x00, op::STZ2,
+    // .DateTime/month DEI2 #02 STZ2
+    op::LIT, 0xc2, op::DEI2, op::LIT, 0x02, op::STZ2,
+    // .DateTime/hour DEI2 #04 STZ2
+    op::LIT, 0xc4, op::DEI2, op::LIT, 0x04, op::STZ2,
+    // .DateTime/second DEI2 #06 STZ2
+    op::LIT, 0xc6, op::DEI2, op::LIT, 0x06, op::STZ2,
+    // .DateTime/doty DEI2 #08 STZ2
+    op::LIT, 0xc8, op::DEI2, op::LIT, 0x08, op::STZ2,
+    // .DateTime/isdst DEI #0a STZ BRK
+    op::LIT, 0xca, op::DEI, op::LIT, 0x0a, op::STZ, op::BRK,
+];
+
+fn time(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(y, mo, d)
+        .unwrap()
+        .and_hms_opt(h, mi, s)
+        .unwrap()
+}
+
+#[test]
+fn fixed_clock() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder()
+        .clock(FixedClock(time(2024, 2, 29, 13, 45, 7)))
+        .build();
+    let extra = vm.reset(READ_ALL);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Months are 0-indexed; 2024-02-29 was a Thursday
+    assert_eq!(vm.ram()[..10], [0x07, 0xe8, 1, 29, 13, 45, 7, 4, 0, 59]);
+}
+
+#[test]
+fn out_of_range_year() {
+    for (y, hi, lo) in [(-44, 0, 0), (70000, 0xff, 0xff)] {
+        let mut ram = UxnRam::new();
+        let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+        let mut dev = Varvara::builder()
+            .clock(FixedClock(time(y, 3, 15, 12, 0, 0)))
+            .build();
+        let extra = vm.reset(READ_ALL);
+        dev.reset(extra);
+        vm.run(&mut dev, 0x100);
+
+        // Years outside the port's range are clamped
+        assert_eq!(vm.ram()[..4], [hi, lo, 2, 15], "year {y}");
+    }
+}
+
+#[test]
+fn scripted_clock() {
+    let mut t = time(2023, 12, 31, 23, 59, 59);
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder()
+        .clock(move || {
+            let out = t;
+            t += chrono::Duration::seconds(1);
+            out
+        })
+        .build();
+    let extra = vm.reset(READ_ALL);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Each DEI2 reads a new time, so the year is from before the rollover
+    assert_eq!(vm.ram()[..10], [0x07, 0xe7, 0, 1, 0, 0, 2, 1, 0, 0]);
+}
+
+#[test]
+fn offset_clock() {
+    let offset = FixedOffset::east_opt(5 * 3600 + 30 * 60).unwrap();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().clock(OffsetClock(offset)).build();
+    let extra = vm.reset(READ_ALL);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    let t = chrono::Utc::now().with_timezone(&offset);
+
+    // Allow for the minute changing between the two readings
+    let minutes = |h: u32, m: u32| h * 60 + m;
+    let expected = minutes(t.hour(), t.minute());
+    let actual = minutes(vm.ram()[4].into(), vm.ram()[5].into());
+    assert!((expected + 1440 - actual) % 1440 <= 1);
+
+    // There's no daylight saving time with a fixed offset
+    assert_eq!(vm.ram()[10], 0);
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use raven_varvara::{Key, MouseState, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Sets the red channel, prints `!`, and writes `hi` to `out.txt`
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // #f0f0 .System/r DEO2
+    op::LIT2, 0xf0, 0xf0, op::LIT, 0x08, op::DEO2,
+    // LIT "! .Console/write DEO
+    op::LIT, b'!', op::LIT, 0x18, op::DEO,
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x20, op::LIT, 0xa8, op::DEO2,
+    // #0002 .File0/length DEO2
+    op::LIT2, 0x00, 0x02, op::LIT, 0xaa, op::DEO2,
+    // ;data .File0/write DEO2 BRK
+    op::LIT2, 0x01, 0x1e, op::LIT, 0xae, op::DEO2, op::BRK,
+    // @data "hi @name "out.txt 00
+    b'h', b'i',
+    b'o', b'u', b't', b'.', b't', b'x', b't', 0,
+];
+
+#[test]
+fn device_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_root(dir.path()).build();
+    dev.reset_from(&mut vm, Arc::new(ROM.to_vec().into()));
+    vm.run(&mut dev, 0x100);
+
+    let d = dev.devices();
+    assert_eq!(d.system.palette(&vm), [0xf0f0, 0, 0]);
+    assert_eq!(d.console.pending_stdout(), b"!");
+    assert!(d.console.pending_stderr().is_empty());
+    assert_eq!(
+        d.file.open_path(0),
+        Some(dir.path().join("out.txt").as_path())
+    );
+    assert!(d.file.is_writing(0));
+    assert!(d.file.atomic_writes());
+    assert_eq!(d.file.root(), Some(dir.path()));
+    assert!(!d.audio.muted());
+
+    // Taking the output drains the console
+    let out = dev.output(&vm);
+    assert_eq!(out.stdout, b"!");
+    let d = dev.devices();
+    assert!(d.console.pending_stdout().is_empty());
+    assert_eq!(d.screen.size(), (512, 320));
+
+    dev.mouse(&mut vm, MouseState::new((3.0, 4.0)).with_buttons(1));
+    dev.pressed(&mut vm, Key::Ctrl, false);
+    let d = dev.devices();
+    assert_eq!(d.mouse.position(), (3.0, 4.0));
+    assert_eq!(d.mouse.buttons(), 1);
+    assert!(d.controller.is_down(Key::Ctrl));
+    assert_eq!(d.controller.buttons(), 1);
+
+    // Resetting closes the file
+    dev.reset(&[]);
+    assert_eq!(dev.devices().file.open_path(0), None);
+    assert_eq!(std::fs::read(dir.path().join("out.txt")).unwrap(), b"hi");
+}
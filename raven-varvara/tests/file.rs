@@ -0,0 +1,487 @@
+use raven_varvara::{vfs::FileInfo, FileBackend, MemoryFs, StdFs, Varvara};
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Writes `hi` to `out.txt`, then stops
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x15, op::LIT, 0xa8, op::DEO2,
+    // #0002 .File0/length DEO2
+    op::LIT2, 0x00, 0x02, op::LIT, 0xaa, op::DEO2,
+    // ;data .File0/write DEO2 BRK
+    op::LIT2, 0x01, 0x13, op::LIT, 0xae, op::DEO2, op::BRK,
+    // @data "hi @name "out.txt 00
+    b'h', b'i',
+    b'o', b'u', b't', b'.', b't', b'x', b't', 0,
+];
+
+// The file device works relative to the current directory, so this is the
+// only test in this binary which uses it (to avoid races when changing
+// directory); other tests set a file root instead.
+#[test]
+fn atomic_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    std::fs::write("out.txt", "old").unwrap();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Data is written to a temporary file until the handle is closed
+    assert_eq!(std::fs::read("out.txt").unwrap(), b"old");
+    assert_eq!(std::fs::read(".out.txt.raven-tmp").unwrap(), b"hi");
+    dev.reset(&[]);
+    assert_eq!(std::fs::read("out.txt").unwrap(), b"hi");
+    assert!(!std::path::Path::new(".out.txt.raven-tmp").exists());
+
+    // With atomic writes disabled, data goes straight to the file
+    std::fs::write("out.txt", "old").unwrap();
+    dev.set_atomic_file_writes(false);
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(std::fs::read("out.txt").unwrap(), b"hi");
+    assert!(!std::path::Path::new(".out.txt.raven-tmp").exists());
+
+    // Dropping the system also closes the file
+    std::fs::remove_file("out.txt").unwrap();
+    dev.set_atomic_file_writes(true);
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert!(!std::path::Path::new("out.txt").exists());
+    drop(dev);
+    assert_eq!(std::fs::read("out.txt").unwrap(), b"hi");
+}
+
+/// Stats the file named at 0x119, writing to 0x80 and storing `success` at 0x90
+#[rustfmt::skip]
+const STAT_ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x19, op::LIT, 0xa8, op::DEO2,
+    // #0004 .File0/length DEO2
+    op::LIT2, 0x00, 0x04, op::LIT, 0xaa, op::DEO2,
+    // #0080 .File0/stat DEO2
+    op::LIT2, 0x00, 0x80, op::LIT, 0xa4, op::DEO2,
+    // .File0/success DEI2 #90 STZ2 BRK
+    op::LIT, 0xa2, op::DEI2, op::LIT, 0x90, op::STZ2, op::BRK,
+    // @name
+];
+
+#[test]
+fn stat() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), [0; 0x2a]).unwrap();
+    std::fs::write(dir.path().join("big.bin"), vec![0; 0x10000]).unwrap();
+    std::fs::create_dir(dir.path().join("sub")).unwrap();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_root(dir.path()).build();
+
+    // Returns the stat string and success flag for the given name and length
+    let mut stat = |name: &str, len: u8| {
+        let extra = vm.reset(STAT_ROM);
+        dev.reset(extra);
+        vm.ram_write_byte(0x108, len);
+        for (i, c) in name.bytes().chain([0]).enumerate() {
+            vm.ram_write_byte(0x119 + i as u16, c);
+        }
+        for i in 0..16 {
+            vm.ram_write_byte(0x80 + i, b'.');
+        }
+        vm.run(&mut dev, 0x100);
+        let out = (0..len)
+            .map(|i| vm.ram_read_byte(0x80 + u16::from(i)))
+            .collect::<Vec<u8>>();
+        let success = u16::from_be_bytes([
+            vm.ram_read_byte(0x90),
+            vm.ram_read_byte(0x91),
+        ]);
+        (String::from_utf8(out).unwrap(), success)
+    };
+
+    assert_eq!(stat("a.txt", 4), ("002a".to_owned(), 4));
+    assert_eq!(stat("a.txt", 2), ("2a".to_owned(), 2));
+    assert_eq!(stat("a.txt", 1), ("?".to_owned(), 1));
+    assert_eq!(stat("big.bin", 4), ("????".to_owned(), 4));
+    assert_eq!(stat("big.bin", 6), ("010000".to_owned(), 6));
+    assert_eq!(stat("sub", 4), ("----".to_owned(), 4));
+    assert_eq!(stat("missing", 4), ("!!!!".to_owned(), 4));
+
+    // Paths outside the root aren't touched
+    assert_eq!(stat("../a.txt", 4), ("....".to_owned(), 0));
+}
+
+/// Deletes the file named at 0x112, storing `success` at 0x90
+#[rustfmt::skip]
+const DELETE_ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x12, op::LIT, 0xa8, op::DEO2,
+    // #01 .File0/delete DEO
+    op::LIT, 0x01, op::LIT, 0xa6, op::DEO,
+    // .File0/success DEI2 #90 STZ2 BRK
+    op::LIT, 0xa2, op::DEI2, op::LIT, 0x90, op::STZ2, op::BRK,
+    // @name
+];
+
+#[test]
+fn create_dirs_and_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_root(dir.path()).build();
+
+    // Runs a ROM with the given filename written at `addr`, then resets
+    let mut run = |rom: &[u8], addr: u16, name: &str, create: bool| {
+        let extra = vm.reset(rom);
+        dev.reset(extra);
+        dev.set_create_file_dirs(create);
+        for (i, c) in name.bytes().chain([0]).enumerate() {
+            vm.ram_write_byte(addr + i as u16, c);
+        }
+        vm.run(&mut dev, 0x100);
+        dev.reset(&[]); // close the file
+        u16::from_be_bytes([vm.ram_read_byte(0x90), vm.ram_read_byte(0x91)])
+    };
+    let path = dir.path().join("project/notes/out.txt");
+
+    // Directories aren't created by default
+    run(ROM, 0x115, "project/notes/out.txt", false);
+    assert!(!path.exists());
+
+    run(ROM, 0x115, "project/notes/out.txt", true);
+    assert_eq!(std::fs::read(&path).unwrap(), b"hi");
+
+    // Directories can only be deleted once they're empty
+    assert_eq!(run(DELETE_ROM, 0x112, "project/notes", false), 0xffff);
+    assert!(dir.path().join("project/notes").exists());
+    assert_eq!(run(DELETE_ROM, 0x112, "project/notes/out.txt", false), 0);
+    assert!(!path.exists());
+    assert_eq!(run(DELETE_ROM, 0x112, "project/notes", false), 0);
+    assert!(!dir.path().join("project/notes").exists());
+    assert!(dir.path().join("project").exists());
+}
+
+#[test]
+fn set_file_root() {
+    let a = tempfile::tempdir().unwrap();
+    let b = tempfile::tempdir().unwrap();
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_file_root(Some(a.path()));
+    assert_eq!(dev.devices().file.root(), Some(a.path()));
+
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Changing the root closes the open file, and is kept across resets
+    dev.set_file_root(Some(b.path()));
+    assert_eq!(std::fs::read(a.path().join("out.txt")).unwrap(), b"hi");
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    drop(dev);
+    assert_eq!(std::fs::read(b.path().join("out.txt")).unwrap(), b"hi");
+}
+
+#[test]
+fn memory_backend() {
+    let fs = MemoryFs::new();
+    fs.insert("assets/a.txt", &[0; 0x2a]).unwrap();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_backend(fs.clone()).build();
+
+    // Writes go to memory, and are visible once the file is closed
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(fs.get("out.txt"), None);
+    dev.reset(&[]);
+    assert_eq!(fs.get("out.txt").unwrap(), b"hi");
+
+    // Pre-populated files can be examined by the ROM
+    let extra = vm.reset(STAT_ROM);
+    dev.reset(extra);
+    for (i, c) in b"assets/a.txt\0".iter().enumerate() {
+        vm.ram_write_byte(0x119 + i as u16, *c);
+    }
+    vm.run(&mut dev, 0x100);
+    let out = (0..4)
+        .map(|i| vm.ram_read_byte(0x80 + i))
+        .collect::<Vec<u8>>();
+    assert_eq!(out, b"002a");
+
+    // Directories are created on demand, and listed in order
+    let mut b = fs.clone();
+    assert!(b.create(Path::new("new/b.txt"), false, false).is_err());
+    b.create_dir_all(Path::new("new")).unwrap();
+    b.create(Path::new("new/b.txt"), false, false)
+        .unwrap()
+        .write_all(b"abc")
+        .unwrap();
+    assert_eq!(fs.get("new/b.txt").unwrap(), b"abc");
+    let names = b
+        .read_dir(Path::new(""))
+        .unwrap()
+        .map(|d| d.unwrap().name.into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["assets", "new", "out.txt"]);
+    assert_eq!(
+        b.info(Path::new("new")).unwrap(),
+        FileInfo {
+            is_dir: true,
+            len: 0
+        }
+    );
+
+    // Only empty directories can be removed
+    assert!(b.remove(Path::new("new")).is_err());
+    b.remove(Path::new("new/b.txt")).unwrap();
+    b.remove(Path::new("new")).unwrap();
+    assert_eq!(
+        b.info(Path::new("new")).unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn overwrite_shorter_file() {
+    fn check(fs: &mut dyn FileBackend, path: &Path) {
+        for atomic in [false, true] {
+            let mut f = fs.create(path, false, atomic).unwrap();
+            f.write_all(b"a longer line").unwrap();
+            f.finish().unwrap();
+            let mut f = fs.create(path, false, atomic).unwrap();
+            f.write_all(b"short").unwrap();
+            f.finish().unwrap();
+            let mut data = vec![];
+            fs.open(path).unwrap().read_to_end(&mut data).unwrap();
+            assert_eq!(data, b"short", "atomic: {atomic}");
+        }
+    }
+    let dir = tempfile::tempdir().unwrap();
+    check(&mut StdFs, &dir.path().join("a.txt"));
+    check(&mut MemoryFs::new(), Path::new("a.txt"));
+}
+
+/// Reads a `0x8000`-byte chunk of `big.bin` into the upper half of RAM,
+/// storing the success flag at address 0; later chunks are read from `0x10c`
+#[rustfmt::skip]
+const STREAM_READ_ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x19, op::LIT, 0xa8, op::DEO2,
+    // #8000 .File0/length DEO2
+    op::LIT2, 0x80, 0x00, op::LIT, 0xaa, op::DEO2,
+    // @chunk #8000 .File0/read DEO2
+    op::LIT2, 0x80, 0x00, op::LIT, 0xac, op::DEO2,
+    // .File0/success DEI2 #00 STZ2 BRK
+    op::LIT, 0xa2, op::DEI2, op::LIT, 0x00, op::STZ2, op::BRK,
+    // @name "big.bin 00
+    b'b', b'i', b'g', b'.', b'b', b'i', b'n', 0,
+];
+
+/// Like [`STREAM_READ_ROM`], but writing chunks instead of reading them
+#[rustfmt::skip]
+const STREAM_WRITE_ROM: &[u8] = &[
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x19, op::LIT, 0xa8, op::DEO2,
+    // #8000 .File0/length DEO2
+    op::LIT2, 0x80, 0x00, op::LIT, 0xaa, op::DEO2,
+    // @chunk #8000 .File0/write DEO2
+    op::LIT2, 0x80, 0x00, op::LIT, 0xae, op::DEO2,
+    // .File0/success DEI2 #00 STZ2 BRK
+    op::LIT, 0xa2, op::DEI2, op::LIT, 0x00, op::STZ2, op::BRK,
+    // @name "big.bin 00
+    b'b', b'i', b'g', b'.', b'b', b'i', b'n', 0,
+];
+
+/// Builds a few megabytes of data which doesn't repeat every chunk
+fn big_data(len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect()
+}
+
+#[test]
+fn stream_large_read() {
+    let data = big_data(3 << 20 | 0x1234);
+    let fs = MemoryFs::new();
+    fs.insert("big.bin", &data).unwrap();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_backend(fs).build();
+    let extra = vm.reset(STREAM_READ_ROM);
+    dev.reset(extra);
+
+    let mut pc = 0x100;
+    for chunk in data.chunks(0x8000).chain([&[][..]]) {
+        vm.run(&mut dev, pc);
+        pc = 0x10c;
+        let n = u16::from_be_bytes([vm.ram_read_byte(0), vm.ram_read_byte(1)]);
+        assert_eq!(usize::from(n), chunk.len());
+        assert_eq!(&vm.ram_slice(0x8000, chunk.len()).unwrap().0, &chunk);
+    }
+    let file = &dev.devices().file;
+    assert_eq!(file.position(0), Some(data.len() as u64));
+    assert_eq!(file.open_len(0), Some(data.len() as u64));
+}
+
+#[test]
+fn stream_large_write() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = big_data(3 << 20);
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_root(dir.path()).build();
+    let extra = vm.reset(STREAM_WRITE_ROM);
+    dev.reset(extra);
+
+    let mut pc = 0x100;
+    for (i, chunk) in data.chunks(0x8000).enumerate() {
+        vm.ram_slice_mut(0x8000, chunk.len())
+            .unwrap()
+            .0
+            .copy_from_slice(chunk);
+        vm.run(&mut dev, pc);
+        pc = 0x10c;
+        let n = u16::from_be_bytes([vm.ram_read_byte(0), vm.ram_read_byte(1)]);
+        assert_eq!(usize::from(n), chunk.len());
+        assert_eq!(
+            dev.devices().file.position(0),
+            Some(((i + 1) * 0x8000) as u64)
+        );
+    }
+    dev.reset(&[]);
+    assert_eq!(std::fs::read(dir.path().join("big.bin")).unwrap(), data);
+}
+
+/// Copies `src.bin` to `dst.bin` in `0x1000`-byte chunks, reading with File0
+/// and writing with File1
+#[rustfmt::skip]
+const COPY_ROM: &[u8] = &[
+    // ;src .File0/name DEO2
+    op::LIT2, 0x01, 0x2a, op::LIT, 0xa8, op::DEO2,
+    // ;dst .File1/name DEO2
+    op::LIT2, 0x01, 0x32, op::LIT, 0xb8, op::DEO2,
+    // #1000 .File0/length DEO2
+    op::LIT2, 0x10, 0x00, op::LIT, 0xaa, op::DEO2,
+    // @loop #8000 .File0/read DEO2
+    op::LIT2, 0x80, 0x00, op::LIT, 0xac, op::DEO2,
+    // .File0/success DEI2 DUP2 .File1/length DEO2
+    op::LIT, 0xa2, op::DEI2, op::DUP2, op::LIT, 0xba, op::DEO2,
+    // #8000 .File1/write DEO2
+    op::LIT2, 0x80, 0x00, op::LIT, 0xbe, op::DEO2,
+    // ORA ?loop BRK
+    op::ORA, op::JCI, 0xff, 0xe9, op::BRK,
+    // @src "src.bin 00 @dst "dst.bin 00
+    b's', b'r', b'c', b'.', b'b', b'i', b'n', 0,
+    b'd', b's', b't', b'.', b'b', b'i', b'n', 0,
+];
+
+#[test]
+fn copy_with_both_devices() {
+    let data = big_data(0x12345);
+    let fs = MemoryFs::new();
+    fs.insert("src.bin", &data).unwrap();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder().file_backend(fs.clone()).build();
+    let extra = vm.reset(COPY_ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Each device keeps its own handle open
+    let file = &dev.devices().file;
+    assert_eq!(file.open_path(0), Some(Path::new("src.bin")));
+    assert_eq!(file.open_path(1), Some(Path::new("dst.bin")));
+    assert!(!file.is_writing(0));
+    assert!(file.is_writing(1));
+    assert_eq!(file.position(0), Some(data.len() as u64));
+    assert_eq!(file.position(1), Some(data.len() as u64));
+
+    dev.reset(&[]);
+    assert_eq!(fs.get("dst.bin").unwrap(), data);
+}
+
+/// Reads 4 bytes of `a.txt` into address 0x80, storing the success flag at
+/// address 0 straight away, then at address 2 from the file vector
+#[rustfmt::skip]
+const ASYNC_ROM: &[u8] = &[
+    // ;on-file .File0/vector DEO2
+    op::LIT2, 0x01, 0x1f, op::LIT, 0xa0, op::DEO2,
+    // ;name .File0/name DEO2
+    op::LIT2, 0x01, 0x26, op::LIT, 0xa8, op::DEO2,
+    // #0004 .File0/length DEO2
+    op::LIT2, 0x00, 0x04, op::LIT, 0xaa, op::DEO2,
+    // #0080 .File0/read DEO2
+    op::LIT2, 0x00, 0x80, op::LIT, 0xac, op::DEO2,
+    // .File0/success DEI2 #00 STZ2 BRK
+    op::LIT, 0xa2, op::DEI2, op::LIT, 0x00, op::STZ2, op::BRK,
+    // @on-file .File0/success DEI2 #02 STZ2 BRK
+    op::LIT, 0xa2, op::DEI2, op::LIT, 0x02, op::STZ2, op::BRK,
+    // @name "a.txt 00
+    b'a', b'.', b't', b'x', b't', 0,
+];
+
+#[test]
+fn async_io() {
+    let fs = MemoryFs::new();
+    fs.insert("a.txt", b"abcd").unwrap();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::builder()
+        .file_backend(fs.clone())
+        .async_file_io(true)
+        .build();
+    assert!(dev.devices().file.is_async());
+
+    // The read finishes in the background, then calls the file vector
+    let extra = vm.reset(ASYNC_ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(vm.ram_read_byte(0x80), 0);
+    assert_eq!(vm.ram_read_byte(0x01), 0);
+    while dev.file(&mut vm) {
+        std::thread::yield_now();
+    }
+    let out = (0..4)
+        .map(|i| vm.ram_read_byte(0x80 + i))
+        .collect::<Vec<u8>>();
+    assert_eq!(out, b"abcd");
+    assert_eq!(vm.ram_read_byte(0x01), 0);
+    assert_eq!(vm.ram_read_byte(0x03), 4);
+    assert_eq!(dev.devices().file.position(0), Some(4));
+
+    // Resetting waits for pending writes, then closes the file
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    dev.reset(&[]);
+    assert_eq!(fs.get("out.txt").unwrap(), b"hi");
+    assert_eq!(dev.devices().file.pending(), 0);
+
+    // Synchronous mode finishes operations during the `DEO`
+    dev.set_async_file_io(false);
+    let extra = vm.reset(ASYNC_ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(vm.ram_read_byte(0x80), b'a');
+    assert_eq!(vm.ram_read_byte(0x01), 4);
+}
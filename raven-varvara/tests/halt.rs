@@ -0,0 +1,23 @@
+use raven_varvara::Varvara;
+use uxn::{op, Backend, Halt, Uxn, UxnRam};
+
+#[test]
+fn exit_code() {
+    // #81 .System/state DEO BRK
+    let rom = [op::LIT, 0x81, op::LIT, 0x0f, op::DEO, op::BRK];
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+
+    let h = vm.run_halt(&mut dev, 0x100);
+    assert_eq!(
+        h,
+        Halt::Exit {
+            pc: 0x105,
+            code: Some(1)
+        }
+    );
+    assert_eq!(dev.output(&vm).exit, Some(1));
+}
@@ -0,0 +1,88 @@
+use raven_varvara::{run_headless, HeadlessLimits};
+use uxn::op;
+
+/// Echoes console input to stdout, exiting with code 1 on `q`
+const ECHO: &[u8] = &[
+    // |0100 ;on-console .Console/vector DEO2 BRK
+    op::LIT | 0x20,
+    0x01,
+    0x07,
+    op::LIT,
+    0x10,
+    op::DEO | 0x20,
+    op::BRK,
+    // @on-console .Console/read DEI DUP .Console/write DEO
+    op::LIT,
+    0x12,
+    op::DEI,
+    op::DUP,
+    op::LIT,
+    0x18,
+    op::DEO,
+    // LIT "q" EQU ?quit BRK
+    op::LIT,
+    b'q',
+    op::EQU,
+    op::JCI,
+    0x00,
+    0x01,
+    op::BRK,
+    // @quit #81 .System/state DEO BRK
+    op::LIT,
+    0x81,
+    op::LIT,
+    0x0f,
+    op::DEO,
+    op::BRK,
+];
+
+#[test]
+fn echo() {
+    let out = run_headless(ECHO, b"hello", HeadlessLimits::default());
+    // The final null byte is the end-of-input event
+    assert_eq!(out.stdout, b"hello\0");
+    assert!(out.stderr.is_empty());
+    assert_eq!(out.exit, None);
+    assert!(out.frames.is_empty());
+}
+
+#[test]
+fn exit() {
+    let out = run_headless(ECHO, b"hiqthere", HeadlessLimits::new(2));
+    assert_eq!(out.stdout, b"hiq");
+    assert_eq!(out.exit, Some(1));
+    assert!(out.frames.is_empty());
+}
+
+#[test]
+fn frames() {
+    let out = run_headless(ECHO, b"", HeadlessLimits::new(2));
+    assert_eq!(out.frames.len(), 2);
+    for f in &out.frames {
+        assert_eq!(f.size, (512, 320));
+        assert_eq!(f.data.len(), 512 * 320 * 4);
+    }
+}
+
+#[test]
+fn fuel() {
+    // |0100 @loop !loop
+    let rom = [op::JMI, 0xff, 0xfd];
+    let out =
+        run_headless(&rom, b"hello", HeadlessLimits::new(2).with_fuel(1000));
+    assert!(out.out_of_fuel);
+    assert!(out.frames.is_empty());
+
+    // The budget covers the whole run, not each vector: the reset vector takes
+    // 4 instructions and each console vector takes 9, so this stops just after
+    // the second character is written
+    let limits = HeadlessLimits::new(100).with_fuel(20);
+    let out = run_headless(ECHO, b"hello", limits);
+    assert!(out.out_of_fuel);
+    assert_eq!(out.stdout, b"he");
+
+    let out = run_headless(ECHO, b"hi", HeadlessLimits::new(2).with_fuel(1000));
+    assert!(!out.out_of_fuel);
+    assert_eq!(out.stdout, b"hi\0");
+    assert_eq!(out.frames.len(), 2);
+}
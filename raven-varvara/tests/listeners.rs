@@ -0,0 +1,92 @@
+use std::sync::{Arc, Mutex};
+
+use raven_varvara::Varvara;
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Prints `hi`, resizes the screen, plays a note, then exits with code 1
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // LIT "h .Console/write DEO LIT "i .Console/error DEO
+    op::LIT, b'h', op::LIT, 0x18, op::DEO,
+    op::LIT, b'i', op::LIT, 0x19, op::DEO,
+    // #0040 .Screen/width DEO2 #0030 .Screen/height DEO2
+    op::LIT2, 0x00, 0x40, op::LIT, 0x22, op::DEO2,
+    op::LIT2, 0x00, 0x30, op::LIT, 0x24, op::DEO2,
+    // #0010 .Audio2/length DEO2 #ff .Audio2/volume DEO #bc .Audio2/pitch DEO
+    op::LIT2, 0x00, 0x10, op::LIT, 0x5a, op::DEO2,
+    op::LIT, 0xff, op::LIT, 0x5e, op::DEO,
+    op::LIT, 0xbc, op::LIT, 0x5f, op::DEO,
+    // #81 .System/state DEO BRK
+    op::LIT, 0x81, op::LIT, 0x0f, op::DEO, op::BRK,
+];
+
+#[derive(Debug, Default, PartialEq)]
+struct Log {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    resize: Vec<(u16, u16)>,
+    frames: Vec<((u16, u16), usize)>,
+    exit: Vec<i32>,
+    notes: Vec<usize>,
+}
+
+#[test]
+fn listeners() {
+    let log = Arc::new(Mutex::new(Log::default()));
+    let mut dev = Varvara::new();
+    let l = log.clone();
+    dev.on_stdout(move |c| l.lock().unwrap().stdout.push(c));
+    let l = log.clone();
+    dev.on_stderr(move |c| l.lock().unwrap().stderr.push(c));
+    let l = log.clone();
+    dev.on_resize(move |s| l.lock().unwrap().resize.push(s));
+    let l = log.clone();
+    dev.on_frame(move |s, f| l.lock().unwrap().frames.push((s, f.len())));
+    let l = log.clone();
+    dev.on_exit(move |c| l.lock().unwrap().exit.push(c));
+    let l = log.clone();
+    dev.on_audio_note_end(move |i| l.lock().unwrap().notes.push(i));
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    {
+        let log = log.lock().unwrap();
+        assert_eq!(log.stdout, b"h");
+        assert_eq!(log.stderr, b"i");
+        assert_eq!(log.resize, [(0x40, 320), (0x40, 0x30)]);
+        assert_eq!(log.exit, [1]);
+        assert!(log.frames.is_empty());
+    }
+
+    assert!(dev.redraw(&mut vm));
+    assert!(!dev.redraw(&mut vm));
+    assert_eq!(
+        log.lock().unwrap().frames,
+        [((0x40, 0x30), 0x40 * 0x30 * 4)]
+    );
+
+    // Play the note to completion
+    let stream = dev.audio_streams()[2].clone();
+    let mut buf = [0f32; 4096];
+    for _ in 0..100 {
+        stream.lock().unwrap().next(&mut buf);
+    }
+    assert!(dev.audio(&mut vm));
+    assert_eq!(log.lock().unwrap().notes, [2]);
+
+    // Console output sent to listeners isn't buffered, but other output is
+    // still accumulated as usual
+    let out = dev.output(&vm);
+    assert!(out.stdout.is_empty());
+    assert!(out.stderr.is_empty());
+    assert_eq!(out.exit, Some(1));
+
+    // Listeners can be removed, after which output is buffered again
+    dev.clear_listeners();
+    vm.run(&mut dev, 0x100);
+    assert_eq!(log.lock().unwrap().stdout, b"h");
+    assert_eq!(dev.output(&vm).stdout, b"h");
+}
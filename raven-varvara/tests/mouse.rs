@@ -0,0 +1,229 @@
+use raven_varvara::{
+    MouseState, Touch, TouchPhase, Varvara, SCROLL_PIXELS_PER_LINE,
+};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Accumulates `Mouse/scrolly` into the zero page on every mouse event
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // |0100 ;on-mouse .Mouse/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x90, op::DEO2, op::BRK,
+    // @on-mouse .Mouse/scrolly DEI2 #00 LDZ2 ADD2 #00 STZ2 BRK
+    op::LIT, 0x9c, op::DEI2, op::LIT, 0x00, op::LDZ2, op::ADD2,
+    op::LIT, 0x00, op::STZ2, op::BRK,
+];
+
+fn scroll(pixels: f32, lines: f32) -> MouseState {
+    MouseState::default()
+        .with_scroll((0.0, pixels))
+        .with_scroll_lines((0.0, lines))
+}
+
+#[test]
+fn scroll_units() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // A single wheel notch is delivered immediately
+    dev.mouse(&mut vm, scroll(0.0, 1.0));
+    assert_eq!(vm.ram_read_word(0x00), 1);
+
+    // Pixels are normalized into lines
+    dev.mouse(&mut vm, scroll(SCROLL_PIXELS_PER_LINE * 2.0, 0.0));
+    assert_eq!(vm.ram_read_word(0x00), 3);
+
+    // Fractional lines accumulate until they add up to a full line
+    dev.mouse(&mut vm, scroll(0.0, 0.5));
+    assert_eq!(vm.ram_read_word(0x00), 3);
+    dev.mouse(&mut vm, scroll(SCROLL_PIXELS_PER_LINE / 2.0, 0.0));
+    assert_eq!(vm.ram_read_word(0x00), 4);
+
+    // Scrolling up is negative
+    dev.mouse(&mut vm, scroll(0.0, -2.0));
+    assert_eq!(vm.ram_read_word(0x00), 2);
+}
+
+/// Records `Mouse/scrolly` from its vector, with a probe to read it later
+#[rustfmt::skip]
+const PROBE: &[u8] = &[
+    // |0100 ;on-mouse .Mouse/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x90, op::DEO2, op::BRK,
+    // @on-mouse .Mouse/scrolly DEI2 #00 STZ2 BRK
+    op::LIT, 0x9c, op::DEI2, op::LIT, 0x00, op::STZ2, op::BRK,
+    // @probe .Mouse/scrolly DEI2 #02 STZ2 BRK
+    op::LIT, 0x9c, op::DEI2, op::LIT, 0x02, op::STZ2, op::BRK,
+    // @on-mouse-write #1234 .Mouse/scrolly DEO2 BRK
+    op::LIT2, 0x12, 0x34, op::LIT, 0x9c, op::DEO2, op::BRK,
+];
+
+#[test]
+fn scroll_reset() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(PROBE);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Scrolling is visible to the vector, then reset to 0
+    dev.mouse(&mut vm, scroll(0.0, 1.0));
+    vm.run(&mut dev, 0x10e);
+    assert_eq!(vm.ram_read_word(0x00), 1);
+    assert_eq!(vm.ram_read_word(0x02), 0);
+
+    // Values written by the vector itself are kept
+    vm.write_dev_mem(0x91, 0x15);
+    dev.mouse(&mut vm, scroll(0.0, 1.0));
+    vm.run(&mut dev, 0x10e);
+    assert_eq!(vm.ram_read_word(0x02), 0x1234);
+
+    // Without a vector, scrolling is discarded
+    vm.write_dev_mem(0x90, 0x00);
+    vm.write_dev_mem(0x91, 0x00);
+    dev.mouse(&mut vm, scroll(0.0, 1.0));
+    vm.run(&mut dev, 0x10e);
+    assert_eq!(vm.ram_read_word(0x02), 0);
+}
+
+/// Stores `Mouse/state` and `Mouse/scrollx` on every mouse event
+#[rustfmt::skip]
+const STATE: &[u8] = &[
+    // |0100 ;on-mouse .Mouse/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x90, op::DEO2, op::BRK,
+    // @on-mouse .Mouse/state DEI #00 STZ .Mouse/scrollx DEI2 #02 STZ2 BRK
+    op::LIT, 0x96, op::DEI, op::LIT, 0x00, op::STZ,
+    op::LIT, 0x9a, op::DEI2, op::LIT, 0x02, op::STZ2, op::BRK,
+];
+
+#[test]
+fn buttons_and_horizontal_scroll() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(STATE);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Extra buttons are passed through, including chords
+    let m = |buttons, x| {
+        MouseState::default()
+            .with_buttons(buttons)
+            .with_scroll_lines((x, 0.0))
+    };
+    dev.mouse(&mut vm, m(0b11001, 0.0));
+    assert_eq!(vm.ram_read_byte(0x00), 0b11001);
+    assert_eq!(dev.devices().mouse.buttons(), 0b11001);
+
+    // Unknown bits are masked off
+    dev.mouse(&mut vm, m(0xf0, 0.0));
+    assert_eq!(vm.ram_read_byte(0x00), 0x10);
+
+    // Horizontal scrolling accumulates fractions, in both directions
+    dev.mouse(&mut vm, m(0x10, 0.75));
+    assert_eq!(vm.ram_read_word(0x02), 0);
+    dev.mouse(&mut vm, m(0x10, 0.5));
+    assert_eq!(vm.ram_read_word(0x02), 1);
+    dev.mouse(&mut vm, m(0x10, -2.5));
+    assert_eq!(vm.ram_read_word(0x02), (-2i16) as u16);
+
+    // Bad values from the host are ignored
+    dev.mouse(&mut vm, m(0x10, f32::NAN));
+    dev.mouse(&mut vm, m(0x10, 1.25));
+    assert_eq!(vm.ram_read_word(0x02), 1);
+}
+
+/// Stores `Mouse/x` and `Mouse/y` on every mouse event
+#[rustfmt::skip]
+const POSITION: &[u8] = &[
+    // |0100 ;on-mouse .Mouse/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x90, op::DEO2, op::BRK,
+    // @on-mouse .Mouse/x DEI2 #00 STZ2 .Mouse/y DEI2 #02 STZ2 BRK
+    op::LIT, 0x92, op::DEI2, op::LIT, 0x00, op::STZ2,
+    op::LIT, 0x94, op::DEI2, op::LIT, 0x02, op::STZ2, op::BRK,
+];
+
+#[test]
+fn capture() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(POSITION);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let m = |pos, delta| MouseState::new(pos).with_delta(delta);
+    dev.mouse(&mut vm, m((10.0, 20.0), (0.0, 0.0)));
+    assert_eq!(vm.ram_read_word(0x00), 10);
+    assert_eq!(vm.ram_read_word(0x02), 20);
+    assert!(!dev.output(&vm).capture_mouse);
+
+    // In relative mode, the absolute position is ignored
+    dev.set_mouse_capture(true);
+    assert!(dev.output(&vm).capture_mouse);
+    assert!(dev.devices().mouse.captured());
+    dev.mouse(&mut vm, m((100.0, 100.0), (5.0, -3.0)));
+    assert_eq!(vm.ram_read_word(0x00), 15);
+    assert_eq!(vm.ram_read_word(0x02), 17);
+
+    // Motion isn't bounded by the screen, and wraps around
+    dev.mouse(&mut vm, m((0.0, 0.0), (1000.0, -20.0)));
+    assert_eq!(vm.ram_read_word(0x00), 1015);
+    assert_eq!(vm.ram_read_word(0x02), (-3i16) as u16);
+
+    // Leaving relative mode returns to absolute positions
+    dev.set_mouse_capture(false);
+    dev.mouse(&mut vm, m((30.0, 40.0), (1.0, 1.0)));
+    assert_eq!(vm.ram_read_word(0x00), 30);
+    assert_eq!(vm.ram_read_word(0x02), 40);
+}
+
+#[test]
+fn touch() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(POSITION);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let t = |id, phase, x| Touch {
+        id,
+        phase,
+        pos: (x, 5.0),
+    };
+    let buttons = |dev: &Varvara| dev.devices().mouse.buttons();
+
+    // A single finger moves the cursor and holds the left button
+    dev.touch(&mut vm, t(7, TouchPhase::Start, 10.0));
+    assert_eq!(vm.ram_read_word(0x00), 10);
+    assert_eq!(vm.ram_read_word(0x02), 5);
+    assert_eq!(buttons(&dev), 0b001);
+    dev.touch(&mut vm, t(7, TouchPhase::Move, 12.0));
+    assert_eq!(vm.ram_read_word(0x00), 12);
+
+    // A second finger switches to the right button, without moving
+    dev.touch(&mut vm, t(8, TouchPhase::Start, 50.0));
+    assert_eq!(vm.ram_read_word(0x00), 12);
+    assert_eq!(buttons(&dev), 0b100);
+    dev.touch(&mut vm, t(9, TouchPhase::Start, 60.0));
+    assert_eq!(buttons(&dev), 0b010);
+    assert_eq!(dev.devices().mouse.touches(), 3);
+
+    // Lifting the first finger hands the position to the next one
+    dev.touch(&mut vm, t(7, TouchPhase::End, 12.0));
+    assert_eq!(vm.ram_read_word(0x00), 50);
+    assert_eq!(buttons(&dev), 0b100);
+    dev.touch(&mut vm, t(9, TouchPhase::Cancel, 60.0));
+    dev.touch(&mut vm, t(8, TouchPhase::End, 55.0));
+    assert_eq!(buttons(&dev), 0);
+    assert_eq!(dev.devices().mouse.touches(), 0);
+
+    // Unknown touches are ignored
+    dev.touch(&mut vm, t(1, TouchPhase::Move, 80.0));
+    assert_eq!(vm.ram_read_word(0x00), 50);
+    assert_eq!(buttons(&dev), 0);
+}
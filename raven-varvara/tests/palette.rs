@@ -0,0 +1,73 @@
+use raven_varvara::Varvara;
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Writes the high byte of `System/r`, then reads back all color registers
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // #a0 .System/r DEO
+    op::LIT, 0xa0, op::LIT, 0x08, op::DEO,
+    // .System/r DEI2 #00 STZ2
+    op::LIT, 0x08, op::DEI2, op::LIT, 0x00, op::STZ2,
+    // .System/g DEI2 #02 STZ2
+    op::LIT, 0x0a, op::DEI2, op::LIT, 0x02, op::STZ2,
+    // .System/b DEI2 #04 STZ2
+    op::LIT, 0x0c, op::DEI2, op::LIT, 0x04, op::STZ2,
+    op::BRK,
+];
+
+#[test]
+fn default_palette() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_default_palette(0x1234, 0x5678, 0x9abc);
+
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The partial write only replaces one byte of the red register
+    assert_eq!(vm.ram_read_word(0x00), 0xa034);
+    assert_eq!(vm.ram_read_word(0x02), 0x5678);
+    assert_eq!(vm.ram_read_word(0x04), 0x9abc);
+
+    // The screen is drawn with the effective palette (color 0 is the first
+    // nibble of each register)
+    let out = dev.output(&vm);
+    assert_eq!(out.frame[..4], [0x99, 0x55, 0xaa, 0xff]);
+
+    // Resetting the system returns to the default palette
+    let extra = vm.reset(&[op::BRK]);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    let out = dev.output(&vm);
+    assert_eq!(out.frame[..4], [0x99, 0x55, 0x11, 0xff]);
+}
+
+#[test]
+fn palette_changes() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_default_palette(0x1234, 0x5678, 0x9abc);
+    let extra = vm.reset(&[op::BRK]);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The first output after a reset reports the palette
+    let colors = [0xff115599, 0xff2266aa, 0xff3377bb, 0xff4488cc];
+    assert_eq!(dev.output(&vm).palette, Some(colors));
+    assert_eq!(dev.output(&vm).palette, None);
+
+    // Writing the color registers reports the new palette
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    let _ = dev.output(&vm);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(dev.output(&vm).palette.unwrap()[0], 0xffaa5599);
+    assert_eq!(dev.output(&vm).palette, None);
+
+    // ...as does changing the default palette
+    dev.set_default_palette(0x1234, 0x0678, 0x9abc);
+    assert_eq!(dev.output(&vm).palette.unwrap()[0], 0xffaa0099);
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use raven_varvara::{ports::name_of, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+#[test]
+fn port_names() {
+    assert_eq!(name_of(0x00), None);
+    assert_eq!(name_of(0x02), Some("System/expansion"));
+    assert_eq!(name_of(0x0e), Some("System/debug"));
+    assert_eq!(name_of(0x18), Some("Console/write"));
+    assert_eq!(name_of(0x22), Some("Screen/width"));
+    assert_eq!(name_of(0x23), Some("Screen/width"));
+    assert_eq!(name_of(0x3f), Some("Audio0/pitch"));
+    assert_eq!(name_of(0x6f), Some("Audio3/pitch"));
+    assert_eq!(name_of(0x83), Some("Controller/key"));
+    assert_eq!(name_of(0x92), Some("Mouse/x"));
+    assert_eq!(name_of(0x9c), Some("Mouse/scrolly"));
+    assert_eq!(name_of(0xa2), Some("File0/success"));
+    assert_eq!(name_of(0xbe), Some("File1/write"));
+    assert_eq!(name_of(0xc7), Some("DateTime/dotw"));
+    assert_eq!(name_of(0xd0), None);
+}
+
+#[test]
+fn mapped_pages() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    assert_eq!(vm.ports_iter().count(), 0);
+
+    let mut dev = Varvara::new();
+    // #1234 .Screen/x DEO2 BRK
+    let rom = vec![op::LIT2, 0x12, 0x34, op::LIT, 0x28, op::DEO2, op::BRK];
+    dev.reset_from(&mut vm, Arc::new(rom.into()));
+    vm.run(&mut dev, 0x100);
+    let bases: Vec<u8> = vm.ports_iter().map(|p| p.base()).collect();
+    assert_eq!(
+        bases,
+        [
+            0x00, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x80, 0x90, 0xa0, 0xb0,
+            0xc0
+        ]
+    );
+    assert!(vm.is_mapped(0x2f));
+    assert!(!vm.is_mapped(0x70));
+
+    let screen = vm.ports_iter().find(|p| p.base() == 0x20).unwrap();
+    assert_eq!(screen.bytes()[8..10], [0x12, 0x34]);
+
+    // Mapped pages are kept through a reset
+    let _ = vm.reset(&[]);
+    assert_eq!(vm.ports_iter().count(), 12);
+    vm.unmap_pages();
+    assert_eq!(vm.ports_iter().count(), 0);
+}
+
+#[test]
+fn load_rom_maps_pages() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.load_rom(&mut vm, &[op::BRK]);
+    assert_eq!(vm.ports_iter().count(), 12);
+    assert!(vm.is_mapped(0x2f));
+    assert!(!vm.is_mapped(0x70));
+}
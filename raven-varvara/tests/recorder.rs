@@ -0,0 +1,111 @@
+use raven_varvara::{Recorder, Varvara};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Sets a red and black palette, then draws a black pixel at (2, 1)
+///
+/// A second vector (at `0x100 + MOVE`) draws another pixel at (3, 1).
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // #f00f .System/r DEO2
+    op::LIT2, 0xf0, 0x0f, op::LIT, 0x08, op::DEO2,
+    // #0002 .Screen/x DEO2 #0001 .Screen/y DEO2
+    op::LIT2, 0x00, 0x02, op::LIT, 0x28, op::DEO2,
+    op::LIT2, 0x00, 0x01, op::LIT, 0x2a, op::DEO2,
+    // #01 .Screen/pixel DEO BRK
+    op::LIT, 0x01, op::LIT, 0x2e, op::DEO, op::BRK,
+    // #0003 .Screen/x DEO2 #01 .Screen/pixel DEO BRK
+    op::LIT2, 0x00, 0x03, op::LIT, 0x28, op::DEO2,
+    op::LIT, 0x01, op::LIT, 0x2e, op::DEO, op::BRK,
+];
+const MOVE: u16 = 24;
+
+/// Output buffer which can be inspected after the recorder is finished
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Records three frames, drawing a second pixel before the third
+fn record(rec: Recorder) -> (u16, u16) {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    assert!(dev.start_recording(rec).is_none());
+    assert!(dev.is_recording());
+    dev.redraw(&mut vm);
+    dev.redraw(&mut vm);
+    vm.run(&mut dev, 0x100 + MOVE);
+    dev.redraw(&mut vm);
+    let rec = dev.stop_recording().unwrap();
+    assert!(!dev.is_recording());
+    assert_eq!(rec.frames(), 3);
+    rec.finish().unwrap();
+
+    // Frames are no longer captured
+    dev.redraw(&mut vm);
+    dev.devices().screen.size()
+}
+
+#[test]
+fn y4m() {
+    let out = Shared::default();
+    let (w, h) = record(Recorder::y4m(out.clone()));
+    let data = out.0.lock().unwrap().clone();
+
+    let header = format!("YUV4MPEG2 W{w} H{h} F60:1 Ip A1:1 C444\n");
+    assert!(data.starts_with(header.as_bytes()));
+    let n = usize::from(w) * usize::from(h);
+    let frames: Vec<&[u8]> = data[header.len()..].chunks(6 + n * 3).collect();
+    assert_eq!(frames.len(), 3);
+
+    // Luma of red (color 0) and black (color 1), in the Y plane
+    let y = |f: &[u8], x: usize, y: usize| {
+        assert_eq!(&f[..6], b"FRAME\n");
+        f[6 + y * usize::from(w) + x]
+    };
+    assert_eq!(y(frames[0], 0, 0), 82);
+    assert_eq!(y(frames[0], 2, 1), 16);
+    assert_eq!(y(frames[1], 3, 1), 82);
+    assert_eq!(y(frames[2], 3, 1), 16);
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn apng() {
+    use image::{codecs::png::PngDecoder, AnimationDecoder};
+
+    let out = Shared::default();
+    let (w, h) = record(Recorder::apng(out.clone()));
+    let data = out.0.lock().unwrap().clone();
+
+    let dec = PngDecoder::new(std::io::Cursor::new(data)).unwrap();
+    assert!(dec.is_apng().unwrap());
+    let frames = dec.apng().unwrap().into_frames().collect_frames().unwrap();
+
+    // The first two frames are identical, so they're merged
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].delay().numer_denom_ms(), (100, 3));
+    assert_eq!(frames[1].delay().numer_denom_ms(), (50, 3));
+    let (a, b) = (frames[0].buffer(), frames[1].buffer());
+    assert_eq!(a.dimensions(), (u32::from(w), u32::from(h)));
+    assert_eq!(a.get_pixel(0, 0).0, [0xff, 0, 0, 0xff]);
+    assert_eq!(a.get_pixel(2, 1).0, [0, 0, 0, 0xff]);
+    assert_eq!(a.get_pixel(3, 1).0, [0xff, 0, 0, 0xff]);
+    assert_eq!(b.get_pixel(3, 1).0, [0, 0, 0, 0xff]);
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use raven_varvara::{
+    rom::{RomFile, RomInfo},
+    Varvara,
+};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+#[test]
+fn metadata() {
+    let mut rom = vec![0xa0, 0x01, 0x07, 0x80, 0x06, 0x37, 0x00];
+    rom.push(0x00); // version
+    rom.extend(b"Nasu\nSprite Editor\nBy Hundred Rabbits\nJan 1, 2024\0");
+    rom.extend([0x12, 0x34]);
+
+    let info = RomInfo::parse(&rom).unwrap();
+    assert_eq!(info.version, 0);
+    assert_eq!(info.name, "Nasu");
+    assert_eq!(info.details, Some("Sprite Editor"));
+    assert_eq!(info.author, Some("By Hundred Rabbits"));
+    assert_eq!(info.date, Some("Jan 1, 2024"));
+}
+
+#[test]
+fn partial_metadata() {
+    let mut rom = vec![0xa0, 0x01, 0x06, 0x80, 0x06, 0x37];
+    rom.push(0x01);
+    rom.extend(b"Left");
+
+    let info = RomInfo::parse(&rom).unwrap();
+    assert_eq!(info.version, 1);
+    assert_eq!(info.text, "Left");
+    assert_eq!(info.name, "Left");
+    assert_eq!(info.details, None);
+}
+
+#[test]
+fn no_metadata() {
+    assert_eq!(RomInfo::parse(&[]), None);
+
+    // Sets System/r instead of System/metadata
+    assert_eq!(RomInfo::parse(&[0xa0, 0x0f, 0x8f, 0x80, 0x08, 0x37]), None);
+
+    // Metadata block is out of range
+    assert_eq!(RomInfo::parse(&[0xa0, 0x02, 0x00, 0x80, 0x06, 0x37]), None);
+    assert_eq!(RomInfo::parse(&[0xa0, 0x00, 0x10, 0x80, 0x06, 0x37]), None);
+
+    // Invalid UTF-8
+    assert_eq!(
+        RomInfo::parse(&[0xa0, 0x01, 0x06, 0x80, 0x06, 0x37, 0x00, 0xff]),
+        None
+    );
+}
+
+/// Copies four bytes from the second expansion bank into the zero page
+#[rustfmt::skip]
+const EXPANSION: &[u8] = &[
+    // ;cmd .System/expansion DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x02, op::DEO2, op::BRK,
+    // @cmd [ 01 0004 0002 0010 0000 0000 ]
+    0x01, 0x00, 0x04, 0x00, 0x02, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn mapped_expansion() {
+    let mut rom = EXPANSION.to_vec();
+    rom.resize(0xff00 + 0x10020, 0);
+    rom[0xff00 + 0x10010..][..5].copy_from_slice(b"raven");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.rom");
+    std::fs::write(&path, &rom).unwrap();
+    // SAFETY: the file isn't modified while it's mapped
+    let file = unsafe { RomFile::open(&path) }.unwrap();
+    assert_eq!(&*file, rom.as_slice());
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.reset_from(&mut vm, Arc::new(file));
+    vm.run(&mut dev, 0x100);
+    assert_eq!(&vm.ram()[..5], b"rave\0");
+
+    // Loading the ROM up front gives the same result
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(&vm.ram()[..5], b"rave\0");
+}
+
+#[test]
+fn empty_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty.rom");
+    std::fs::write(&path, []).unwrap();
+    // SAFETY: the file isn't modified while it's mapped
+    let file = unsafe { RomFile::open(&path) }.unwrap();
+    assert!(file.is_empty());
+    assert!(RomFile::read(&path).unwrap().is_empty());
+
+    let missing = dir.path().join("missing.rom");
+    assert!(unsafe { RomFile::open(&missing) }.is_err());
+    assert!(RomFile::read(&missing).is_err());
+}
+
+#[test]
+fn metadata_port() {
+    // ;meta .System/metadata DEO2 BRK
+    let mut rom = vec![0xa0, 0x01, 0x07, 0x80, 0x06, 0x37, 0x00];
+    rom.push(0x01); // version
+    rom.extend(b"Orca\nLivecoding\0");
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    assert_eq!(dev.metadata(), None);
+
+    vm.run(&mut dev, 0x100);
+    let m = dev.metadata().unwrap();
+    assert_eq!(m.version, 1);
+    assert_eq!(m.name, "Orca");
+    assert_eq!(m.details.as_deref(), Some("Livecoding"));
+    assert_eq!(m.author, None);
+    assert_eq!(m, RomInfo::parse(&rom).unwrap().into());
+
+    // Metadata is cleared on reset
+    dev.reset(&[]);
+    assert_eq!(dev.metadata(), None);
+}
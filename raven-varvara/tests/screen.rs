@@ -0,0 +1,387 @@
+use raven_varvara::{Layer, PixelFormat, Region, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Sets a palette, then draws a single pixel at (2, 1)
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // #f00f .System/r DEO2
+    op::LIT2, 0xf0, 0x0f, op::LIT, 0x08, op::DEO2,
+    // #0002 .Screen/x DEO2 #0001 .Screen/y DEO2
+    op::LIT2, 0x00, 0x02, op::LIT, 0x28, op::DEO2,
+    op::LIT2, 0x00, 0x01, op::LIT, 0x2a, op::DEO2,
+    // #01 .Screen/pixel DEO BRK
+    op::LIT, 0x01, op::LIT, 0x2e, op::DEO, op::BRK,
+];
+
+#[test]
+fn copy_region() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let region = dev.copy_region(&vm, 1, 0, 3, 2);
+    assert_eq!(region.len(), 3 * 2 * 4);
+
+    let out = dev.output(&vm);
+    let (w, _h) = out.size;
+    let frame = out.frame.to_vec();
+    let row = |y: usize| {
+        let start = (y * usize::from(w) + 1) * 4;
+        frame[start..][..3 * 4].to_vec()
+    };
+    assert_eq!(region[..12], row(0));
+    assert_eq!(region[12..], row(1));
+    assert_ne!(region[16..20], region[12..16]);
+
+    // Pixels beyond the edge of the screen are zeros
+    let region = dev.copy_region(&vm, w - 1, 0, 2, 1);
+    let end = (usize::from(w) - 1) * 4;
+    assert_eq!(region[..4], frame[end..][..4]);
+    assert_eq!(region[4..], [0; 4]);
+    assert!(dev.copy_region(&vm, w, 0, 2, 2).iter().all(|b| *b == 0));
+}
+
+#[test]
+fn redraw_dirty() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The ROM drew a pixel, but has no screen vector
+    assert!(dev.redraw(&mut vm));
+    assert!(!dev.redraw(&mut vm));
+
+    // Palette changes also count as a change to the screen
+    dev.set_default_palette(0x0000, 0x1234, 0x0000);
+    assert!(dev.redraw(&mut vm));
+    assert!(!dev.redraw(&mut vm));
+}
+
+/// Draws solid sprites from `0x0200` with the given `auto` and `sprite` bytes
+///
+/// Returns the top-left corner of every 8×8 cell which was drawn, and the
+/// final values of the `x`, `y`, and `addr` ports.
+fn auto_sprite(auto: u8, sprite: u8, x: u8) -> (Vec<(u16, u16)>, [u16; 3]) {
+    #[rustfmt::skip]
+    let mut rom = vec![
+        // #08f4 .System/r DEO2
+        op::LIT2, 0x08, 0xf4, op::LIT, 0x08, op::DEO2,
+        // #auto .Screen/auto DEO
+        op::LIT, auto, op::LIT, 0x26, op::DEO,
+        // #00x .Screen/x DEO2 #0000 .Screen/y DEO2
+        op::LIT2, 0x00, x, op::LIT, 0x28, op::DEO2,
+        op::LIT2, 0x00, 0x00, op::LIT, 0x2a, op::DEO2,
+        // #0200 .Screen/addr DEO2
+        op::LIT2, 0x02, 0x00, op::LIT, 0x2c, op::DEO2,
+        // #sprite .Screen/sprite DEO
+        op::LIT, sprite, op::LIT, 0x2f, op::DEO,
+        // .Screen/x DEI2 #00 STZ2 .Screen/y DEI2 #02 STZ2
+        op::LIT, 0x28, op::DEI2, op::LIT, 0x00, op::STZ2,
+        op::LIT, 0x2a, op::DEI2, op::LIT, 0x02, op::STZ2,
+        // .Screen/addr DEI2 #04 STZ2 BRK
+        op::LIT, 0x2c, op::DEI2, op::LIT, 0x04, op::STZ2,
+        op::BRK,
+    ];
+    rom.resize(0x100, 0);
+    rom.extend([0xff; 0x40]);
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let ports = [0, 2, 4].map(|i| vm.ram_read_word(i));
+    let background = dev.copy_region(&vm, 255, 255, 1, 1);
+    let mut drawn = vec![];
+    for y in (0..64).step_by(8) {
+        for x in (0..64).step_by(8) {
+            if dev.copy_region(&vm, x, y, 8, 8) != background.repeat(64) {
+                drawn.push((x, y));
+            }
+        }
+    }
+    (drawn, ports)
+}
+
+#[test]
+fn auto_sprite_length() {
+    // Two sprites, advancing x and addr: sprites are drawn downwards, then
+    // the x port moves by one sprite
+    let (drawn, ports) = auto_sprite(0x15, 0x01, 8);
+    assert_eq!(drawn, [(8, 0), (8, 8)]);
+    assert_eq!(ports, [16, 0, 0x0210]);
+
+    // Three 2bpp sprites, advancing y and addr: sprites are drawn sideways,
+    // then the y port moves by one sprite
+    let (drawn, ports) = auto_sprite(0x26, 0x81, 8);
+    assert_eq!(drawn, [(8, 0), (16, 0), (24, 0)]);
+    assert_eq!(ports, [8, 8, 0x0230]);
+
+    // Advancing both x and y draws a diagonal; flipping horizontally
+    // reverses the direction along x
+    let (drawn, ports) = auto_sprite(0x23, 0x91, 32);
+    assert_eq!(drawn, [(32, 0), (24, 8), (16, 16)]);
+    assert_eq!(ports, [24, 8, 0x0200]);
+
+    // Without auto-length, only a single sprite is drawn
+    let (drawn, ports) = auto_sprite(0x07, 0x01, 0);
+    assert_eq!(drawn, [(0, 0)]);
+    assert_eq!(ports, [8, 8, 0x0208]);
+}
+
+#[test]
+fn dirty_region() {
+    /// Draws pixels at (5, 7) and (9, 3), then redraws the pixel at (2, 1)
+    #[rustfmt::skip]
+    const DRAW: &[u8] = &[
+        // #0005 .Screen/x DEO2 #0007 .Screen/y DEO2 #01 .Screen/pixel DEO
+        op::LIT2, 0x00, 0x05, op::LIT, 0x28, op::DEO2,
+        op::LIT2, 0x00, 0x07, op::LIT, 0x2a, op::DEO2,
+        op::LIT, 0x01, op::LIT, 0x2e, op::DEO,
+        // #0009 .Screen/x DEO2 #0003 .Screen/y DEO2 #01 .Screen/pixel DEO
+        op::LIT2, 0x00, 0x09, op::LIT, 0x28, op::DEO2,
+        op::LIT2, 0x00, 0x03, op::LIT, 0x2a, op::DEO2,
+        op::LIT, 0x01, op::LIT, 0x2e, op::DEO,
+        // #0002 .Screen/x DEO2 #0001 .Screen/y DEO2 #01 .Screen/pixel DEO
+        op::LIT2, 0x00, 0x02, op::LIT, 0x28, op::DEO2,
+        op::LIT2, 0x00, 0x01, op::LIT, 0x2a, op::DEO2,
+        op::LIT, 0x01, op::LIT, 0x2e, op::DEO,
+        op::BRK,
+    ];
+    let rom = [ROM, DRAW].concat();
+    let draw = 0x100 + ROM.len() as u16;
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The first output reports the whole screen
+    let out = dev.output(&vm);
+    let (w, h) = out.size;
+    assert_eq!(out.dirty, Some(Region { x: 0, y: 0, w, h }));
+    assert_eq!(dev.output(&vm).dirty, None);
+
+    // Only the bounding box of changed pixels is reported; redrawing a pixel
+    // with the same color doesn't count as a change
+    vm.run(&mut dev, draw);
+    let out = dev.output(&vm);
+    assert_eq!(
+        out.dirty,
+        Some(Region {
+            x: 5,
+            y: 3,
+            w: 5,
+            h: 5
+        })
+    );
+    let frame = out.frame.to_vec();
+    assert_eq!(dev.output(&vm).dirty, None);
+    vm.run(&mut dev, draw);
+    assert_eq!(dev.output(&vm).dirty, None);
+
+    // The partially-updated frame matches a freshly rendered one
+    let fresh = dev.copy_region(&vm, 0, 0, w, h);
+    assert_eq!(frame[..fresh.len()], fresh);
+
+    // A palette change redraws the entire screen
+    dev.set_default_palette(0x0000, 0x1234, 0x0000);
+    let out = dev.output(&vm);
+    assert!(out.palette.is_some());
+    assert_eq!(out.dirty, Some(Region { x: 0, y: 0, w, h }));
+}
+
+#[test]
+fn pixel_format() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The background is color 0, which is pure red
+    let out = dev.output(&vm);
+    let (w, h) = out.size;
+    assert_eq!(out.frame[..4], [0x00, 0x00, 0xff, 0xff]);
+
+    // Changing the format re-renders the entire frame
+    dev.set_pixel_format(PixelFormat::Rgba);
+    let out = dev.output(&vm);
+    assert_eq!(out.dirty, Some(Region { x: 0, y: 0, w, h }));
+    assert_eq!(out.frame[..4], [0xff, 0x00, 0x00, 0xff]);
+    assert_eq!(dev.copy_region(&vm, 0, 0, 1, 1), [0xff, 0x00, 0x00, 0xff]);
+
+    // The format is kept across a reset
+    dev.set_pixel_format(PixelFormat::Argb);
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    let out = dev.output(&vm);
+    assert_eq!(out.frame[..4], [0xff, 0xff, 0x00, 0x00]);
+}
+
+#[test]
+fn layers() {
+    /// Draws a foreground pixel at (3, 1) with color 2
+    #[rustfmt::skip]
+    const FG: &[u8] = &[
+        // #0003 .Screen/x DEO2 #42 .Screen/pixel DEO BRK
+        op::LIT2, 0x00, 0x03, op::LIT, 0x28, op::DEO2,
+        op::LIT, 0x42, op::LIT, 0x2e, op::DEO, op::BRK,
+    ];
+    let rom = [ROM, FG].concat();
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    vm.run(&mut dev, 0x100 + ROM.len() as u16);
+
+    let (w, h) = dev.devices().screen.size();
+    let n = usize::from(w) * usize::from(h);
+    let at = |x: usize, y: usize| y * usize::from(w) + x;
+    let bg: Vec<u8> = dev.devices().screen.layer(Layer::Background).collect();
+    let fg: Vec<u8> = dev.devices().screen.layer(Layer::Foreground).collect();
+    assert_eq!(bg.len(), n);
+    assert_eq!(fg.len(), n);
+    assert_eq!((bg[at(2, 1)], fg[at(2, 1)]), (1, 0));
+    assert_eq!((bg[at(3, 1)], fg[at(3, 1)]), (0, 2));
+
+    // Compositing the rendered layers reproduces the frame
+    let bg = dev.render_layer(&vm, Layer::Background);
+    let fg = dev.render_layer(&vm, Layer::Foreground);
+    assert_eq!(fg[at(2, 1) * 4..][..4], [0; 4]);
+    let composite: Vec<u8> = bg
+        .chunks(4)
+        .zip(fg.chunks(4))
+        .flat_map(|(b, f)| if f[3] == 0 { b } else { f }.to_vec())
+        .collect();
+    let out = dev.output(&vm);
+    assert_eq!(composite, out.frame[..n * 4]);
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn screenshot() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let png = dev.screenshot(&vm);
+    let img = image::load_from_memory(&png).unwrap().into_rgba8();
+    let (w, h) = dev.devices().screen.size();
+    assert_eq!(img.dimensions(), (u32::from(w), u32::from(h)));
+    assert_eq!(img.get_pixel(0, 0).0, [0xff, 0x00, 0x00, 0xff]);
+    assert_eq!(img.get_pixel(2, 1).0, [0x00, 0x00, 0x00, 0xff]);
+
+    // Screenshots from Output are the same, regardless of pixel format
+    for f in [PixelFormat::Bgra, PixelFormat::Rgba, PixelFormat::Argb] {
+        dev.set_pixel_format(f);
+        assert_eq!(dev.output(&vm).screenshot(), png);
+    }
+}
+
+#[test]
+fn large_screen() {
+    #[rustfmt::skip]
+    const BIG: &[u8] = &[
+        // #0800 .Screen/width DEO2 #0600 .Screen/height DEO2
+        op::LIT2, 0x08, 0x00, op::LIT, 0x22, op::DEO2,
+        op::LIT2, 0x06, 0x00, op::LIT, 0x24, op::DEO2,
+        // #07d0 .Screen/x DEO2 #05dc .Screen/y DEO2 #01 .Screen/pixel DEO
+        op::LIT2, 0x07, 0xd0, op::LIT, 0x28, op::DEO2,
+        op::LIT2, 0x05, 0xdc, op::LIT, 0x2a, op::DEO2,
+        op::LIT, 0x01, op::LIT, 0x2e, op::DEO,
+        // #2000 .Screen/width DEO2 ( too large, so ignored )
+        op::LIT2, 0x20, 0x00, op::LIT, 0x22, op::DEO2,
+        // .Screen/width DEI2 #00 STZ2 BRK
+        op::LIT, 0x22, op::DEI2, op::LIT, 0x00, op::STZ2, op::BRK,
+        // @shrink #0400 .Screen/width DEO2 BRK
+        op::LIT2, 0x04, 0x00, op::LIT, 0x22, op::DEO2, op::BRK,
+    ];
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(BIG);
+    dev.reset(extra);
+
+    // The initial size is reported after a reset
+    let out = dev.output(&vm);
+    assert_eq!(out.resized, Some(out.size));
+    assert_eq!(dev.output(&vm).resized, None);
+
+    vm.run(&mut dev, 0x100);
+    assert_eq!(vm.ram_read_word(0), 0x800);
+    let out = dev.output(&vm);
+    assert_eq!(out.resized, Some((2048, 1536)));
+    assert_eq!(out.size, (2048, 1536));
+    assert_eq!(out.frame.len(), 2048 * 1536 * 4);
+    assert_eq!(dev.output(&vm).resized, None);
+    let bg = |dev: &Varvara| -> Vec<u8> {
+        dev.devices().screen.layer(Layer::Background).collect()
+    };
+    assert_eq!(bg(&dev)[1500 * 2048 + 2000], 1);
+
+    // Resizing clears the screen
+    vm.run(&mut dev, 0x100 + BIG.len() as u16 - 7);
+    let out = dev.output(&vm);
+    assert_eq!(out.resized, Some((1024, 1536)));
+    assert_eq!(out.frame.len(), 1024 * 1536 * 4);
+    assert!(bg(&dev).iter().all(|p| *p == 0));
+}
+
+#[test]
+fn dirty_region_after_shrink() {
+    /// Shrinks the screen to 64x48
+    #[rustfmt::skip]
+    const SHRINK: &[u8] = &[
+        // #0040 .Screen/width DEO2 #0030 .Screen/height DEO2 BRK
+        op::LIT2, 0x00, 0x40, op::LIT, 0x22, op::DEO2,
+        op::LIT2, 0x00, 0x30, op::LIT, 0x24, op::DEO2,
+        op::BRK,
+    ];
+    let rom = [ROM, SHRINK].concat();
+    let shrink = 0x100 + ROM.len() as u16;
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.on_frame(|_, _| ());
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // The listener renders the full-size frame, then the ROM shrinks it
+    assert!(dev.redraw(&mut vm));
+    vm.run(&mut dev, shrink);
+
+    // Only the new screen is reported, so the region fits in the frame
+    let out = dev.output(&vm);
+    assert_eq!(out.size, (0x40, 0x30));
+    assert_eq!(
+        out.dirty,
+        Some(Region {
+            x: 0,
+            y: 0,
+            w: 0x40,
+            h: 0x30
+        })
+    );
+    assert_eq!(out.frame.len(), 0x40 * 0x30 * 4);
+}
@@ -23,11 +23,11 @@ fn get_snapshot(rom: &[u8]) -> Result<Snapshot, std::io::Error> {
     // Do some input!
     dev.mouse(
         &mut vm,
-        raven_varvara::MouseState {
-            pos: (size.0 as f32 / 2.0, size.1 as f32 / 2.0),
-            buttons: 1,
-            scroll: (0.0, 0.0),
-        },
+        raven_varvara::MouseState::new((
+            size.0 as f32 / 2.0,
+            size.1 as f32 / 2.0,
+        ))
+        .with_buttons(1),
     );
     dev.pressed(&mut vm, raven_varvara::Key::Right, false);
     dev.pressed(&mut vm, raven_varvara::Key::Char(b'a'), false);
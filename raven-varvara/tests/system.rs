@@ -0,0 +1,216 @@
+use raven_varvara::{rom::Symbols, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+/// Address of the data block in [`run`]
+const DATA: u16 = 0x1000;
+
+/// Runs each expansion command in turn, returning the zero page afterwards
+///
+/// `b"abcdef"` is loaded at [`DATA`] for use as a copy source.
+fn run(cmds: &[&[u8]]) -> Vec<u8> {
+    // Each command is sent with `LIT2 cmd LIT .System/expansion DEO2`
+    let mut addr = 0x100 + cmds.len() as u16 * 6 + 1;
+    let mut code = vec![];
+    let mut data = vec![];
+    for c in cmds {
+        let [hi, lo] = addr.to_be_bytes();
+        code.extend([op::LIT2, hi, lo, op::LIT, 0x02, op::DEO2]);
+        data.extend_from_slice(c);
+        addr += c.len() as u16;
+    }
+    code.push(op::BRK);
+    code.extend(data);
+    code.resize(usize::from(DATA - 0x100), 0);
+    code.extend(b"abcdef");
+
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(&code);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    vm.ram()[..0x40].to_vec()
+}
+
+#[test]
+fn fill() {
+    let z = run(&[
+        // Fill bank 2, then copy it into the zero page
+        &[0x00, 0x00, 0x04, 0x00, 0x02, 0x80, 0x00, 0x55],
+        &[
+            0x01, 0x00, 0x04, 0x00, 0x02, 0x80, 0x00, 0x00, 0x00, 0x00, 0x10,
+        ],
+        // Fill main memory directly
+        &[0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x12, 0xaa],
+    ]);
+    assert_eq!(z[0x10..0x15], [0x55, 0x55, 0xaa, 0xaa, 0x00]);
+}
+
+#[test]
+fn copy_overlapping() {
+    let [hi, lo] = DATA.to_be_bytes();
+    let z = run(&[
+        // Copy "abcdef" to 0x10 and 0x20
+        &[0x01, 0x00, 0x06, 0x00, 0x00, hi, lo, 0x00, 0x00, 0x00, 0x10],
+        &[0x01, 0x00, 0x06, 0x00, 0x00, hi, lo, 0x00, 0x00, 0x00, 0x20],
+        // Copy 4 bytes two places to the right, going left-to-right...
+        &[
+            0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x12,
+        ],
+        // ...and right-to-left
+        &[
+            0x02, 0x00, 0x04, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x22,
+        ],
+    ]);
+    assert_eq!(&z[0x10..0x16], b"ababab");
+    assert_eq!(&z[0x20..0x26], b"ababcd");
+}
+
+#[test]
+fn invalid_bank() {
+    let z = run(&[
+        // Banks past the end of expansion memory are ignored
+        &[0x00, 0x00, 0x04, 0x00, 0x10, 0x00, 0x10, 0xff],
+        &[
+            0x01, 0x00, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10,
+        ],
+        &[
+            0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x10,
+        ],
+        // Later commands still work
+        &[0x00, 0x00, 0x01, 0x00, 0x0f, 0x00, 0x10, 0x11],
+        &[
+            0x01, 0x00, 0x01, 0x00, 0x0f, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10,
+        ],
+    ]);
+    assert_eq!(z[0x10..0x14], [0x11, 0, 0, 0]);
+}
+
+/// Labels for the ROM in [`debug_dump`], ending with a truncated entry
+#[rustfmt::skip]
+const SYMBOL_FILE: &[u8] = b"\x01\x00on-reset\0\x01\x08sub\0\x01\x09data\0\x01";
+
+#[test]
+fn symbols() {
+    let s = Symbols::parse(SYMBOL_FILE);
+    assert_eq!(s.len(), 3);
+    assert_eq!(s.get(0x108), Some("sub"));
+    assert_eq!(s.get(0x107), None);
+    assert_eq!(s.nearest(0x107), Some(("on-reset", 7)));
+    assert_eq!(s.nearest(0x200), Some(("data", 0xf7)));
+    assert_eq!(s.nearest(0xff), None);
+}
+
+#[test]
+fn load_symbols() {
+    let dir = tempfile::tempdir().unwrap();
+    let rom = dir.path().join("foo.rom");
+    assert!(Symbols::load(None, &rom).unwrap().is_none());
+
+    std::fs::write(dir.path().join("foo.rom.sym"), SYMBOL_FILE).unwrap();
+    let s = Symbols::load(None, &rom).unwrap().unwrap();
+    assert_eq!(s.get(0x108), Some("sub"));
+
+    // An explicit symbol file must exist, and the error names it
+    let missing = dir.path().join("missing.sym");
+    let err = Symbols::load(Some(&missing), &rom).unwrap_err();
+    assert!(err.to_string().contains("missing.sym"));
+}
+
+#[test]
+fn debug_dump() {
+    #[rustfmt::skip]
+    const ROM: &[u8] = &[
+        // |0100 ;data ;sub JSR2 BRK
+        op::LIT2, 0x01, 0x09, op::LIT2, 0x01, 0x08, op::JSR2, op::BRK,
+        // @sub BRK @data
+        op::BRK,
+    ];
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert_eq!(
+        dev.debug_dump(&vm),
+        "WST 00 00 00 00 00 00|01 09 <\n\
+         RST 00 00 00 00 00 00|01 07 <\n"
+    );
+
+    dev.set_symbols(Some(Symbols::parse(SYMBOL_FILE)));
+    assert_eq!(
+        dev.debug_dump(&vm),
+        "WST 00 00 00 00 00 00|01 09 <\n    #0109 @data\n\
+         RST 00 00 00 00 00 00|01 07 <\n    #0107 @on-reset+7\n"
+    );
+
+    // Symbols belong to the ROM, so they're cleared on reset
+    dev.reset(&[]);
+    assert!(!dev.debug_dump(&vm).contains('@'));
+}
+
+/// Runs a ROM, returning the working and return stacks (bottom first)
+fn stacks(rom: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    let get = |s: &uxn::Stack| {
+        (0..s.len()).rev().map(|i| s.peek_byte_at(i)).collect()
+    };
+    (get(vm.stack()), get(vm.ret()))
+}
+
+/// Reading and writing `System/wst` and `System/rst`
+///
+/// Expected values match the reference implementation, which makes room for
+/// the result of `DEI` before reading the port, so the depth includes it.
+#[test]
+fn stack_depth() {
+    // #11 #22 .System/wst DEI
+    let r = stacks(&[op::LIT, 0x11, op::LIT, 0x22, op::LIT, 0x04, op::DEI]);
+    assert_eq!(r, (vec![0x11, 0x22, 0x03], vec![]));
+
+    // #11 .System/wst DEI2 (which also reads System/rst)
+    let r = stacks(&[op::LIT, 0x11, op::LIT, 0x04, op::DEI2]);
+    assert_eq!(r, (vec![0x11, 0x03, 0x00], vec![]));
+
+    // .System/wst DEIk
+    let r = stacks(&[op::LIT, 0x04, op::DEIk]);
+    assert_eq!(r, (vec![0x04, 0x02], vec![]));
+
+    // LIT2r 1234 .System/rst DEI
+    let r = stacks(&[op::LIT2r, 0x12, 0x34, op::LIT, 0x05, op::DEI]);
+    assert_eq!(r, (vec![0x02], vec![0x12, 0x34]));
+
+    // LITr 12 LITr 05 DEIr
+    let r = stacks(&[op::LITr, 0x12, op::LITr, 0x05, op::DEIr]);
+    assert_eq!(r, (vec![], vec![0x12, 0x02]));
+
+    // #11 #22 #33 #01 .System/wst DEO
+    #[rustfmt::skip]
+    let r = stacks(&[
+        op::LIT, 0x11, op::LIT, 0x22, op::LIT, 0x33,
+        op::LIT, 0x01, op::LIT, 0x04, op::DEO,
+    ]);
+    assert_eq!(r, (vec![0x11], vec![]));
+
+    // LIT2r 1234 #00 .System/rst DEO
+    #[rustfmt::skip]
+    let r = stacks(&[
+        op::LIT2r, 0x12, 0x34, op::LIT, 0x00, op::LIT, 0x05, op::DEO,
+    ]);
+    assert_eq!(r, (vec![], vec![]));
+
+    // Growing the stack exposes whatever was previously there
+    // #1122 #3344 POP2 POP2 #04 .System/wst DEO
+    #[rustfmt::skip]
+    let r = stacks(&[
+        op::LIT2, 0x11, 0x22, op::LIT2, 0x33, 0x44, op::POP2, op::POP2,
+        op::LIT, 0x04, op::LIT, 0x04, op::DEO,
+    ]);
+    assert_eq!(r, (vec![0x04, 0x04, 0x33, 0x44], vec![]));
+}
@@ -0,0 +1,38 @@
+use raven_varvara::{theme::Theme, Varvara};
+use uxn::{op, Backend, Uxn, UxnRam};
+
+#[test]
+fn parse() {
+    let t = Theme::parse(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]).unwrap();
+    assert_eq!(
+        t,
+        Theme {
+            r: 0x1234,
+            g: 0x5678,
+            b: 0x9abc
+        }
+    );
+    assert!(Theme::parse(&[0x12, 0x34, 0x56, 0x78, 0x9a]).is_none());
+    assert!(Theme::parse(&[0; 7]).is_none());
+}
+
+#[test]
+fn rom_colors_win() {
+    // #f00f .System/r DEO2 BRK
+    #[rustfmt::skip]
+    let rom = [
+        op::LIT2, 0xf0, 0x0f, op::LIT, 0x08, op::DEO2, op::BRK,
+    ];
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    dev.set_theme(Theme::parse(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]).unwrap());
+
+    let extra = vm.reset(&rom);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Red comes from the ROM; green and blue come from the theme
+    let out = dev.output(&vm);
+    assert_eq!(out.frame[..4], [0x99, 0x55, 0xff, 0xff]);
+}
@@ -0,0 +1,63 @@
+use raven_varvara::{FrameTimer, Varvara, FRAME_PERIOD};
+use std::time::Duration;
+use uxn::{op, Backend, Uxn, UxnRam};
+
+fn ms(t: u64) -> Duration {
+    Duration::from_millis(t)
+}
+
+#[test]
+fn due() {
+    let mut t = FrameTimer::new();
+    let ns = Duration::from_nanos(1);
+
+    // The first frame is due immediately
+    assert_eq!(t.due(ms(100)), 1);
+    assert_eq!(t.due(ms(100)), 0);
+    assert_eq!(t.due(ms(110)), 0);
+    assert_eq!(t.due(ms(100) + FRAME_PERIOD - ns), 0);
+
+    // Late wakeups don't accumulate drift
+    assert_eq!(t.due(ms(120)), 1);
+    assert_eq!(t.due(ms(134)), 1);
+    assert_eq!(t.due(ms(100) + FRAME_PERIOD * 3 - ns), 0);
+
+    // Missed frames are caught up
+    assert_eq!(t.due(ms(190)), 3);
+    assert_eq!(t.due(ms(100) + FRAME_PERIOD * 6 - ns), 0);
+
+    // After a long pause, the timer starts again from the current time
+    assert_eq!(t.due(ms(10_000)), 4);
+    assert_eq!(t.due(ms(10_000) + FRAME_PERIOD - ns), 0);
+    assert_eq!(t.due(ms(10_000) + FRAME_PERIOD), 1);
+
+    let mut t = FrameTimer::new().with_max_catchup(2);
+    assert_eq!(t.due(ms(0)), 1);
+    assert_eq!(t.due(ms(1000)), 2);
+}
+
+/// Increments the byte at `0x00` in the screen vector
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // ;on-frame .Screen/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x20, op::DEO2, op::BRK,
+    // @on-frame #00 LDZ INC #00 STZ BRK
+    op::LIT, 0x00, op::LDZ, op::INC, op::LIT, 0x00, op::STZ, op::BRK,
+];
+
+#[test]
+fn tick() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    let mut t = FrameTimer::new();
+    dev.tick(&mut vm, &mut t, ms(0));
+    dev.tick(&mut vm, &mut t, ms(10));
+    assert_eq!(vm.ram_read_byte(0), 1);
+    dev.tick(&mut vm, &mut t, ms(60));
+    assert_eq!(vm.ram_read_byte(0), 4);
+}
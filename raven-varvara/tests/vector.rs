@@ -0,0 +1,104 @@
+use raven_varvara::{Event, EventData, Varvara, SUBSCRIBE_CAPACITY};
+use std::sync::atomic::AtomicBool;
+use uxn::{op, Backend, Halt, Uxn, UxnRam, UNIT_CYCLE_COSTS};
+
+/// Installs a console vector which does nothing
+#[rustfmt::skip]
+const ROM: &[u8] = &[
+    // |0100 ;on-console .Console/vector DEO2 BRK
+    op::LIT2, 0x01, 0x07, op::LIT, 0x10, op::DEO2, op::BRK,
+    // @on-console BRK
+    op::BRK,
+];
+
+#[test]
+fn last_vector() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+    assert!(dev.last_vector().is_none());
+
+    // The screen vector is unassigned, so it isn't recorded
+    dev.redraw(&mut vm);
+    assert!(dev.last_vector().is_none());
+
+    dev.console(&mut vm, b'a');
+    let v = dev.last_vector().unwrap();
+    assert_eq!(v.device, 0x10);
+    assert_eq!(v.vector, 0x107);
+    assert_eq!(v.halt, Halt::Break { pc: 0x108 });
+    assert!(v.to_string().starts_with("Console/vector #0107"));
+    assert!(!v.to_string().contains('['));
+}
+
+#[test]
+fn last_vector_halt() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    // Running out of cycles is reported separately from an interrupt
+    vm.set_cycle_costs(Some(&UNIT_CYCLE_COSTS));
+    vm.set_cycle_limit(Some(0));
+    dev.console(&mut vm, b'a');
+    let v = dev.last_vector().unwrap();
+    assert_eq!(v.halt, Halt::OutOfCycles { pc: 0x107 });
+    assert!(v.to_string().ends_with("[out of cycles]"));
+    vm.set_cycle_limit(None);
+
+    let flag = AtomicBool::new(true);
+    vm.set_interrupt(Some(&flag));
+    dev.console(&mut vm, b'a');
+    let v = dev.last_vector().unwrap();
+    assert_eq!(v.halt, Halt::Interrupted { pc: 0x107 });
+    assert!(v.to_string().ends_with("[interrupted]"));
+}
+
+#[test]
+fn subscribe() {
+    let mut ram = UxnRam::new();
+    let mut vm = Uxn::new(&mut ram, Backend::Interpreter);
+    let mut dev = Varvara::new();
+    let rx = dev.subscribe();
+    let extra = vm.reset(ROM);
+    dev.reset(extra);
+    vm.run(&mut dev, 0x100);
+
+    dev.redraw(&mut vm);
+    dev.console(&mut vm, b'a');
+    dev.console(&mut vm, b'b');
+    let events: Vec<Event> = rx.try_iter().collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].device, 0x10);
+    assert_eq!(events[0].vector, 0x107);
+    assert_eq!(
+        events[1].data,
+        Some(EventData {
+            addr: 0x12,
+            value: b'b',
+            clear: false
+        })
+    );
+
+    // If the receiver falls behind, newer events are dropped
+    for _ in 0..SUBSCRIBE_CAPACITY + 10 {
+        dev.console(&mut vm, b'x');
+    }
+    dev.console(&mut vm, b'y');
+    let events: Vec<Event> = rx.try_iter().collect();
+    assert_eq!(events.len(), SUBSCRIBE_CAPACITY);
+    assert!(events.iter().all(|e| e.data.unwrap().value == b'x'));
+    dev.console(&mut vm, b'z');
+    assert_eq!(rx.try_recv().unwrap().data.unwrap().value, b'z');
+
+    // Dropping the receiver ends the subscription
+    drop(rx);
+    dev.console(&mut vm, b'c');
+    assert_eq!(dev.last_vector().unwrap().vector, 0x107);
+}